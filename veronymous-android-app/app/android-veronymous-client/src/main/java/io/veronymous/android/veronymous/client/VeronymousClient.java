@@ -99,6 +99,14 @@ public class VeronymousClient {
         EXECUTOR.execute(() -> {
             GetServersResult result = VeronymousClientJni.getServers(serversState);
 
+            // Only the server names are used
+            if (result.hasError()) {
+                try {
+                    Log.w(TAG, "Could not get the servers info. " + result.getError());
+                } catch (IllegalStateException ignored) {
+                }
+            }
+
             if (result.getServersStateResult().hasUpdate()) {
                 try {
                     saveServersState(context, result.getServersStateResult().getServersState());
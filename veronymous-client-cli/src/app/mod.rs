@@ -1,151 +1,1202 @@
+mod output;
+
+use crate::app::output::{error_message, print_json, Failure, OutputFormat};
+use crate::config::state::StateFiles;
+use crate::config::CliConfig;
+use crate::constants::app::{CONTROL_SOCKET_PATH, TUNNEL_LOCK_FILE_PATH};
 use crate::constants::cli::{
-    ABOUT, APP_NAME, APP_VERSION_01, AUTHOR, CONNECT_COMMAND, CONNECT_COMMAND_ABOUT,
-    CONNECT_COMMAND_VERSION, LIST_SERVERS, LIST_SERVERS_ABOUT, LIST_SERVERS_VERSION, SERVER_NAME,
-    TUNNEL_ONLY_ARG,
+    ABOUT, ALLOWED_IPS_ARG, ALLOW_LAN_ARG, APP_NAME, APP_VERSION_01, AUTHOR, BACKEND_ARG,
+    COMMAND_ARG, CONFIG_ARG, CONNECT_COMMAND, CONNECT_COMMAND_ABOUT, CONNECT_COMMAND_VERSION,
+    COUNTRY_ARG, CREDENTIALS_FILE_ARG, DAEMON_COMMAND, DAEMON_COMMAND_ABOUT,
+    DAEMON_COMMAND_VERSION, DEFAULT_EXEC_INTERFACE, DEFAULT_LISTEN_ADDRESS, DISCONNECT_COMMAND,
+    DISCONNECT_COMMAND_ABOUT, DISCONNECT_COMMAND_VERSION, DNS_ARG, DRY_RUN_ARG, ERROR_EXIT_CODE,
+    EXCLUDE_ARG, EXEC_COMMAND, EXEC_COMMAND_ABOUT, EXEC_COMMAND_VERSION, FEATURE_ARG,
+    FOREGROUND_ARG, FWMARK_ARG, GROUP_BY_REGION_ARG, HEALTH_CHECK_ADDRESS_ARG,
+    HEALTH_CHECK_INTERVAL_ARG, INCLUDE_ARG, INTERFACE_ARG, IN_PLACE_ROTATION, JSON_ARG,
+    JSON_OUTPUT, KERNEL_BACKEND, KILL_SWITCH_ARG, LISTEN_ARG, LIST_SERVERS, LIST_SERVERS_ABOUT,
+    LIST_SERVERS_VERSION, LOGIN_COMMAND, LOGIN_COMMAND_ABOUT, LOGIN_COMMAND_VERSION, MTU_ARG,
+    OUTPUT_ARG, PASSWORD_ENV, PASSWORD_STDIN_ARG, PERSISTENT_KEEPALIVE_ARG, PROXY_ARG,
+    PROXY_COMMAND, PROXY_COMMAND_ABOUT, PROXY_COMMAND_VERSION, ROTATION_ARG, ROTATION_GRACE_ARG,
+    SEAMLESS_ROTATION, SERVERS_FILE_ARG, SERVER_NAME, SOCKET_ARG, SOCKET_GROUP_ARG, STATE_DIR_ARG,
+    STATUS_COMMAND, STATUS_COMMAND_ABOUT, STATUS_COMMAND_VERSION, SWITCH_COMMAND,
+    SWITCH_COMMAND_ABOUT, SWITCH_COMMAND_VERSION, TABLE_ARG, TEXT_OUTPUT, TUNNEL_ONLY_ARG,
+    USERNAME_ARG, USERNAME_ENV, USERSPACE_BACKEND,
+};
+use crate::daemon;
+use crate::daemon::client::ControlClient;
+use crate::daemon::protocol::{ConnectionState, ControlCommand, DaemonStatus};
+use crate::daemon::Daemon;
+use crate::error::CliClientError::{CommandError, ProxyError};
+use crate::error::{io_error, CliClientError};
+use crate::proxy;
+use crate::utils::capability_utils::has_net_admin;
+use crate::utils::cli_utils::{get_password, get_user_input, read_line, stdin_is_terminal};
+use crate::utils::lock_utils::lock_file;
+use crate::utils::systemd_utils::{notify, notify_ready, notify_status};
+use crate::vpn_client::{CliVpnClient, ClientSettings, ConnectionEvent};
+use crate::wg::{
+    check_interface_name, exec_command, KernelBackend, KillSwitch, NetstackBackend,
+    RecordingBackend, Rotation, RoutingOptions, TunnelBackend, TunnelStatus, UserspaceBackend,
 };
-use crate::error::CliClientError;
-use crate::utils::cli_utils::{get_password, get_user_input};
-use crate::vpn_client::CliVpnClient;
-use crate::wg::wg_down;
 use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::PermissionsExt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use veronymous_client::config::VERONYMOUS_CLIENT_CONFIG;
 use veronymous_client::error::VeronymousClientError;
+use veronymous_client::servers::query::{group_by_region, ServerFilter, ServerInfo};
+use veronymous_client::vpn::ServerFeature;
 
-type RedoRequired = bool;
+// How often the exec command checks if the tunnel is up
+const COMMAND_START_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
 pub async fn run() {
     // Get the CLI
     let matches = get_matches();
 
+    // The command line options take precedence
+    let config = match CliConfig::load(matches.value_of(CONFIG_ARG)) {
+        Ok(config) => config,
+        Err(e) => OutputFormat::from_matches(&matches).fail(Failure::Error, &e.to_string()),
+    };
+    let config = &config;
+
     if let Some(matches) = matches.subcommand_matches(CONNECT_COMMAND) {
-        run_connect(matches).await;
+        run_connect(matches, config).await;
+    } else if let Some(matches) = matches.subcommand_matches(DAEMON_COMMAND) {
+        run_daemon(matches, config).await;
+    } else if let Some(matches) = matches.subcommand_matches(DISCONNECT_COMMAND) {
+        run_daemon_command(matches, config, ControlCommand::Disconnect).await;
+    } else if let Some(matches) = matches.subcommand_matches(SWITCH_COMMAND) {
+        let server = matches.value_of(SERVER_NAME).unwrap().to_string();
+
+        run_daemon_command(matches, config, ControlCommand::Switch { server }).await;
+    } else if let Some(matches) = matches.subcommand_matches(LOGIN_COMMAND) {
+        run_login(matches, config).await;
+    } else if let Some(matches) = matches.subcommand_matches(STATUS_COMMAND) {
+        run_status(matches, config).await;
+    } else if let Some(matches) = matches.subcommand_matches(EXEC_COMMAND) {
+        run_exec(matches, config).await;
+    } else if let Some(matches) = matches.subcommand_matches(PROXY_COMMAND) {
+        run_proxy(matches, config).await;
     } else if let Some(matches) = matches.subcommand_matches(LIST_SERVERS) {
-        run_list_servers(matches).await;
+        run_list_servers(matches, config).await;
     } else {
         debug!("Command is not supported.");
     }
 }
 
-pub async fn run_connect(matches: &ArgMatches) {
+/*
+* The daemon makes the connection if it is running, with its own options.
+* Otherwise, the connection is kept up until Ctrl-C.
+*/
+pub async fn run_connect(matches: &ArgMatches, config: &CliConfig) {
+    let output = OutputFormat::from_matches(matches);
     let server_name = matches.value_of(SERVER_NAME).unwrap().to_string();
-    let tunnel_only = matches.is_present(TUNNEL_ONLY_ARG);
-    let mut vpn_client = CliVpnClient::create().await.unwrap();
+    let socket = control_socket(matches, config);
 
-    // Set the Ctrl-C handler
-    set_disconnect_handler();
+    match ControlClient::connect(&socket).await {
+        Ok(None) => {}
+        Ok(Some(client)) if !matches.is_present(FOREGROUND_ARG) => {
+            run_control(
+                client,
+                ControlCommand::Connect {
+                    server: server_name,
+                },
+                output,
+            )
+            .await;
+            return;
+        }
+        // The daemon's tunnel would be torn down. The dry-run mode does not change the host.
+        Ok(Some(_)) if !matches.is_present(DRY_RUN_ARG) => output.fail(
+            Failure::Error,
+            &format!(
+                "A daemon is running on {}. Connect without --foreground or stop the daemon.",
+                socket
+            ),
+        ),
+        Ok(Some(_)) => {}
+        Err(e) => output.fail_with_error(&e),
+    }
+
+    let options = match routing_options(matches, config) {
+        Ok(options) => options,
+        Err(e) => output.fail(Failure::Error, &e),
+    };
+
+    require_net_admin(matches.is_present(DRY_RUN_ARG), output);
+
+    // Another connection would share the routing state (e.g., another veronymous@ instance)
+    let _lock = lock_tunnel(matches.is_present(DRY_RUN_ARG), output);
+
+    let tunnel = tunnel_backend(matches, config, output);
+
+    // Leftovers from a previous run
+    if let Err(e) = tunnel.recover() {
+        output.fail(
+            Failure::of(&e),
+            &format!("Could not clean up the previous connection. {}", e),
+        );
+    }
+
+    let mut vpn_client = create_vpn_client(matches, config, Some(tunnel.clone()), output).await;
+    set_event_output(&mut vpn_client, output);
+
+    // Ctrl-C and SIGTERM disconnect, SIGHUP reloads the config
+    set_signal_handlers(matches, tunnel.clone(), &mut vpn_client, output);
+
+    let exit_code = connect(&server_name, &options, &mut vpn_client, output).await;
+
+    // The connection has ended, disconnect
+    disconnect_with_code(tunnel.as_ref(), exit_code);
+}
+
+/*
+* Keep the requested connection up until stopped (Ctrl-C or SIGTERM).
+* Connects to the server at startup if set. Reloads the config on SIGHUP.
+*/
+pub async fn run_daemon(matches: &ArgMatches, config: &CliConfig) {
+    let output = OutputFormat::from_matches(matches);
+    let server_name = matches
+        .value_of(SERVER_NAME)
+        .map(|server| server.to_string());
+    let options = match routing_options(matches, config) {
+        Ok(options) => options,
+        Err(e) => output.fail(Failure::Error, &e),
+    };
+    let socket = control_socket(matches, config);
+
+    require_net_admin(matches.is_present(DRY_RUN_ARG), output);
+
+    let _lock = lock_tunnel(matches.is_present(DRY_RUN_ARG), output);
+
+    // Fails if another daemon is running
+    let listener = match daemon::bind(&socket, matches.value_of(SOCKET_GROUP_ARG)).await {
+        Ok(listener) => listener,
+        Err(e) => output.fail_with_error(&e),
+    };
 
-    while connect(&server_name, tunnel_only, &mut vpn_client).await {
-        // Redo
-        connect(&server_name, tunnel_only, &mut vpn_client).await;
+    let tunnel = tunnel_backend(matches, config, output);
+
+    // Leftovers from a previous run
+    if let Err(e) = tunnel.recover() {
+        output.fail(
+            Failure::of(&e),
+            &format!("Could not clean up the previous connection. {}", e),
+        );
+    }
+
+    let mut vpn_client = create_vpn_client(matches, config, Some(tunnel.clone()), output).await;
+    set_event_output(&mut vpn_client, output);
+
+    // Ctrl-C and SIGTERM disconnect, SIGHUP reloads the config
+    set_signal_handlers(matches, tunnel.clone(), &mut vpn_client, output);
+
+    info!("Listening on {}.", socket);
+
+    Daemon::new(vpn_client, tunnel.clone(), options, server_name)
+        .run(listener)
+        .await;
+
+    disconnect(tunnel.as_ref());
+}
+
+/*
+* Save the account's tokens for the connections, including the daemon's.
+* Does not prompt if the input is not a terminal.
+*/
+pub async fn run_login(matches: &ArgMatches, config: &CliConfig) {
+    let output = OutputFormat::from_matches(matches);
+    let (username, password) = match login_credentials(matches) {
+        Ok(credentials) => credentials,
+        Err(e) => output.fail(Failure::AuthRequired, &e),
+    };
+
+    let vpn_client = create_vpn_client(matches, config, None, output).await;
+
+    if let Err(e) = vpn_client.authenticate(username, password).await {
+        output.fail(Failure::of(&e), &auth_error_message(&e));
     }
 
-    // An error has occurred, disconnect
-    disconnect();
+    match output {
+        OutputFormat::Text => println!("Logged in."),
+        OutputFormat::Json => print_json(&json!({ "logged_in": true })),
+    }
+}
+
+// e.g., {"username": "user@example.com", "password": "..."}
+#[derive(Deserialize)]
+struct CredentialsFile {
+    username: String,
+
+    password: String,
+}
+
+/*
+* The credentials file, or the username and password from the options, then the environment,
+* then the prompts.
+*/
+fn login_credentials(matches: &ArgMatches) -> Result<(String, String), String> {
+    if let Some(path) = matches.value_of(CREDENTIALS_FILE_ARG) {
+        let credentials = read_credentials_file(path)?;
+
+        return Ok((credentials.username, credentials.password));
+    }
+
+    entered_credentials(
+        matches.value_of(USERNAME_ARG),
+        matches.is_present(PASSWORD_STDIN_ARG),
+    )
+}
+
+// The username and password from the options, then the environment, then the prompts
+fn entered_credentials(
+    username: Option<&str>,
+    password_stdin: bool,
+) -> Result<(String, String), String> {
+    // The password is read from the input
+    let interactive = stdin_is_terminal() && !password_stdin;
+
+    let username = match username
+        .map(|username| username.to_string())
+        .or_else(|| env::var(USERNAME_ENV).ok())
+    {
+        Some(username) => username,
+        // Keeps the output for the result
+        None if interactive => {
+            eprintln!("Enter username:");
+            get_user_input()
+        }
+        None => {
+            return Err(format!(
+                "Missing username. Set --username or {}.",
+                USERNAME_ENV
+            ))
+        }
+    };
+
+    let password = if password_stdin {
+        read_line().map_err(|e| format!("Could not read the password. {}", e))?
+    } else if let Ok(password) = env::var(PASSWORD_ENV) {
+        password
+    } else if interactive {
+        eprintln!("Enter password:");
+        get_password()
+    } else {
+        return Err(format!(
+            "Missing password. Set --password-stdin or {}.",
+            PASSWORD_ENV
+        ));
+    };
+
+    if password.is_empty() {
+        return Err("Empty password.".to_string());
+    }
+
+    Ok((username, password))
+}
+
+fn read_credentials_file(path: &str) -> Result<CredentialsFile, String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("Could not read {}. {}", path, e))?;
+    if metadata.permissions().mode() & 0o077 != 0 {
+        warn!(
+            "{} is readable by other users. Restrict it with `chmod 600 {}`.",
+            path, path
+        );
+    }
+
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Could not read {}. {}", path, e))?;
+
+    serde_json::from_str(&content).map_err(|e| format!("Invalid credentials file {}. {}", path, e))
+}
+
+/*
+* The daemon's connection, or the interface's if the daemon is not running.
+* The tunnel statistics require root.
+*/
+pub async fn run_status(matches: &ArgMatches, config: &CliConfig) {
+    let output = OutputFormat::from_matches(matches);
+    let status = match ControlClient::connect(&control_socket(matches, config)).await {
+        Ok(Some(mut client)) => match client.call(&ControlCommand::Status).await {
+            Ok(status) => status,
+            Err(e) => output.fail_with_error(&e),
+        },
+        Ok(None) => {
+            let interface = interface_name(matches, config, output);
+
+            let state = state_files(matches, config, output);
+
+            daemon::local_status(&KernelBackend::new(interface), &state)
+        }
+        Err(e) => output.fail_with_error(&e),
+    };
+
+    // Same as --output json
+    if output.is_json() || matches.is_present(JSON_ARG) {
+        print_json(&status);
+    } else {
+        print_status_details(&status);
+    }
+}
+
+// Disconnect and switch through the daemon
+pub async fn run_daemon_command(matches: &ArgMatches, config: &CliConfig, command: ControlCommand) {
+    let output = OutputFormat::from_matches(matches);
+
+    match ControlClient::connect(&control_socket(matches, config)).await {
+        Ok(Some(client)) => run_control(client, command, output).await,
+        Ok(None) => output.fail(Failure::Error, "The daemon is not running."),
+        Err(e) => output.fail_with_error(&e),
+    }
+}
+
+// Prints the daemon's status after the command
+async fn run_control(mut client: ControlClient, command: ControlCommand, output: OutputFormat) {
+    match client.call(&command).await {
+        Ok(status) if output.is_json() => print_json(&status),
+        Ok(status) => print_status(&status),
+        Err(e) => output.fail_with_error(&e),
+    }
+}
+
+fn print_status(status: &DaemonStatus) {
+    let server = status.server.clone().unwrap_or_default();
+
+    match status.state {
+        ConnectionState::Disconnected => println!("Disconnected."),
+        ConnectionState::Connecting => println!("Connecting to {}...", server),
+        ConnectionState::Connected => {
+            let interface = status.interface.clone().unwrap_or_default();
+
+            match &status.server {
+                Some(server) => println!("Connected to {} on {}.", server, interface),
+                // Not made by the daemon
+                None => println!("Connected on {}.", interface),
+            }
+        }
+        ConnectionState::Failed => println!(
+            "The connection to {} failed. {}",
+            server,
+            status.error.clone().unwrap_or_default()
+        ),
+    }
+}
+
+fn print_status_details(status: &DaemonStatus) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    print_status(status);
+
+    if !status.addresses.is_empty() {
+        println!("Addresses: {}", status.addresses.join(", "));
+    }
+    if let Some(tunnel) = &status.tunnel {
+        match tunnel.latest_handshake {
+            None => println!("Latest handshake: none"),
+            Some(handshake) => println!(
+                "Latest handshake: {} ago",
+                format_duration(now.saturating_sub(handshake))
+            ),
+        }
+        println!(
+            "Transfer: {} received, {} sent",
+            format_bytes(tunnel.rx_bytes),
+            format_bytes(tunnel.tx_bytes)
+        );
+    }
+    if let Some(next_rotation) = status.next_rotation {
+        println!(
+            "Next rotation: in {}",
+            format_duration(next_rotation.saturating_sub(now))
+        );
+    }
+
+    let credentials = match &status.credentials {
+        Some(credentials) => credentials,
+        None => return,
+    };
+
+    println!("Epoch: {}", credentials.epoch);
+    println!("Key epoch: {}", credentials.key_epoch);
+
+    for cache in &credentials.key_epochs {
+        println!(
+            "Key epoch {}: root token {}, issuer info {}",
+            cache.key_epoch,
+            cached(cache.root_token),
+            cached(cache.issuer_info)
+        );
+    }
+
+    match credentials.refresh_token_expiry {
+        None => println!("Not logged in."),
+        Some(expiry) if expiry <= now => println!("Refresh token expired."),
+        Some(expiry) => println!("Refresh token expires in {}", format_duration(expiry - now)),
+    }
+}
+
+fn cached(cached: bool) -> &'static str {
+    match cached {
+        true => "cached",
+        false => "missing",
+    }
 }
 
-async fn run_list_servers(_matches: &ArgMatches) {
-    let vpn_client = CliVpnClient::create().await.unwrap();
+// e.g., 1h 2m 5s
+fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+
+    match (hours, minutes) {
+        (0, 0) => format!("{}s", seconds),
+        (0, _) => format!("{}m {}s", minutes, seconds),
+        _ => format!("{}h {}m {}s", hours, minutes, seconds),
+    }
+}
+
+// e.g., 14:05:12
+fn format_time(unix_time: u64) -> String {
+    let seconds = unix_time % 86400;
+
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+// e.g., 1.5 MiB
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+// Kill switch, routing, rotation, health check and split tunnel options
+fn routing_options(matches: &ArgMatches, config: &CliConfig) -> Result<RoutingOptions, String> {
+    let kill_switch = match matches.is_present(KILL_SWITCH_ARG) {
+        false => KillSwitch::Disabled,
+        true => KillSwitch::Enabled {
+            allow_lan: matches.is_present(ALLOW_LAN_ARG),
+        },
+    };
+
+    let mut options = RoutingOptions::new(matches.is_present(TUNNEL_ONLY_ARG), kill_switch, config);
+    set_tunnel_settings(matches, &mut options)?;
+    set_routing_settings(matches, &mut options)?;
+    set_rotation(matches, &mut options)?;
+    set_health_check(matches, &mut options)?;
+    set_split_tunnel(matches, &mut options)?;
+
+    Ok(options)
+}
+
+// Only log the network changes in dry-run mode
+fn tunnel_backend(
+    matches: &ArgMatches,
+    config: &CliConfig,
+    output: OutputFormat,
+) -> Arc<dyn TunnelBackend> {
+    let interface = interface_name(matches, config, output);
+
+    match (
+        matches.is_present(DRY_RUN_ARG),
+        matches.value_of(BACKEND_ARG),
+    ) {
+        (true, _) => Arc::new(RecordingBackend::new(interface)),
+        (false, Some(USERSPACE_BACKEND)) => Arc::new(UserspaceBackend::new(interface)),
+        (false, _) => Arc::new(KernelBackend::new(interface)),
+    }
+}
+
+// The option, then the config file, then the client's config
+// Exits if the name is invalid
+fn interface_name(matches: &ArgMatches, config: &CliConfig, output: OutputFormat) -> String {
+    let interface = matches
+        .value_of(INTERFACE_ARG)
+        .map(|interface| interface.to_string())
+        .or_else(|| config.interface.clone())
+        .unwrap_or_else(|| VERONYMOUS_CLIENT_CONFIG.tunnel.interface.clone());
+
+    if let Err(e) = check_interface_name(&interface) {
+        output.fail(Failure::Error, &e.to_string());
+    }
+
+    interface
+}
+
+fn control_socket(matches: &ArgMatches, config: &CliConfig) -> String {
+    matches
+        .value_of(SOCKET_ARG)
+        .map(|socket| socket.to_string())
+        .or_else(|| config.socket.clone())
+        .unwrap_or_else(|| CONTROL_SOCKET_PATH.to_string())
+}
+
+// Exits if the state directory is unknown (e.g., HOME is not set)
+fn state_files(matches: &ArgMatches, config: &CliConfig, output: OutputFormat) -> StateFiles {
+    match StateFiles::resolve(matches.value_of(STATE_DIR_ARG), config) {
+        Ok(state) => state,
+        Err(e) => output.fail_with_error(&e),
+    }
+}
+
+/*
+* Held until the process exits. One process owns the tunnel at a time.
+* The dry-run mode does not change the host.
+*/
+fn lock_tunnel(dry_run: bool, output: OutputFormat) -> Option<File> {
+    if dry_run {
+        return None;
+    }
+
+    match lock_file(TUNNEL_LOCK_FILE_PATH) {
+        Ok(lock) => Some(lock),
+        Err(e) => output.fail_with_error(&e),
+    }
+}
+
+// The dry-run mode only logs the changes
+fn require_net_admin(dry_run: bool, output: OutputFormat) {
+    if !dry_run && !has_net_admin() {
+        output.fail(
+            Failure::Permission,
+            "Root or CAP_NET_ADMIN is required to create the tunnel.",
+        );
+    }
+}
+
+/*
+* Exits if the client could not be created (e.g., invalid proxy).
+* The commands that do not connect have no tunnel.
+*/
+async fn create_vpn_client(
+    matches: &ArgMatches,
+    config: &CliConfig,
+    tunnel: Option<Arc<dyn TunnelBackend>>,
+    output: OutputFormat,
+) -> CliVpnClient {
+    let state = state_files(matches, config, output);
+
+    match CliVpnClient::create(client_settings(matches, config), state, tunnel).await {
+        Ok(vpn_client) => vpn_client,
+        Err(e) => output.fail_with_error(&e),
+    }
+}
+
+// The command line options, then the config file
+fn client_settings(matches: &ArgMatches, config: &CliConfig) -> ClientSettings {
+    ClientSettings {
+        proxy: matches
+            .value_of(PROXY_ARG)
+            .map(|proxy| proxy.to_string())
+            .or_else(|| config.proxy.clone()),
+        token_endpoint_pins: config
+            .token_endpoint_pins
+            .clone()
+            .unwrap_or_else(|| VERONYMOUS_CLIENT_CONFIG.token_endpoint_pins.clone()),
+        servers_file: matches
+            .value_of(SERVERS_FILE_ARG)
+            .map(|file| file.to_string())
+            .or_else(|| config.servers_file.clone()),
+        servers_mirrors: config
+            .servers_mirrors
+            .clone()
+            .unwrap_or_else(|| VERONYMOUS_CLIENT_CONFIG.servers_mirrors.clone()),
+        servers_public_key: config
+            .servers_public_key
+            .clone()
+            .or_else(|| VERONYMOUS_CLIENT_CONFIG.servers_public_key.clone()),
+    }
+}
+
+/*
+* One JSON line per connection event.
+* Also sent to systemd if started as a service. Ready once the tunnel is up.
+*/
+fn set_event_output(vpn_client: &mut CliVpnClient, output: OutputFormat) {
+    let json = output.is_json();
+
+    vpn_client.set_event_handler(Box::new(move |event: &ConnectionEvent| {
+        if json {
+            print_json(event);
+        }
+
+        notify_event(event);
+    }));
+}
+
+fn notify_event(event: &ConnectionEvent) {
+    match event {
+        ConnectionEvent::Connecting { server } => {
+            notify_status(&format!("Connecting to {}...", server))
+        }
+        ConnectionEvent::Connected {
+            server,
+            next_rotation: Some(next_rotation),
+            ..
+        } => notify_ready(&format!(
+            "Connected to {}. Next rotation at {} UTC.",
+            server,
+            format_time(*next_rotation)
+        )),
+        ConnectionEvent::Connected { server, .. } => {
+            notify_ready(&format!("Connected to {}.", server))
+        }
+        ConnectionEvent::Rotating { server } => {
+            notify_status(&format!("Rotating the connection to {}...", server))
+        }
+        ConnectionEvent::Reconnecting { server, reason } => {
+            notify_status(&format!("Reconnecting to {}. {}", server, reason))
+        }
+    }
+}
+
+/*
+* Ctrl-C and SIGTERM tear down the tunnel and exit.
+* SIGHUP reloads the config file. The state directory and control socket are kept.
+*/
+fn set_signal_handlers(
+    matches: &ArgMatches,
+    tunnel: Arc<dyn TunnelBackend>,
+    vpn_client: &mut CliVpnClient,
+    output: OutputFormat,
+) {
+    let signals = [
+        SignalKind::interrupt(),
+        SignalKind::terminate(),
+        SignalKind::hangup(),
+    ]
+    .map(signal);
+
+    let (mut interrupt, mut terminate, mut hangup) = match signals {
+        [Ok(interrupt), Ok(terminate), Ok(hangup)] => (interrupt, terminate, hangup),
+        _ => output.fail(Failure::Error, "Could not set the signal handlers."),
+    };
+
+    let (reloads_sender, reloads) = mpsc::unbounded_channel();
+    vpn_client.set_reloads(reloads);
+
+    let matches = matches.clone();
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = interrupt.recv() => break,
+                _ = terminate.recv() => break,
+                _ = hangup.recv() => {
+                    info!("Reloading the config...");
+
+                    match CliConfig::load(matches.value_of(CONFIG_ARG)) {
+                        Ok(config) => {
+                            let _ = reloads_sender.send(client_settings(&matches, &config));
+                        }
+                        Err(e) => error!("Could not reload the config. {}", e),
+                    }
+                }
+            }
+        }
+
+        info!("Received Exit Signal!");
+        notify("STOPPING=1");
+
+        disconnect(tunnel.as_ref());
+    });
+}
+
+/*
+* Run the command in a namespace with the tunnel as its only route.
+* The host's routes and resolvers are not changed. Exits with the command's status.
+*/
+pub async fn run_exec(matches: &ArgMatches, config: &CliConfig) {
+    let output = OutputFormat::from_matches(matches);
+    let server_name = matches.value_of(SERVER_NAME).unwrap().to_string();
+    let command: Vec<String> = matches
+        .values_of(COMMAND_ARG)
+        .unwrap()
+        .map(|arg| arg.to_string())
+        .collect();
+
+    let mut options = RoutingOptions::new(false, KillSwitch::Disabled, config);
+    if let Err(e) = set_tunnel_settings(matches, &mut options) {
+        output.fail(Failure::Error, &e);
+    }
+    match parse_value(matches, HEALTH_CHECK_INTERVAL_ARG, "health check interval") {
+        Ok(Some(interval)) => options.health_check.interval = Duration::from_secs(interval),
+        Ok(None) => {}
+        Err(e) => output.fail(Failure::Error, &e),
+    }
+    // The standby interface would be created in the host's namespace
+    options.rotation = Rotation::InPlace;
+    // Not reachable through the host's interfaces
+    options.health_check.ping_address = None;
+    // The tunnel is the namespace's only route
+    options.split_include.clear();
+    options.split_exclude.clear();
+
+    // The namespace is named after the interface
+    let interface = matches.value_of(INTERFACE_ARG).unwrap().to_string();
+    if let Err(e) = check_interface_name(&interface) {
+        output.fail(Failure::Error, &e.to_string());
+    }
+
+    require_net_admin(false, output);
+
+    let tunnel: Arc<dyn TunnelBackend> = match matches.value_of(BACKEND_ARG) {
+        Some(USERSPACE_BACKEND) => Arc::new(UserspaceBackend::in_namespace(
+            interface.clone(),
+            interface.clone(),
+        )),
+        _ => Arc::new(KernelBackend::in_namespace(
+            interface.clone(),
+            interface.clone(),
+        )),
+    };
+
+    // Leftovers from a previous run
+    if let Err(e) = tunnel.recover() {
+        output.fail(
+            Failure::of(&e),
+            &format!("Could not clean up the previous namespace. {}", e),
+        );
+    }
+
+    // The command's output is not mixed with the events
+    let mut vpn_client = create_vpn_client(matches, config, Some(tunnel.clone()), output).await;
 
     // Set the Ctrl-C handler
-    set_disconnect_handler();
+    set_disconnect_handler(tunnel.clone());
+
+    // The connection keeps rotating while the command runs
+    let exit_code = tokio::select! {
+        // Ended before the command
+        code = connect(&server_name, &options, &mut vpn_client, output) => code.max(ERROR_EXIT_CODE),
+        status = run_command(tunnel.as_ref(), &interface, &command) => match status {
+            Ok(code) => code,
+            Err(e) => {
+                output.error(Failure::of(&e), &e.to_string());
+                ERROR_EXIT_CODE
+            }
+        },
+    };
+
+    disconnect_with_code(tunnel.as_ref(), exit_code);
+}
 
-    let servers = match vpn_client.get_servers(None).await {
+/*
+* Local proxy through a userspace tunnel. No interface or routes are created.
+*/
+pub async fn run_proxy(matches: &ArgMatches, config: &CliConfig) {
+    let output = OutputFormat::from_matches(matches);
+    let server_name = matches.value_of(SERVER_NAME).unwrap().to_string();
+
+    let listen_address = match matches.value_of(LISTEN_ARG).unwrap().parse::<SocketAddr>() {
+        Ok(address) => address,
+        Err(e) => output.fail(Failure::Error, &format!("Invalid listen address. {}", e)),
+    };
+
+    // The connection's resolvers are used by default
+    let dns_servers = match matches
+        .values_of(DNS_ARG)
+        .unwrap_or_default()
+        .map(|server| server.parse::<IpAddr>())
+        .collect::<Result<Vec<IpAddr>, _>>()
+    {
         Ok(servers) => servers,
-        Err(error) => {
-            println!("An error has occurred.");
-            debug!("Could not get servers. {:?}", error);
-            return;
+        Err(e) => output.fail(Failure::Error, &format!("Invalid DNS server. {}", e)),
+    };
+
+    let mut options = RoutingOptions::new(true, KillSwitch::Disabled, config);
+    if let Err(e) = set_tunnel_settings(matches, &mut options) {
+        output.fail(Failure::Error, &e);
+    }
+    if let Err(e) = set_health_check(matches, &mut options) {
+        output.fail(Failure::Error, &e);
+    }
+    // No host interface to ping through
+    options.health_check.ping_address = None;
+
+    let backend = match NetstackBackend::new(dns_servers) {
+        Ok(backend) => backend,
+        Err(e) => output.fail_with_error(&e),
+    };
+
+    let listener = match TcpListener::bind(listen_address).await {
+        Ok(listener) => listener,
+        Err(e) => output.fail_with_error(&io_error(
+            e,
+            &format!("Could not listen on {}.", listen_address),
+            ProxyError,
+        )),
+    };
+
+    info!("Proxy listening on {}.", listen_address);
+    tokio::spawn(proxy::serve(listener, backend.dialer()));
+
+    let tunnel: Arc<dyn TunnelBackend> = Arc::new(backend);
+
+    let mut vpn_client = create_vpn_client(matches, config, Some(tunnel.clone()), output).await;
+    set_event_output(&mut vpn_client, output);
+
+    // Set the Ctrl-C handler
+    set_disconnect_handler(tunnel.clone());
+
+    let exit_code = connect(&server_name, &options, &mut vpn_client, output).await;
+
+    // The connection has ended, disconnect
+    disconnect_with_code(tunnel.as_ref(), exit_code);
+}
+
+// MTU and keepalive. Overrides the config and the server's suggestion.
+fn set_tunnel_settings(matches: &ArgMatches, options: &mut RoutingOptions) -> Result<(), String> {
+    if let Some(mtu) = parse_value(matches, MTU_ARG, "MTU")? {
+        options.mtu = Some(mtu);
+    }
+    if let Some(persistent_keepalive) =
+        parse_value(matches, PERSISTENT_KEEPALIVE_ARG, "persistent keepalive")?
+    {
+        options.persistent_keepalive = Some(persistent_keepalive);
+    }
+
+    Ok(())
+}
+
+// Fwmark, routing table and allowed ips. Overrides the config.
+fn set_routing_settings(matches: &ArgMatches, options: &mut RoutingOptions) -> Result<(), String> {
+    if let Some(fwmark) = parse_value(matches, FWMARK_ARG, "fwmark")? {
+        options.fwmark = fwmark;
+    }
+    if let Some(table) = parse_value(matches, TABLE_ARG, "routing table")? {
+        options.table = table;
+    }
+    if let Some(allowed_ips) = matches.values_of(ALLOWED_IPS_ARG) {
+        options.allowed_ips = allowed_ips
+            .map(|allowed_ip| allowed_ip.trim().to_string())
+            .collect();
+    }
+
+    Ok(())
+}
+
+// The grace period is read from the config if not set
+fn set_rotation(matches: &ArgMatches, options: &mut RoutingOptions) -> Result<(), String> {
+    let grace_period = match parse_value(matches, ROTATION_GRACE_ARG, "rotation grace period")? {
+        Some(grace_period) => Duration::from_secs(grace_period),
+        None => Duration::from_secs(VERONYMOUS_CLIENT_CONFIG.tunnel.rotation_grace_period),
+    };
+
+    match matches.value_of(ROTATION_ARG) {
+        Some(SEAMLESS_ROTATION) => options.rotation = Rotation::Seamless { grace_period },
+        Some(_) => options.rotation = Rotation::InPlace,
+        None => {
+            if let Rotation::Seamless { .. } = options.rotation {
+                options.rotation = Rotation::Seamless { grace_period };
+            }
         }
+    }
+
+    Ok(())
+}
+
+// Interval and ping address. Overrides the config.
+fn set_health_check(matches: &ArgMatches, options: &mut RoutingOptions) -> Result<(), String> {
+    if let Some(interval) =
+        parse_value(matches, HEALTH_CHECK_INTERVAL_ARG, "health check interval")?
+    {
+        options.health_check.interval = Duration::from_secs(interval);
+    }
+    if let Some(address) = parse_value(matches, HEALTH_CHECK_ADDRESS_ARG, "health check address")? {
+        options.health_check.ping_address = Some(address);
+    }
+
+    Ok(())
+}
+
+/*
+* Included or excluded destinations. Overrides the config.
+* The domains are resolved on every connection.
+*/
+fn set_split_tunnel(matches: &ArgMatches, options: &mut RoutingOptions) -> Result<(), String> {
+    if options.tunnel_only {
+        return Ok(());
+    }
+
+    let include = matches.values_of(INCLUDE_ARG);
+    let exclude = matches.values_of(EXCLUDE_ARG);
+
+    if include.is_some() || exclude.is_some() {
+        options.split_include = values(include);
+        options.split_exclude = values(exclude);
+    }
+
+    if !options.split_include.is_empty() && !options.split_exclude.is_empty() {
+        return Err("The destinations cannot be both included and excluded.".to_string());
+    }
+
+    if !options.split_include.is_empty()
+        && matches!(options.kill_switch, KillSwitch::Enabled { .. })
+    {
+        return Err(
+            "The kill switch cannot be used when only some destinations go through the tunnel."
+                .to_string(),
+        );
+    }
+
+    Ok(())
+}
+
+fn values(values: Option<clap::Values>) -> Vec<String> {
+    values
+        .map(|values| values.map(|value| value.trim().to_string()).collect())
+        .unwrap_or_default()
+}
+
+fn parse_value<T: FromStr>(matches: &ArgMatches, arg: &str, name: &str) -> Result<Option<T>, String>
+where
+    T::Err: std::fmt::Display,
+{
+    match matches.value_of(arg) {
+        None => Ok(None),
+        Some(value) => value
+            .parse::<T>()
+            .map(Some)
+            .map_err(|e| format!("Invalid {} {}. {}", name, value, e)),
+    }
+}
+
+async fn run_list_servers(matches: &ArgMatches, config: &CliConfig) {
+    let output = OutputFormat::from_matches(matches);
+    let country = matches
+        .value_of(COUNTRY_ARG)
+        .map(|country| country.to_string());
+    let group_by_region_enabled = matches.is_present(GROUP_BY_REGION_ARG);
+
+    let feature = match matches.value_of(FEATURE_ARG).map(ServerFeature::parse) {
+        None => None,
+        Some(Ok(feature)) => Some(feature),
+        Some(Err(error)) => output.fail(Failure::Error, &error.to_string()),
+    };
+
+    // Does not own a tunnel
+    let vpn_client = create_vpn_client(matches, config, None, output).await;
+
+    let filter = ServerFilter::new(country, feature);
+
+    let servers = match vpn_client.get_servers(&filter).await {
+        Ok(servers) => servers,
+        Err(error) => output.fail(
+            Failure::of(&error),
+            &format!("Could not get servers. {}", error_message(&error)),
+        ),
     };
 
+    // An array, or an object of arrays by region
+    if output.is_json() {
+        match group_by_region_enabled {
+            true => print_json(&group_by_region(servers)),
+            false => print_json(&servers),
+        }
+        return;
+    }
+
     println!("VPN Servers");
 
-    // Print the servers
-    for server in servers {
-        println!("\t* {}", server);
+    if group_by_region_enabled {
+        for (region, servers) in group_by_region(servers) {
+            println!("{}", region);
+
+            for server in servers {
+                println!("\t* {}", format_server(&server));
+            }
+        }
+    } else {
+        // Print the servers
+        for server in servers {
+            println!("\t* {}", format_server(&server));
+        }
     }
 }
 
+// e.g., new_york - New York, New York, US [ipv6, multihop]
+fn format_server(server: &ServerInfo) -> String {
+    let mut line = server.domain.clone();
+
+    let location: Vec<&str> = [&server.city, &server.country]
+        .iter()
+        .filter_map(|value| value.as_deref())
+        .collect();
+
+    if server.display_name != server.domain || !location.is_empty() {
+        line.push_str(" - ");
+        line.push_str(&server.display_name);
+    }
+
+    for value in location {
+        line.push_str(", ");
+        line.push_str(value);
+    }
+
+    if !server.features.is_empty() {
+        let features: Vec<String> = server
+            .features
+            .iter()
+            .map(|feature| feature.to_string())
+            .collect();
+
+        line.push_str(&format!(" [{}]", features.join(", ")));
+    }
+
+    line
+}
+
+/*
+* Returns the exit code once the connection has ended.
+* Prompts for the credentials once if required and in a terminal. Fails otherwise.
+*/
 async fn connect(
     server_name: &String,
-    tunnel_only: bool,
+    options: &RoutingOptions,
     client: &mut CliVpnClient,
-) -> RedoRequired {
-    match client.connect(server_name.to_string(), tunnel_only).await {
-        Ok(_) => {}
-        Err(error) => match error {
-            CliClientError::VeronymousClientError(error) => match error {
-                VeronymousClientError::AuthRequired() => {
-                    user_auth(&client).await;
-                    return true;
-                }
-                VeronymousClientError::SubscriptionRequired() => {
-                    debug!("Subscription is required");
-                    return false;
-                }
-                _ => {
-                    error!("An error has occurred. {:?}", error);
+    output: OutputFormat,
+) -> i32 {
+    let mut logged_in = false;
+
+    loop {
+        let error = match client.connect(server_name.to_string(), options).await {
+            Ok(_) => return 0,
+            Err(error) => error,
+        };
+
+        if let CliClientError::VeronymousClientError(VeronymousClientError::AuthRequired()) = error
+        {
+            if !logged_in && stdin_is_terminal() && !output.is_json() {
+                if let Err((failure, message)) = user_auth(client).await {
+                    output.connection_failed(failure, &message);
+
+                    return failure.exit_code();
                 }
-            },
-            CliClientError::SubscriptionRequired => {
-                error!("VPN Subscription is required.");
-            }
-            _ => {
-                error!("An error has occurred. {:?}", error);
+
+                // Redo once logged in
+                logged_in = true;
+                continue;
             }
-        },
-    }
+        }
+
+        let failure = Failure::of(&error);
+        let message = match failure {
+            Failure::AuthRequired => format!(
+                "Authentication is required. Log in with `{} {}`.",
+                APP_NAME, LOGIN_COMMAND
+            ),
+            Failure::SubscriptionRequired => "VPN subscription is required.".to_string(),
+            _ => format!("An error has occurred. {}", error_message(&error)),
+        };
 
-    false
+        output.connection_failed(failure, &message);
+
+        return failure.exit_code();
+    }
 }
 
-async fn user_auth(client: &CliVpnClient) {
-    println!("Enter username:");
-    let user_name = get_user_input();
+// Same credentials as the login command, without its options
+async fn user_auth(client: &CliVpnClient) -> Result<(), (Failure, String)> {
+    let (username, password) =
+        entered_credentials(None, false).map_err(|message| (Failure::AuthRequired, message))?;
 
-    println!("Enter password:");
-    let password = get_password();
+    client
+        .authenticate(username, password)
+        .await
+        .map_err(|e| (Failure::of(&e), auth_error_message(&e)))
+}
 
-    match client.authenticate(user_name, password).await {
-        Ok(_) => {}
-        Err(e) => match e {
-            CliClientError::VeronymousClientError(e) => match e {
-                VeronymousClientError::OidcError(_) => {
-                    error!("Authentication failed.");
-                }
-                VeronymousClientError::SubscriptionRequired() => {
-                    error!("VPN subscription is required.");
-                }
-                _ => {
-                    error!("An error has occurred. {:?}", e);
-                }
-            },
-            _ => {
-                error!("An error has occurred. {:?}", e);
-            }
-        },
+fn auth_error_message(error: &CliClientError) -> String {
+    match Failure::of(error) {
+        Failure::AuthRequired => "Authentication failed.".to_string(),
+        Failure::SubscriptionRequired => "VPN subscription is required.".to_string(),
+        _ => format!("An error has occurred. {}", error_message(error)),
     }
 }
 
-fn set_disconnect_handler() {
+fn set_disconnect_handler(tunnel: Arc<dyn TunnelBackend>) {
     ctrlc::set_handler(move || {
         info!("Received Exit Signal!");
 
-        disconnect();
+        disconnect(tunnel.as_ref());
     })
     .expect("Could not set ctrl-c handler.");
 }
 
-fn disconnect() {
-    match wg_down() {
+/*
+* Run the command once the tunnel is up.
+* Returns its exit code, 1 if it was killed by a signal.
+*/
+async fn run_command(
+    tunnel: &dyn TunnelBackend,
+    namespace: &str,
+    command: &[String],
+) -> Result<i32, CliClientError> {
+    while !matches!(tunnel.status()?, TunnelStatus::Up { .. }) {
+        tokio::time::sleep(COMMAND_START_POLL_INTERVAL).await;
+    }
+
+    let mut child = exec_command(namespace, command)?
+        .spawn()
+        .map_err(|e| CommandError(format!("Could not run {}. {:?}", command[0], e)))?;
+
+    let status = tokio::task::spawn_blocking(move || child.wait())
+        .await
+        .map_err(|e| CommandError(format!("Could not wait for {}. {:?}", command[0], e)))?
+        .map_err(|e| CommandError(format!("Could not wait for {}. {:?}", command[0], e)))?;
+
+    Ok(status.code().unwrap_or(1))
+}
+
+fn disconnect(tunnel: &dyn TunnelBackend) {
+    disconnect_with_code(tunnel, 0);
+}
+
+// Exits with the code, or 1 if the tunnel could not be torn down
+fn disconnect_with_code(tunnel: &dyn TunnelBackend, code: i32) {
+    if let Ok(TunnelStatus::Up { interface }) = tunnel.status() {
+        info!("Tearing down {}...", interface);
+    }
+
+    match tunnel.down() {
         Ok(_) => {
-            std::process::exit(0);
+            std::process::exit(code);
         }
         Err(e) => {
             error!(
                 "Encountered an error when tearing down the connection. {:?}",
                 e
             );
-            std::process::exit(1);
+            std::process::exit(ERROR_EXIT_CODE);
         }
     }
 }
@@ -155,6 +1206,55 @@ pub fn get_matches() -> ArgMatches {
         .version(APP_VERSION_01)
         .author(AUTHOR)
         .about(ABOUT)
+        .arg(
+            Arg::with_name(PROXY_ARG)
+                .help("SOCKS5 proxy for the token issuer and IdP traffic (e.g., socks5h://127.0.0.1:9050 for Tor).")
+                .long("proxy")
+                .global(true)
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(SOCKET_ARG)
+                .help("Control socket of the daemon.")
+                .long("socket")
+                .global(true)
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(OUTPUT_ARG)
+                .help("Output format. json prints one JSON value per line (results, connection events and errors).")
+                .long("output")
+                .global(true)
+                .required(false)
+                .takes_value(true)
+                .possible_values(&[TEXT_OUTPUT, JSON_OUTPUT]),
+        )
+        .arg(
+            Arg::with_name(CONFIG_ARG)
+                .help("Config file. Defaults to /etc/veronymous-vpn/config.toml for root, $XDG_CONFIG_HOME/veronymous-vpn/config.toml otherwise.")
+                .long("config")
+                .global(true)
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(STATE_DIR_ARG)
+                .help("Client state and servers list directory. Defaults to /var/lib/veronymous for root, $XDG_STATE_HOME/veronymous-vpn otherwise.")
+                .long("state-dir")
+                .global(true)
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(SERVERS_FILE_ARG)
+                .help("Local servers list file (e.g., for self-hosted setups). A detached signature is read from <file>.sig.")
+                .long("servers-file")
+                .global(true)
+                .required(false)
+                .takes_value(true),
+        )
         .subcommand(
             SubCommand::with_name(CONNECT_COMMAND)
                 .about(CONNECT_COMMAND_ABOUT)
@@ -166,20 +1266,315 @@ pub fn get_matches() -> ArgMatches {
                         .required(true)
                         .takes_value(true),
                 )
+                .args(tunnel_args())
                 .arg(
-                    Arg::with_name(TUNNEL_ONLY_ARG)
-                        .help("Don't forward all traffic through the VPN server.")
-                        .long("--tunnel-only")
-                        .short('t')
+                    Arg::with_name(FOREGROUND_ARG)
+                        .help("Connect in this process even if the daemon is running.")
+                        .long("foreground")
                         .required(false)
                         .takes_value(false),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name(DAEMON_COMMAND)
+                .about(DAEMON_COMMAND_ABOUT)
+                .version(DAEMON_COMMAND_VERSION)
+                .author(AUTHOR)
+                .arg(
+                    Arg::with_name(SERVER_NAME)
+                        .help("Server connected at startup.")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .args(tunnel_args())
+                .arg(
+                    Arg::with_name(SOCKET_GROUP_ARG)
+                        .help("Group allowed to use the control socket. Only root can use it by default.")
+                        .long("socket-group")
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(DISCONNECT_COMMAND)
+                .about(DISCONNECT_COMMAND_ABOUT)
+                .version(DISCONNECT_COMMAND_VERSION)
+                .author(AUTHOR),
+        )
+        .subcommand(
+            SubCommand::with_name(SWITCH_COMMAND)
+                .about(SWITCH_COMMAND_ABOUT)
+                .version(SWITCH_COMMAND_VERSION)
+                .author(AUTHOR)
+                .arg(
+                    Arg::with_name(SERVER_NAME)
+                        .help("Server name.")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(LOGIN_COMMAND)
+                .about(LOGIN_COMMAND_ABOUT)
+                .version(LOGIN_COMMAND_VERSION)
+                .author(AUTHOR)
+                .arg(
+                    Arg::with_name(USERNAME_ARG)
+                        .help("Account username. Defaults to $VERONYMOUS_USERNAME.")
+                        .long("username")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(PASSWORD_STDIN_ARG)
+                        .help("Read the password from the first line of the input. Defaults to $VERONYMOUS_PASSWORD.")
+                        .long("password-stdin")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name(CREDENTIALS_FILE_ARG)
+                        .help("JSON file with the username and password (e.g., {\"username\": \"...\", \"password\": \"...\"}).")
+                        .long("credentials-file")
+                        .required(false)
+                        .takes_value(true)
+                        .conflicts_with_all(&[USERNAME_ARG, PASSWORD_STDIN_ARG]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(STATUS_COMMAND)
+                .about(STATUS_COMMAND_ABOUT)
+                .version(STATUS_COMMAND_VERSION)
+                .author(AUTHOR)
+                .arg(
+                    Arg::with_name(INTERFACE_ARG)
+                        .help("WireGuard interface shown if the daemon is not running. Defaults to the configured one (veron0).")
+                        .long("interface")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(JSON_ARG)
+                        .help("Print the status as JSON.")
+                        .long("json")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(EXEC_COMMAND)
+                .about(EXEC_COMMAND_ABOUT)
+                .version(EXEC_COMMAND_VERSION)
+                .author(AUTHOR)
+                .arg(
+                    Arg::with_name(SERVER_NAME)
+                        .help("Server name.")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(COMMAND_ARG)
+                        .help("Command run in the namespace, after --. Run as the sudo user if started with sudo.")
+                        .required(true)
+                        .takes_value(true)
+                        .multiple_values(true)
+                        .last(true),
+                )
+                .arg(backend_arg())
+                .arg(
+                    Arg::with_name(INTERFACE_ARG)
+                        .help("Name of the WireGuard interface and of its namespace. Use another one to run several commands through different connections.")
+                        .long("interface")
+                        .required(false)
+                        .takes_value(true)
+                        .default_value(DEFAULT_EXEC_INTERFACE),
+                )
+                .arg(mtu_arg())
+                .arg(persistent_keepalive_arg())
+                .arg(health_check_interval_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name(PROXY_COMMAND)
+                .about(PROXY_COMMAND_ABOUT)
+                .version(PROXY_COMMAND_VERSION)
+                .author(AUTHOR)
+                .arg(
+                    Arg::with_name(SERVER_NAME)
+                        .help("Server name.")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(LISTEN_ARG)
+                        .help("Local address of the SOCKS5/HTTP CONNECT proxy.")
+                        .long("listen")
+                        .required(false)
+                        .takes_value(true)
+                        .default_value(DEFAULT_LISTEN_ADDRESS),
+                )
+                .arg(
+                    Arg::with_name(DNS_ARG)
+                        .help("DNS server used through the tunnel to resolve the proxied host names. Defaults to the server's resolvers.")
+                        .long("dns")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(mtu_arg())
+                .arg(persistent_keepalive_arg())
+                .arg(health_check_interval_arg()),
+        )
         .subcommand(
             SubCommand::with_name(LIST_SERVERS)
                 .about(LIST_SERVERS_ABOUT)
                 .version(LIST_SERVERS_VERSION)
-                .author(AUTHOR),
+                .author(AUTHOR)
+                .arg(
+                    Arg::with_name(COUNTRY_ARG)
+                        .help("Only list the servers in the country (e.g., CA).")
+                        .long("country")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(FEATURE_ARG)
+                        .help("Only list the servers supporting the feature.")
+                        .long("feature")
+                        .possible_values(["ipv6", "multihop", "obfuscation"])
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(GROUP_BY_REGION_ARG)
+                        .help("Group the servers by region.")
+                        .long("group-by-region")
+                        .required(false)
+                        .takes_value(false),
+                ),
         )
         .get_matches()
 }
+
+// Connection options of the connect and daemon commands
+fn tunnel_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::with_name(TUNNEL_ONLY_ARG)
+            .help("Don't forward all traffic through the VPN server.")
+            .long("--tunnel-only")
+            .short('t')
+            .required(false)
+            .takes_value(false),
+        Arg::with_name(KILL_SWITCH_ARG)
+            .help("Block all traffic outside of the tunnel (nftables). Stays in place until disconnecting.")
+            .long("kill-switch")
+            .required(false)
+            .takes_value(false)
+            .conflicts_with(TUNNEL_ONLY_ARG),
+        Arg::with_name(ALLOW_LAN_ARG)
+            .help("Allow the local network traffic with the kill switch.")
+            .long("allow-lan")
+            .required(false)
+            .takes_value(false)
+            .requires(KILL_SWITCH_ARG),
+        backend_arg(),
+        Arg::with_name(INTERFACE_ARG)
+            .help("Name of the WireGuard interface. Defaults to the configured one (veron0).")
+            .long("interface")
+            .required(false)
+            .takes_value(true),
+        mtu_arg(),
+        persistent_keepalive_arg(),
+        Arg::with_name(FWMARK_ARG)
+            .help("Firewall mark of the tunnel's own packets. Change it if it collides with another VPN.")
+            .long("fwmark")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name(TABLE_ARG)
+            .help("Routing table of the tunnel routes.")
+            .long("table")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name(ALLOWED_IPS_ARG)
+            .help("Networks routed to the server (e.g., 0.0.0.0/0,::/0).")
+            .long("allowed-ips")
+            .required(false)
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .use_value_delimiter(true),
+        Arg::with_name(INCLUDE_ARG)
+            .help("Only route these networks or domains through the tunnel (e.g., 10.0.0.0/8,example.com). The domains are resolved when connecting.")
+            .long("include")
+            .required(false)
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .use_value_delimiter(true)
+            .conflicts_with_all(&[TUNNEL_ONLY_ARG, KILL_SWITCH_ARG, ALLOWED_IPS_ARG]),
+        Arg::with_name(EXCLUDE_ARG)
+            .help("Route these networks or domains outside of the tunnel (e.g., 192.168.0.0/16,example.com). The domains are resolved when connecting.")
+            .long("exclude")
+            .required(false)
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .use_value_delimiter(true)
+            .conflicts_with_all(&[TUNNEL_ONLY_ARG, INCLUDE_ARG]),
+        Arg::with_name(ROTATION_ARG)
+            .help("How the connection is switched to the next epoch. Seamless brings the next connection up on a second interface before tearing down the current one.")
+            .long("rotation")
+            .required(false)
+            .takes_value(true)
+            .possible_values([SEAMLESS_ROTATION, IN_PLACE_ROTATION])
+            .conflicts_with(TUNNEL_ONLY_ARG),
+        Arg::with_name(ROTATION_GRACE_ARG)
+            .help("Seconds the previous connection is kept after a seamless rotation.")
+            .long("rotation-grace")
+            .required(false)
+            .takes_value(true),
+        health_check_interval_arg(),
+        Arg::with_name(HEALTH_CHECK_ADDRESS_ARG)
+            .help("Address pinged through the tunnel on every health check (e.g., the server's resolver). Reconnects after 3 failed pings.")
+            .long("health-check-address")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name(DRY_RUN_ARG)
+            .help("Log the network configuration instead of applying it.")
+            .long("dry-run")
+            .required(false)
+            .takes_value(false),
+    ]
+}
+
+fn backend_arg() -> Arg<'static> {
+    Arg::with_name(BACKEND_ARG)
+        .help(
+            "WireGuard implementation. Use userspace on hosts without the wireguard kernel module.",
+        )
+        .long("backend")
+        .required(false)
+        .takes_value(true)
+        .possible_values([KERNEL_BACKEND, USERSPACE_BACKEND])
+        .default_value(KERNEL_BACKEND)
+}
+
+fn mtu_arg() -> Arg<'static> {
+    Arg::with_name(MTU_ARG)
+        .help("MTU of the tunnel. Defaults to the server's suggestion or 1420.")
+        .long("mtu")
+        .required(false)
+        .takes_value(true)
+}
+
+fn persistent_keepalive_arg() -> Arg<'static> {
+    Arg::with_name(PERSISTENT_KEEPALIVE_ARG)
+        .help("Keepalive interval in seconds (e.g., 25 behind NAT). 0 disables it.")
+        .long("persistent-keepalive")
+        .required(false)
+        .takes_value(true)
+}
+
+fn health_check_interval_arg() -> Arg<'static> {
+    Arg::with_name(HEALTH_CHECK_INTERVAL_ARG)
+        .help("Seconds between the tunnel health checks. Reconnects if the server stops responding. 0 disables them.")
+        .long("health-check-interval")
+        .required(false)
+        .takes_value(true)
+}
@@ -0,0 +1,217 @@
+use crate::constants::cli::{
+    AUTH_REQUIRED_EXIT_CODE, ERROR_EXIT_CODE, JSON_OUTPUT, NETWORK_ERROR_EXIT_CODE, OUTPUT_ARG,
+    PERMISSION_ERROR_EXIT_CODE, SUBSCRIPTION_REQUIRED_EXIT_CODE,
+};
+use crate::error::CliClientError;
+use clap::ArgMatches;
+use serde::Serialize;
+use veronymous_client::error::VeronymousClientError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+
+    // One JSON value per line on stdout. The logs stay on stderr.
+    Json,
+}
+
+// Why a command failed. Sets the exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    Error,
+
+    AuthRequired,
+
+    SubscriptionRequired,
+
+    // e.g., the servers, the token issuer or the IdP could not be reached
+    Network,
+
+    // e.g., not root, or not allowed to use the control socket
+    Permission,
+}
+
+/*
+* e.g., {"error":"auth_required","message":"...","exit_code":3}
+* Connection failures are events: {"event":"failed","error":"network",...}
+*/
+#[derive(Serialize)]
+struct ErrorOutput<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<&'static str>,
+
+    error: Failure,
+
+    message: &'a str,
+
+    exit_code: i32,
+}
+
+impl OutputFormat {
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        match matches.value_of(OUTPUT_ARG) {
+            Some(JSON_OUTPUT) => OutputFormat::Json,
+            _ => OutputFormat::Text,
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        *self == OutputFormat::Json
+    }
+
+    // Logged in text mode
+    pub fn error(&self, failure: Failure, message: &str) {
+        self.report(None, failure, message);
+    }
+
+    // The connection has ended with the failure
+    pub fn connection_failed(&self, failure: Failure, message: &str) {
+        self.report(Some("failed"), failure, message);
+    }
+
+    pub fn fail(&self, failure: Failure, message: &str) -> ! {
+        self.error(failure, message);
+
+        std::process::exit(failure.exit_code());
+    }
+
+    pub fn fail_with_error(&self, error: &CliClientError) -> ! {
+        self.fail(Failure::of(error), &error_message(error));
+    }
+
+    fn report(&self, event: Option<&'static str>, failure: Failure, message: &str) {
+        match self {
+            OutputFormat::Text => error!("{}", message),
+            OutputFormat::Json => print_json(&ErrorOutput {
+                event,
+                error: failure,
+                message,
+                exit_code: failure.exit_code(),
+            }),
+        }
+    }
+}
+
+impl Failure {
+    pub fn of(error: &CliClientError) -> Self {
+        match error {
+            CliClientError::SubscriptionRequired => Failure::SubscriptionRequired,
+            CliClientError::PermissionError(_) => Failure::Permission,
+            CliClientError::HandshakeError(_) => Failure::Network,
+            CliClientError::VeronymousClientError(error) => match error {
+                // OIDC errors are the IdP's invalid_grant responses. The others are HTTP errors.
+                VeronymousClientError::AuthRequired() | VeronymousClientError::OidcError(_) => {
+                    Failure::AuthRequired
+                }
+                VeronymousClientError::SubscriptionRequired() => Failure::SubscriptionRequired,
+                VeronymousClientError::ConnectError(_)
+                | VeronymousClientError::HttpError(_)
+                | VeronymousClientError::ProxyError(_)
+                | VeronymousClientError::TokenClientError(_) => Failure::Network,
+                _ => Failure::Error,
+            },
+            _ => Failure::Error,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Error => ERROR_EXIT_CODE,
+            Failure::AuthRequired => AUTH_REQUIRED_EXIT_CODE,
+            Failure::SubscriptionRequired => SUBSCRIPTION_REQUIRED_EXIT_CODE,
+            Failure::Network => NETWORK_ERROR_EXIT_CODE,
+            Failure::Permission => PERMISSION_ERROR_EXIT_CODE,
+        }
+    }
+}
+
+// The library error's message instead of its wrapper's
+pub fn error_message(error: &CliClientError) -> String {
+    match error {
+        CliClientError::VeronymousClientError(error) => error.to_string(),
+        _ => error.to_string(),
+    }
+}
+
+pub fn print_json<T: Serialize>(value: &T) {
+    match serde_json::to_string(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => error!("Could not encode the output. {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::output::{ErrorOutput, Failure};
+    use crate::error::CliClientError;
+    use veronymous_client::error::VeronymousClientError;
+
+    #[test]
+    fn test_failure() {
+        let failure = |error: VeronymousClientError| {
+            Failure::of(&CliClientError::VeronymousClientError(error))
+        };
+
+        assert_eq!(
+            Failure::AuthRequired,
+            failure(VeronymousClientError::AuthRequired())
+        );
+        assert_eq!(
+            Failure::Network,
+            failure(VeronymousClientError::HttpError("Timed out.".to_string()))
+        );
+        assert_eq!(
+            Failure::Error,
+            failure(VeronymousClientError::ParseError("Invalid.".to_string()))
+        );
+        assert_eq!(
+            Failure::AuthRequired,
+            failure(VeronymousClientError::OidcError(
+                "Invalid username or password.".to_string()
+            ))
+        );
+        // e.g., the http client could not be created
+        assert_eq!(
+            Failure::Error,
+            failure(VeronymousClientError::VeronymousError(
+                "Could not create http client.".to_string()
+            ))
+        );
+        assert_eq!(
+            Failure::SubscriptionRequired,
+            Failure::of(&CliClientError::SubscriptionRequired)
+        );
+        assert_eq!(
+            Failure::Permission,
+            Failure::of(&CliClientError::PermissionError("Denied.".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_error_output() {
+        let output = ErrorOutput {
+            event: None,
+            error: Failure::SubscriptionRequired,
+            message: "VPN subscription is required.",
+            exit_code: Failure::SubscriptionRequired.exit_code(),
+        };
+
+        assert_eq!(
+            r#"{"error":"subscription_required","message":"VPN subscription is required.","exit_code":4}"#,
+            serde_json::to_string(&output).unwrap()
+        );
+
+        let output = ErrorOutput {
+            event: Some("failed"),
+            error: Failure::Network,
+            message: "Could not fetch servers.",
+            exit_code: Failure::Network.exit_code(),
+        };
+
+        assert_eq!(
+            r#"{"event":"failed","error":"network","message":"Could not fetch servers.","exit_code":5}"#,
+            serde_json::to_string(&output).unwrap()
+        );
+    }
+}
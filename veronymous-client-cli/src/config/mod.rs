@@ -0,0 +1,180 @@
+pub mod state;
+
+use crate::constants::app::{APP_DIR_NAME, CONFIG_FILE_NAME, SYSTEM_CONFIG_DIR};
+use crate::error::CliClientError::{ParseError, ReadFileError};
+use crate::error::{io_error, CliClientError};
+use crate::utils::path_utils::get_xdg_path;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+/*
+* CLI settings. Overridden by the command line options.
+* e.g.,
+* state_dir = "/var/lib/veronymous"
+* proxy = "socks5h://127.0.0.1:9050"
+* interface = "veron1"
+* fwmark = 51821
+*/
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CliConfig {
+    // Client state and servers list directory
+    pub state_dir: Option<String>,
+
+    // Local servers list file (e.g., for self-hosted setups)
+    pub servers_file: Option<String>,
+
+    // Tried in order if the servers endpoint is unavailable
+    pub servers_mirrors: Option<Vec<String>>,
+
+    // Base64 Ed25519 key the servers lists must be signed with (e.g., for self-hosted setups)
+    pub servers_public_key: Option<String>,
+
+    // SOCKS5 proxy for the token issuer and IdP traffic
+    pub proxy: Option<String>,
+
+    // Token issuer public key pins (sha256/<base64>). Every additional pin is a backup.
+    pub token_endpoint_pins: Option<Vec<String>>,
+
+    // Control socket of the daemon
+    pub socket: Option<String>,
+
+    // Tunnel interface name
+    pub interface: Option<String>,
+
+    // Overrides the server's suggestion
+    pub mtu: Option<u32>,
+
+    pub fwmark: Option<u32>,
+
+    // Tunnel routing table
+    pub table: Option<u32>,
+
+    // Seconds. Overrides the server's suggestion.
+    pub persistent_keepalive: Option<u16>,
+
+    // <address>/<prefix length>
+    pub allowed_ips: Option<Vec<String>>,
+
+    // Only these destinations go through the tunnel. <address>[/<prefix length>] or domains.
+    pub split_include: Option<Vec<String>>,
+
+    // These destinations bypass the tunnel
+    pub split_exclude: Option<Vec<String>>,
+}
+
+impl CliConfig {
+    /*
+     * The file set on the command line must exist.
+     * The default one (/etc/veronymous-vpn or $XDG_CONFIG_HOME/veronymous-vpn) is optional.
+     */
+    pub fn load(path: Option<&str>) -> Result<Self, CliClientError> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => match default_config_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let content = fs::read_to_string(&path).map_err(|e| {
+            io_error(
+                e,
+                &format!("Could not read {}.", path.display()),
+                ReadFileError,
+            )
+        })?;
+
+        Self::parse(&content)
+            .map_err(|e| ParseError(format!("Invalid config file {}. {}", path.display(), e)))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(content)
+    }
+}
+
+// Root uses the system's config file
+fn default_config_path() -> Option<PathBuf> {
+    let dir = match state::system_install() {
+        true => PathBuf::from(SYSTEM_CONFIG_DIR),
+        false => get_xdg_path("XDG_CONFIG_HOME", ".config")?.join(APP_DIR_NAME),
+    };
+
+    Some(dir.join(CONFIG_FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::CliConfig;
+
+    #[test]
+    fn test_parse() {
+        let config = CliConfig::parse(
+            r#"
+            state_dir = "/srv/veronymous"
+            proxy = "socks5h://127.0.0.1:9050"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(Some("/srv/veronymous".to_string()), config.state_dir);
+        assert_eq!(Some("socks5h://127.0.0.1:9050".to_string()), config.proxy);
+        assert_eq!(None, config.servers_file);
+
+        let config = CliConfig::parse(
+            r#"token_endpoint_pins = ["sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(vec![
+                "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_string()
+            ]),
+            config.token_endpoint_pins
+        );
+
+        let config =
+            CliConfig::parse(r#"servers_mirrors = ["https://mirror.example.com/servers.json"]"#)
+                .unwrap();
+
+        assert_eq!(
+            Some(vec!["https://mirror.example.com/servers.json".to_string()]),
+            config.servers_mirrors
+        );
+
+        let config = CliConfig::parse(
+            r#"
+            interface = "veron1"
+            mtu = 1380
+            fwmark = 51821
+            table = 51821
+            persistent_keepalive = 25
+            allowed_ips = ["10.0.0.0/8"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(Some("veron1".to_string()), config.interface);
+        assert_eq!(Some(1380), config.mtu);
+        assert_eq!(Some(51821), config.fwmark);
+        assert_eq!(Some(51821), config.table);
+        assert_eq!(Some(25), config.persistent_keepalive);
+        assert_eq!(Some(vec!["10.0.0.0/8".to_string()]), config.allowed_ips);
+
+        let config = CliConfig::parse(r#"split_exclude = ["192.168.0.0/16", "zoom.us"]"#).unwrap();
+
+        assert_eq!(
+            Some(vec!["192.168.0.0/16".to_string(), "zoom.us".to_string()]),
+            config.split_exclude
+        );
+        assert_eq!(None, config.split_include);
+
+        // Every setting is optional
+        assert_eq!(CliConfig::default(), CliConfig::parse("").unwrap());
+
+        // Typos are not ignored
+        assert!(CliConfig::parse(r#"statedir = "/srv/veronymous""#).is_err());
+    }
+}
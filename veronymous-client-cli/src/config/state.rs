@@ -0,0 +1,208 @@
+use crate::config::CliConfig;
+use crate::constants::app::{
+    APP_DIR_NAME, CLIENT_FILE_NAME, LEGACY_STATE_DIR, STATE_DIR_ENV, SYSTEM_STATE_DIR,
+    VPN_SERVERS_FILE_NAME,
+};
+use crate::error::CliClientError::{InitializationError, IoError};
+use crate::error::{io_error, CliClientError};
+use crate::utils::path_utils::{get_home_path, get_xdg_path};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+// The client state holds the user's tokens
+const STATE_DIR_MODE: u32 = 0o700;
+const STATE_FILE_MODE: u32 = 0o600;
+
+// Client state and servers list files
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateFiles {
+    pub client_file: PathBuf,
+
+    pub servers_file: PathBuf,
+}
+
+impl StateFiles {
+    pub fn new(state_dir: &Path) -> Self {
+        Self {
+            client_file: state_dir.join(CLIENT_FILE_NAME),
+            servers_file: state_dir.join(VPN_SERVERS_FILE_NAME),
+        }
+    }
+
+    // The files of the previous versions are moved to the state directory
+    pub fn resolve(state_dir: Option<&str>, config: &CliConfig) -> Result<Self, CliClientError> {
+        let state_dir = resolve_state_dir(state_dir, config)?;
+
+        migrate(&state_dir, &legacy_dirs());
+
+        Ok(Self::new(&state_dir))
+    }
+}
+
+// Root's files are shared by the system (e.g., the daemon)
+pub fn system_install() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/*
+* The command line option, then VERONYMOUS_STATE_DIR, then the config file.
+* Defaults to /var/lib/veronymous for root, $XDG_STATE_HOME/veronymous-vpn otherwise.
+*/
+fn resolve_state_dir(
+    state_dir: Option<&str>,
+    config: &CliConfig,
+) -> Result<PathBuf, CliClientError> {
+    let state_dir = state_dir
+        .map(|dir| dir.to_string())
+        .or_else(|| env::var(STATE_DIR_ENV).ok())
+        .or_else(|| config.state_dir.clone())
+        .filter(|dir| !dir.is_empty());
+
+    if let Some(state_dir) = state_dir {
+        return Ok(PathBuf::from(state_dir));
+    }
+
+    if system_install() {
+        return Ok(PathBuf::from(SYSTEM_STATE_DIR));
+    }
+
+    match get_xdg_path("XDG_STATE_HOME", ".local/state") {
+        Some(dir) => Ok(dir.join(APP_DIR_NAME)),
+        None => Err(InitializationError(format!(
+            "HOME is not set. Set --state-dir or {}.",
+            STATE_DIR_ENV
+        ))),
+    }
+}
+
+// Root's files are not moved to the users' directories
+fn legacy_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = get_home_path(LEGACY_STATE_DIR.trim_start_matches('/'))
+        .into_iter()
+        .collect();
+
+    if system_install() {
+        dirs.push(PathBuf::from(LEGACY_STATE_DIR));
+    }
+
+    dirs
+}
+
+/*
+* Move the client state and servers list from the first previous directory that has them.
+* Files already in the state directory are kept. Best effort.
+*/
+pub fn migrate(state_dir: &Path, legacy_dirs: &[PathBuf]) {
+    for name in [CLIENT_FILE_NAME, VPN_SERVERS_FILE_NAME] {
+        let target = state_dir.join(name);
+
+        if target.exists() {
+            continue;
+        }
+
+        let source = match legacy_dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|file| file.is_file())
+        {
+            Some(source) => source,
+            None => continue,
+        };
+
+        match move_file(&source, &target) {
+            Ok(()) => info!("Moved {} to {}.", source.display(), target.display()),
+            Err(e) => warn!(
+                "Could not move {} to {}. {}",
+                source.display(),
+                target.display(),
+                e
+            ),
+        }
+    }
+}
+
+fn move_file(source: &Path, target: &Path) -> Result<(), CliClientError> {
+    if let Some(parent) = target.parent() {
+        create_state_dir(parent)?;
+    }
+
+    // Across file systems, copy then remove
+    if fs::rename(source, target).is_err() {
+        let contents = fs::read(source)
+            .map_err(|e| io_error(e, &format!("Could not read {}.", source.display()), IoError))?;
+
+        write_state_file(target, &contents)?;
+
+        fs::remove_file(source).map_err(|e| {
+            io_error(
+                e,
+                &format!("Could not remove {}.", source.display()),
+                IoError,
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+// Only readable by its owner
+pub fn create_state_dir(dir: &Path) -> Result<(), CliClientError> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(STATE_DIR_MODE)
+        .create(dir)
+        .map_err(|e| io_error(e, &format!("Could not create {}.", dir.display()), IoError))
+}
+
+// Creates the state directory if it does not exist
+pub fn write_state_file(path: &Path, contents: &[u8]) -> Result<(), CliClientError> {
+    if let Some(parent) = path.parent() {
+        create_state_dir(parent)?;
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(STATE_FILE_MODE)
+        .open(path)
+        .map_err(|e| io_error(e, &format!("Could not write {}.", path.display()), IoError))?;
+
+    file.write_all(contents)
+        .map_err(|e| io_error(e, &format!("Could not write {}.", path.display()), IoError))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::state::migrate;
+    use crate::constants::app::{CLIENT_FILE_NAME, VPN_SERVERS_FILE_NAME};
+    use std::fs;
+
+    #[test]
+    fn test_migrate() {
+        let dir = std::env::temp_dir().join(format!("veronymous-migrate-{}", std::process::id()));
+        let legacy_dir = dir.join("opt");
+        let state_dir = dir.join("state");
+
+        fs::create_dir_all(&legacy_dir).unwrap();
+        fs::create_dir_all(&state_dir).unwrap();
+        fs::write(legacy_dir.join(CLIENT_FILE_NAME), "previous").unwrap();
+        fs::write(legacy_dir.join(VPN_SERVERS_FILE_NAME), "previous").unwrap();
+        // Already migrated
+        fs::write(state_dir.join(VPN_SERVERS_FILE_NAME), "current").unwrap();
+
+        migrate(&state_dir, &[dir.join("missing"), legacy_dir.clone()]);
+
+        let read = |path| fs::read_to_string(path).unwrap();
+
+        assert_eq!("previous", read(state_dir.join(CLIENT_FILE_NAME)));
+        assert!(!legacy_dir.join(CLIENT_FILE_NAME).exists());
+        assert_eq!("current", read(state_dir.join(VPN_SERVERS_FILE_NAME)));
+        assert_eq!("previous", read(legacy_dir.join(VPN_SERVERS_FILE_NAME)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
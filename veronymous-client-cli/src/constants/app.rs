@@ -1,2 +1,19 @@
-pub const CLIENT_FILE_PATH: &str = "/opt/veronymous-vpn/vpn_client.json";
-pub const VPN_SERVERS_FILE_PATH: &str = "/opt/veronymous-vpn/servers.json";
+pub const APP_DIR_NAME: &str = "veronymous-vpn";
+pub const CLIENT_FILE_NAME: &str = "vpn_client.json";
+pub const VPN_SERVERS_FILE_NAME: &str = "servers.json";
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+// System installs (root)
+pub const SYSTEM_STATE_DIR: &str = "/var/lib/veronymous";
+pub const SYSTEM_CONFIG_DIR: &str = "/etc/veronymous-vpn";
+
+// Overrides the config file's state directory
+pub const STATE_DIR_ENV: &str = "VERONYMOUS_STATE_DIR";
+
+// State directory of the previous versions. Also relative to the home directory.
+pub const LEGACY_STATE_DIR: &str = "/opt/veronymous-vpn";
+
+pub const ROUTING_STATE_FILE_PATH: &str = "/run/veronymous-vpn/routing.json";
+pub const CONTROL_SOCKET_PATH: &str = "/run/veronymous-vpn/control.sock";
+// Held by the process owning the tunnel (daemon or connect --foreground)
+pub const TUNNEL_LOCK_FILE_PATH: &str = "/run/veronymous-vpn/tunnel.lock";
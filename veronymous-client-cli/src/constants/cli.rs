@@ -3,13 +3,100 @@ pub const APP_VERSION_01: &str = "0.1";
 pub const APP_NAME: &str = "veronymous-vpn";
 pub const AUTHOR: &str = "Noah Bouma";
 pub const ABOUT: &str = "Veronymous VPN client application";
+pub const PROXY_ARG: &str = "PROXY";
+pub const SERVERS_FILE_ARG: &str = "SERVERS_FILE";
+pub const SOCKET_ARG: &str = "SOCKET";
+pub const OUTPUT_ARG: &str = "OUTPUT";
+pub const CONFIG_ARG: &str = "CONFIG";
+pub const STATE_DIR_ARG: &str = "STATE_DIR";
+pub const TEXT_OUTPUT: &str = "text";
+pub const JSON_OUTPUT: &str = "json";
+
+/*
+* Exit codes. Stable, scripts rely on them (see the README).
+* 2 is the invalid usage exit code of the argument parser.
+*/
+pub const ERROR_EXIT_CODE: i32 = 1;
+pub const AUTH_REQUIRED_EXIT_CODE: i32 = 3;
+pub const SUBSCRIPTION_REQUIRED_EXIT_CODE: i32 = 4;
+pub const NETWORK_ERROR_EXIT_CODE: i32 = 5;
+pub const PERMISSION_ERROR_EXIT_CODE: i32 = 6;
 
 pub const LIST_SERVERS: &str = "list-servers";
 pub const LIST_SERVERS_ABOUT: &str = "List available Veronymous VPN servers.";
 pub const LIST_SERVERS_VERSION: &str = "0.1";
+pub const COUNTRY_ARG: &str = "COUNTRY";
+pub const FEATURE_ARG: &str = "FEATURE";
+pub const GROUP_BY_REGION_ARG: &str = "GROUP_BY_REGION";
 
 pub const CONNECT_COMMAND: &str = "connect";
 pub const CONNECT_COMMAND_ABOUT: &str = "Connect to a Veronymous VPN server.";
 pub const CONNECT_COMMAND_VERSION: &str = "0.1";
 pub const SERVER_NAME: &str = "SERVER_NAME";
 pub const TUNNEL_ONLY_ARG: &str = "TUNNEL_ONLY";
+pub const DRY_RUN_ARG: &str = "DRY_RUN";
+pub const KILL_SWITCH_ARG: &str = "KILL_SWITCH";
+pub const ALLOW_LAN_ARG: &str = "ALLOW_LAN";
+pub const BACKEND_ARG: &str = "BACKEND";
+pub const KERNEL_BACKEND: &str = "kernel";
+pub const USERSPACE_BACKEND: &str = "userspace";
+pub const INTERFACE_ARG: &str = "INTERFACE";
+pub const MTU_ARG: &str = "MTU";
+pub const FWMARK_ARG: &str = "FWMARK";
+pub const TABLE_ARG: &str = "TABLE";
+pub const PERSISTENT_KEEPALIVE_ARG: &str = "PERSISTENT_KEEPALIVE";
+pub const ALLOWED_IPS_ARG: &str = "ALLOWED_IPS";
+pub const ROTATION_ARG: &str = "ROTATION";
+pub const SEAMLESS_ROTATION: &str = "seamless";
+pub const IN_PLACE_ROTATION: &str = "in-place";
+pub const ROTATION_GRACE_ARG: &str = "ROTATION_GRACE";
+pub const HEALTH_CHECK_INTERVAL_ARG: &str = "HEALTH_CHECK_INTERVAL";
+pub const HEALTH_CHECK_ADDRESS_ARG: &str = "HEALTH_CHECK_ADDRESS";
+pub const INCLUDE_ARG: &str = "INCLUDE";
+pub const EXCLUDE_ARG: &str = "EXCLUDE";
+pub const FOREGROUND_ARG: &str = "FOREGROUND";
+
+pub const DAEMON_COMMAND: &str = "daemon";
+pub const DAEMON_COMMAND_ABOUT: &str =
+    "Run the service keeping the connection up, controlled through a local socket.";
+pub const DAEMON_COMMAND_VERSION: &str = "0.1";
+pub const SOCKET_GROUP_ARG: &str = "SOCKET_GROUP";
+
+pub const DISCONNECT_COMMAND: &str = "disconnect";
+pub const DISCONNECT_COMMAND_ABOUT: &str = "Disconnect the daemon from the VPN server.";
+pub const DISCONNECT_COMMAND_VERSION: &str = "0.1";
+
+pub const SWITCH_COMMAND: &str = "switch";
+pub const SWITCH_COMMAND_ABOUT: &str = "Switch the daemon's connection to another VPN server.";
+pub const SWITCH_COMMAND_VERSION: &str = "0.1";
+
+pub const LOGIN_COMMAND: &str = "login";
+pub const LOGIN_COMMAND_ABOUT: &str =
+    "Log in to the Veronymous account. Prompts for the missing credentials if run in a terminal.";
+pub const LOGIN_COMMAND_VERSION: &str = "0.1";
+pub const USERNAME_ARG: &str = "USERNAME";
+pub const PASSWORD_STDIN_ARG: &str = "PASSWORD_STDIN";
+pub const CREDENTIALS_FILE_ARG: &str = "CREDENTIALS_FILE";
+pub const USERNAME_ENV: &str = "VERONYMOUS_USERNAME";
+pub const PASSWORD_ENV: &str = "VERONYMOUS_PASSWORD";
+
+pub const STATUS_COMMAND: &str = "status";
+pub const STATUS_COMMAND_ABOUT: &str =
+    "Show the connection, its epochs and the cached tokens and credentials.";
+pub const STATUS_COMMAND_VERSION: &str = "0.1";
+pub const JSON_ARG: &str = "JSON";
+
+pub const EXEC_COMMAND: &str = "exec";
+pub const EXEC_COMMAND_ABOUT: &str =
+    "Run a command in a network namespace whose only route is a tunnel to a Veronymous VPN server.";
+pub const EXEC_COMMAND_VERSION: &str = "0.1";
+pub const COMMAND_ARG: &str = "COMMAND";
+pub const DEFAULT_EXEC_INTERFACE: &str = "veronx0";
+
+pub const PROXY_COMMAND: &str = "proxy";
+pub const PROXY_COMMAND_ABOUT: &str =
+    "Run a local SOCKS5/HTTP proxy through a Veronymous VPN server. Does not require root.";
+pub const PROXY_COMMAND_VERSION: &str = "0.1";
+pub const LISTEN_ARG: &str = "LISTEN";
+pub const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:1080";
+pub const DNS_ARG: &str = "DNS";
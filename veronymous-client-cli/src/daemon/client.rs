@@ -0,0 +1,84 @@
+use crate::daemon::protocol::{ControlCommand, ControlResponse, DaemonStatus};
+use crate::error::CliClientError::{ControlError, EncodingError, ParseError};
+use crate::error::{io_error, CliClientError};
+use std::io::ErrorKind;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+
+/*
+* Client of the daemon's control socket.
+*/
+pub struct ControlClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+
+    writer: OwnedWriteHalf,
+
+    next_id: u64,
+}
+
+impl ControlClient {
+    // None if no daemon is listening on the socket
+    pub async fn connect(path: &str) -> Result<Option<Self>, CliClientError> {
+        let stream = match UnixStream::connect(path).await {
+            Ok(stream) => stream,
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(io_error(
+                    e,
+                    &format!("Could not connect to the daemon on {}.", path),
+                    ControlError,
+                ));
+            }
+        };
+
+        let (reader, writer) = stream.into_split();
+
+        Ok(Some(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 1,
+        }))
+    }
+
+    // The daemon's status after the command. The daemon's error message if it failed.
+    pub async fn call(&mut self, command: &ControlCommand) -> Result<DaemonStatus, CliClientError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut request = serde_json::to_string(&command.to_request(id))
+            .map_err(|e| EncodingError(e.to_string()))?;
+        request.push('\n');
+
+        self.writer
+            .write_all(request.as_bytes())
+            .await
+            .map_err(|e| ControlError(format!("Could not send request. {:?}", e)))?;
+
+        let line = self
+            .lines
+            .next_line()
+            .await
+            .map_err(|e| ControlError(format!("Could not read response. {:?}", e)))?
+            .ok_or_else(|| ControlError("The daemon closed the connection.".to_string()))?;
+
+        let response: ControlResponse = serde_json::from_str(&line)
+            .map_err(|e| ParseError(format!("Invalid response. {:?}", e)))?;
+
+        if response.id != id {
+            return Err(ControlError(format!(
+                "Unexpected response {} to request {}.",
+                response.id, id
+            )));
+        }
+
+        match (response.result, response.error) {
+            (_, Some(error)) => Err(ControlError(error.message)),
+            (Some(result), None) => serde_json::from_value(result)
+                .map_err(|e| ParseError(format!("Invalid status. {:?}", e))),
+            (None, None) => Err(ControlError("Empty response.".to_string())),
+        }
+    }
+}
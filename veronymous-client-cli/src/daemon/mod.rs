@@ -0,0 +1,533 @@
+pub mod client;
+pub mod protocol;
+
+use crate::config::state::StateFiles;
+use crate::constants::cli::{APP_NAME, LOGIN_COMMAND};
+use crate::daemon::protocol::{
+    parse_request, ConnectionState, ControlCommand, ControlResponse, CredentialsStatus,
+    DaemonStatus, RpcError, TunnelStatistics, DAEMON_ERROR,
+};
+use crate::error::CliClientError::{ControlError, EncodingError};
+use crate::error::{io_error, CliClientError};
+use crate::utils::systemd_utils::{notify_ready, notify_watchdog, watchdog_interval};
+use crate::vpn_client::{CliVpnClient, CurrentConnection};
+use crate::wg::{RoutingOptions, TunnelBackend, TunnelStatus};
+use std::ffi::CString;
+use std::fs;
+use std::future;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use veronymous_client::error::VeronymousClientError;
+
+// Root only, or root and the control group
+const SOCKET_MODE: u32 = 0o600;
+const GROUP_SOCKET_MODE: u32 = 0o660;
+
+type Reply = oneshot::Sender<Result<DaemonStatus, String>>;
+
+/*
+* Long-running service owning the tunnel and the epoch rotations.
+* Controlled through the Unix domain socket by the connect, disconnect, switch and status commands.
+*/
+pub struct Daemon {
+    vpn_client: CliVpnClient,
+
+    tunnel: Arc<dyn TunnelBackend>,
+
+    options: RoutingOptions,
+
+    state: Arc<Mutex<DaemonState>>,
+
+    // How often the service manager's watchdog is pinged. None if not enabled.
+    watchdog: Option<Duration>,
+}
+
+// Shared with the control connections
+struct DaemonState {
+    // Server of the requested connection
+    server: Option<String>,
+
+    // Why the connection to the server failed
+    error: Option<String>,
+
+    // Addresses and rotation time of the up connection
+    current: Arc<Mutex<CurrentConnection>>,
+
+    // Read for the credentials status
+    state_files: StateFiles,
+}
+
+impl Daemon {
+    pub fn new(
+        vpn_client: CliVpnClient,
+        tunnel: Arc<dyn TunnelBackend>,
+        options: RoutingOptions,
+        server: Option<String>,
+    ) -> Self {
+        let current = vpn_client.current_connection();
+        let state_files = vpn_client.state_files().clone();
+
+        Self {
+            vpn_client,
+            tunnel,
+            options,
+            state: Arc::new(Mutex::new(DaemonState {
+                server,
+                error: None,
+                current,
+                state_files,
+            })),
+            watchdog: watchdog_interval(),
+        }
+    }
+
+    /*
+     * Serve the control socket and keep the requested connection up.
+     * The connection is replaced when another server is requested.
+     */
+    pub async fn run(mut self, listener: UnixListener) {
+        let (commands_sender, mut commands) = mpsc::unbounded_channel();
+
+        tokio::spawn(serve(
+            listener,
+            commands_sender,
+            self.state.clone(),
+            self.tunnel.clone(),
+        ));
+
+        loop {
+            let server = self.state.lock().unwrap().active_server();
+
+            let server = match server {
+                Some(server) => server,
+                // Wait for a connect command
+                None => {
+                    // Also ready if the connection at startup failed
+                    notify_ready(&idle_status(&self.state));
+
+                    tokio::select! {
+                        command = commands.recv() => match command {
+                            None => return,
+                            Some((command, reply)) => {
+                                let _ = reply.send(execute(command, &self.state, self.tunnel.as_ref()));
+                            }
+                        },
+                        settings = self.vpn_client.next_reload() => {
+                            self.vpn_client.reload(settings).await;
+                        }
+                        _ = watchdog_tick(self.watchdog) => notify_watchdog(),
+                    }
+
+                    continue;
+                }
+            };
+
+            info!("Connecting to {}...", server);
+
+            let connection = self.vpn_client.connect(server.clone(), &self.options);
+            tokio::pin!(connection);
+
+            loop {
+                tokio::select! {
+                    result = &mut connection => {
+                        on_connection_ended(&server, result, &self.state, self.tunnel.as_ref());
+                        break;
+                    }
+                    command = commands.recv() => match command {
+                        None => return,
+                        Some((command, reply)) => {
+                            let status = execute(command, &self.state, self.tunnel.as_ref());
+                            let _ = reply.send(status);
+
+                            let active_server = self.state.lock().unwrap().active_server();
+
+                            // Disconnected or switched
+                            if active_server.as_ref() != Some(&server) {
+                                break;
+                            }
+                        }
+                    },
+                    // e.g., while requesting the connection
+                    _ = watchdog_tick(self.watchdog) => notify_watchdog(),
+                }
+            }
+        }
+    }
+}
+
+impl DaemonState {
+    // None if disconnected or failed
+    fn active_server(&self) -> Option<String> {
+        match self.error {
+            None => self.server.clone(),
+            Some(_) => None,
+        }
+    }
+}
+
+/*
+* Listen on the control socket. Replaces the socket left over by a daemon that did not exit cleanly.
+* Only root, and the group members if set, can connect.
+*/
+pub async fn bind(path: &str, group: Option<&str>) -> Result<UnixListener, CliClientError> {
+    if UnixStream::connect(path).await.is_ok() {
+        return Err(ControlError(format!(
+            "A daemon is already listening on {}.",
+            path
+        )));
+    }
+
+    let socket_path = Path::new(path);
+
+    if socket_path.exists() {
+        fs::remove_file(socket_path)
+            .map_err(|e| io_error(e, &format!("Could not remove {}.", path), ControlError))?;
+    }
+    if let Some(parent) = socket_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| {
+            io_error(
+                e,
+                &format!("Could not create {}.", parent.display()),
+                ControlError,
+            )
+        })?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .map_err(|e| io_error(e, &format!("Could not listen on {}.", path), ControlError))?;
+
+    let mode = match group {
+        None => SOCKET_MODE,
+        Some(group) => {
+            set_group(path, group_id(group)?)?;
+            GROUP_SOCKET_MODE
+        }
+    };
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(|e| {
+        ControlError(format!(
+            "Could not set the permissions of {}. {:?}",
+            path, e
+        ))
+    })?;
+
+    Ok(listener)
+}
+
+async fn serve(
+    listener: UnixListener,
+    commands: mpsc::UnboundedSender<(ControlCommand, Reply)>,
+    state: Arc<Mutex<DaemonState>>,
+    tunnel: Arc<dyn TunnelBackend>,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("Could not accept control connection. {:?}", e);
+                continue;
+            }
+        };
+
+        let commands = commands.clone();
+        let state = state.clone();
+        let tunnel = tunnel.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, &commands, &state, tunnel.as_ref()).await {
+                debug!("Control connection failed. {:?}", e);
+            }
+        });
+    }
+}
+
+// One response per request line, until the client closes the connection
+async fn handle_client(
+    stream: UnixStream,
+    commands: &mpsc::UnboundedSender<(ControlCommand, Reply)>,
+    state: &Mutex<DaemonState>,
+    tunnel: &dyn TunnelBackend,
+) -> Result<(), CliClientError> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|e| ControlError(format!("Could not read request. {:?}", e)))?
+    {
+        let response = respond(&line, commands, state, tunnel).await;
+
+        let mut response =
+            serde_json::to_string(&response).map_err(|e| EncodingError(e.to_string()))?;
+        response.push('\n');
+
+        writer
+            .write_all(response.as_bytes())
+            .await
+            .map_err(|e| ControlError(format!("Could not write response. {:?}", e)))?;
+    }
+
+    Ok(())
+}
+
+// The status is read here. The other commands are executed by the daemon's loop.
+async fn respond(
+    line: &str,
+    commands: &mpsc::UnboundedSender<(ControlCommand, Reply)>,
+    state: &Mutex<DaemonState>,
+    tunnel: &dyn TunnelBackend,
+) -> ControlResponse {
+    let request = match parse_request(line) {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+
+    let command = match request.command() {
+        Ok(command) => command,
+        Err(e) => return ControlResponse::error(request.id, e),
+    };
+
+    let result = match command {
+        ControlCommand::Status => Ok(status(state, tunnel)),
+        command => send_command(command, commands).await,
+    };
+
+    match result.and_then(|status| serde_json::to_value(&status).map_err(|e| e.to_string())) {
+        Ok(status) => ControlResponse::result(request.id, status),
+        Err(message) => ControlResponse::error(request.id, RpcError::new(DAEMON_ERROR, message)),
+    }
+}
+
+async fn send_command(
+    command: ControlCommand,
+    commands: &mpsc::UnboundedSender<(ControlCommand, Reply)>,
+) -> Result<DaemonStatus, String> {
+    let (reply, result) = oneshot::channel();
+
+    commands
+        .send((command, reply))
+        .map_err(|_| "The daemon is stopping.".to_string())?;
+
+    result
+        .await
+        .map_err(|_| "The daemon is stopping.".to_string())?
+}
+
+/*
+* Update the requested connection. The current tunnel is torn down on disconnect and switch.
+* Connecting again to the current server or switching to it does nothing.
+*/
+fn execute(
+    command: ControlCommand,
+    state: &Mutex<DaemonState>,
+    tunnel: &dyn TunnelBackend,
+) -> Result<DaemonStatus, String> {
+    let active_server = state.lock().unwrap().active_server();
+
+    match (command, active_server) {
+        (ControlCommand::Connect { server }, None) => set_server(state, Some(server)),
+        (ControlCommand::Connect { server }, Some(current)) => {
+            if server != current {
+                return Err(format!(
+                    "Already connected to {}. Switch to {} instead.",
+                    current, server
+                ));
+            }
+        }
+        (ControlCommand::Switch { .. }, None) => return Err("Not connected.".to_string()),
+        (ControlCommand::Switch { server }, Some(current)) => {
+            if server != current {
+                tear_down(tunnel)?;
+                set_server(state, Some(server));
+            }
+        }
+        (ControlCommand::Disconnect, _) => {
+            tear_down(tunnel)?;
+            set_server(state, None);
+        }
+        (ControlCommand::Status, _) => {}
+    }
+
+    Ok(status(state, tunnel))
+}
+
+fn on_connection_ended(
+    server: &str,
+    result: Result<(), CliClientError>,
+    state: &Mutex<DaemonState>,
+    tunnel: &dyn TunnelBackend,
+) {
+    let reason = match result {
+        Ok(()) => "The connection ended.".to_string(),
+        Err(CliClientError::VeronymousClientError(VeronymousClientError::AuthRequired())) => {
+            format!(
+                "Authentication is required. Log in with `{} {}`.",
+                APP_NAME, LOGIN_COMMAND
+            )
+        }
+        Err(CliClientError::SubscriptionRequired) => "VPN subscription is required.".to_string(),
+        Err(e) => format!("An error has occurred. {:?}", e),
+    };
+
+    error!("The connection to {} failed. {}", server, reason);
+
+    if let Err(e) = tear_down(tunnel) {
+        warn!("{}", e);
+    }
+
+    state.lock().unwrap().error = Some(reason);
+}
+
+// e.g., shown by `systemctl status`
+fn idle_status(state: &Mutex<DaemonState>) -> String {
+    let state = state.lock().unwrap();
+
+    match (&state.server, &state.error) {
+        (Some(server), Some(error)) => format!("The connection to {} failed. {}", server, error),
+        _ => "Disconnected.".to_string(),
+    }
+}
+
+// Pending forever if the watchdog is not enabled
+async fn watchdog_tick(watchdog: Option<Duration>) {
+    match watchdog {
+        Some(watchdog) => tokio::time::sleep(watchdog).await,
+        None => future::pending().await,
+    }
+}
+
+fn set_server(state: &Mutex<DaemonState>, server: Option<String>) {
+    let mut state = state.lock().unwrap();
+
+    state.server = server;
+    state.error = None;
+}
+
+fn tear_down(tunnel: &dyn TunnelBackend) -> Result<(), String> {
+    if let Ok(TunnelStatus::Up { interface }) = tunnel.status() {
+        info!("Tearing down {}...", interface);
+    }
+
+    tunnel
+        .down()
+        .map_err(|e| format!("Could not tear down the connection. {:?}", e))
+}
+
+// Connected once the tunnel is up
+fn status(state: &Mutex<DaemonState>, tunnel: &dyn TunnelBackend) -> DaemonStatus {
+    let state = state.lock().unwrap();
+
+    let (connection_state, interface) = match (&state.server, &state.error, tunnel.status()) {
+        (None, _, _) => (ConnectionState::Disconnected, None),
+        (Some(_), Some(_), _) => (ConnectionState::Failed, None),
+        (Some(_), None, Ok(TunnelStatus::Up { interface })) => {
+            (ConnectionState::Connected, Some(interface))
+        }
+        (Some(_), None, _) => (ConnectionState::Connecting, None),
+    };
+
+    let mut status = DaemonStatus::new(connection_state);
+    status.server = state.server.clone();
+    status.interface = interface;
+    status.error = state.error.clone();
+    status.credentials = credentials_status(&state.state_files);
+
+    if status.state == ConnectionState::Connected {
+        let current = state.current.lock().unwrap();
+
+        status.addresses = current.addresses.clone();
+        status.next_rotation = current.next_rotation.map(unix_time);
+        status.tunnel = tunnel_statistics(tunnel);
+    }
+
+    status
+}
+
+/*
+* Status without the daemon (e.g., a connection in the foreground).
+* The server and addresses are unknown.
+*/
+pub fn local_status(tunnel: &dyn TunnelBackend, state: &StateFiles) -> DaemonStatus {
+    let mut status = match tunnel.status() {
+        Ok(TunnelStatus::Up { interface }) => {
+            let mut status = DaemonStatus::new(ConnectionState::Connected);
+            status.interface = Some(interface);
+            status.tunnel = tunnel_statistics(tunnel);
+
+            status
+        }
+        _ => DaemonStatus::new(ConnectionState::Disconnected),
+    };
+
+    status.credentials = credentials_status(state);
+
+    status
+}
+
+// None if the client state could not be read (e.g., another user's)
+fn credentials_status(state: &StateFiles) -> Option<CredentialsStatus> {
+    match CliVpnClient::credentials_status(state) {
+        Ok(credentials) => Some(credentials),
+        Err(e) => {
+            debug!("Could not read the credentials status. {:?}", e);
+            None
+        }
+    }
+}
+
+// None if not readable (e.g., without root)
+fn tunnel_statistics(tunnel: &dyn TunnelBackend) -> Option<TunnelStatistics> {
+    match tunnel.health() {
+        Ok(health) => health.map(|health| TunnelStatistics {
+            latest_handshake: health.last_handshake.map(unix_time),
+            rx_bytes: health.rx_bytes,
+            tx_bytes: health.tx_bytes,
+        }),
+        Err(e) => {
+            debug!("Could not read the tunnel statistics. {:?}", e);
+            None
+        }
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn group_id(group: &str) -> Result<libc::gid_t, CliClientError> {
+    let name = CString::new(group)
+        .map_err(|e| ControlError(format!("Invalid group {}. {:?}", group, e)))?;
+
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+
+    if entry.is_null() {
+        return Err(ControlError(format!("Unknown group {}.", group)));
+    }
+
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+// The owner is kept
+fn set_group(path: &str, gid: libc::gid_t) -> Result<(), CliClientError> {
+    let c_path =
+        CString::new(path).map_err(|e| ControlError(format!("Invalid path {}. {:?}", path, e)))?;
+
+    if unsafe { libc::chown(c_path.as_ptr(), libc::uid_t::MAX, gid) } != 0 {
+        return Err(ControlError(format!(
+            "Could not set the group of {}. {:?}",
+            path,
+            io::Error::last_os_error()
+        )));
+    }
+
+    Ok(())
+}
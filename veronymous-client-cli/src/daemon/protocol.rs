@@ -0,0 +1,385 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const JSON_RPC_VERSION: &str = "2.0";
+
+// Incremented on incompatible changes. The daemon rejects the other versions.
+pub const CONTROL_PROTOCOL_VERSION: u32 = 1;
+
+pub const CONNECT_METHOD: &str = "connect";
+pub const DISCONNECT_METHOD: &str = "disconnect";
+pub const SWITCH_METHOD: &str = "switch";
+pub const STATUS_METHOD: &str = "status";
+
+// JSON-RPC error codes
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+pub const DAEMON_ERROR: i32 = -32000;
+
+/*
+* JSON-RPC 2.0 request, one per line.
+* e.g., {"jsonrpc":"2.0","version":1,"id":1,"method":"connect","params":{"server":"new_york"}}
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ControlRequest {
+    pub jsonrpc: String,
+
+    // Control protocol version. 0 if missing.
+    #[serde(default)]
+    pub version: u32,
+
+    pub id: u64,
+
+    pub method: String,
+
+    #[serde(default)]
+    pub params: ControlParams,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ControlParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub jsonrpc: String,
+
+    pub version: u32,
+
+    // 0 if the request could not be parsed
+    pub id: u64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i32,
+
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlCommand {
+    Connect { server: String },
+
+    Disconnect,
+
+    // Replace the current connection with one to another server
+    Switch { server: String },
+
+    Status,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Disconnected,
+
+    Connecting,
+
+    Connected,
+
+    // The connection ended with an error. Connect again once resolved.
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub state: ConnectionState,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+
+    // Why the last connection failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    // Assigned by the server
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<TunnelStatistics>,
+
+    // Unix time of the switch to the next epoch's connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_rotation: Option<u64>,
+
+    // None if the client state could not be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<CredentialsStatus>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TunnelStatistics {
+    // Unix time. None before the first handshake.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latest_handshake: Option<u64>,
+
+    pub rx_bytes: u64,
+
+    pub tx_bytes: u64,
+}
+
+// What the client has cached to connect in the current and next key epochs
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CredentialsStatus {
+    pub epoch: u64,
+
+    pub key_epoch: u64,
+
+    // The current key epoch, then the next one
+    pub key_epochs: Vec<KeyEpochCache>,
+
+    // Unix time. None if not logged in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token_expiry: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KeyEpochCache {
+    pub key_epoch: u64,
+
+    pub root_token: bool,
+
+    pub issuer_info: bool,
+}
+
+impl DaemonStatus {
+    pub fn new(state: ConnectionState) -> Self {
+        Self {
+            state,
+            server: None,
+            interface: None,
+            error: None,
+            addresses: vec![],
+            tunnel: None,
+            next_rotation: None,
+            credentials: None,
+        }
+    }
+}
+
+impl ControlCommand {
+    pub fn to_request(&self, id: u64) -> ControlRequest {
+        let (method, server) = match self {
+            ControlCommand::Connect { server } => (CONNECT_METHOD, Some(server.clone())),
+            ControlCommand::Disconnect => (DISCONNECT_METHOD, None),
+            ControlCommand::Switch { server } => (SWITCH_METHOD, Some(server.clone())),
+            ControlCommand::Status => (STATUS_METHOD, None),
+        };
+
+        ControlRequest {
+            jsonrpc: JSON_RPC_VERSION.to_string(),
+            version: CONTROL_PROTOCOL_VERSION,
+            id,
+            method: method.to_string(),
+            params: ControlParams { server },
+        }
+    }
+}
+
+impl ControlRequest {
+    pub fn command(&self) -> Result<ControlCommand, RpcError> {
+        if self.jsonrpc != JSON_RPC_VERSION {
+            return Err(RpcError::new(
+                INVALID_REQUEST,
+                format!("Unsupported JSON-RPC version {}.", self.jsonrpc),
+            ));
+        }
+        if self.version != CONTROL_PROTOCOL_VERSION {
+            return Err(RpcError::new(
+                INVALID_REQUEST,
+                format!(
+                    "Unsupported control protocol version {}. The daemon uses version {}.",
+                    self.version, CONTROL_PROTOCOL_VERSION
+                ),
+            ));
+        }
+
+        match self.method.as_str() {
+            CONNECT_METHOD => Ok(ControlCommand::Connect {
+                server: self.server()?,
+            }),
+            DISCONNECT_METHOD => Ok(ControlCommand::Disconnect),
+            SWITCH_METHOD => Ok(ControlCommand::Switch {
+                server: self.server()?,
+            }),
+            STATUS_METHOD => Ok(ControlCommand::Status),
+            method => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method {}.", method),
+            )),
+        }
+    }
+
+    fn server(&self) -> Result<String, RpcError> {
+        self.params
+            .server
+            .clone()
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing server.".to_string()))
+    }
+}
+
+impl ControlResponse {
+    pub fn result(id: u64, result: Value) -> Self {
+        Self {
+            jsonrpc: JSON_RPC_VERSION.to_string(),
+            version: CONTROL_PROTOCOL_VERSION,
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn error(id: u64, error: RpcError) -> Self {
+        Self {
+            jsonrpc: JSON_RPC_VERSION.to_string(),
+            version: CONTROL_PROTOCOL_VERSION,
+            id,
+            result: None,
+            error: Some(error),
+        }
+    }
+}
+
+impl RpcError {
+    pub fn new(code: i32, message: String) -> Self {
+        Self { code, message }
+    }
+}
+
+// One request from a line. The error response if it is not a valid request.
+pub fn parse_request(line: &str) -> Result<ControlRequest, ControlResponse> {
+    serde_json::from_str(line).map_err(|e| {
+        ControlResponse::error(
+            0,
+            RpcError::new(PARSE_ERROR, format!("Invalid request. {}", e)),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::protocol::{
+        parse_request, ConnectionState, ControlCommand, ControlResponse, DaemonStatus, RpcError,
+        DAEMON_ERROR, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR,
+    };
+
+    #[test]
+    fn test_request() {
+        let command = ControlCommand::Connect {
+            server: "new_york".to_string(),
+        };
+        let line = serde_json::to_string(&command.to_request(7)).unwrap();
+        assert_eq!(
+            r#"{"jsonrpc":"2.0","version":1,"id":7,"method":"connect","params":{"server":"new_york"}}"#,
+            line
+        );
+
+        let request = parse_request(&line).unwrap();
+        assert_eq!(7, request.id);
+        assert_eq!(command, request.command().unwrap());
+
+        // The parameters are optional
+        let request =
+            parse_request(r#"{"jsonrpc":"2.0","version":1,"id":1,"method":"status"}"#).unwrap();
+        assert_eq!(ControlCommand::Status, request.command().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_request() {
+        let error = |line: &str| parse_request(line).unwrap().command().unwrap_err().code;
+
+        assert_eq!(
+            PARSE_ERROR,
+            parse_request("connect new_york")
+                .unwrap_err()
+                .error
+                .unwrap()
+                .code
+        );
+        // Missing or other version
+        assert_eq!(
+            INVALID_REQUEST,
+            error(r#"{"jsonrpc":"2.0","id":1,"method":"status"}"#)
+        );
+        assert_eq!(
+            INVALID_REQUEST,
+            error(r#"{"jsonrpc":"2.0","version":2,"id":1,"method":"status"}"#)
+        );
+        assert_eq!(
+            METHOD_NOT_FOUND,
+            error(r#"{"jsonrpc":"2.0","version":1,"id":1,"method":"restart"}"#)
+        );
+        assert_eq!(
+            INVALID_PARAMS,
+            error(r#"{"jsonrpc":"2.0","version":1,"id":1,"method":"switch"}"#)
+        );
+    }
+
+    #[test]
+    fn test_response() {
+        let mut status = DaemonStatus::new(ConnectionState::Connected);
+        status.server = Some("new_york".to_string());
+        status.interface = Some("veron0".to_string());
+        let response = ControlResponse::result(3, serde_json::to_value(&status).unwrap());
+
+        // The result's fields are sorted
+        assert_eq!(
+            r#"{"jsonrpc":"2.0","version":1,"id":3,"result":{"interface":"veron0","server":"new_york","state":"connected"}}"#,
+            serde_json::to_string(&response).unwrap()
+        );
+
+        let response =
+            ControlResponse::error(3, RpcError::new(DAEMON_ERROR, "Not connected.".to_string()));
+
+        assert_eq!(
+            r#"{"jsonrpc":"2.0","version":1,"id":3,"error":{"code":-32000,"message":"Not connected."}}"#,
+            serde_json::to_string(&response).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_status_details() {
+        // The details are optional
+        let status: DaemonStatus = serde_json::from_str(r#"{"state":"disconnected"}"#).unwrap();
+        assert_eq!(DaemonStatus::new(ConnectionState::Disconnected), status);
+
+        let mut status = DaemonStatus::new(ConnectionState::Connected);
+        status.addresses = vec!["10.8.0.2".to_string()];
+        status.next_rotation = Some(7140);
+        status.credentials = Some(CredentialsStatus {
+            epoch: 3600,
+            key_epoch: 0,
+            key_epochs: vec![
+                KeyEpochCache {
+                    key_epoch: 0,
+                    root_token: true,
+                    issuer_info: true,
+                },
+                KeyEpochCache {
+                    key_epoch: 43200,
+                    root_token: false,
+                    issuer_info: false,
+                },
+            ],
+            refresh_token_expiry: None,
+        });
+
+        let json = serde_json::to_string(&status).unwrap();
+        assert_eq!(status, serde_json::from_str(&json).unwrap());
+    }
+}
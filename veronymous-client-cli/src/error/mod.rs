@@ -1,3 +1,4 @@
+use std::io;
 use thiserror::Error;
 use veronymous_client::error::VeronymousClientError;
 
@@ -21,9 +22,47 @@ pub enum CliClientError {
     #[error("{0}")]
     CommandError(String),
 
+    #[error("{0}")]
+    NetlinkError(String),
+
+    #[error("{0}")]
+    ProxyError(String),
+
+    #[error("{0}")]
+    FirewallError(String),
+
+    #[error("{0}")]
+    DnsError(String),
+
+    #[error("{0}")]
+    HandshakeError(String),
+
+    #[error("{0}")]
+    NamespaceError(String),
+
+    #[error("{0}")]
+    ControlError(String),
+
+    #[error("{0}")]
+    PermissionError(String),
+
     #[error("Veronymous client error.")]
     VeronymousClientError(VeronymousClientError),
 
     #[error("Subscription required")]
     SubscriptionRequired,
 }
+
+// Permission errors are kept apart (e.g., for the exit code)
+pub fn io_error(
+    error: io::Error,
+    message: &str,
+    other: fn(String) -> CliClientError,
+) -> CliClientError {
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => {
+            CliClientError::PermissionError(format!("{} {}", message, error))
+        }
+        _ => other(format!("{} {:?}", message, error)),
+    }
+}
@@ -1,6 +1,9 @@
 mod app;
+mod config;
 mod constants;
+mod daemon;
 mod error;
+mod proxy;
 mod utils;
 mod vpn_client;
 mod wg;
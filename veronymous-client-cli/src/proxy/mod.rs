@@ -0,0 +1,112 @@
+pub mod protocol;
+
+use crate::error::CliClientError;
+use crate::error::CliClientError::ProxyError;
+use crate::proxy::protocol::{read_request, write_reply, Target};
+use crate::wg::netstack::{Dialer, TcpChannel};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const READ_BUFFER_SIZE: usize = 16384;
+
+/*
+* Local SOCKS5 and HTTP CONNECT proxy.
+* The connections are opened through the tunnel.
+*/
+pub async fn serve(listener: TcpListener, dialer: Dialer) {
+    loop {
+        let (stream, client) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Could not accept proxy connection. {:?}", e);
+                continue;
+            }
+        };
+
+        let dialer = dialer.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, &dialer).await {
+                debug!("Proxy connection from {} failed. {:?}", client, e);
+            }
+        });
+    }
+}
+
+async fn handle_client(mut stream: TcpStream, dialer: &Dialer) -> Result<(), CliClientError> {
+    let request = read_request(&mut stream).await?;
+
+    let channel = match open_channel(&request.target, dialer).await {
+        Ok(channel) => channel,
+        Err(e) => {
+            write_reply(&mut stream, &request.protocol, false).await?;
+            return Err(e);
+        }
+    };
+
+    write_reply(&mut stream, &request.protocol, true).await?;
+
+    relay(stream, channel).await
+}
+
+async fn open_channel(target: &Target, dialer: &Dialer) -> Result<TcpChannel, CliClientError> {
+    let address = match target {
+        Target::Address(address) => *address,
+        Target::Domain(name, port) => {
+            let addresses = dialer.resolve(name).await?;
+
+            let address = addresses
+                .first()
+                .ok_or_else(|| ProxyError(format!("Could not resolve {}.", name)))?;
+
+            SocketAddr::new(*address, *port)
+        }
+    };
+
+    debug!("Opening connection to {}.", address);
+
+    dialer.connect(address).await
+}
+
+async fn relay(stream: TcpStream, channel: TcpChannel) -> Result<(), CliClientError> {
+    let (mut reader, mut writer) = stream.into_split();
+    let TcpChannel {
+        to_remote,
+        mut from_remote,
+    } = channel;
+
+    // Client to remote host. Dropping to_remote closes the connection.
+    let upload = async move {
+        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+
+        loop {
+            let size = reader
+                .read(&mut buffer)
+                .await
+                .map_err(|e| ProxyError(format!("Could not read from client. {:?}", e)))?;
+
+            if size == 0 || to_remote.send(buffer[..size].to_vec()).await.is_err() {
+                return Ok::<(), CliClientError>(());
+            }
+        }
+    };
+
+    // Remote host to client
+    let download = async move {
+        while let Some(data) = from_remote.recv().await {
+            writer
+                .write_all(&data)
+                .await
+                .map_err(|e| ProxyError(format!("Could not write to client. {:?}", e)))?;
+        }
+
+        let _ = writer.shutdown().await;
+
+        Ok::<(), CliClientError>(())
+    };
+
+    let (upload, download) = tokio::join!(upload, download);
+
+    upload.and(download)
+}
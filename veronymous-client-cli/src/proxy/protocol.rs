@@ -0,0 +1,435 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::ProxyError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const SOCKS_VERSION: u8 = 0x05;
+const SOCKS_NO_AUTH: u8 = 0x00;
+const SOCKS_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const SOCKS_CONNECT: u8 = 0x01;
+const SOCKS_IPV4: u8 = 0x01;
+const SOCKS_DOMAIN: u8 = 0x03;
+const SOCKS_IPV6: u8 = 0x04;
+
+const SOCKS_SUCCEEDED: u8 = 0x00;
+const SOCKS_GENERAL_FAILURE: u8 = 0x01;
+const SOCKS_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const SOCKS_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+// Maximum size of the HTTP CONNECT request headers
+const MAX_HTTP_HEADERS_SIZE: usize = 8192;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProxyProtocol {
+    Socks5,
+
+    HttpConnect,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    Address(SocketAddr),
+
+    // Resolved through the tunnel
+    Domain(String, u16),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProxyRequest {
+    pub protocol: ProxyProtocol,
+
+    pub target: Target,
+}
+
+/*
+* Read a SOCKS5 (no authentication, CONNECT) or HTTP CONNECT request.
+* The protocol is detected from the first byte.
+*/
+pub async fn read_request<S>(stream: &mut S) -> Result<ProxyRequest, CliClientError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let first = read_u8(stream).await?;
+
+    match first {
+        SOCKS_VERSION => read_socks_request(stream).await,
+        _ => read_http_request(stream, first).await,
+    }
+}
+
+pub async fn write_reply<S>(
+    stream: &mut S,
+    protocol: &ProxyProtocol,
+    success: bool,
+) -> Result<(), CliClientError>
+where
+    S: AsyncWrite + Unpin,
+{
+    let reply: &[u8] = match (protocol, success) {
+        (ProxyProtocol::Socks5, true) => &[
+            SOCKS_VERSION,
+            SOCKS_SUCCEEDED,
+            0x00,
+            SOCKS_IPV4,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ],
+        (ProxyProtocol::Socks5, false) => &[
+            SOCKS_VERSION,
+            SOCKS_GENERAL_FAILURE,
+            0x00,
+            SOCKS_IPV4,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ],
+        (ProxyProtocol::HttpConnect, true) => b"HTTP/1.1 200 Connection established\r\n\r\n",
+        (ProxyProtocol::HttpConnect, false) => b"HTTP/1.1 502 Bad Gateway\r\n\r\n",
+    };
+
+    write_all(stream, reply).await
+}
+
+async fn read_socks_request<S>(stream: &mut S) -> Result<ProxyRequest, CliClientError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Greeting. The version is already read.
+    let method_count = read_u8(stream).await?;
+    let mut methods = vec![0u8; method_count as usize];
+    read_exact(stream, &mut methods).await?;
+
+    if !methods.contains(&SOCKS_NO_AUTH) {
+        write_all(stream, &[SOCKS_VERSION, SOCKS_NO_ACCEPTABLE_METHOD]).await?;
+
+        return Err(ProxyError(
+            "The SOCKS client does not support no authentication.".to_string(),
+        ));
+    }
+
+    write_all(stream, &[SOCKS_VERSION, SOCKS_NO_AUTH]).await?;
+
+    // Request
+    let mut header = [0u8; 4];
+    read_exact(stream, &mut header).await?;
+
+    let [version, command, _, address_type] = header;
+
+    if version != SOCKS_VERSION {
+        return Err(ProxyError(format!("Invalid SOCKS version {}.", version)));
+    }
+
+    if command != SOCKS_CONNECT {
+        write_socks_error(stream, SOCKS_COMMAND_NOT_SUPPORTED).await?;
+
+        return Err(ProxyError(format!(
+            "SOCKS command {} is not supported.",
+            command
+        )));
+    }
+
+    let target = match address_type {
+        SOCKS_IPV4 => {
+            let mut address = [0u8; 4];
+            read_exact(stream, &mut address).await?;
+
+            Target::Address(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::from(address)),
+                read_port(stream).await?,
+            ))
+        }
+        SOCKS_IPV6 => {
+            let mut address = [0u8; 16];
+            read_exact(stream, &mut address).await?;
+
+            Target::Address(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(address)),
+                read_port(stream).await?,
+            ))
+        }
+        SOCKS_DOMAIN => {
+            let length = read_u8(stream).await?;
+            let mut domain = vec![0u8; length as usize];
+            read_exact(stream, &mut domain).await?;
+
+            let domain = String::from_utf8(domain)
+                .map_err(|e| ProxyError(format!("Invalid domain. {:?}", e)))?;
+
+            Target::Domain(domain, read_port(stream).await?)
+        }
+        _ => {
+            write_socks_error(stream, SOCKS_ADDRESS_NOT_SUPPORTED).await?;
+
+            return Err(ProxyError(format!(
+                "SOCKS address type {} is not supported.",
+                address_type
+            )));
+        }
+    };
+
+    Ok(ProxyRequest {
+        protocol: ProxyProtocol::Socks5,
+        target,
+    })
+}
+
+async fn read_http_request<S>(stream: &mut S, first: u8) -> Result<ProxyRequest, CliClientError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Read byte by byte so the data after the headers is left for the tunnel
+    let mut headers = vec![first];
+    while !headers.ends_with(b"\r\n\r\n") {
+        if headers.len() >= MAX_HTTP_HEADERS_SIZE {
+            return Err(ProxyError("The HTTP request is too large.".to_string()));
+        }
+
+        headers.push(read_u8(stream).await?);
+    }
+
+    let headers = String::from_utf8_lossy(&headers);
+    let request_line = headers.lines().next().unwrap_or_default();
+    let parts: Vec<&str> = request_line.split_whitespace().collect();
+
+    let (method, authority) = match parts.as_slice() {
+        [method, authority, _version] => (*method, *authority),
+        _ => {
+            write_all(stream, b"HTTP/1.1 400 Bad Request\r\n\r\n").await?;
+
+            return Err(ProxyError(format!(
+                "Invalid HTTP request {}.",
+                request_line
+            )));
+        }
+    };
+
+    if !method.eq_ignore_ascii_case("CONNECT") {
+        write_all(
+            stream,
+            b"HTTP/1.1 405 Method Not Allowed\r\nAllow: CONNECT\r\n\r\n",
+        )
+        .await?;
+
+        return Err(ProxyError(format!(
+            "HTTP method {} is not supported.",
+            method
+        )));
+    }
+
+    let target = match parse_authority(authority) {
+        Some(target) => target,
+        None => {
+            write_all(stream, b"HTTP/1.1 400 Bad Request\r\n\r\n").await?;
+
+            return Err(ProxyError(format!("Invalid authority {}.", authority)));
+        }
+    };
+
+    Ok(ProxyRequest {
+        protocol: ProxyProtocol::HttpConnect,
+        target,
+    })
+}
+
+// host:port, ipv4:port or [ipv6]:port
+fn parse_authority(authority: &str) -> Option<Target> {
+    if let Ok(address) = authority.parse::<SocketAddr>() {
+        return Some(Target::Address(address));
+    }
+
+    let (host, port) = authority.rsplit_once(':')?;
+    let port = port.parse::<u16>().ok()?;
+
+    if host.is_empty() || host.contains(['[', ']', ':']) {
+        return None;
+    }
+
+    Some(Target::Domain(host.to_string(), port))
+}
+
+async fn write_socks_error<S>(stream: &mut S, code: u8) -> Result<(), CliClientError>
+where
+    S: AsyncWrite + Unpin,
+{
+    write_all(
+        stream,
+        &[SOCKS_VERSION, code, 0x00, SOCKS_IPV4, 0, 0, 0, 0, 0, 0],
+    )
+    .await
+}
+
+async fn read_port<S>(stream: &mut S) -> Result<u16, CliClientError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut port = [0u8; 2];
+    read_exact(stream, &mut port).await?;
+
+    Ok(u16::from_be_bytes(port))
+}
+
+async fn read_u8<S>(stream: &mut S) -> Result<u8, CliClientError>
+where
+    S: AsyncRead + Unpin,
+{
+    stream
+        .read_u8()
+        .await
+        .map_err(|e| ProxyError(format!("Could not read proxy request. {:?}", e)))
+}
+
+async fn read_exact<S>(stream: &mut S, buffer: &mut [u8]) -> Result<(), CliClientError>
+where
+    S: AsyncRead + Unpin,
+{
+    stream
+        .read_exact(buffer)
+        .await
+        .map(|_| ())
+        .map_err(|e| ProxyError(format!("Could not read proxy request. {:?}", e)))
+}
+
+async fn write_all<S>(stream: &mut S, data: &[u8]) -> Result<(), CliClientError>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream
+        .write_all(data)
+        .await
+        .map_err(|e| ProxyError(format!("Could not write proxy reply. {:?}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proxy::protocol::{read_request, write_reply, ProxyProtocol, ProxyRequest, Target};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_socks5_request() {
+        let (mut client, mut server) = duplex(1024);
+
+        // Greeting, CONNECT example.com:443
+        client.write_all(&[5, 2, 2, 0]).await.unwrap();
+        client.write_all(&[5, 1, 0, 3, 11]).await.unwrap();
+        client.write_all(b"example.com").await.unwrap();
+        client.write_all(&[1, 187]).await.unwrap();
+
+        let request = read_request(&mut server).await.unwrap();
+        assert_eq!(
+            ProxyRequest {
+                protocol: ProxyProtocol::Socks5,
+                target: Target::Domain("example.com".to_string(), 443),
+            },
+            request
+        );
+
+        write_reply(&mut server, &request.protocol, true)
+            .await
+            .unwrap();
+
+        let mut reply = [0u8; 12];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!([5, 0], reply[..2]);
+        assert_eq!([5, 0, 0, 1], reply[2..6]);
+
+        // IPv6 target
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(&[5, 1, 0]).await.unwrap();
+        client.write_all(&[5, 1, 0, 4]).await.unwrap();
+        client
+            .write_all(
+                &"2001:db8::1"
+                    .parse::<std::net::Ipv6Addr>()
+                    .unwrap()
+                    .octets(),
+            )
+            .await
+            .unwrap();
+        client.write_all(&[0, 80]).await.unwrap();
+
+        assert_eq!(
+            Target::Address("[2001:db8::1]:80".parse().unwrap()),
+            read_request(&mut server).await.unwrap().target
+        );
+    }
+
+    #[tokio::test]
+    async fn test_socks5_unsupported() {
+        // Username/password authentication only
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(&[5, 1, 2]).await.unwrap();
+
+        assert!(read_request(&mut server).await.is_err());
+
+        let mut reply = [0u8; 2];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!([5, 0xff], reply);
+
+        // UDP associate
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(&[5, 1, 0]).await.unwrap();
+        client
+            .write_all(&[5, 3, 0, 1, 127, 0, 0, 1, 0, 53])
+            .await
+            .unwrap();
+
+        assert!(read_request(&mut server).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_connect_request() {
+        let (mut client, mut server) = duplex(1024);
+
+        client
+            .write_all(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\nhello")
+            .await
+            .unwrap();
+
+        let request = read_request(&mut server).await.unwrap();
+        assert_eq!(
+            ProxyRequest {
+                protocol: ProxyProtocol::HttpConnect,
+                target: Target::Domain("example.com".to_string(), 443),
+            },
+            request
+        );
+
+        // The data after the headers is not consumed
+        let mut data = [0u8; 5];
+        server.read_exact(&mut data).await.unwrap();
+        assert_eq!(b"hello", &data);
+
+        let (mut client, mut server) = duplex(1024);
+        client
+            .write_all(b"CONNECT [2001:db8::1]:443 HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            Target::Address("[2001:db8::1]:443".parse().unwrap()),
+            read_request(&mut server).await.unwrap().target
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_method_not_allowed() {
+        let (mut client, mut server) = duplex(1024);
+
+        client
+            .write_all(b"GET http://example.com/ HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+
+        assert!(read_request(&mut server).await.is_err());
+
+        let mut reply = vec![0u8; 12];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(b"HTTP/1.1 405", reply.as_slice());
+    }
+}
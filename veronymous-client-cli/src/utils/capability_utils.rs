@@ -0,0 +1,21 @@
+use std::fs;
+
+const CAP_NET_ADMIN: u32 = 12;
+
+/*
+* Root or CAP_NET_ADMIN, required to create the interfaces and routes.
+* Read from the effective capabilities of the process.
+*/
+pub fn has_net_admin() -> bool {
+    let status = match fs::read_to_string("/proc/self/status") {
+        Ok(status) => status,
+        Err(_) => return unsafe { libc::geteuid() } == 0,
+    };
+
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|capabilities| u64::from_str_radix(capabilities.trim(), 16).ok())
+        .map(|capabilities| capabilities & (1 << CAP_NET_ADMIN) != 0)
+        .unwrap_or(false)
+}
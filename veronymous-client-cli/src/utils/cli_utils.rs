@@ -11,3 +11,16 @@ pub fn get_user_input() -> String {
 pub fn get_password() -> String {
     rpassword::read_password().unwrap()
 }
+
+// False if the input is piped or redirected (e.g., a service or a script)
+pub fn stdin_is_terminal() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+// One line without the line ending, e.g., a piped password
+pub fn read_line() -> std::io::Result<String> {
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
@@ -0,0 +1,44 @@
+use crate::error::CliClientError::ControlError;
+use crate::error::{io_error, CliClientError};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/*
+* Exclusive lock on the file, held until the returned file is dropped (or the process exits).
+* Fails immediately if another process holds it.
+*/
+pub fn lock_file(path: &str) -> Result<File, CliClientError> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent).map_err(|e| {
+            io_error(
+                e,
+                &format!("Could not create {}.", parent.display()),
+                ControlError,
+            )
+        })?;
+    }
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|e| io_error(e, &format!("Could not open {}.", path), ControlError))?;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let error = io::Error::last_os_error();
+
+        return Err(match error.kind() {
+            io::ErrorKind::WouldBlock => ControlError(format!(
+                "Another connection is running (locked {}). Stop it first.",
+                path
+            )),
+            _ => io_error(error, &format!("Could not lock {}.", path), ControlError),
+        });
+    }
+
+    Ok(file)
+}
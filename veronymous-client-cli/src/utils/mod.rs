@@ -1,2 +1,5 @@
+pub mod capability_utils;
 pub mod cli_utils;
+pub mod lock_utils;
 pub mod path_utils;
+pub mod systemd_utils;
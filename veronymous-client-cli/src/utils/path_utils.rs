@@ -1,12 +1,22 @@
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /*
-* Joins path with home
+* Joins path with home. None if HOME is not set (e.g., a system service).
 */
-pub fn get_home_path(path: &str) -> String {
-    let home = env::var("HOME").unwrap();
-    let home = Path::new(&home);
+pub fn get_home_path(path: &str) -> Option<PathBuf> {
+    let home = env::var_os("HOME")?;
 
-    home.join(Path::new(path)).to_str().unwrap().to_string()
+    Some(Path::new(&home).join(path))
+}
+
+/*
+* XDG base directory (e.g., XDG_STATE_HOME), or its default relative to home (e.g., .local/state)
+*/
+pub fn get_xdg_path(variable: &str, default: &str) -> Option<PathBuf> {
+    match env::var_os(variable) {
+        // Relative paths are invalid
+        Some(dir) if Path::new(&dir).is_absolute() => Some(PathBuf::from(dir)),
+        _ => get_home_path(default),
+    }
 }
@@ -0,0 +1,71 @@
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+const WATCHDOG_USEC_ENV: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID_ENV: &str = "WATCHDOG_PID";
+
+/*
+* Send the state to the service manager (sd_notify(3)), e.g., "READY=1".
+* Does nothing if not started by systemd. Best effort.
+*/
+pub fn notify(state: &str) {
+    let path = match env::var(NOTIFY_SOCKET_ENV) {
+        Ok(path) if !path.is_empty() => path,
+        _ => return,
+    };
+
+    if let Err(e) = send(&path, state) {
+        debug!("Could not notify the service manager. {:?}", e);
+    }
+}
+
+// Once the service can be used
+pub fn notify_ready(status: &str) {
+    notify(&format!("READY=1\nSTATUS={}", status));
+}
+
+// Shown by `systemctl status`
+pub fn notify_status(status: &str) {
+    notify(&format!("STATUS={}", status));
+}
+
+pub fn notify_watchdog() {
+    notify("WATCHDOG=1");
+}
+
+/*
+* How often the watchdog must be pinged. Half of the service's WatchdogSec.
+* None if the watchdog is not enabled for this process.
+*/
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var(WATCHDOG_PID_ENV) {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    parse_watchdog_usec(&env::var(WATCHDOG_USEC_ENV).ok()?)
+}
+
+fn parse_watchdog_usec(usec: &str) -> Option<Duration> {
+    match usec.parse::<u64>() {
+        Ok(usec) if usec > 0 => Some(Duration::from_micros(usec / 2)),
+        _ => None,
+    }
+}
+
+// A path, or an abstract socket starting with @
+fn send(path: &str, state: &str) -> std::io::Result<()> {
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &address)?;
+
+    Ok(())
+}
@@ -0,0 +1,431 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::CommandError;
+use crate::wg::TunnelHealth;
+use std::io;
+use std::mem;
+use std::net::IpAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::process;
+use std::time::{Duration, Instant, SystemTime};
+
+/*
+* The peer answers the data with a keepalive within 10 seconds (WireGuard passive keepalive).
+* Data sent without any response for longer means the tunnel is dead.
+*/
+const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// A session is rejected 180 seconds after its handshake (WireGuard REJECT_AFTER_TIME)
+const SESSION_EXPIRY: Duration = Duration::from_secs(180);
+
+const MAX_FAILED_PINGS: u32 = 3;
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HealthStatus {
+    Healthy,
+
+    Unhealthy { reason: String },
+}
+
+/*
+* Watches the peer's handshake and counters between two reconnections.
+* Only sending without receiving is unhealthy. An idle tunnel does not handshake.
+*/
+pub struct HealthMonitor {
+    // Health at the previous check
+    previous: Option<(TunnelHealth, Instant)>,
+
+    // When the data started to be sent without any response
+    unanswered_since: Option<Instant>,
+
+    // When the data started to be sent continuously
+    sending_since: Option<Instant>,
+
+    // Data was received since the previous check
+    responded: bool,
+
+    failed_pings: u32,
+}
+
+impl HealthMonitor {
+    pub fn new() -> Self {
+        Self {
+            previous: None,
+            unanswered_since: None,
+            sending_since: None,
+            responded: false,
+            failed_pings: 0,
+        }
+    }
+
+    pub fn check(&mut self, health: &TunnelHealth, now: Instant, time: SystemTime) -> HealthStatus {
+        let (previous, checked_at) = match self.previous.replace((health.clone(), now)) {
+            None => return HealthStatus::Healthy,
+            Some(previous) => previous,
+        };
+
+        // The counters are reset with the session (e.g., on reconnection)
+        let sent = health.tx_bytes > previous.tx_bytes;
+        let received = health.rx_bytes != previous.rx_bytes;
+        self.responded = received;
+
+        // Sent at the latest right after the previous check
+        self.sending_since = match sent {
+            false => None,
+            true => self.sending_since.or(Some(checked_at)),
+        };
+        self.unanswered_since = match received {
+            true => None,
+            false => self.unanswered_since.or(self.sending_since),
+        };
+
+        if let Some(unanswered_since) = self.unanswered_since {
+            let unanswered = now.saturating_duration_since(unanswered_since);
+
+            if unanswered > STALL_TIMEOUT {
+                return HealthStatus::Unhealthy {
+                    reason: format!("No response from the server for {}s.", unanswered.as_secs()),
+                };
+            }
+        }
+
+        /*
+         * Sending renews the session within seconds.
+         * Covers the backends that do not count the handshake responses as received.
+         */
+        if let Some(sending_since) = self.sending_since {
+            let expired = match health.last_handshake {
+                None => true,
+                Some(last_handshake) => time
+                    .duration_since(last_handshake)
+                    .map(|age| age > SESSION_EXPIRY)
+                    .unwrap_or(false),
+            };
+
+            if expired && now.saturating_duration_since(sending_since) > STALL_TIMEOUT {
+                return HealthStatus::Unhealthy {
+                    reason: "The session expired and was not renewed.".to_string(),
+                };
+            }
+        }
+
+        HealthStatus::Healthy
+    }
+
+    pub fn responded(&self) -> bool {
+        self.responded
+    }
+
+    pub fn record_ping(&mut self, success: bool) -> HealthStatus {
+        match success {
+            true => self.failed_pings = 0,
+            false => self.failed_pings += 1,
+        }
+
+        match self.failed_pings >= MAX_FAILED_PINGS {
+            false => HealthStatus::Healthy,
+            true => HealthStatus::Unhealthy {
+                reason: format!("{} pings through the tunnel failed.", self.failed_pings),
+            },
+        }
+    }
+}
+
+// Exponential backoff between the reconnection attempts (1-based)
+pub fn reconnect_delay(attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+
+    MIN_RECONNECT_DELAY
+        .saturating_mul(factor)
+        .min(MAX_RECONNECT_DELAY)
+}
+
+/*
+* Ping the address through the interface, from a datagram ICMP socket bound to it.
+* Blocks for up to the ping timeout.
+*/
+pub fn ping(address: &IpAddr, interface: &str) -> Result<bool, CliClientError> {
+    let (domain, protocol, request_type, reply_type) = match address {
+        IpAddr::V4(_) => (libc::AF_INET, libc::IPPROTO_ICMP, 8, 0),
+        IpAddr::V6(_) => (libc::AF_INET6, libc::IPPROTO_ICMPV6, 128, 129),
+    };
+
+    let fd = unsafe { libc::socket(domain, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, protocol) };
+    if fd < 0 {
+        return Err(CommandError(format!(
+            "Could not open the ICMP socket. {:?}",
+            io::Error::last_os_error()
+        )));
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface.as_ptr() as *const libc::c_void,
+            interface.len() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(CommandError(format!(
+            "Could not bind the ICMP socket to {}. {:?}",
+            interface,
+            io::Error::last_os_error()
+        )));
+    }
+
+    // The kernel sets the identifier and the checksum
+    let sequence = (process::id() as u16).to_be_bytes();
+    let request = [request_type, 0, 0, 0, 0, 0, sequence[0], sequence[1]];
+
+    let sent = unsafe {
+        let (address, length) = socket_address(address);
+
+        libc::sendto(
+            socket.as_raw_fd(),
+            request.as_ptr() as *const libc::c_void,
+            request.len(),
+            0,
+            &address as *const libc::sockaddr_storage as *const libc::sockaddr,
+            length,
+        )
+    };
+    // e.g., the network is unreachable
+    if sent < 0 {
+        return Ok(false);
+    }
+
+    let deadline = Instant::now() + PING_TIMEOUT;
+    let mut reply = [0u8; 1500];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+
+        set_receive_timeout(&socket, remaining)?;
+
+        let received = unsafe {
+            libc::recv(
+                socket.as_raw_fd(),
+                reply.as_mut_ptr() as *mut libc::c_void,
+                reply.len(),
+                0,
+            )
+        };
+        // Timed out, or an ICMP error
+        if received < 0 {
+            return Ok(false);
+        }
+
+        // Only the replies to this socket's identifier are received
+        if received >= 8 && reply[0] == reply_type && reply[6..8] == sequence {
+            return Ok(true);
+        }
+    }
+}
+
+fn socket_address(address: &IpAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+    match address {
+        IpAddr::V4(address) => {
+            let socket_address = unsafe {
+                &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in)
+            };
+            socket_address.sin_family = libc::AF_INET as libc::sa_family_t;
+            socket_address.sin_addr.s_addr = u32::from_ne_bytes(address.octets());
+
+            (
+                storage,
+                mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            )
+        }
+        IpAddr::V6(address) => {
+            let socket_address = unsafe {
+                &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6)
+            };
+            socket_address.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            socket_address.sin6_addr.s6_addr = address.octets();
+
+            (
+                storage,
+                mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+            )
+        }
+    }
+}
+
+fn set_receive_timeout(socket: &OwnedFd, timeout: Duration) -> Result<(), CliClientError> {
+    let timeval = libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_usec: timeout.subsec_micros().max(1) as libc::suseconds_t,
+    };
+
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &timeval as *const libc::timeval as *const libc::c_void,
+            mem::size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+
+    match result {
+        0 => Ok(()),
+        _ => Err(CommandError(format!(
+            "Could not set the ICMP socket's timeout. {:?}",
+            io::Error::last_os_error()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vpn_client::health::{reconnect_delay, HealthMonitor, HealthStatus};
+    use crate::wg::TunnelHealth;
+    use std::time::{Duration, Instant, SystemTime};
+
+    #[test]
+    fn test_stalled_tunnel() {
+        let mut monitor = HealthMonitor::new();
+        let start = Instant::now();
+        let time = SystemTime::now();
+
+        assert_eq!(
+            HealthStatus::Healthy,
+            monitor.check(&health(Some(time), 100, 100), start, time)
+        );
+
+        // Sending without a response
+        for seconds in [10, 20, 30] {
+            assert_eq!(
+                HealthStatus::Healthy,
+                monitor.check(
+                    &health(Some(time), 100, 100 + seconds),
+                    start + Duration::from_secs(seconds),
+                    time
+                )
+            );
+        }
+
+        assert!(matches!(
+            monitor.check(
+                &health(Some(time), 100, 200),
+                start + Duration::from_secs(40),
+                time
+            ),
+            HealthStatus::Unhealthy { .. }
+        ));
+
+        assert!(!monitor.responded());
+
+        // A response resets the stall
+        assert_eq!(
+            HealthStatus::Healthy,
+            monitor.check(
+                &health(Some(time), 150, 300),
+                start + Duration::from_secs(50),
+                time
+            )
+        );
+        assert!(monitor.responded());
+    }
+
+    #[test]
+    fn test_idle_tunnel() {
+        let mut monitor = HealthMonitor::new();
+        let start = Instant::now();
+        let time = SystemTime::now();
+
+        monitor.check(&health(None, 0, 0), start, time);
+
+        // No traffic, no handshake
+        assert_eq!(
+            HealthStatus::Healthy,
+            monitor.check(
+                &health(None, 0, 0),
+                start + Duration::from_secs(3600),
+                time + Duration::from_secs(3600)
+            )
+        );
+    }
+
+    #[test]
+    fn test_expired_session() {
+        let mut monitor = HealthMonitor::new();
+        let start = Instant::now();
+        let time = SystemTime::now();
+        let last_handshake = Some(time - Duration::from_secs(170));
+
+        monitor.check(&health(last_handshake, 100, 100), start, time);
+
+        // Still valid
+        assert_eq!(
+            HealthStatus::Healthy,
+            monitor.check(
+                &health(last_handshake, 200, 200),
+                start + Duration::from_secs(5),
+                time + Duration::from_secs(5)
+            )
+        );
+
+        // Expired, the handshake may be in progress
+        assert_eq!(
+            HealthStatus::Healthy,
+            monitor.check(
+                &health(last_handshake, 300, 300),
+                start + Duration::from_secs(20),
+                time + Duration::from_secs(20)
+            )
+        );
+
+        // Not renewed while sending
+        assert!(matches!(
+            monitor.check(
+                &health(last_handshake, 400, 400),
+                start + Duration::from_secs(40),
+                time + Duration::from_secs(40)
+            ),
+            HealthStatus::Unhealthy { .. }
+        ));
+    }
+
+    #[test]
+    fn test_failed_pings() {
+        let mut monitor = HealthMonitor::new();
+
+        assert_eq!(HealthStatus::Healthy, monitor.record_ping(false));
+        assert_eq!(HealthStatus::Healthy, monitor.record_ping(false));
+        assert_eq!(HealthStatus::Healthy, monitor.record_ping(true));
+        assert_eq!(HealthStatus::Healthy, monitor.record_ping(false));
+        assert_eq!(HealthStatus::Healthy, monitor.record_ping(false));
+        assert!(matches!(
+            monitor.record_ping(false),
+            HealthStatus::Unhealthy { .. }
+        ));
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        assert_eq!(Duration::from_secs(1), reconnect_delay(1));
+        assert_eq!(Duration::from_secs(8), reconnect_delay(4));
+        assert_eq!(Duration::from_secs(60), reconnect_delay(10));
+        assert_eq!(Duration::from_secs(60), reconnect_delay(100));
+    }
+
+    fn health(last_handshake: Option<SystemTime>, rx_bytes: u64, tx_bytes: u64) -> TunnelHealth {
+        TunnelHealth {
+            last_handshake,
+            rx_bytes,
+            tx_bytes,
+        }
+    }
+}
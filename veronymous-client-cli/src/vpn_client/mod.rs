@@ -1,43 +1,215 @@
-use crate::constants::app::{CLIENT_FILE_PATH, VPN_SERVERS_FILE_PATH};
-use crate::error::CliClientError;
+mod health;
+
+use crate::config::state::{write_state_file, StateFiles};
+use crate::daemon::protocol::{CredentialsStatus, KeyEpochCache};
 use crate::error::CliClientError::{
-    EncodingError, InitializationError, IoError, ParseError, ReadFileError,
+    CommandError, EncodingError, InitializationError, ParseError, ReadFileError,
+};
+use crate::error::{io_error, CliClientError};
+use crate::utils::systemd_utils::{notify_watchdog, watchdog_interval};
+use crate::vpn_client::health::{ping, reconnect_delay, HealthMonitor, HealthStatus};
+use crate::wg::{
+    endpoint_host, watch_network, HealthCheck, NetworkEvent, NetworkPath, Rotation, RoutingOptions,
+    TunnelBackend, TunnelStatus,
 };
-use crate::utils::path_utils::get_home_path;
-use crate::wg::{wg_refresh, wg_up};
 use rand::Rng;
+use serde::Serialize;
+use std::fs;
+use std::future;
 use std::path::Path;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use std::{fs, thread};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedReceiver;
 use veronymous_client::client::state::{ClientState, VpnConnection};
 use veronymous_client::client::VeronymousClient;
 use veronymous_client::config::VERONYMOUS_CLIENT_CONFIG;
 use veronymous_client::error::VeronymousClientError;
 use veronymous_client::oidc::client::OidcClient;
 use veronymous_client::oidc::credentials::UserCredentials;
+use veronymous_client::proxy::parse_proxy;
+use veronymous_client::servers::query::{ServerFilter, ServerInfo};
+use veronymous_client::servers::source::{servers_sources, FileServersSource, ServersSource};
 use veronymous_client::servers::VpnServers;
 use veronymous_client::veronymous_token::client::VeronymousTokenClient;
 use veronymous_token::token::get_next_epoch;
 
+// The network changes come in bursts (e.g., link, addresses, then routes)
+const NETWORK_SETTLE_TIME: Duration = Duration::from_secs(2);
+
 pub struct CliVpnClient {
     veronymous_client: VeronymousClient,
+
+    // Local servers list file. Replaces the servers endpoint if set.
+    servers_file: Option<String>,
+
+    servers_mirrors: Vec<String>,
+
+    servers_public_key: Option<String>,
+
+    // Client state and cached servers list
+    state: StateFiles,
+
+    // None for the commands that do not connect (e.g., login)
+    tunnel: Option<Arc<dyn TunnelBackend>>,
+
+    // None if the network changes are not watched
+    network_events: Option<UnboundedReceiver<NetworkEvent>>,
+
+    // Path of the tunnel's packets when the network was last checked
+    network_path: Option<NetworkPath>,
+
+    current: Arc<Mutex<CurrentConnection>>,
+
+    // e.g., printed as JSON lines
+    event_handler: Option<Box<dyn Fn(&ConnectionEvent) + Send + Sync>>,
+
+    // Settings reloaded from the config file (e.g., on SIGHUP)
+    reloads: Option<UnboundedReceiver<ClientSettings>>,
+
+    // How often the service manager's watchdog is pinged. None if not enabled.
+    watchdog: Option<Duration>,
+}
+
+/*
+* Proxy and servers list. Replaced on reload, from the next connection on.
+* The state directory is not reloaded.
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientSettings {
+    pub proxy: Option<String>,
+
+    // Token issuer public key pins (sha256/<base64>)
+    pub token_endpoint_pins: Vec<String>,
+
+    // Local servers list file. Replaces the servers endpoint if set.
+    pub servers_file: Option<String>,
+
+    // Tried in order if the servers endpoint is unavailable
+    pub servers_mirrors: Vec<String>,
+
+    // The servers lists must be signed with it
+    pub servers_public_key: Option<String>,
+}
+
+// The tunnel's connection. Shared with the status requests.
+#[derive(Clone, Debug, Default)]
+pub struct CurrentConnection {
+    pub addresses: Vec<String>,
+
+    // When the connection is switched to the next epoch's
+    pub next_rotation: Option<SystemTime>,
+}
+
+// Progress of the connection, in order
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ConnectionEvent {
+    Connecting {
+        server: String,
+    },
+
+    // Also after a rotation or a reconnection
+    Connected {
+        server: String,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        interface: Option<String>,
+
+        addresses: Vec<String>,
+
+        // Unix time
+        #[serde(skip_serializing_if = "Option::is_none")]
+        next_rotation: Option<u64>,
+    },
+
+    // Switching to the next epoch's connection
+    Rotating {
+        server: String,
+    },
+
+    Reconnecting {
+        server: String,
+
+        reason: String,
+    },
 }
 
 impl CliVpnClient {
-    pub async fn create() -> Result<Self, CliClientError> {
+    pub async fn create(
+        settings: ClientSettings,
+        state: StateFiles,
+        tunnel: Option<Arc<dyn TunnelBackend>>,
+    ) -> Result<Self, CliClientError> {
+        let veronymous_client =
+            Self::create_client(settings.proxy, &settings.token_endpoint_pins).await?;
+
+        Ok(Self {
+            veronymous_client,
+            servers_file: settings.servers_file,
+            servers_mirrors: settings.servers_mirrors,
+            servers_public_key: settings.servers_public_key,
+            state,
+            tunnel,
+            network_events: None,
+            network_path: None,
+            current: Arc::new(Mutex::new(CurrentConnection::default())),
+            event_handler: None,
+            reloads: None,
+            watchdog: watchdog_interval(),
+        })
+    }
+
+    async fn create_client(
+        proxy: Option<String>,
+        token_endpoint_pins: &[String],
+    ) -> Result<VeronymousClient, CliClientError> {
+        // The command line proxy takes precedence over the configured one
+        let proxy = parse_proxy(&proxy.or_else(|| VERONYMOUS_CLIENT_CONFIG.proxy.clone()))
+            .map_err(|e| InitializationError(e.to_string()))?;
+
         let oidc_client = OidcClient::new(
             VERONYMOUS_CLIENT_CONFIG.oidc_endpoint.clone(),
             VERONYMOUS_CLIENT_CONFIG.oidc_client_id.clone(),
+            proxy.clone(),
         );
         let token_client = VeronymousTokenClient::create(
             &VERONYMOUS_CLIENT_CONFIG.token_endpoint,
             &VERONYMOUS_CLIENT_CONFIG.token_endpoint_ca,
+            token_endpoint_pins,
+            &proxy,
         )
         .await
-        .map_err(|e| InitializationError(e.to_string()))?;
+        .map_err(|e| CliClientError::VeronymousClientError(e))?;
+
+        Ok(VeronymousClient::new(oidc_client, token_client))
+    }
+
+    pub fn set_event_handler(&mut self, handler: Box<dyn Fn(&ConnectionEvent) + Send + Sync>) {
+        self.event_handler = Some(handler);
+    }
+
+    // Applied while connected, or through reload when idle
+    pub fn set_reloads(&mut self, reloads: UnboundedReceiver<ClientSettings>) {
+        self.reloads = Some(reloads);
+    }
 
-        let veronymous_client = VeronymousClient::new(oidc_client, token_client);
-        Ok(Self { veronymous_client })
+    pub async fn next_reload(&mut self) -> ClientSettings {
+        next_settings(&mut self.reloads).await
+    }
+
+    // The current settings are kept if the new ones are invalid (e.g., invalid proxy)
+    pub async fn reload(&mut self, settings: ClientSettings) {
+        match Self::create_client(settings.proxy, &settings.token_endpoint_pins).await {
+            Ok(veronymous_client) => {
+                self.veronymous_client = veronymous_client;
+                self.servers_file = settings.servers_file;
+                self.servers_mirrors = settings.servers_mirrors;
+                self.servers_public_key = settings.servers_public_key;
+
+                info!("Reloaded the config.");
+            }
+            Err(e) => error!("Could not reload the config. {:?}", e),
+        }
     }
 
     pub async fn authenticate(
@@ -48,70 +220,543 @@ impl CliVpnClient {
         let credentials = UserCredentials::new(username, password);
 
         // read the client state
-        let mut client_state = Self::read_client_state(None)?;
+        let mut client_state = Self::read_client_state(&self.state.client_file)?;
 
         self.veronymous_client
             .authenticate(&credentials, &mut client_state)
             .await
             .map_err(|e| CliClientError::VeronymousClientError(e))?;
 
-        Self::save_client_state(&mut client_state, None)?;
+        Self::save_client_state(&mut client_state, &self.state.client_file)?;
 
         Ok(())
     }
 
-    pub async fn get_servers(&self, path: Option<String>) -> Result<Vec<String>, CliClientError> {
-        let mut vpn_servers = Self::read_vpn_servers(path)?;
+    pub async fn get_servers(
+        &self,
+        filter: &ServerFilter,
+    ) -> Result<Vec<ServerInfo>, CliClientError> {
+        let mut vpn_servers = Self::read_vpn_servers(&self.state.servers_file)?;
 
-        Self::update_servers(&mut vpn_servers).await?;
+        self.update_servers(&mut vpn_servers).await?;
 
-        Ok(vpn_servers.list_domains())
+        Ok(vpn_servers.list_servers(filter))
     }
 
     pub async fn connect(
         &mut self,
         server: String,
-        tunnel_only: bool,
+        options: &RoutingOptions,
     ) -> Result<(), CliClientError> {
+        self.tunnel()?;
         info!("Connecting...");
 
-        let connection = self.create_connection(&server).await?;
+        self.set_current(None, None);
+        self.emit(ConnectionEvent::Connecting {
+            server: server.clone(),
+        });
+
+        // The domains' current addresses. Kept until the next connection.
+        let options = &options.resolve_split_tunnel()?;
 
-        wg_up(&connection, tunnel_only)?;
+        // Fails with its exit code. Only a lost tunnel is retried.
+        let mut connection = self.create_connection(&server).await?;
+
+        // Resolved before the kill switch blocks the DNS. Reused by the reconnections.
+        let options = &options.resolve_kill_switch_hosts(&self.kill_switch_hosts(&server)?)?;
+        self.tunnel()?.up(&connection, options)?;
         info!("Connected.");
 
+        self.watch_network(options);
+
         loop {
             let delay = Self::get_refresh_start();
 
             info!("Updating connection in {}s", delay.as_secs());
+            self.set_current(Some(&connection), Some(SystemTime::now() + delay));
+            self.emit_connected(&server);
 
-            thread::sleep(delay);
+            self.monitor(&server, options, &mut connection, delay)
+                .await?;
 
             info!("Update connection...");
+            self.emit(ConnectionEvent::Rotating {
+                server: server.clone(),
+            });
+
+            let result = match self.create_connection(&server).await {
+                Ok(next) => self
+                    .switch_connection(&connection, &next, options)
+                    .await
+                    .map(|_| next),
+                Err(e) => Err(e),
+            };
+
+            connection = match result {
+                Ok(next) => next,
+                // e.g., the token issuer is unavailable
+                Err(e) if is_transient(&e) => {
+                    warn!("Could not rotate the connection. {:?}", e);
+                    self.emit(ConnectionEvent::Reconnecting {
+                        server: server.clone(),
+                        reason: format!("Could not rotate the connection. {:?}", e),
+                    });
+
+                    let mut reconnects = 1;
+                    self.reconnect(&server, options, &mut reconnects).await?
+                }
+                Err(e) => return Err(e),
+            };
+        }
+    }
 
-            let connection = self.create_connection(&server).await?;
+    pub fn current_connection(&self) -> Arc<Mutex<CurrentConnection>> {
+        self.current.clone()
+    }
 
-            wg_refresh(&connection, tunnel_only)?;
+    pub fn state_files(&self) -> &StateFiles {
+        &self.state
+    }
 
-            info!("Connected.");
+    /*
+     * Epochs, cached tokens and credentials of the client state.
+     * Read from the state file. Does not contact the servers.
+     */
+    pub fn credentials_status(state: &StateFiles) -> Result<CredentialsStatus, CliClientError> {
+        let client_state = Self::read_client_state(&state.client_file)?;
+
+        let now = Self::now();
+        let key_epoch = VeronymousClient::get_current_key_epoch(Some(now));
+        let next_key_epoch = key_epoch + VERONYMOUS_CLIENT_CONFIG.key_lifetime;
+
+        let key_epochs = [key_epoch, next_key_epoch]
+            .iter()
+            .map(|key_epoch| KeyEpochCache {
+                key_epoch: *key_epoch,
+                root_token: client_state.root_tokens.tokens.contains_key(key_epoch),
+                issuer_info: client_state
+                    .issuer_infos
+                    .issuer_infos
+                    .contains_key(key_epoch),
+            })
+            .collect();
+
+        let refresh_token_expiry = match &client_state.oidc_credentials {
+            None => None,
+            Some(credentials) => Some(
+                credentials
+                    .refresh_token_expiry()
+                    .map_err(|e| CliClientError::VeronymousClientError(e))?,
+            ),
+        };
+
+        Ok(CredentialsStatus {
+            epoch: VeronymousClient::get_current_epoch(Some(now)),
+            key_epoch,
+            key_epochs,
+            refresh_token_expiry,
+        })
+    }
+
+    fn set_current(&self, connection: Option<&VpnConnection>, next_rotation: Option<SystemTime>) {
+        let mut current = self.current.lock().unwrap();
+
+        current.addresses = connection
+            .map(|connection| connection.client_addresses.clone())
+            .unwrap_or_default();
+        current.next_rotation = next_rotation;
+    }
+
+    fn emit(&self, event: ConnectionEvent) {
+        if let Some(handler) = &self.event_handler {
+            handler(&event);
+        }
+    }
+
+    fn emit_connected(&self, server: &str) {
+        if self.event_handler.is_none() {
+            return;
+        }
+
+        let current = self.current.lock().unwrap().clone();
+        let interface = match self.tunnel().and_then(|tunnel| tunnel.status()) {
+            Ok(TunnelStatus::Up { interface }) => Some(interface),
+            _ => None,
+        };
+
+        self.emit(ConnectionEvent::Connected {
+            server: server.to_string(),
+            interface,
+            addresses: current.addresses,
+            next_rotation: current.next_rotation.map(|next_rotation| {
+                next_rotation
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+            }),
+        });
+    }
+
+    // Best effort. The connection is still monitored by the health checks.
+    fn watch_network(&mut self, options: &RoutingOptions) {
+        if self.network_events.is_none() {
+            match watch_network() {
+                Ok(events) => self.network_events = Some(events),
+                Err(e) => warn!("Could not watch the network changes. {:?}", e),
+            }
         }
+
+        self.network_path = match NetworkPath::current(options.fwmark) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                warn!("Could not get the network path. {:?}", e);
+                None
+            }
+        };
     }
 
     /*
-     * Connect to a Veronymous VPN Server.
-     * TODO: Optional client file path
-     * TODO: Optional auth file (user name and password)
+     * Checks the tunnel's health and the network changes until the delay elapsed.
+     * Reconnects if the tunnel is unhealthy.
+     * The delay includes the time suspended.
      */
+    async fn monitor(
+        &mut self,
+        server: &String,
+        options: &RoutingOptions,
+        connection: &mut VpnConnection,
+        delay: Duration,
+    ) -> Result<(), CliClientError> {
+        let health_check = &options.health_check;
+
+        let deadline = SystemTime::now() + delay;
+        let mut monitor = HealthMonitor::new();
+        let mut next_check = SystemTime::now() + health_check.interval;
+
+        // Reconnections without a response from the server
+        let mut reconnects = 0;
+
+        loop {
+            // The loop is running
+            if self.watchdog.is_some() {
+                notify_watchdog();
+            }
+
+            let now = SystemTime::now();
+            let remaining = deadline.duration_since(now).unwrap_or_default();
+
+            if remaining.is_zero() {
+                return Ok(());
+            }
+
+            let mut wait = match health_check.interval.is_zero() {
+                true => remaining,
+                false => next_check
+                    .duration_since(now)
+                    .unwrap_or_default()
+                    .min(remaining),
+            };
+            if let Some(watchdog) = self.watchdog {
+                wait = wait.min(watchdog);
+            }
+
+            let status = tokio::select! {
+                _ = tokio::time::sleep(wait) => {
+                    // Woken up for the watchdog
+                    if health_check.interval.is_zero() || SystemTime::now() < next_check {
+                        continue;
+                    }
+
+                    next_check = SystemTime::now() + health_check.interval;
+
+                    self.check_health(&mut monitor, health_check).await
+                }
+                settings = next_settings(&mut self.reloads) => {
+                    self.reload(settings).await;
+                    continue;
+                }
+                event = next_network_event(&mut self.network_events) => {
+                    match self.on_network_event(event, connection, options).await {
+                        None => continue,
+                        Some(status) => {
+                            // The counters are compared from the new network on
+                            monitor = HealthMonitor::new();
+
+                            status
+                        }
+                    }
+                }
+            };
+
+            match status {
+                HealthStatus::Healthy => {
+                    if monitor.responded() {
+                        reconnects = 0;
+                    }
+                }
+                HealthStatus::Unhealthy { reason } => {
+                    warn!("The tunnel is unhealthy. {}", reason);
+                    self.emit(ConnectionEvent::Reconnecting {
+                        server: server.clone(),
+                        reason,
+                    });
+
+                    *connection = self.reconnect(server, options, &mut reconnects).await?;
+                    monitor = HealthMonitor::new();
+
+                    self.set_current(Some(&*connection), Some(deadline));
+                    self.emit_connected(server);
+                }
+            }
+        }
+    }
+
+    /*
+     * Refresh the tunnel if the host moved to another network or resumed.
+     * None if the network did not change. Unhealthy if the tunnel could not be refreshed.
+     */
+    async fn on_network_event(
+        &mut self,
+        event: NetworkEvent,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Option<HealthStatus> {
+        tokio::time::sleep(NETWORK_SETTLE_TIME).await;
+
+        let mut resumed = false;
+        let mut next_event = Some(event);
+
+        while let Some(event) = next_event {
+            if let NetworkEvent::Resumed { suspended } = event {
+                info!("Resumed after {}s.", suspended.as_secs());
+                resumed = true;
+            }
+
+            next_event = self
+                .network_events
+                .as_mut()
+                .and_then(|events| events.try_recv().ok());
+        }
+
+        let path = match NetworkPath::current(options.fwmark) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Could not get the network path. {:?}", e);
+                return None;
+            }
+        };
+
+        if !resumed && self.network_path.as_ref() == Some(&path) {
+            return None;
+        }
+
+        self.network_path = Some(path.clone());
+
+        // Refreshed once the network is back
+        if path.is_down() {
+            info!("The network is down.");
+            return None;
+        }
+
+        info!("The network changed. Refreshing the connection...");
+
+        match self
+            .tunnel()
+            .and_then(|tunnel| tunnel.refresh(connection, options))
+        {
+            Ok(()) => {
+                info!("Connected.");
+                Some(HealthStatus::Healthy)
+            }
+            Err(e) => Some(HealthStatus::Unhealthy {
+                reason: format!("Could not refresh the connection. {:?}", e),
+            }),
+        }
+    }
+
+    async fn check_health(
+        &self,
+        monitor: &mut HealthMonitor,
+        health_check: &HealthCheck,
+    ) -> HealthStatus {
+        let status = match self.tunnel().and_then(|tunnel| tunnel.health()) {
+            // Not supported by the backend
+            Ok(None) => HealthStatus::Healthy,
+            Ok(Some(health)) => monitor.check(&health, Instant::now(), SystemTime::now()),
+            Err(e) => HealthStatus::Unhealthy {
+                reason: format!("Could not read the tunnel's state. {:?}", e),
+            },
+        };
+
+        let tunnel_status = self.tunnel().and_then(|tunnel| tunnel.status());
+
+        let (address, interface) = match (&status, health_check.ping_address, tunnel_status) {
+            (HealthStatus::Healthy, Some(address), Ok(TunnelStatus::Up { interface })) => {
+                (address, interface)
+            }
+            _ => return status,
+        };
+
+        let result = tokio::task::spawn_blocking(move || ping(&address, &interface))
+            .await
+            .map_err(|e| CommandError(format!("Could not run ping. {:?}", e)))
+            .and_then(|result| result);
+
+        match result {
+            Ok(success) => monitor.record_ping(success),
+            Err(e) => {
+                warn!("Could not ping {}. {:?}", address, e);
+
+                status
+            }
+        }
+    }
+
+    /*
+     * Bring the tunnel up again, with a backoff between the attempts.
+     * Only the network failures are retried. The other errors will not go away by themselves.
+     * The first attempt reuses the current connection (e.g., the network was down).
+     * The next ones request a new connection from the server.
+     */
+    async fn reconnect(
+        &mut self,
+        server: &String,
+        options: &RoutingOptions,
+        reconnects: &mut u32,
+    ) -> Result<VpnConnection, CliClientError> {
+        loop {
+            if *reconnects > 0 {
+                let delay = reconnect_delay(*reconnects);
+
+                info!("Reconnecting in {}s", delay.as_secs());
+                self.sleep(delay).await;
+
+                self.discard_connection(server)?;
+            }
+
+            *reconnects += 1;
+            info!("Reconnecting...");
+
+            let result = match self.create_connection(server).await {
+                Ok(connection) => self
+                    .tunnel()
+                    .and_then(|tunnel| tunnel.up(&connection, options))
+                    .map(|_| connection),
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(connection) => {
+                    info!("Connected.");
+
+                    return Ok(connection);
+                }
+                Err(e) if is_transient(&e) => warn!("Could not reconnect. {:?}", e),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // The next connect creates a new connection for the current epoch
+    fn discard_connection(&self, domain: &String) -> Result<(), CliClientError> {
+        let mut client_state = Self::read_client_state(&self.state.client_file)?;
+
+        client_state
+            .connections
+            .remove_connection(&VeronymousClient::get_current_epoch(None), domain);
+
+        Self::save_client_state(&mut client_state, &self.state.client_file)
+    }
+
+    // Switch the tunnel to the next epoch's connection
+    async fn switch_connection(
+        &self,
+        previous: &VpnConnection,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        match &options.rotation {
+            Rotation::InPlace => {
+                self.tunnel()?.reconfigure(connection, options)?;
+                info!("Connected.");
+
+                Ok(())
+            }
+            Rotation::Seamless { grace_period } => {
+                self.rotate(previous, connection, options, grace_period)
+                    .await
+            }
+        }
+    }
+
+    /*
+     * Make before break. The previous tunnel is kept for the grace period (e.g., packets in flight
+     * and the open connections from its addresses).
+     * Falls back to reconfiguring the tunnel in place if the new tunnel does not come up.
+     */
+    async fn rotate(
+        &self,
+        previous: &VpnConnection,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+        grace_period: &Duration,
+    ) -> Result<(), CliClientError> {
+        let tunnel = self.tunnel()?;
+
+        if let Err(e) = tunnel.rotate(previous, connection, options) {
+            warn!("Could not rotate the connection seamlessly. {:?}", e);
+
+            tunnel.reconfigure(connection, options)?;
+            info!("Connected.");
+
+            return Ok(());
+        }
+
+        info!("Connected.");
+
+        self.sleep(*grace_period).await;
+
+        tunnel.retire_previous(options)
+    }
+
+    // Keeps pinging the watchdog while waiting
+    async fn sleep(&self, duration: Duration) {
+        let watchdog = match self.watchdog {
+            None => return tokio::time::sleep(duration).await,
+            Some(watchdog) => watchdog,
+        };
+
+        let deadline = Instant::now() + duration;
+
+        loop {
+            notify_watchdog();
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return;
+            }
+
+            tokio::time::sleep(remaining.min(watchdog)).await;
+        }
+    }
+
+    fn tunnel(&self) -> Result<&dyn TunnelBackend, CliClientError> {
+        self.tunnel
+            .as_deref()
+            .ok_or_else(|| InitializationError("The client has no tunnel.".to_string()))
+    }
+
+    // Connect to a Veronymous VPN Server.
     async fn create_connection(
         &mut self,
         domain: &String,
     ) -> Result<VpnConnection, CliClientError> {
         // Read and update the vpn servers
-        let mut vpn_servers = Self::read_vpn_servers(None)?;
-        Self::update_servers(&mut vpn_servers).await?;
+        let mut vpn_servers = Self::read_vpn_servers(&self.state.servers_file)?;
+        self.update_servers(&mut vpn_servers).await?;
 
         // read the client state
-        let mut client_state = Self::read_client_state(None)?;
+        let mut client_state = Self::read_client_state(&self.state.client_file)?;
 
         // Establish connection with the vpn router
         let connection = match self
@@ -120,7 +765,7 @@ impl CliVpnClient {
             .await
         {
             Ok(connection) => {
-                Self::save_client_state(&mut client_state, None)?;
+                Self::save_client_state(&mut client_state, &self.state.client_file)?;
                 connection
             }
             Err(e) => {
@@ -136,14 +781,14 @@ impl CliVpnClient {
                     VeronymousClientError::SubscriptionRequired() => {
                         client_state.oidc_credentials = None;
 
-                        Self::save_client_state(&mut client_state, None)?;
+                        Self::save_client_state(&mut client_state, &self.state.client_file)?;
 
                         return Err(CliClientError::SubscriptionRequired);
                     }
                     _ => {}
                 };
 
-                Self::save_client_state(&mut client_state, None)?;
+                Self::save_client_state(&mut client_state, &self.state.client_file)?;
 
                 return Err(CliClientError::VeronymousClientError(e));
             }
@@ -152,32 +797,55 @@ impl CliVpnClient {
         Ok(connection)
     }
 
-    async fn update_servers(vpn_servers: &mut VpnServers) -> Result<(), CliClientError> {
+    // The router agents of the domain and the servers endpoints. Reached outside the tunnel.
+    fn kill_switch_hosts(&self, domain: &String) -> Result<Vec<String>, CliClientError> {
+        let vpn_servers = Self::read_vpn_servers(&self.state.servers_file)?;
+
+        let mut endpoints: Vec<String> = match vpn_servers.servers.get(domain) {
+            Some(profiles) => profiles
+                .values()
+                .map(|profile| profile.agent_endpoint.clone())
+                .collect(),
+            None => vec![],
+        };
+
+        if self.servers_file.is_none() {
+            endpoints.push(VERONYMOUS_CLIENT_CONFIG.servers_endpoint.clone());
+            endpoints.extend(self.servers_mirrors.iter().cloned());
+        }
+
+        Ok(endpoints
+            .iter()
+            .map(|endpoint| endpoint_host(endpoint))
+            .collect())
+    }
+
+    async fn update_servers(&self, vpn_servers: &mut VpnServers) -> Result<(), CliClientError> {
+        let sources: Vec<Box<dyn ServersSource>> = match &self.servers_file {
+            None => servers_sources(&self.servers_mirrors, self.servers_public_key.clone()),
+            Some(servers_file) => vec![Box::new(FileServersSource::new(servers_file.clone()))],
+        };
+
         let updated = vpn_servers
-            .update()
+            .update_from(&sources, self.servers_public_key.as_deref())
             .await
             .map_err(|e| CliClientError::VeronymousClientError(e))?;
 
         if updated {
-            Self::save_vpn_servers(vpn_servers, None)?;
+            Self::save_vpn_servers(vpn_servers, &self.state.servers_file)?;
         }
 
         Ok(())
     }
 
-    fn read_client_state(path: Option<String>) -> Result<ClientState, CliClientError> {
-        let path = match path {
-            None => get_home_path(CLIENT_FILE_PATH),
-            Some(path) => path,
-        };
-
+    fn read_client_state(path: &Path) -> Result<ClientState, CliClientError> {
         let client_state;
-        if !Path::new(&path).exists() {
+        if !path.exists() {
             // Does not exists, create
             client_state = ClientState::empty();
         } else {
             let contents = fs::read(path)
-                .map_err(|e| ReadFileError(format!("Could not read client file. {:?}", e)))?;
+                .map_err(|e| io_error(e, "Could not read client file.", ReadFileError))?;
 
             client_state =
                 serde_json::from_slice(&contents).map_err(|e| ParseError(format!("{:?}", e)))?;
@@ -188,7 +856,7 @@ impl CliVpnClient {
 
     fn save_client_state(
         client_state: &mut ClientState,
-        path: Option<String>,
+        path: &Path,
     ) -> Result<(), CliClientError> {
         // Clear old connections
         client_state.clear_old(
@@ -196,39 +864,20 @@ impl CliVpnClient {
             VeronymousClient::get_current_epoch(None),
         );
 
-        let path = match path {
-            None => get_home_path(CLIENT_FILE_PATH),
-            Some(path) => path,
-        };
-
-        let parent = Path::new(&path).parent().unwrap();
-
-        // Create parent directory if it does not exist
-        if !parent.exists() {
-            fs::create_dir_all(parent).map_err(|e| IoError(e.to_string()))?;
-        }
-
         let contents =
             serde_json::to_vec(client_state).map_err(|e| EncodingError(e.to_string()))?;
 
-        fs::write(path, contents).map_err(|e| IoError(e.to_string()))?;
-
-        Ok(())
+        write_state_file(path, &contents)
     }
 
-    fn read_vpn_servers(path: Option<String>) -> Result<VpnServers, CliClientError> {
-        let path = match path {
-            None => get_home_path(VPN_SERVERS_FILE_PATH),
-            Some(path) => path,
-        };
-
+    fn read_vpn_servers(path: &Path) -> Result<VpnServers, CliClientError> {
         let vpn_servers;
-        if !Path::new(&path).exists() {
+        if !path.exists() {
             // Does not exists, create
             vpn_servers = VpnServers::new();
         } else {
             let contents = fs::read(path)
-                .map_err(|e| ReadFileError(format!("Could not read vpn servers file. {:?}", e)))?;
+                .map_err(|e| io_error(e, "Could not read vpn servers file.", ReadFileError))?;
 
             vpn_servers =
                 serde_json::from_slice(&contents).map_err(|e| ParseError(format!("{:?}", e)))?;
@@ -237,28 +886,10 @@ impl CliVpnClient {
         Ok(vpn_servers)
     }
 
-    fn save_vpn_servers(
-        client_state: &mut VpnServers,
-        path: Option<String>,
-    ) -> Result<(), CliClientError> {
-        let path = match path {
-            None => get_home_path(VPN_SERVERS_FILE_PATH),
-            Some(path) => path,
-        };
-
-        let parent = Path::new(&path).parent().unwrap();
+    fn save_vpn_servers(vpn_servers: &mut VpnServers, path: &Path) -> Result<(), CliClientError> {
+        let contents = serde_json::to_vec(vpn_servers).map_err(|e| EncodingError(e.to_string()))?;
 
-        // Create parent directory if it does not exist
-        if !parent.exists() {
-            fs::create_dir_all(parent).map_err(|e| IoError(e.to_string()))?;
-        }
-
-        let contents =
-            serde_json::to_vec(client_state).map_err(|e| EncodingError(e.to_string()))?;
-
-        fs::write(path, contents).map_err(|e| IoError(e.to_string()))?;
-
-        Ok(())
+        write_state_file(path, &contents)
     }
 
     fn get_refresh_start() -> Duration {
@@ -292,3 +923,39 @@ impl CliVpnClient {
         now.duration_since(UNIX_EPOCH).unwrap().as_secs()
     }
 }
+
+// Pending forever if the network changes are not watched
+async fn next_network_event(events: &mut Option<UnboundedReceiver<NetworkEvent>>) -> NetworkEvent {
+    let event = match events {
+        None => None,
+        Some(events) => events.recv().await,
+    };
+
+    match event {
+        Some(event) => event,
+        None => future::pending().await,
+    }
+}
+
+// Pending forever if the settings are not reloaded
+async fn next_settings(reloads: &mut Option<UnboundedReceiver<ClientSettings>>) -> ClientSettings {
+    let settings = match reloads {
+        None => None,
+        Some(reloads) => reloads.recv().await,
+    };
+
+    match settings {
+        Some(settings) => settings,
+        None => future::pending().await,
+    }
+}
+
+// Retried with a backoff (e.g., the server is unreachable). The other errors are returned.
+fn is_transient(error: &CliClientError) -> bool {
+    matches!(
+        error,
+        CliClientError::HandshakeError(_)
+            | CliClientError::VeronymousClientError(VeronymousClientError::ConnectError(_))
+            | CliClientError::VeronymousClientError(VeronymousClientError::HttpError(_))
+    )
+}
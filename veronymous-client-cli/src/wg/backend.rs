@@ -0,0 +1,1195 @@
+use crate::config::CliConfig;
+use crate::error::CliClientError;
+use crate::error::CliClientError::{CommandError, FirewallError, HandshakeError, ParseError};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use veronymous_client::client::state::VpnConnection;
+use veronymous_client::config::VERONYMOUS_CLIENT_CONFIG;
+
+// Used if neither the user nor the server set the MTU
+pub const WG_MTU: u32 = 1420;
+
+pub const RT_TABLE_MAIN: u32 = libc::RT_TABLE_MAIN as u32;
+
+// How long the seamless rotation waits for the new tunnel's handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const HANDSHAKE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Maximum interface name length (IFNAMSIZ without the nul byte)
+const MAX_INTERFACE_NAME_LENGTH: usize = 15;
+
+/*
+* Brings the vpn tunnel up and down.
+* The backends execute the operations planned by plan_up, plan_reconfigure, plan_rotate and plan_down.
+*/
+pub trait TunnelBackend: Send + Sync {
+    fn up(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError>;
+
+    // Switch an up tunnel to a new connection (e.g., the next epoch)
+    fn reconfigure(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError>;
+
+    /*
+     * Bring the new connection up next to the current one and move the traffic over.
+     * The previous tunnel is kept until retire_previous is called,
+     * with the traffic from its addresses (e.g., open connections).
+     */
+    fn rotate(
+        &self,
+        _previous: &VpnConnection,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        self.reconfigure(connection, options)
+    }
+
+    // Tear down the tunnel replaced by rotate
+    fn retire_previous(&self, _options: &RoutingOptions) -> Result<(), CliClientError> {
+        Ok(())
+    }
+
+    // Adapt the up tunnel to a host network change (e.g., another Wi-Fi network or a resume)
+    fn refresh(
+        &self,
+        _connection: &VpnConnection,
+        _options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        Ok(())
+    }
+
+    fn down(&self) -> Result<(), CliClientError>;
+
+    // Clean up after a previous run that did not exit cleanly. The kill switch is kept.
+    fn recover(&self) -> Result<(), CliClientError>;
+
+    fn status(&self) -> Result<TunnelStatus, CliClientError>;
+
+    // The peer's handshake and counters. None if unknown (e.g., dry-run or tunnel down).
+    fn health(&self) -> Result<Option<TunnelHealth>, CliClientError> {
+        Ok(None)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoutingOptions {
+    // Don't forward all traffic through the tunnel
+    pub tunnel_only: bool,
+
+    // Hosts reached outside of the tunnel (e.g., the token issuer)
+    pub out_of_band_hosts: Vec<String>,
+
+    pub kill_switch: KillSwitch,
+
+    // Addresses allowed by the kill switch (e.g., the token issuer and the router agent).
+    // Set by resolve_kill_switch_hosts, before the kill switch blocks the DNS queries.
+    pub kill_switch_hosts: Vec<IpAddr>,
+
+    // Overrides the server's suggestion
+    pub mtu: Option<u32>,
+
+    // Marks the tunnel's own packets so they skip the tunnel routing table
+    pub fwmark: u32,
+
+    pub table: u32,
+
+    // Seconds. Overrides the server's suggestion.
+    pub persistent_keepalive: Option<u16>,
+
+    // <address>/<prefix length>
+    pub allowed_ips: Vec<String>,
+
+    // How the tunnel is switched to the next epoch's connection
+    pub rotation: Rotation,
+
+    // Reconnects if the tunnel is unhealthy
+    pub health_check: HealthCheck,
+
+    // Destinations (<address>[/<prefix length>] or domains) routed through or around the tunnel
+    pub split_include: Vec<String>,
+
+    pub split_exclude: Vec<String>,
+
+    // The resolved destinations. Set by resolve_split_tunnel.
+    pub split_tunnel: SplitTunnel,
+}
+
+impl RoutingOptions {
+    // The tunnel settings are read from the config file, then the client's config
+    pub fn new(tunnel_only: bool, kill_switch: KillSwitch, config: &CliConfig) -> Self {
+        let tunnel = &VERONYMOUS_CLIENT_CONFIG.tunnel;
+
+        Self {
+            tunnel_only,
+            out_of_band_hosts: VERONYMOUS_CLIENT_CONFIG.out_of_band_hosts.clone(),
+            kill_switch,
+            // The hosts are resolved when connecting
+            kill_switch_hosts: vec![],
+            mtu: config.mtu.or(tunnel.mtu),
+            fwmark: config.fwmark.unwrap_or(tunnel.fwmark),
+            table: config.table.unwrap_or(tunnel.table),
+            persistent_keepalive: config.persistent_keepalive.or(tunnel.persistent_keepalive),
+            allowed_ips: config
+                .allowed_ips
+                .clone()
+                .unwrap_or_else(|| tunnel.allowed_ips.clone()),
+            // The user's routes are bound to the interface
+            rotation: match tunnel_only || !tunnel.seamless_rotation {
+                true => Rotation::InPlace,
+                false => Rotation::Seamless {
+                    grace_period: Duration::from_secs(tunnel.rotation_grace_period),
+                },
+            },
+            health_check: HealthCheck {
+                interval: Duration::from_secs(tunnel.health_check_interval),
+                ping_address: tunnel.health_check_address,
+            },
+            split_include: config
+                .split_include
+                .clone()
+                .unwrap_or_else(|| tunnel.split_include.clone()),
+            split_exclude: config
+                .split_exclude
+                .clone()
+                .unwrap_or_else(|| tunnel.split_exclude.clone()),
+            // The destinations are resolved when connecting
+            split_tunnel: SplitTunnel::Disabled,
+        }
+    }
+
+    /*
+     * The options with the split tunnel destinations resolved.
+     * Called on every connection, so the domains' current addresses are routed.
+     */
+    pub fn resolve_split_tunnel(&self) -> Result<Self, CliClientError> {
+        let split_tunnel = match self.tunnel_only {
+            true => SplitTunnel::Disabled,
+            false => SplitTunnel::resolve(&self.split_include, &self.split_exclude)?,
+        };
+
+        Ok(Self {
+            split_tunnel,
+            ..self.clone()
+        })
+    }
+
+    /*
+     * The options with the addresses the kill switch allows: the out of band hosts and the hosts
+     * (e.g., the router agent and the servers endpoint).
+     * Resolved once, so the reconnections do not depend on the DNS. Fails if a host is unresolved.
+     */
+    pub fn resolve_kill_switch_hosts(&self, hosts: &[String]) -> Result<Self, CliClientError> {
+        if self.kill_switch == KillSwitch::Disabled {
+            return Ok(self.clone());
+        }
+
+        let mut addresses = vec![];
+
+        for host in self.out_of_band_hosts.iter().chain(hosts) {
+            let resolved = host.to_socket_addrs().map_err(|e| {
+                FirewallError(format!(
+                    "Could not resolve {} for the kill switch. {:?}",
+                    host, e
+                ))
+            })?;
+
+            addresses.extend(resolved.map(|address| address.ip()));
+        }
+
+        addresses.sort();
+        addresses.dedup();
+
+        Ok(Self {
+            kill_switch_hosts: addresses,
+            ..self.clone()
+        })
+    }
+
+    // Routes the previous tunnel's traffic during the seamless rotation's grace period
+    pub fn previous_table(&self) -> u32 {
+        self.table + 1
+    }
+
+    // The user's setting, the server's suggestion or the default
+    pub fn mtu(&self, connection: &VpnConnection) -> u32 {
+        self.mtu.or(connection.tunnel_hints.mtu).unwrap_or(WG_MTU)
+    }
+
+    // 0 disables the keepalive
+    pub fn persistent_keepalive(&self, connection: &VpnConnection) -> Option<u16> {
+        self.persistent_keepalive
+            .or(connection.tunnel_hints.persistent_keepalive)
+            .filter(|interval| *interval > 0)
+    }
+}
+
+/*
+* Blocks the traffic outside of the tunnel.
+* Stays in place while the tunnel is reconfigured, and if the client crashes.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum KillSwitch {
+    Disabled,
+
+    Enabled { allow_lan: bool },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rotation {
+    // Replace the keys and addresses of the interface. Drops the open connections.
+    InPlace,
+
+    /*
+     * Make before break. The next connection is brought up on the standby interface
+     * and the previous one is torn down after the grace period.
+     */
+    Seamless { grace_period: Duration },
+}
+
+/*
+* Routes only some destinations through the tunnel, or all of them but some.
+* The tunnel routes are IPv4 only, so the IPv6 destinations can only be excluded.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum SplitTunnel {
+    Disabled,
+
+    // Only the traffic to the networks goes through the tunnel
+    Include { networks: Vec<(IpAddr, u8)> },
+
+    // The traffic to the networks bypasses the tunnel
+    Exclude { networks: Vec<(IpAddr, u8)> },
+}
+
+impl SplitTunnel {
+    /*
+     * The destinations are <address>[/<prefix length>] or domains.
+     * A domain is resolved once, to host routes for each of its addresses.
+     */
+    pub fn resolve(include: &[String], exclude: &[String]) -> Result<Self, CliClientError> {
+        match (include.is_empty(), exclude.is_empty()) {
+            (true, true) => Ok(Self::Disabled),
+            (false, true) => {
+                let mut networks = vec![];
+
+                for destination in include {
+                    let resolved: Vec<(IpAddr, u8)> = resolve_destination(destination)?
+                        .into_iter()
+                        .filter(|(address, _)| address.is_ipv4())
+                        .collect();
+
+                    if resolved.is_empty() {
+                        return Err(ParseError(format!(
+                            "Cannot include {}. Only the IPv4 destinations can be included.",
+                            destination
+                        )));
+                    }
+
+                    networks.extend(resolved);
+                }
+
+                Ok(Self::Include {
+                    networks: deduplicated(networks),
+                })
+            }
+            (true, false) => {
+                let mut networks = vec![];
+
+                for destination in exclude {
+                    networks.extend(resolve_destination(destination)?);
+                }
+
+                Ok(Self::Exclude {
+                    networks: deduplicated(networks),
+                })
+            }
+            (false, false) => Err(ParseError(
+                "The destinations cannot be both included and excluded.".to_string(),
+            )),
+        }
+    }
+
+    // Bypassing the tunnel
+    pub fn excluded_networks(&self) -> Vec<(IpAddr, u8)> {
+        match self {
+            Self::Exclude { networks } => networks.clone(),
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthCheck {
+    // Disabled if zero
+    pub interval: Duration,
+
+    // Pinged through the tunnel on every check
+    pub ping_address: Option<IpAddr>,
+}
+
+/*
+* The tunnel's interface and the standby interface used by the seamless rotation.
+* They swap roles on every rotation.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct TunnelInterfaces {
+    pub active: String,
+
+    pub standby: String,
+
+    // Network namespace holding the interface (exec). The host's routing is not changed.
+    pub namespace: Option<String>,
+}
+
+impl TunnelInterfaces {
+    pub fn new(interface: String) -> Self {
+        let standby = standby_interface(&interface);
+
+        Self {
+            active: interface,
+            standby,
+            namespace: None,
+        }
+    }
+
+    pub fn in_namespace(interface: String, namespace: String) -> Self {
+        Self {
+            namespace: Some(namespace),
+            ..Self::new(interface)
+        }
+    }
+
+    pub fn swap(&mut self) {
+        std::mem::swap(&mut self.active, &mut self.standby);
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        vec![self.active.clone(), self.standby.clone()]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TunnelStatus {
+    Down,
+
+    Up { interface: String },
+}
+
+// Sampled by the health monitor
+#[derive(Clone, Debug, PartialEq)]
+pub struct TunnelHealth {
+    pub last_handshake: Option<SystemTime>,
+
+    pub rx_bytes: u64,
+
+    pub tx_bytes: u64,
+}
+
+// A single change to the host network configuration
+/*
+ * The host and port of an endpoint (e.g., https://files.veronymous.io/servers.json).
+ * Without a port, the scheme's default.
+ */
+pub fn endpoint_host(endpoint: &str) -> String {
+    let (scheme, rest) = match endpoint.split_once("://") {
+        Some((scheme, rest)) => (scheme, rest),
+        None => ("https", endpoint),
+    };
+
+    let authority = rest.split(&['/', '?', '#'][..]).next().unwrap_or(rest);
+    let authority = authority.rsplit('@').next().unwrap_or(authority);
+
+    let has_port = match authority.rfind(']') {
+        // IPv6 literal
+        Some(end) => authority[end..].contains(':'),
+        None => authority.contains(':'),
+    };
+
+    match has_port {
+        true => authority.to_string(),
+        false if scheme == "http" => format!("{}:80", authority),
+        false => format!("{}:443", authority),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TunnelOperation {
+    // Replaces the existing kill switch
+    InstallKillSwitch {
+        interfaces: Vec<String>,
+        endpoint: String,
+        hosts: Vec<IpAddr>,
+        allow_lan: bool,
+        // Excluded from the tunnel
+        excluded: Vec<(IpAddr, u8)>,
+    },
+
+    // Removes the kill switch if it exists
+    RemoveKillSwitch,
+
+    // Route the hosts through their current route, outside of the tunnel
+    AddOutOfBandRoutes {
+        hosts: Vec<String>,
+    },
+
+    // Removes the routes added by AddOutOfBandRoutes (e.g., through the previous network)
+    RemoveOutOfBandRoutes,
+
+    // The next operations apply to the interface
+    SelectInterface {
+        interface: String,
+    },
+
+    // Deletes the interface if it exists
+    DeleteInterface,
+
+    CreateInterface,
+
+    AddAddress {
+        address: IpAddr,
+        prefix_len: u8,
+    },
+
+    FlushAddresses,
+
+    ConfigureDevice {
+        private_key: String,
+        peer_public_key: String,
+        endpoint: String,
+        allowed_ips: Vec<(IpAddr, u8)>,
+        persistent_keepalive: Option<u16>,
+    },
+
+    SetLinkUp {
+        mtu: u32,
+    },
+
+    // Re-resolves the endpoint and sends to its current address. The session is kept.
+    UpdateEndpoint {
+        peer_public_key: String,
+        endpoint: String,
+    },
+
+    // Fails if the peer did not respond in time
+    WaitForHandshake,
+
+    SetFwmark {
+        fwmark: u32,
+    },
+
+    AddDefaultRoute {
+        table: u32,
+    },
+
+    // Points the default route to the interface atomically
+    ReplaceDefaultRoute {
+        table: u32,
+    },
+
+    // Route to the network through the interface (split tunnel)
+    AddRoute {
+        destination: IpAddr,
+        prefix_len: u8,
+        table: u32,
+    },
+
+    // Points the route to the interface atomically
+    ReplaceRoute {
+        destination: IpAddr,
+        prefix_len: u8,
+        table: u32,
+    },
+
+    // Send the traffic to the network through the main table, outside of the tunnel
+    AddExcludeRule {
+        destination: IpAddr,
+        prefix_len: u8,
+    },
+
+    // Send the traffic from the address through the table (e.g., the previous tunnel's)
+    AddSourceRule {
+        source: IpAddr,
+        table: u32,
+    },
+
+    // Removes the source rules and the routes of the table
+    RemoveSourceRouting {
+        table: u32,
+    },
+
+    AddFwmarkRule {
+        fwmark: u32,
+        table: u32,
+    },
+
+    AddSuppressPrefixRule {
+        table: u32,
+        prefix_len: u32,
+    },
+
+    // Removes the routes and rules added by the previous operations
+    RemoveRoutingConfiguration,
+
+    // Send the DNS queries through the tunnel
+    ConfigureDns {
+        servers: Vec<IpAddr>,
+    },
+
+    // Restores the original DNS configuration if it was changed
+    RestoreDns,
+
+    // Replaces the existing namespace
+    CreateNamespace {
+        namespace: String,
+    },
+
+    // Deletes the namespace and its interfaces if it exists
+    DeleteNamespace {
+        namespace: String,
+    },
+
+    // Moves the interface to the namespace. Its wireguard socket stays in the host's namespace.
+    MoveToNamespace {
+        namespace: String,
+    },
+
+    // The next operations apply in the namespace (None for the host's)
+    SelectNamespace {
+        namespace: Option<String>,
+    },
+
+    // Route through the interface in the main table. Removed with the interface.
+    AddInterfaceRoute {
+        destination: IpAddr,
+        prefix_len: u8,
+    },
+
+    // resolv.conf of the commands run in the namespace
+    ConfigureNamespaceDns {
+        namespace: String,
+        servers: Vec<IpAddr>,
+    },
+}
+
+pub fn plan_up(
+    connection: &VpnConnection,
+    options: &RoutingOptions,
+    interfaces: &TunnelInterfaces,
+) -> Result<Vec<TunnelOperation>, CliClientError> {
+    if let Some(namespace) = &interfaces.namespace {
+        return plan_namespace_up(connection, options, namespace);
+    }
+
+    // Block the traffic before the existing connection is torn down
+    let mut operations = kill_switch_operations(connection, options, interfaces);
+
+    operations.extend([
+        TunnelOperation::AddOutOfBandRoutes {
+            hosts: options.out_of_band_hosts.clone(),
+        },
+        // Tear down existing connection
+        TunnelOperation::DeleteInterface,
+        TunnelOperation::CreateInterface,
+    ]);
+
+    operations.extend(address_operations(connection)?);
+    operations.push(configure_device_operation(connection, options)?);
+    operations.push(TunnelOperation::SetLinkUp {
+        mtu: options.mtu(connection),
+    });
+    operations.extend(routing_operations(options));
+    operations.extend(dns_operations(connection, options)?);
+
+    Ok(operations)
+}
+
+pub fn plan_reconfigure(
+    connection: &VpnConnection,
+    options: &RoutingOptions,
+    interfaces: &TunnelInterfaces,
+) -> Result<Vec<TunnelOperation>, CliClientError> {
+    if let Some(namespace) = &interfaces.namespace {
+        return plan_namespace_reconfigure(connection, options, namespace);
+    }
+
+    let mut operations = kill_switch_operations(connection, options, interfaces);
+
+    operations.extend([
+        configure_device_operation(connection, options)?,
+        // Replace the addresses
+        TunnelOperation::FlushAddresses,
+    ]);
+
+    operations.extend(address_operations(connection)?);
+    operations.extend(routing_operations(options));
+    operations.extend(dns_operations(connection, options)?);
+
+    Ok(operations)
+}
+
+/*
+* Bring the connection up on the standby interface while the active one keeps the traffic.
+* The routes are only moved once the peer responded. The backend swaps the interfaces afterwards.
+* The traffic from the previous connection's addresses keeps going through the active interface.
+*/
+pub fn plan_rotate(
+    previous: &VpnConnection,
+    connection: &VpnConnection,
+    options: &RoutingOptions,
+    interfaces: &TunnelInterfaces,
+) -> Result<Vec<TunnelOperation>, CliClientError> {
+    // The standby interface's routes would be added to the host
+    if interfaces.namespace.is_some() {
+        return Err(CommandError(
+            "The seamless rotation is not supported in a namespace.".to_string(),
+        ));
+    }
+
+    let mut operations = kill_switch_operations(connection, options, interfaces);
+
+    operations.extend([
+        TunnelOperation::AddOutOfBandRoutes {
+            hosts: options.out_of_band_hosts.clone(),
+        },
+        TunnelOperation::SelectInterface {
+            interface: interfaces.standby.clone(),
+        },
+        // Leftover from a failed rotation
+        TunnelOperation::DeleteInterface,
+        TunnelOperation::CreateInterface,
+    ]);
+
+    operations.extend(address_operations(connection)?);
+    operations.push(configure_device_operation(connection, options)?);
+
+    // Before the handshake. Otherwise the tunnel's packets would go through the active tunnel.
+    if !options.tunnel_only {
+        operations.push(TunnelOperation::SetFwmark {
+            fwmark: options.fwmark,
+        });
+    }
+
+    operations.extend([
+        TunnelOperation::SetLinkUp {
+            mtu: options.mtu(connection),
+        },
+        TunnelOperation::WaitForHandshake,
+    ]);
+
+    if !options.tunnel_only {
+        operations.extend(previous_routing_operations(previous, options, interfaces)?);
+
+        match &options.split_tunnel {
+            SplitTunnel::Include { networks } => {
+                operations.extend(networks.iter().map(|(destination, prefix_len)| {
+                    TunnelOperation::ReplaceRoute {
+                        destination: *destination,
+                        prefix_len: *prefix_len,
+                        table: options.table,
+                    }
+                }))
+            }
+            _ => operations.push(TunnelOperation::ReplaceDefaultRoute {
+                table: options.table,
+            }),
+        }
+
+        operations.extend(exclude_operations(options));
+        operations.extend([
+            TunnelOperation::AddFwmarkRule {
+                fwmark: options.fwmark,
+                table: options.table,
+            },
+            TunnelOperation::AddSuppressPrefixRule {
+                table: RT_TABLE_MAIN,
+                prefix_len: 0,
+            },
+        ]);
+    }
+
+    operations.extend(dns_operations(connection, options)?);
+
+    Ok(operations)
+}
+
+/*
+* Re-pin the out-of-band routes to the current network and re-resolve the endpoint.
+* The kill switch and the resolvers are set again (e.g., the endpoint's address changed,
+* or the network manager replaced resolv.conf).
+*/
+pub fn plan_refresh(
+    connection: &VpnConnection,
+    options: &RoutingOptions,
+    interfaces: &TunnelInterfaces,
+) -> Result<Vec<TunnelOperation>, CliClientError> {
+    // Only the endpoint depends on the host's network
+    if let Some(namespace) = &interfaces.namespace {
+        return Ok(vec![
+            TunnelOperation::SelectNamespace {
+                namespace: Some(namespace.clone()),
+            },
+            TunnelOperation::UpdateEndpoint {
+                peer_public_key: connection.wg_public_key.clone(),
+                endpoint: connection.wg_endpoint.clone(),
+            },
+        ]);
+    }
+
+    let mut operations = kill_switch_operations(connection, options, interfaces);
+
+    operations.extend([
+        TunnelOperation::RemoveOutOfBandRoutes,
+        TunnelOperation::AddOutOfBandRoutes {
+            hosts: options.out_of_band_hosts.clone(),
+        },
+        TunnelOperation::UpdateEndpoint {
+            peer_public_key: connection.wg_public_key.clone(),
+            endpoint: connection.wg_endpoint.clone(),
+        },
+    ]);
+
+    operations.extend(dns_operations(connection, options)?);
+
+    Ok(operations)
+}
+
+/*
+* Delete the standby interface and the routing of its addresses.
+* The previous tunnel after a rotation, or the new one if the rotation failed.
+*/
+pub fn plan_retire(
+    options: &RoutingOptions,
+    interfaces: &TunnelInterfaces,
+) -> Vec<TunnelOperation> {
+    vec![
+        TunnelOperation::RemoveSourceRouting {
+            table: options.previous_table(),
+        },
+        TunnelOperation::SelectInterface {
+            interface: interfaces.standby.clone(),
+        },
+        TunnelOperation::DeleteInterface,
+    ]
+}
+
+pub fn plan_down(interfaces: &TunnelInterfaces) -> Vec<TunnelOperation> {
+    let mut operations = plan_recover(interfaces);
+
+    if interfaces.namespace.is_none() {
+        operations.push(TunnelOperation::RemoveKillSwitch);
+    }
+
+    operations
+}
+
+pub fn plan_recover(interfaces: &TunnelInterfaces) -> Vec<TunnelOperation> {
+    /*
+     * The namespace's routes and resolvers are deleted with it.
+     * The host's configuration may belong to another connection.
+     */
+    if let Some(namespace) = &interfaces.namespace {
+        return vec![
+            TunnelOperation::DeleteNamespace {
+                namespace: namespace.clone(),
+            },
+            // Left over if interrupted before it was moved
+            TunnelOperation::DeleteInterface,
+        ];
+    }
+
+    vec![
+        TunnelOperation::RestoreDns,
+        TunnelOperation::DeleteInterface,
+        // Left over if interrupted during a rotation
+        TunnelOperation::SelectInterface {
+            interface: interfaces.standby.clone(),
+        },
+        TunnelOperation::DeleteInterface,
+        TunnelOperation::RemoveRoutingConfiguration,
+    ]
+}
+
+// The standby interface's name. The interface name with an "r" suffix, truncated to fit.
+pub fn standby_interface(interface: &str) -> String {
+    let mut name: String = interface
+        .chars()
+        .take(MAX_INTERFACE_NAME_LENGTH - 1)
+        .collect();
+    name.push('r');
+
+    // The truncated name could end up being the interface name
+    if name == interface {
+        name.pop();
+        name.push('s');
+    }
+
+    name
+}
+
+/*
+* Interface and namespace names end up in paths (e.g., /etc/netns/<name>) and nft scripts.
+* Letters, digits, "_", "." and "-", at most 15 characters, and neither "." nor "..".
+*/
+pub fn check_interface_name(name: &str) -> Result<(), CliClientError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_INTERFACE_NAME_LENGTH
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+
+    if !valid {
+        return Err(ParseError(format!("Invalid interface name {:?}.", name)));
+    }
+
+    Ok(())
+}
+
+// Polls the tunnel until the peer responded
+pub fn wait_for_handshake<F>(mut handshake_done: F) -> Result<(), CliClientError>
+where
+    F: FnMut() -> Result<bool, CliClientError>,
+{
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+
+    while !handshake_done()? {
+        if Instant::now() >= deadline {
+            return Err(HandshakeError(format!(
+                "No handshake with the server after {}s.",
+                HANDSHAKE_TIMEOUT.as_secs()
+            )));
+        }
+
+        thread::sleep(HANDSHAKE_POLL_INTERVAL);
+    }
+
+    Ok(())
+}
+
+fn kill_switch_operations(
+    connection: &VpnConnection,
+    options: &RoutingOptions,
+    interfaces: &TunnelInterfaces,
+) -> Vec<TunnelOperation> {
+    match options.kill_switch {
+        KillSwitch::Disabled => vec![],
+        KillSwitch::Enabled { allow_lan } => vec![TunnelOperation::InstallKillSwitch {
+            interfaces: interfaces.names(),
+            endpoint: connection.wg_endpoint.clone(),
+            hosts: options.kill_switch_hosts.clone(),
+            allow_lan,
+            excluded: options.split_tunnel.excluded_networks(),
+        }],
+    }
+}
+
+/*
+* Route the traffic from the previous connection's addresses through the active interface,
+* before the tunnel's routes are moved to the standby one. The routes are IPv4 only.
+*/
+fn previous_routing_operations(
+    previous: &VpnConnection,
+    options: &RoutingOptions,
+    interfaces: &TunnelInterfaces,
+) -> Result<Vec<TunnelOperation>, CliClientError> {
+    let table = options.previous_table();
+
+    let mut operations = vec![
+        TunnelOperation::SelectInterface {
+            interface: interfaces.active.clone(),
+        },
+        TunnelOperation::ReplaceDefaultRoute { table },
+    ];
+
+    for address in &previous.client_addresses {
+        let (source, _) = parse_address(address)?;
+
+        if source.is_ipv4() {
+            operations.push(TunnelOperation::AddSourceRule { source, table });
+        }
+    }
+
+    operations.push(TunnelOperation::SelectInterface {
+        interface: interfaces.standby.clone(),
+    });
+
+    Ok(operations)
+}
+
+fn address_operations(connection: &VpnConnection) -> Result<Vec<TunnelOperation>, CliClientError> {
+    connection
+        .client_addresses
+        .iter()
+        .map(|address| {
+            let (address, prefix_len) = parse_address(address)?;
+
+            Ok(TunnelOperation::AddAddress {
+                address,
+                prefix_len,
+            })
+        })
+        .collect()
+}
+
+/*
+* The interface is created in the host's namespace, where its wireguard socket stays,
+* and moved to a new namespace. Only the namespace's traffic goes through the tunnel.
+*/
+fn plan_namespace_up(
+    connection: &VpnConnection,
+    options: &RoutingOptions,
+    namespace: &str,
+) -> Result<Vec<TunnelOperation>, CliClientError> {
+    let mut operations = vec![
+        TunnelOperation::CreateNamespace {
+            namespace: namespace.to_string(),
+        },
+        TunnelOperation::DeleteInterface,
+        TunnelOperation::CreateInterface,
+        TunnelOperation::MoveToNamespace {
+            namespace: namespace.to_string(),
+        },
+        TunnelOperation::SelectNamespace {
+            namespace: Some(namespace.to_string()),
+        },
+    ];
+
+    operations.extend(address_operations(connection)?);
+    operations.push(configure_device_operation(connection, options)?);
+    operations.push(TunnelOperation::SetLinkUp {
+        mtu: options.mtu(connection),
+    });
+    operations.extend(namespace_routing_operations(
+        connection, options, namespace,
+    )?);
+
+    Ok(operations)
+}
+
+fn plan_namespace_reconfigure(
+    connection: &VpnConnection,
+    options: &RoutingOptions,
+    namespace: &str,
+) -> Result<Vec<TunnelOperation>, CliClientError> {
+    let mut operations = vec![
+        TunnelOperation::SelectNamespace {
+            namespace: Some(namespace.to_string()),
+        },
+        configure_device_operation(connection, options)?,
+        TunnelOperation::FlushAddresses,
+    ];
+
+    operations.extend(address_operations(connection)?);
+    operations.extend(namespace_routing_operations(
+        connection, options, namespace,
+    )?);
+
+    Ok(operations)
+}
+
+// The allowed ips are routed through the interface. The namespace has no other route.
+fn namespace_routing_operations(
+    connection: &VpnConnection,
+    options: &RoutingOptions,
+    namespace: &str,
+) -> Result<Vec<TunnelOperation>, CliClientError> {
+    let mut operations: Vec<TunnelOperation> = allowed_ips(options)?
+        .into_iter()
+        .map(
+            |(destination, prefix_len)| TunnelOperation::AddInterfaceRoute {
+                destination,
+                prefix_len,
+            },
+        )
+        .collect();
+
+    if !connection.dns_servers.is_empty() {
+        operations.push(TunnelOperation::ConfigureNamespaceDns {
+            namespace: namespace.to_string(),
+            servers: dns_servers(connection)?,
+        });
+    }
+
+    Ok(operations)
+}
+
+/*
+* Configure wireguard
+* The server peer gets the allowed ips' traffic (by default 0.0.0.0/0, ::/0),
+* or only the included networks' traffic.
+*/
+fn configure_device_operation(
+    connection: &VpnConnection,
+    options: &RoutingOptions,
+) -> Result<TunnelOperation, CliClientError> {
+    Ok(TunnelOperation::ConfigureDevice {
+        private_key: connection.client_private_key.clone(),
+        peer_public_key: connection.wg_public_key.clone(),
+        endpoint: connection.wg_endpoint.clone(),
+        allowed_ips: allowed_ips(options)?,
+        persistent_keepalive: options.persistent_keepalive(connection),
+    })
+}
+
+fn allowed_ips(options: &RoutingOptions) -> Result<Vec<(IpAddr, u8)>, CliClientError> {
+    match &options.split_tunnel {
+        SplitTunnel::Include { networks } => Ok(networks.clone()),
+        _ => options
+            .allowed_ips
+            .iter()
+            .map(|allowed_ip| parse_address(allowed_ip))
+            .collect(),
+    }
+}
+
+/*
+* Configure routing of all traffic through the wireguard interface.
+* With the included networks, only their traffic.
+*/
+fn routing_operations(options: &RoutingOptions) -> Vec<TunnelOperation> {
+    if options.tunnel_only {
+        return vec![];
+    }
+
+    let mut operations = vec![TunnelOperation::SetFwmark {
+        fwmark: options.fwmark,
+    }];
+
+    match &options.split_tunnel {
+        SplitTunnel::Include { networks } => {
+            operations.extend(networks.iter().map(|(destination, prefix_len)| {
+                TunnelOperation::AddRoute {
+                    destination: *destination,
+                    prefix_len: *prefix_len,
+                    table: options.table,
+                }
+            }))
+        }
+        _ => operations.push(TunnelOperation::AddDefaultRoute {
+            table: options.table,
+        }),
+    }
+
+    // Before the traffic is sent to the tunnel
+    operations.extend(exclude_operations(options));
+    operations.extend([
+        TunnelOperation::AddFwmarkRule {
+            fwmark: options.fwmark,
+            table: options.table,
+        },
+        TunnelOperation::AddSuppressPrefixRule {
+            table: RT_TABLE_MAIN,
+            prefix_len: 0,
+        },
+    ]);
+
+    operations
+}
+
+fn exclude_operations(options: &RoutingOptions) -> Vec<TunnelOperation> {
+    options
+        .split_tunnel
+        .excluded_networks()
+        .into_iter()
+        .map(
+            |(destination, prefix_len)| TunnelOperation::AddExcludeRule {
+                destination,
+                prefix_len,
+            },
+        )
+        .collect()
+}
+
+/*
+* Only when all the traffic goes through the tunnel.
+* With the included networks, the resolvers are likely outside of them.
+*/
+fn dns_operations(
+    connection: &VpnConnection,
+    options: &RoutingOptions,
+) -> Result<Vec<TunnelOperation>, CliClientError> {
+    let included = matches!(options.split_tunnel, SplitTunnel::Include { .. });
+
+    if options.tunnel_only || included || connection.dns_servers.is_empty() {
+        return Ok(vec![]);
+    }
+
+    Ok(vec![TunnelOperation::ConfigureDns {
+        servers: dns_servers(connection)?,
+    }])
+}
+
+fn dns_servers(connection: &VpnConnection) -> Result<Vec<IpAddr>, CliClientError> {
+    connection
+        .dns_servers
+        .iter()
+        .map(|server| {
+            server
+                .parse::<IpAddr>()
+                .map_err(|e| ParseError(format!("Invalid DNS server {}. {:?}", server, e)))
+        })
+        .collect()
+}
+
+// <address>[/<prefix length>]. A single host if the prefix length is missing.
+pub fn parse_address(address: &str) -> Result<(IpAddr, u8), CliClientError> {
+    let (ip, prefix_len) = match address.split_once('/') {
+        Some((ip, prefix_len)) => (ip, Some(prefix_len)),
+        None => (address, None),
+    };
+
+    let ip: IpAddr = ip
+        .parse()
+        .map_err(|e| ParseError(format!("Invalid address {}. {:?}", address, e)))?;
+
+    let max_prefix_len = match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+
+    let prefix_len = match prefix_len {
+        None => max_prefix_len,
+        Some(prefix_len) => prefix_len
+            .parse::<u8>()
+            .ok()
+            .filter(|prefix_len| *prefix_len <= max_prefix_len)
+            .ok_or_else(|| ParseError(format!("Invalid prefix length {}.", address)))?,
+    };
+
+    Ok((ip, prefix_len))
+}
+
+// A network or the host routes of a domain's addresses
+fn resolve_destination(destination: &str) -> Result<Vec<(IpAddr, u8)>, CliClientError> {
+    let destination = destination.trim();
+
+    if destination.contains('/') || destination.parse::<IpAddr>().is_ok() {
+        return Ok(vec![parse_address(destination)?]);
+    }
+
+    let addresses = (destination, 0)
+        .to_socket_addrs()
+        .map_err(|e| CommandError(format!("Could not resolve {}. {:?}", destination, e)))?;
+
+    Ok(addresses
+        .map(|address| match address.ip() {
+            IpAddr::V4(ip) => (IpAddr::V4(ip), 32),
+            IpAddr::V6(ip) => (IpAddr::V6(ip), 128),
+        })
+        .collect())
+}
+
+fn deduplicated(mut networks: Vec<(IpAddr, u8)>) -> Vec<(IpAddr, u8)> {
+    networks.sort();
+    networks.dedup();
+
+    networks
+}
@@ -0,0 +1,241 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::DnsError;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+// The original resolv.conf (or symlink) while the tunnel is up
+const RESOLV_CONF_BACKUP: &str = "/etc/resolv.conf.veronymous";
+
+const RESOLVED_RUNTIME_DIR: &str = "/run/systemd/resolve";
+
+/*
+* Send the DNS queries to the tunnel resolvers.
+* With systemd-resolved the resolvers are set on the interface with the ~. routing domain.
+* Otherwise resolv.conf is replaced and the original is restored by restore_dns (deleted if there was none).
+*/
+pub fn configure_dns(interface: &str, servers: &[IpAddr]) -> Result<(), CliClientError> {
+    if uses_resolved() {
+        let servers: Vec<String> = servers.iter().map(|server| server.to_string()).collect();
+
+        let mut dns_args = vec!["dns", interface];
+        dns_args.extend(servers.iter().map(|server| server.as_str()));
+
+        resolvectl(&dns_args)?;
+        resolvectl(&["domain", interface, "~."])?;
+        resolvectl(&["default-route", interface, "true"])?;
+
+        return Ok(());
+    }
+
+    replace_resolv_conf(
+        Path::new(RESOLV_CONF),
+        Path::new(RESOLV_CONF_BACKUP),
+        servers,
+    )
+}
+
+// Safe to call if the dns is not configured
+pub fn restore_dns(interface: &str) -> Result<(), CliClientError> {
+    // The link settings are dropped with the interface. Best effort.
+    if uses_resolved() {
+        if let Err(e) = resolvectl(&["revert", interface]) {
+            debug!("{:?}", e);
+        }
+    }
+
+    // A backup also exists if the resolver changed since the tunnel was brought up
+    restore_resolv_conf(Path::new(RESOLV_CONF), Path::new(RESOLV_CONF_BACKUP))
+}
+
+// resolv.conf is managed by systemd-resolved (e.g., stub-resolv.conf)
+fn uses_resolved() -> bool {
+    let resolv_conf = match fs::canonicalize(RESOLV_CONF) {
+        Ok(resolv_conf) => resolv_conf,
+        Err(_) => return false,
+    };
+
+    resolv_conf.starts_with(RESOLVED_RUNTIME_DIR)
+}
+
+fn replace_resolv_conf(
+    resolv_conf: &Path,
+    backup: &Path,
+    servers: &[IpAddr],
+) -> Result<(), CliClientError> {
+    let missing = missing_marker(backup);
+
+    // Keep the original on reconfiguration
+    if fs::symlink_metadata(backup).is_err() && !missing.exists() {
+        match fs::rename(resolv_conf, backup) {
+            Ok(_) => {}
+            // The generated file is deleted on restore
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                fs::write(&missing, "").map_err(|e| {
+                    DnsError(format!("Could not write {}. {:?}", missing.display(), e))
+                })?;
+            }
+            Err(e) => {
+                return Err(DnsError(format!(
+                    "Could not back up {}. {:?}",
+                    resolv_conf.display(),
+                    e
+                )))
+            }
+        }
+    }
+
+    // Replace a symlink instead of writing to its target
+    if fs::symlink_metadata(resolv_conf).is_ok() {
+        fs::remove_file(resolv_conf).map_err(|e| {
+            DnsError(format!(
+                "Could not remove {}. {:?}",
+                resolv_conf.display(),
+                e
+            ))
+        })?;
+    }
+
+    fs::write(resolv_conf, resolv_conf_content(servers)).map_err(|e| {
+        DnsError(format!(
+            "Could not write {}. {:?}",
+            resolv_conf.display(),
+            e
+        ))
+    })
+}
+
+fn restore_resolv_conf(resolv_conf: &Path, backup: &Path) -> Result<(), CliClientError> {
+    let missing = missing_marker(backup);
+
+    if missing.exists() {
+        if let Err(e) = fs::remove_file(resolv_conf) {
+            if e.kind() != ErrorKind::NotFound {
+                return Err(DnsError(format!(
+                    "Could not remove {}. {:?}",
+                    resolv_conf.display(),
+                    e
+                )));
+            }
+        }
+
+        return fs::remove_file(&missing)
+            .map_err(|e| DnsError(format!("Could not remove {}. {:?}", missing.display(), e)));
+    }
+
+    if fs::symlink_metadata(backup).is_err() {
+        return Ok(());
+    }
+
+    fs::rename(backup, resolv_conf).map_err(|e| {
+        DnsError(format!(
+            "Could not restore {}. {:?}",
+            resolv_conf.display(),
+            e
+        ))
+    })
+}
+
+// Records that there was no resolv.conf to back up, e.g., /etc/resolv.conf.veronymous-missing
+fn missing_marker(backup: &Path) -> PathBuf {
+    let mut marker = backup.as_os_str().to_owned();
+    marker.push("-missing");
+
+    PathBuf::from(marker)
+}
+
+pub fn resolv_conf_content(servers: &[IpAddr]) -> String {
+    let mut content = "# Generated by veronymous-vpn. Restored on disconnect.\n".to_string();
+
+    for server in servers {
+        content.push_str(&format!("nameserver {}\n", server));
+    }
+
+    content
+}
+
+fn resolvectl(args: &[&str]) -> Result<(), CliClientError> {
+    let output = Command::new("resolvectl")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| DnsError(format!("Could not run resolvectl. {:?}", e)))?;
+
+    if !output.status.success() {
+        return Err(DnsError(format!(
+            "resolvectl {} failed. {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wg::dns::{replace_resolv_conf, restore_resolv_conf};
+    use std::fs;
+
+    #[test]
+    fn test_replace_and_restore_resolv_conf() {
+        let dir = std::env::temp_dir().join(format!("veronymous-dns-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let resolv_conf = dir.join("resolv.conf");
+        let backup = dir.join("resolv.conf.veronymous");
+        fs::write(&resolv_conf, "nameserver 192.168.1.1\n").unwrap();
+
+        let servers = ["10.64.0.1".parse().unwrap(), "fd00:64::1".parse().unwrap()];
+        replace_resolv_conf(&resolv_conf, &backup, &servers).unwrap();
+
+        let content = fs::read_to_string(&resolv_conf).unwrap();
+        assert!(content.ends_with("nameserver 10.64.0.1\nnameserver fd00:64::1\n"));
+
+        // The original is kept on reconfiguration
+        replace_resolv_conf(&resolv_conf, &backup, &servers[..1]).unwrap();
+        assert_eq!(
+            "nameserver 192.168.1.1\n",
+            fs::read_to_string(&backup).unwrap()
+        );
+
+        restore_resolv_conf(&resolv_conf, &backup).unwrap();
+        assert_eq!(
+            "nameserver 192.168.1.1\n",
+            fs::read_to_string(&resolv_conf).unwrap()
+        );
+        assert!(!backup.exists());
+
+        // Nothing to restore
+        restore_resolv_conf(&resolv_conf, &backup).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore_missing_resolv_conf() {
+        let dir =
+            std::env::temp_dir().join(format!("veronymous-dns-missing-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let resolv_conf = dir.join("resolv.conf");
+        let backup = dir.join("resolv.conf.veronymous");
+
+        let servers = ["10.64.0.1".parse().unwrap()];
+        replace_resolv_conf(&resolv_conf, &backup, &servers).unwrap();
+        // Reconfiguration
+        replace_resolv_conf(&resolv_conf, &backup, &servers).unwrap();
+        assert!(resolv_conf.exists());
+
+        // The generated file is deleted
+        restore_resolv_conf(&resolv_conf, &backup).unwrap();
+        assert!(!resolv_conf.exists());
+        assert_eq!(0, fs::read_dir(&dir).unwrap().count());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::FirewallError;
+use crate::wg::backend::check_interface_name;
+use std::io::{ErrorKind, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Command, Stdio};
+
+// nftables table holding the kill switch rules
+const KILL_SWITCH_TABLE: &str = "veronymous";
+
+const LAN_IPV4: &str =
+    "10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16, 169.254.0.0/16, 224.0.0.0/4, 255.255.255.255";
+const LAN_IPV6: &str = "fe80::/10, fc00::/7, ff00::/8";
+
+pub struct KillSwitchRules<'a> {
+    // The tunnel interfaces
+    pub interfaces: &'a [String],
+
+    pub endpoint: SocketAddr,
+
+    // Reached outside of the tunnel (e.g., the token issuer)
+    pub hosts: &'a [IpAddr],
+
+    pub allow_lan: bool,
+
+    // Networks excluded from the tunnel (split tunnel)
+    pub excluded: &'a [(IpAddr, u8)],
+}
+
+/*
+* Drop all egress traffic except through the tunnel, to the wireguard endpoint and to the hosts.
+* Replaces the existing kill switch atomically so there is no gap between the rule sets.
+*/
+pub fn install_kill_switch(rules: &KillSwitchRules) -> Result<(), CliClientError> {
+    // Spliced into the script
+    for interface in rules.interfaces {
+        check_interface_name(interface)?;
+    }
+
+    run_nft(&kill_switch_ruleset(rules)).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => {
+            FirewallError("Could not install the kill switch. nft is not installed.".to_string())
+        }
+        _ => FirewallError(format!("Could not install the kill switch. {:?}", e)),
+    })
+}
+
+pub fn remove_kill_switch() -> Result<(), CliClientError> {
+    match run_nft(&delete_table_script()) {
+        Ok(_) => Ok(()),
+        // Without nftables there is no kill switch to remove
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(FirewallError(format!(
+            "Could not remove the kill switch. {:?}",
+            e
+        ))),
+    }
+}
+
+// Declaring the table first makes the delete succeed if it does not exist
+fn delete_table_script() -> String {
+    format!(
+        "table inet {table}\ndelete table inet {table}\n",
+        table = KILL_SWITCH_TABLE
+    )
+}
+
+fn kill_switch_ruleset(rules: &KillSwitchRules) -> String {
+    let mut chain = vec![
+        "type filter hook output priority 0; policy drop;".to_string(),
+        "oifname \"lo\" accept".to_string(),
+        format!(
+            "oifname {{ {} }} accept",
+            quoted_interfaces(rules.interfaces)
+        ),
+        format!(
+            "{} udp dport {} accept",
+            daddr(&rules.endpoint.ip()),
+            rules.endpoint.port()
+        ),
+    ];
+
+    for host in rules.hosts {
+        chain.push(format!("{} accept", daddr(host)));
+    }
+
+    for (network, prefix_len) in rules.excluded {
+        chain.push(format!("{}/{} accept", daddr(network), prefix_len));
+    }
+
+    // Keep the lease and the neighbors of the physical interface
+    chain.push("udp dport { 67, 547 } accept".to_string());
+    chain.push(
+        "icmpv6 type { nd-router-solicit, nd-neighbor-solicit, nd-neighbor-advert } accept"
+            .to_string(),
+    );
+
+    if rules.allow_lan {
+        chain.push(format!("ip daddr {{ {} }} accept", LAN_IPV4));
+        chain.push(format!("ip6 daddr {{ {} }} accept", LAN_IPV6));
+    }
+
+    let chain: Vec<String> = chain.iter().map(|rule| format!("\t\t{}\n", rule)).collect();
+
+    format!(
+        "{}table inet {} {{\n\tchain output {{\n{}\t}}\n}}\n",
+        delete_table_script(),
+        KILL_SWITCH_TABLE,
+        chain.concat()
+    )
+}
+
+fn quoted_interfaces(interfaces: &[String]) -> String {
+    interfaces
+        .iter()
+        .map(|interface| format!("\"{}\"", interface))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn daddr(address: &IpAddr) -> String {
+    match address {
+        IpAddr::V4(address) => format!("ip daddr {}", address),
+        IpAddr::V6(address) => format!("ip6 daddr {}", address),
+    }
+}
+
+// Applies the script in a single transaction
+fn run_nft(script: &str) -> std::io::Result<()> {
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(script.as_bytes())?;
+    }
+
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(std::io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wg::backend::check_interface_name;
+    use crate::wg::firewall::{install_kill_switch, kill_switch_ruleset, KillSwitchRules};
+
+    #[test]
+    fn test_kill_switch_ruleset() {
+        let hosts = ["10.1.0.1".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+
+        let ruleset = kill_switch_ruleset(&KillSwitchRules {
+            interfaces: &["veron0".to_string(), "veron0r".to_string()],
+            endpoint: "[2001:db8::2]:51820".parse().unwrap(),
+            hosts: &hosts,
+            allow_lan: false,
+            excluded: &[],
+        });
+
+        assert_eq!(
+            "table inet veronymous\n\
+            delete table inet veronymous\n\
+            table inet veronymous {\n\
+            \tchain output {\n\
+            \t\ttype filter hook output priority 0; policy drop;\n\
+            \t\toifname \"lo\" accept\n\
+            \t\toifname { \"veron0\", \"veron0r\" } accept\n\
+            \t\tip6 daddr 2001:db8::2 udp dport 51820 accept\n\
+            \t\tip daddr 10.1.0.1 accept\n\
+            \t\tip6 daddr 2001:db8::1 accept\n\
+            \t\tudp dport { 67, 547 } accept\n\
+            \t\ticmpv6 type { nd-router-solicit, nd-neighbor-solicit, nd-neighbor-advert } accept\n\
+            \t}\n\
+            }\n",
+            ruleset
+        );
+    }
+
+    #[test]
+    fn test_kill_switch_allow_lan() {
+        let ruleset = kill_switch_ruleset(&KillSwitchRules {
+            interfaces: &["veron0".to_string()],
+            endpoint: "1.2.3.4:51820".parse().unwrap(),
+            hosts: &[],
+            allow_lan: true,
+            excluded: &[],
+        });
+
+        assert!(ruleset.contains("\t\tip daddr 1.2.3.4 udp dport 51820 accept\n"));
+        assert!(ruleset.contains("\t\tip daddr { 10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16, 169.254.0.0/16, 224.0.0.0/4, 255.255.255.255 } accept\n"));
+        assert!(ruleset.contains("\t\tip6 daddr { fe80::/10, fc00::/7, ff00::/8 } accept\n"));
+    }
+
+    #[test]
+    fn test_kill_switch_excluded_networks() {
+        let excluded = [
+            ("198.51.100.0".parse().unwrap(), 24),
+            ("2001:db8:1::".parse().unwrap(), 48),
+        ];
+
+        let ruleset = kill_switch_ruleset(&KillSwitchRules {
+            interfaces: &["veron0".to_string()],
+            endpoint: "1.2.3.4:51820".parse().unwrap(),
+            hosts: &[],
+            allow_lan: false,
+            excluded: &excluded,
+        });
+
+        assert!(ruleset.contains("\t\tip daddr 198.51.100.0/24 accept\n"));
+        assert!(ruleset.contains("\t\tip6 daddr 2001:db8:1::/48 accept\n"));
+    }
+
+    #[test]
+    fn test_check_interface_name() {
+        assert!(check_interface_name("veron0").is_ok());
+        assert!(check_interface_name("wg_home.1-a").is_ok());
+        assert!(check_interface_name("fifteen-chars15").is_ok());
+
+        assert!(check_interface_name("").is_err());
+        assert!(check_interface_name(".").is_err());
+        assert!(check_interface_name("..").is_err());
+        assert!(check_interface_name("../etc").is_err());
+        assert!(check_interface_name("sixteen-chars-16").is_err());
+        assert!(check_interface_name("veron0\" }").is_err());
+    }
+
+    #[test]
+    fn test_kill_switch_rejects_invalid_interface() {
+        // Refused before nft runs
+        let result = install_kill_switch(&KillSwitchRules {
+            interfaces: &["veron0\" accept; }".to_string()],
+            endpoint: "1.2.3.4:51820".parse().unwrap(),
+            hosts: &[],
+            allow_lan: false,
+            excluded: &[],
+        });
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use crate::error::CliClientError;
+use crate::wg::backend::{
+    plan_down, plan_reconfigure, plan_recover, plan_refresh, plan_retire, plan_rotate, plan_up,
+    wait_for_handshake, RoutingOptions, TunnelBackend, TunnelHealth, TunnelInterfaces,
+    TunnelOperation, TunnelStatus,
+};
+use crate::wg::network::{apply_network_operation, resolve_endpoint};
+use crate::wg::wireguard;
+use crate::wg::wireguard::WgPeer;
+use crate::wg::{namespace, netlink};
+use neli::router::synchronous::NlRouter;
+use std::sync::Mutex;
+use veronymous_client::client::state::VpnConnection;
+
+/*
+* Kernel wireguard interface configured over netlink.
+* Requires CAP_NET_ADMIN.
+*/
+pub struct KernelBackend {
+    interfaces: Mutex<TunnelInterfaces>,
+}
+
+impl KernelBackend {
+    pub fn new(interface: String) -> Self {
+        Self {
+            interfaces: Mutex::new(TunnelInterfaces::new(interface)),
+        }
+    }
+
+    // The interface is moved to the network namespace
+    pub fn in_namespace(interface: String, namespace: String) -> Self {
+        Self {
+            interfaces: Mutex::new(TunnelInterfaces::in_namespace(interface, namespace)),
+        }
+    }
+
+    /*
+     * The operations apply to the active interface until another one is selected,
+     * in the host's namespace until another one is selected.
+     */
+    fn execute(
+        &self,
+        interfaces: &TunnelInterfaces,
+        operations: &[TunnelOperation],
+    ) -> Result<(), CliClientError> {
+        let mut router = netlink::connect()?;
+        let mut interface = interfaces.active.clone();
+        let mut in_namespace = None;
+
+        for operation in operations {
+            match operation {
+                TunnelOperation::SelectInterface {
+                    interface: selected,
+                } => {
+                    interface = selected.clone();
+                }
+                TunnelOperation::SelectNamespace {
+                    namespace: selected,
+                } => {
+                    router = namespace::router(selected.as_deref())?;
+                    in_namespace = selected.clone();
+                }
+                operation => {
+                    self.execute_operation(&router, &interface, in_namespace.as_deref(), operation)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The wireguard netlink requests are sent from the interface's namespace
+    fn execute_operation(
+        &self,
+        router: &NlRouter,
+        interface: &str,
+        in_namespace: Option<&str>,
+        operation: &TunnelOperation,
+    ) -> Result<(), CliClientError> {
+        match operation {
+            TunnelOperation::CreateInterface => {
+                netlink::create_wireguard_link(router, interface)?;
+            }
+            TunnelOperation::ConfigureDevice {
+                private_key,
+                peer_public_key,
+                endpoint,
+                allowed_ips,
+                persistent_keepalive,
+            } => {
+                let peer = WgPeer {
+                    public_key: peer_public_key,
+                    endpoint: resolve_endpoint(endpoint)?,
+                    allowed_ips,
+                    persistent_keepalive: *persistent_keepalive,
+                };
+
+                namespace::run_in(in_namespace, || {
+                    wireguard::configure_device(interface, private_key, &peer)
+                })?;
+            }
+            TunnelOperation::UpdateEndpoint {
+                peer_public_key,
+                endpoint,
+            } => {
+                let endpoint = resolve_endpoint(endpoint)?;
+
+                namespace::run_in(in_namespace, || {
+                    wireguard::set_peer_endpoint(interface, peer_public_key, &endpoint)
+                })?;
+            }
+            TunnelOperation::SetFwmark { fwmark } => {
+                namespace::run_in(in_namespace, || wireguard::set_fwmark(interface, *fwmark))?;
+            }
+            TunnelOperation::WaitForHandshake => {
+                namespace::run_in(in_namespace, || {
+                    wait_for_handshake(|| {
+                        let stats = wireguard::peer_stats(interface)?;
+
+                        Ok(stats.and_then(|stats| stats.last_handshake).is_some())
+                    })
+                })?;
+            }
+            operation => apply_network_operation(router, interface, operation)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl TunnelBackend for KernelBackend {
+    fn up(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(&interfaces, &plan_up(connection, options, &interfaces)?)
+    }
+
+    fn reconfigure(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(
+            &interfaces,
+            &plan_reconfigure(connection, options, &interfaces)?,
+        )
+    }
+
+    fn rotate(
+        &self,
+        previous: &VpnConnection,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        let operations = plan_rotate(previous, connection, options, &interfaces)?;
+
+        if let Err(e) = self.execute(&interfaces, &operations) {
+            // The active tunnel keeps the traffic
+            if let Err(e) = self.execute(&interfaces, &plan_retire(options, &interfaces)) {
+                warn!("{:?}", e);
+            }
+
+            return Err(e);
+        }
+
+        interfaces.swap();
+
+        Ok(())
+    }
+
+    fn retire_previous(&self, options: &RoutingOptions) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(&interfaces, &plan_retire(options, &interfaces))
+    }
+
+    fn refresh(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(
+            &interfaces,
+            &plan_refresh(connection, options, &interfaces)?,
+        )
+    }
+
+    fn down(&self) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(&interfaces, &plan_down(&interfaces))
+    }
+
+    fn recover(&self) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(&interfaces, &plan_recover(&interfaces))
+    }
+
+    fn status(&self) -> Result<TunnelStatus, CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap().clone();
+
+        if let Some(namespace) = &interfaces.namespace {
+            if !namespace::namespace_exists(namespace) {
+                return Ok(TunnelStatus::Down);
+            }
+        }
+
+        let router = namespace::router(interfaces.namespace.as_deref())?;
+        let interface = interfaces.active;
+
+        match netlink::link_index(&router, &interface)? {
+            None => Ok(TunnelStatus::Down),
+            Some(_) => Ok(TunnelStatus::Up { interface }),
+        }
+    }
+
+    fn health(&self) -> Result<Option<TunnelHealth>, CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap().clone();
+
+        namespace::run_in(interfaces.namespace.as_deref(), || {
+            wireguard::peer_stats(&interfaces.active)
+        })
+    }
+}
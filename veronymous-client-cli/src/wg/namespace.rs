@@ -0,0 +1,349 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::NamespaceError;
+use crate::wg::backend::check_interface_name;
+use crate::wg::{dns, netlink};
+use neli::router::synchronous::NlRouter;
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::net::IpAddr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr;
+use std::thread;
+
+// Where the named namespaces are bind mounted. Same as iproute2, so "ip netns" lists them.
+const NETNS_DIR: &str = "/run/netns";
+
+// The namespaces' resolv.conf. Bind mounted over /etc/resolv.conf for the exec command.
+const NETNS_ETC_DIR: &str = "/etc/netns";
+
+// MTU of the loopback interface
+const LOOPBACK_MTU: u32 = 65536;
+
+/*
+* Named network namespace holding the tunnel interface of the exec command.
+* Created by a thread that leaves the current namespace and bind mounts the new one.
+* Replaces the existing namespace. The loopback interface is brought up.
+*/
+pub fn create_namespace(namespace: &str) -> Result<(), CliClientError> {
+    delete_namespace(namespace)?;
+
+    let path = namespace_path(namespace);
+
+    fs::create_dir_all(NETNS_DIR)
+        .and_then(|_| OpenOptions::new().write(true).create_new(true).open(&path))
+        .map_err(|e| NamespaceError(format!("Could not create {}. {:?}", path.display(), e)))?;
+
+    if let Err(e) = mount_namespace(namespace, &path) {
+        // Not mounted
+        let _ = fs::remove_file(&path);
+        return Err(e);
+    }
+
+    run_in(Some(namespace), || {
+        let router = netlink::connect()?;
+
+        match netlink::link_index(&router, "lo")? {
+            Some(index) => netlink::set_link_up(&router, index, LOOPBACK_MTU),
+            None => Err(NamespaceError(format!(
+                "Namespace {} has no loopback interface.",
+                namespace
+            ))),
+        }
+    })
+}
+
+// Deletes the namespace's interfaces and resolv.conf. Succeeds if it does not exist.
+pub fn delete_namespace(namespace: &str) -> Result<(), CliClientError> {
+    // The directory is removed as root
+    check_interface_name(namespace)?;
+
+    let path = namespace_path(namespace);
+
+    if path.exists() {
+        let target = c_path(&path)?;
+
+        // EINVAL if it is not mounted (e.g., the creation failed)
+        if unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } != 0 {
+            let error = io::Error::last_os_error();
+
+            if error.raw_os_error() != Some(libc::EINVAL) {
+                return Err(NamespaceError(format!(
+                    "Could not unmount namespace {}. {:?}",
+                    namespace, error
+                )));
+            }
+        }
+
+        fs::remove_file(&path)
+            .map_err(|e| NamespaceError(format!("Could not remove {}. {:?}", path.display(), e)))?;
+    }
+
+    let etc_dir = Path::new(NETNS_ETC_DIR).join(namespace);
+
+    if etc_dir.exists() {
+        fs::remove_dir_all(&etc_dir).map_err(|e| {
+            NamespaceError(format!("Could not remove {}. {:?}", etc_dir.display(), e))
+        })?;
+    }
+
+    Ok(())
+}
+
+pub fn namespace_exists(namespace: &str) -> bool {
+    namespace_path(namespace).exists()
+}
+
+// The interface keeps the sockets it was created with (e.g., the wireguard socket)
+pub fn move_link(router: &NlRouter, index: i32, namespace: &str) -> Result<(), CliClientError> {
+    let namespace_file = open_namespace(namespace)?;
+
+    netlink::set_link_namespace(router, index, namespace_file.as_raw_fd())
+}
+
+pub fn configure_dns(namespace: &str, servers: &[IpAddr]) -> Result<(), CliClientError> {
+    let etc_dir = Path::new(NETNS_ETC_DIR).join(namespace);
+
+    fs::create_dir_all(&etc_dir)
+        .and_then(|_| {
+            fs::write(
+                etc_dir.join("resolv.conf"),
+                dns::resolv_conf_content(servers),
+            )
+        })
+        .map_err(|e| {
+            NamespaceError(format!(
+                "Could not write the resolv.conf of {}. {:?}",
+                namespace, e
+            ))
+        })
+}
+
+/*
+* Run the function in the namespace, or in the current one if None.
+* The sockets it opens stay in the namespace (e.g., a netlink router).
+*/
+pub fn run_in<T, F>(namespace: Option<&str>, function: F) -> Result<T, CliClientError>
+where
+    T: Send,
+    F: FnOnce() -> Result<T, CliClientError> + Send,
+{
+    let namespace = match namespace {
+        None => return function(),
+        Some(namespace) => namespace,
+    };
+
+    let namespace_file = open_namespace(namespace)?;
+
+    // Only the spawned thread enters the namespace
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                if unsafe { libc::setns(namespace_file.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+                    return Err(NamespaceError(format!(
+                        "Could not enter namespace {}. {:?}",
+                        namespace,
+                        io::Error::last_os_error()
+                    )));
+                }
+
+                function()
+            })
+            .join()
+            .map_err(|_| NamespaceError(format!("Failed in namespace {}.", namespace)))?
+    })
+}
+
+// rtnetlink socket in the namespace
+pub fn router(namespace: Option<&str>) -> Result<NlRouter, CliClientError> {
+    run_in(namespace, netlink::connect)
+}
+
+/*
+* The command run in the namespace, with the namespace's resolv.conf.
+* Run as the user who invoked sudo instead of root.
+*/
+pub fn exec_command(namespace: &str, args: &[String]) -> Result<Command, CliClientError> {
+    // Prepared before the fork. The child only makes system calls.
+    let namespace_file = open_namespace(namespace)?;
+
+    let resolv_conf = Path::new(NETNS_ETC_DIR).join(namespace).join("resolv.conf");
+    let resolv_conf = match resolv_conf.exists() {
+        true => Some((
+            c_path(&resolv_conf)?,
+            c_path(Path::new("/etc/resolv.conf"))?,
+        )),
+        false => None,
+    };
+
+    let user = sudo_user()?;
+
+    let mut command = Command::new(&args[0]);
+    command.args(&args[1..]);
+
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setns(namespace_file.as_raw_fd(), libc::CLONE_NEWNET) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if let Some((source, target)) = &resolv_conf {
+                // Private mounts. The bind mount is not seen outside the command.
+                if libc::unshare(libc::CLONE_NEWNS) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                let root = b"/\0".as_ptr() as *const libc::c_char;
+
+                if libc::mount(
+                    ptr::null(),
+                    root,
+                    ptr::null(),
+                    libc::MS_SLAVE | libc::MS_REC,
+                    ptr::null(),
+                ) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+
+                if libc::mount(
+                    source.as_ptr(),
+                    target.as_ptr(),
+                    ptr::null(),
+                    libc::MS_BIND,
+                    ptr::null(),
+                ) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if let Some(user) = &user {
+                if libc::setgroups(user.groups.len(), user.groups.as_ptr()) != 0
+                    || libc::setgid(user.gid) != 0
+                    || libc::setuid(user.uid) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+
+    Ok(command)
+}
+
+// The user who invoked sudo and their groups
+struct SudoUser {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    groups: Vec<libc::gid_t>,
+}
+
+fn sudo_user() -> Result<Option<SudoUser>, CliClientError> {
+    let (uid, gid, name) = match (
+        env::var("SUDO_UID"),
+        env::var("SUDO_GID"),
+        env::var("SUDO_USER"),
+    ) {
+        (Ok(uid), Ok(gid), Ok(name)) => (uid, gid, name),
+        _ => return Ok(None),
+    };
+
+    let uid = uid
+        .parse()
+        .map_err(|e| NamespaceError(format!("Invalid SUDO_UID {}. {:?}", uid, e)))?;
+    let gid = gid
+        .parse()
+        .map_err(|e| NamespaceError(format!("Invalid SUDO_GID {}. {:?}", gid, e)))?;
+    let name = CString::new(name.clone())
+        .map_err(|e| NamespaceError(format!("Invalid SUDO_USER {}. {:?}", name, e)))?;
+
+    // The group count is returned if the list is too small
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+
+    loop {
+        let mut count = groups.len() as libc::c_int;
+
+        let result =
+            unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+
+        if result >= 0 {
+            groups.truncate(count as usize);
+            break;
+        }
+
+        if count as usize <= groups.len() {
+            return Err(NamespaceError(format!(
+                "Could not get the groups of {:?}.",
+                name
+            )));
+        }
+
+        groups.resize(count as usize, 0);
+    }
+
+    Ok(Some(SudoUser { uid, gid, groups }))
+}
+
+// Bind mounts the network namespace of a thread that left the current one
+fn mount_namespace(namespace: &str, path: &Path) -> Result<(), CliClientError> {
+    let target = c_path(path)?;
+
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 {
+                    return Err(NamespaceError(format!(
+                        "Could not create namespace {}. {:?}",
+                        namespace,
+                        io::Error::last_os_error()
+                    )));
+                }
+
+                let source = b"/proc/thread-self/ns/net\0".as_ptr() as *const libc::c_char;
+
+                if unsafe {
+                    libc::mount(
+                        source,
+                        target.as_ptr(),
+                        ptr::null(),
+                        libc::MS_BIND,
+                        ptr::null(),
+                    )
+                } != 0
+                {
+                    return Err(NamespaceError(format!(
+                        "Could not mount namespace {}. {:?}",
+                        namespace,
+                        io::Error::last_os_error()
+                    )));
+                }
+
+                Ok(())
+            })
+            .join()
+            .map_err(|_| NamespaceError(format!("Could not create namespace {}.", namespace)))?
+    })
+}
+
+fn open_namespace(namespace: &str) -> Result<File, CliClientError> {
+    File::open(namespace_path(namespace))
+        .map_err(|e| NamespaceError(format!("Could not open namespace {}. {:?}", namespace, e)))
+}
+
+fn namespace_path(namespace: &str) -> PathBuf {
+    Path::new(NETNS_DIR).join(namespace)
+}
+
+fn c_path(path: &Path) -> Result<CString, CliClientError> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| NamespaceError(format!("Invalid path {}. {:?}", path.display(), e)))
+}
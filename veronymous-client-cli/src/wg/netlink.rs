@@ -0,0 +1,771 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::NetlinkError;
+use crate::wg::backend::RT_TABLE_MAIN;
+use neli::consts::nl::{NlType, NlmF};
+use neli::consts::rtnl::{
+    Ifa, Ifla, IflaInfo, RtAddrFamily, RtScope, RtTable, Rta, RtaType, Rtm, Rtn, Rtprot,
+};
+use neli::consts::socket::NlFamily;
+use neli::err::RouterError;
+use neli::nl::NlPayload;
+use neli::router::synchronous::NlRouter;
+use neli::rtnl::{
+    Ifaddrmsg, IfaddrmsgBuilder, Ifinfomsg, IfinfomsgBuilder, Rtattr, RtattrBuilder, Rtmsg,
+    RtmsgBuilder,
+};
+use neli::types::{Buffer, RtBuffer};
+use neli::utils::Groups;
+use neli::{FromBytesWithInput, Header, Size, ToBytes};
+use std::fmt::Debug;
+use std::net::IpAddr;
+use std::os::unix::io::RawFd;
+
+const WIREGUARD_KIND: &str = "wireguard\0";
+
+// linux/fib_rules.h
+const FRA_DST: u16 = 1;
+const FRA_SRC: u16 = 2;
+const FRA_PRIORITY: u16 = 6;
+const FRA_FWMARK: u16 = 10;
+const FRA_SUPPRESS_PREFIXLEN: u16 = 14;
+const FRA_TABLE: u16 = 15;
+const FIB_RULE_INVERT: u32 = 0x2;
+const FR_ACT_TO_TBL: u8 = 1;
+
+/*
+* Without a priority the kernel assigns a new one to every rule, so the existing rule is not detected.
+* The source rules, the exclude rules and then the suppress prefix rule are looked up
+* before the fwmark rule.
+*/
+const SOURCE_RULE_PRIORITY: u32 = 32762;
+const EXCLUDE_RULE_PRIORITY: u32 = 32763;
+const SUPPRESS_PREFIX_RULE_PRIORITY: u32 = 32764;
+const FWMARK_RULE_PRIORITY: u32 = 32765;
+
+/*
+* struct fib_rule_hdr (linux/fib_rules.h)
+* Header of the routing policy rule messages. Not provided by neli.
+*/
+#[derive(Clone, Debug, Size, ToBytes, FromBytesWithInput, Header)]
+struct FibRuleHdr {
+    family: u8,
+    dst_len: u8,
+    src_len: u8,
+    tos: u8,
+    table: u8,
+    res1: u8,
+    res2: u8,
+    action: u8,
+    flags: u32,
+    #[neli(
+        input = "input.checked_sub(Self::header_size()).ok_or(neli::err::DeError::InvalidInput(input))?"
+    )]
+    rtattrs: RtBuffer<u16, Buffer>,
+}
+
+/*
+* Where the traffic to an address goes: the interface, gateway and source address of its route.
+* Raw attribute payloads, only compared.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoutePath {
+    pub interface: Option<Vec<u8>>,
+
+    pub gateway: Option<Vec<u8>>,
+
+    pub source: Option<Vec<u8>>,
+}
+
+// Failed netlink request with the kernel's errno if there is one
+struct RequestError {
+    errno: Option<i32>,
+
+    message: String,
+}
+
+pub fn connect() -> Result<NlRouter, CliClientError> {
+    let (router, _) = NlRouter::connect(NlFamily::Route, None, Groups::empty())
+        .map_err(|e| NetlinkError(format!("Could not open rtnetlink socket. {:?}", e)))?;
+
+    Ok(router)
+}
+
+// Index of the named interface. None if it does not exist.
+pub fn link_index(router: &NlRouter, name: &str) -> Result<Option<i32>, CliClientError> {
+    let mut attrs = RtBuffer::new();
+    attrs.push(attr(Ifla::Ifname, format!("{}\0", name))?);
+
+    let message = IfinfomsgBuilder::default()
+        .ifi_family(RtAddrFamily::Unspecified)
+        .rtattrs(attrs)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build link request. {:?}", e)))?;
+
+    let responses = router
+        .send::<_, _, Rtm, Ifinfomsg>(Rtm::Getlink, NlmF::empty(), NlPayload::Payload(message))
+        .map_err(|e| NetlinkError(format!("Could not get link {}. {:?}", name, e)))?;
+
+    for response in responses {
+        match response {
+            Ok(response) => {
+                if let NlPayload::Payload(link) = response.nl_payload() {
+                    return Ok(Some(*link.ifi_index()));
+                }
+            }
+            Err(e) if errno(&e) == Some(libc::ENODEV) => return Ok(None),
+            Err(e) => {
+                return Err(NetlinkError(format!(
+                    "Could not get link {}. {:?}",
+                    name, e
+                )))
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+pub fn create_wireguard_link(router: &NlRouter, name: &str) -> Result<(), CliClientError> {
+    let mut link_info = RtBuffer::new();
+    link_info.push(attr(IflaInfo::Kind, WIREGUARD_KIND)?);
+
+    let mut attrs = RtBuffer::new();
+    attrs.push(attr(Ifla::Ifname, format!("{}\0", name))?);
+    attrs.push(attr(Ifla::Linkinfo, link_info)?);
+
+    let message = IfinfomsgBuilder::default()
+        .ifi_family(RtAddrFamily::Unspecified)
+        .rtattrs(attrs)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build link request. {:?}", e)))?;
+
+    request(router, Rtm::Newlink, NlmF::CREATE | NlmF::EXCL, message).map_err(|e| match e.errno {
+        Some(libc::EOPNOTSUPP) => NetlinkError(format!(
+            "Could not create link {}. The wireguard kernel module is not available. Use the userspace backend.",
+            name
+        )),
+        _ => NetlinkError(format!("Could not create link {}. {}", name, e.message)),
+    })
+}
+
+pub fn delete_link(router: &NlRouter, index: i32) -> Result<(), CliClientError> {
+    let message = IfinfomsgBuilder::default()
+        .ifi_family(RtAddrFamily::Unspecified)
+        .ifi_index(index)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build link request. {:?}", e)))?;
+
+    request(router, Rtm::Dellink, NlmF::empty(), message)
+        .map_err(|e| NetlinkError(format!("Could not delete link. {}", e.message)))
+}
+
+pub fn set_link_up(router: &NlRouter, index: i32, mtu: u32) -> Result<(), CliClientError> {
+    let mut attrs = RtBuffer::new();
+    attrs.push(attr(Ifla::Mtu, mtu)?);
+
+    let message = IfinfomsgBuilder::default()
+        .ifi_family(RtAddrFamily::Unspecified)
+        .ifi_index(index)
+        .up()
+        .rtattrs(attrs)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build link request. {:?}", e)))?;
+
+    request(router, Rtm::Setlink, NlmF::empty(), message)
+        .map_err(|e| NetlinkError(format!("Could not set link up. {}", e.message)))
+}
+
+// Move the interface to the network namespace (an open namespace file)
+pub fn set_link_namespace(
+    router: &NlRouter,
+    index: i32,
+    namespace_fd: RawFd,
+) -> Result<(), CliClientError> {
+    let mut attrs = RtBuffer::new();
+    attrs.push(attr(Ifla::NetNsFd, namespace_fd as u32)?);
+
+    let message = IfinfomsgBuilder::default()
+        .ifi_family(RtAddrFamily::Unspecified)
+        .ifi_index(index)
+        .rtattrs(attrs)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build link request. {:?}", e)))?;
+
+    request(router, Rtm::Setlink, NlmF::empty(), message)
+        .map_err(|e| NetlinkError(format!("Could not move link to namespace. {}", e.message)))
+}
+
+pub fn add_address(
+    router: &NlRouter,
+    index: i32,
+    address: IpAddr,
+    prefix_len: u8,
+) -> Result<(), CliClientError> {
+    let mut attrs = RtBuffer::new();
+    attrs.push(attr(Ifa::Local, ip_octets(&address))?);
+    attrs.push(attr(Ifa::Address, ip_octets(&address))?);
+
+    let message = IfaddrmsgBuilder::default()
+        .ifa_family(address_family(&address))
+        .ifa_prefixlen(prefix_len)
+        .ifa_scope(RtScope::Universe)
+        .ifa_index(index as u32)
+        .rtattrs(attrs)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build address request. {:?}", e)))?;
+
+    request(router, Rtm::Newaddr, NlmF::CREATE | NlmF::EXCL, message).map_err(|e| {
+        NetlinkError(format!(
+            "Could not add address {}/{}. {}",
+            address, prefix_len, e.message
+        ))
+    })
+}
+
+// Delete all the addresses of the interface
+pub fn flush_addresses(router: &NlRouter, index: i32) -> Result<(), CliClientError> {
+    let message = IfaddrmsgBuilder::default()
+        .ifa_family(RtAddrFamily::Unspecified)
+        .ifa_prefixlen(0)
+        .ifa_scope(RtScope::Universe)
+        .ifa_index(0)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build address request. {:?}", e)))?;
+
+    let responses = router
+        .send::<_, _, Rtm, Ifaddrmsg>(Rtm::Getaddr, NlmF::DUMP, NlPayload::Payload(message))
+        .map_err(|e| NetlinkError(format!("Could not list addresses. {:?}", e)))?;
+
+    let mut addresses = Vec::new();
+
+    for response in responses {
+        let response =
+            response.map_err(|e| NetlinkError(format!("Could not list addresses. {:?}", e)))?;
+
+        if let NlPayload::Payload(address) = response.nl_payload() {
+            if *address.ifa_index() == index as u32 {
+                addresses.push(address.clone());
+            }
+        }
+    }
+
+    for address in addresses {
+        request(router, Rtm::Deladdr, NlmF::empty(), address)
+            .map_err(|e| NetlinkError(format!("Could not delete address. {}", e.message)))?;
+    }
+
+    Ok(())
+}
+
+/*
+* Route to the network through the interface in the given table.
+* Returns false if the route already exists.
+*/
+pub fn add_route(
+    router: &NlRouter,
+    index: i32,
+    destination: IpAddr,
+    prefix_len: u8,
+    table: u32,
+) -> Result<bool, CliClientError> {
+    request_exists_ok(
+        router,
+        Rtm::Newroute,
+        interface_route(index, destination, prefix_len, table)?,
+    )
+    .map_err(|e| {
+        NetlinkError(format!(
+            "Could not add route to {}/{}. {}",
+            destination, prefix_len, e.message
+        ))
+    })
+}
+
+/*
+* Point the route of the table to the interface, in a single change.
+* Equivalent to "ip route replace <destination> dev <interface> table <table>".
+*/
+pub fn replace_route(
+    router: &NlRouter,
+    index: i32,
+    destination: IpAddr,
+    prefix_len: u8,
+    table: u32,
+) -> Result<(), CliClientError> {
+    request(
+        router,
+        Rtm::Newroute,
+        NlmF::CREATE | NlmF::REPLACE,
+        interface_route(index, destination, prefix_len, table)?,
+    )
+    .map_err(|e| {
+        NetlinkError(format!(
+            "Could not replace route to {}/{}. {}",
+            destination, prefix_len, e.message
+        ))
+    })
+}
+
+fn interface_route(
+    index: i32,
+    destination: IpAddr,
+    prefix_len: u8,
+    table: u32,
+) -> Result<Rtmsg, CliClientError> {
+    let mut attrs = RtBuffer::new();
+    if prefix_len > 0 {
+        attrs.push(attr(Rta::Dst, ip_octets(&destination))?);
+    }
+    attrs.push(attr(Rta::Oif, index as u32)?);
+    attrs.push(attr(Rta::Table, table)?);
+
+    RtmsgBuilder::default()
+        .rtm_family(address_family(&destination))
+        .rtm_dst_len(prefix_len)
+        .rtm_src_len(0)
+        .rtm_tos(0)
+        .rtm_table(RtTable::Unspec)
+        .rtm_protocol(Rtprot::Boot)
+        .rtm_scope(RtScope::Link)
+        .rtm_type(Rtn::Unicast)
+        .rtattrs(attrs)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build route request. {:?}", e)))
+}
+
+/*
+* Pin the current route to the address in the main table.
+* Equivalent to "ip route add $(ip route get <address>)".
+* Returns false if the route already exists.
+*/
+pub fn add_current_route(router: &NlRouter, address: IpAddr) -> Result<bool, CliClientError> {
+    let current = get_route(router, address)?;
+    let current_attrs = current.rtattrs().get_attr_handle();
+
+    let gateway = current_attrs.get_attribute(Rta::Gateway);
+
+    let mut attrs = RtBuffer::new();
+    attrs.push(attr(Rta::Dst, ip_octets(&address))?);
+
+    for attr_type in [Rta::Gateway, Rta::Oif, Rta::Prefsrc] {
+        if let Some(current_attr) = current_attrs.get_attribute(attr_type) {
+            attrs.push(attr(
+                attr_type,
+                current_attr.rta_payload().as_ref().to_vec(),
+            )?);
+        }
+    }
+
+    let message = RtmsgBuilder::default()
+        .rtm_family(address_family(&address))
+        .rtm_dst_len(full_prefix_len(&address))
+        .rtm_src_len(0)
+        .rtm_tos(0)
+        .rtm_table(RtTable::Main)
+        .rtm_protocol(Rtprot::Boot)
+        .rtm_scope(match gateway {
+            Some(_) => RtScope::Universe,
+            None => RtScope::Link,
+        })
+        .rtm_type(Rtn::Unicast)
+        .rtattrs(attrs)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build route request. {:?}", e)))?;
+
+    request_exists_ok(router, Rtm::Newroute, message)
+        .map_err(|e| NetlinkError(format!("Could not add route to {}. {}", address, e.message)))
+}
+
+// Use the table for all the traffic without the fwmark (IPv4). Returns false if the rule exists.
+pub fn add_fwmark_rule(router: &NlRouter, fwmark: u32, table: u32) -> Result<bool, CliClientError> {
+    request_exists_ok(router, Rtm::Newrule, fwmark_rule(fwmark, table)?)
+        .map_err(|e| NetlinkError(format!("Could not add fwmark rule. {}", e.message)))
+}
+
+/*
+* Look up the main table for the traffic to the network, before the tunnel's rules.
+* Equivalent to "ip rule add to <destination> lookup main". Returns false if the rule exists.
+*/
+pub fn add_exclude_rule(
+    router: &NlRouter,
+    destination: IpAddr,
+    prefix_len: u8,
+) -> Result<bool, CliClientError> {
+    request_exists_ok(router, Rtm::Newrule, exclude_rule(destination, prefix_len)?).map_err(|e| {
+        NetlinkError(format!(
+            "Could not add exclude rule for {}/{}. {}",
+            destination, prefix_len, e.message
+        ))
+    })
+}
+
+pub fn delete_exclude_rule(
+    router: &NlRouter,
+    destination: IpAddr,
+    prefix_len: u8,
+) -> Result<(), CliClientError> {
+    request_missing_ok(router, Rtm::Delrule, exclude_rule(destination, prefix_len)?).map_err(|e| {
+        NetlinkError(format!(
+            "Could not delete exclude rule for {}/{}. {}",
+            destination, prefix_len, e.message
+        ))
+    })
+}
+
+/*
+* Use the table for the traffic from the address (IPv4), before the tunnel's rules.
+* Equivalent to "ip rule add from <source> lookup <table>". Returns false if the rule exists.
+*/
+pub fn add_source_rule(
+    router: &NlRouter,
+    source: IpAddr,
+    table: u32,
+) -> Result<bool, CliClientError> {
+    request_exists_ok(router, Rtm::Newrule, source_rule(source, table)?).map_err(|e| {
+        NetlinkError(format!(
+            "Could not add source rule for {}. {}",
+            source, e.message
+        ))
+    })
+}
+
+pub fn delete_source_rule(
+    router: &NlRouter,
+    source: IpAddr,
+    table: u32,
+) -> Result<(), CliClientError> {
+    request_missing_ok(router, Rtm::Delrule, source_rule(source, table)?).map_err(|e| {
+        NetlinkError(format!(
+            "Could not delete source rule for {}. {}",
+            source, e.message
+        ))
+    })
+}
+
+pub fn delete_fwmark_rule(
+    router: &NlRouter,
+    fwmark: u32,
+    table: u32,
+) -> Result<(), CliClientError> {
+    request_missing_ok(router, Rtm::Delrule, fwmark_rule(fwmark, table)?)
+        .map_err(|e| NetlinkError(format!("Could not delete fwmark rule. {}", e.message)))
+}
+
+/*
+* Look up the table but ignore its routes with a prefix length <= the given one (IPv4).
+* Keeps the main table's specific routes while ignoring its default route.
+* Returns false if the rule already exists.
+*/
+pub fn add_suppress_prefix_rule(
+    router: &NlRouter,
+    table: u32,
+    prefix_len: u32,
+) -> Result<bool, CliClientError> {
+    request_exists_ok(
+        router,
+        Rtm::Newrule,
+        suppress_prefix_rule(table, prefix_len)?,
+    )
+    .map_err(|e| NetlinkError(format!("Could not add suppress prefix rule. {}", e.message)))
+}
+
+pub fn delete_suppress_prefix_rule(
+    router: &NlRouter,
+    table: u32,
+    prefix_len: u32,
+) -> Result<(), CliClientError> {
+    request_missing_ok(
+        router,
+        Rtm::Delrule,
+        suppress_prefix_rule(table, prefix_len)?,
+    )
+    .map_err(|e| {
+        NetlinkError(format!(
+            "Could not delete suppress prefix rule. {}",
+            e.message
+        ))
+    })
+}
+
+// Deletes the route to the destination in the table. Succeeds if there is no such route.
+pub fn delete_route(
+    router: &NlRouter,
+    destination: IpAddr,
+    prefix_len: u8,
+    table: u32,
+) -> Result<(), CliClientError> {
+    let mut attrs = RtBuffer::new();
+    if prefix_len > 0 {
+        attrs.push(attr(Rta::Dst, ip_octets(&destination))?);
+    }
+    attrs.push(attr(Rta::Table, table)?);
+
+    let message = RtmsgBuilder::default()
+        .rtm_family(address_family(&destination))
+        .rtm_dst_len(prefix_len)
+        .rtm_src_len(0)
+        .rtm_tos(0)
+        .rtm_table(RtTable::Unspec)
+        .rtm_protocol(Rtprot::Unspec)
+        .rtm_scope(RtScope::Nowhere)
+        .rtm_type(Rtn::Unspec)
+        .rtattrs(attrs)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build route request. {:?}", e)))?;
+
+    request_missing_ok(router, Rtm::Delroute, message).map_err(|e| {
+        NetlinkError(format!(
+            "Could not delete route to {}/{}. {}",
+            destination, prefix_len, e.message
+        ))
+    })
+}
+
+/*
+* Path of the packets to the address carrying the fwmark.
+* Equivalent to "ip route get <address> mark <fwmark>". None if the address is unreachable.
+*/
+pub fn route_path(
+    router: &NlRouter,
+    address: IpAddr,
+    fwmark: u32,
+) -> Result<Option<RoutePath>, CliClientError> {
+    let route = match lookup_route(router, address, Some(fwmark)) {
+        Ok(route) => route,
+        Err(e) if matches!(e.errno, Some(libc::ENETUNREACH) | Some(libc::EHOSTUNREACH)) => {
+            return Ok(None)
+        }
+        Err(e) => {
+            return Err(NetlinkError(format!(
+                "Could not get route to {}. {}",
+                address, e.message
+            )))
+        }
+    };
+
+    Ok(route.map(|route| {
+        let attrs = route.rtattrs().get_attr_handle();
+        let payload = |attr_type| {
+            attrs
+                .get_attribute(attr_type)
+                .map(|attr: &Rtattr<Rta, Buffer>| attr.rta_payload().as_ref().to_vec())
+        };
+
+        RoutePath {
+            interface: payload(Rta::Oif),
+            gateway: payload(Rta::Gateway),
+            source: payload(Rta::Prefsrc),
+        }
+    }))
+}
+
+fn get_route(router: &NlRouter, address: IpAddr) -> Result<Rtmsg, CliClientError> {
+    lookup_route(router, address, None)
+        .map_err(|e| NetlinkError(format!("Could not get route to {}. {}", address, e.message)))?
+        .ok_or_else(|| NetlinkError(format!("No route to {}.", address)))
+}
+
+fn lookup_route(
+    router: &NlRouter,
+    address: IpAddr,
+    fwmark: Option<u32>,
+) -> Result<Option<Rtmsg>, RequestError> {
+    let build_error = |e: &dyn Debug| RequestError {
+        errno: None,
+        message: format!("Could not build route request. {:?}", e),
+    };
+
+    let mut attrs = RtBuffer::new();
+    attrs.push(attr(Rta::Dst, ip_octets(&address)).map_err(|e| build_error(&e))?);
+    if let Some(fwmark) = fwmark {
+        attrs.push(attr(Rta::Mark, fwmark).map_err(|e| build_error(&e))?);
+    }
+
+    let message = RtmsgBuilder::default()
+        .rtm_family(address_family(&address))
+        .rtm_dst_len(full_prefix_len(&address))
+        .rtm_src_len(0)
+        .rtm_tos(0)
+        .rtm_table(RtTable::Unspec)
+        .rtm_protocol(Rtprot::Unspec)
+        .rtm_scope(RtScope::Universe)
+        .rtm_type(Rtn::Unspec)
+        .rtattrs(attrs)
+        .build()
+        .map_err(|e| build_error(&e))?;
+
+    let responses = router
+        .send::<_, _, Rtm, Rtmsg>(Rtm::Getroute, NlmF::empty(), NlPayload::Payload(message))
+        .map_err(|e| RequestError {
+            errno: errno(&e),
+            message: format!("{:?}", e),
+        })?;
+
+    for response in responses {
+        let response = response.map_err(|e| RequestError {
+            errno: errno(&e),
+            message: format!("{:?}", e),
+        })?;
+
+        if let NlPayload::Payload(route) = response.nl_payload() {
+            return Ok(Some(route.clone()));
+        }
+    }
+
+    Ok(None)
+}
+
+fn fwmark_rule(fwmark: u32, table: u32) -> Result<FibRuleHdr, CliClientError> {
+    let mut attrs = RtBuffer::new();
+    attrs.push(attr(FRA_PRIORITY, FWMARK_RULE_PRIORITY)?);
+    attrs.push(attr(FRA_FWMARK, fwmark)?);
+    attrs.push(attr(FRA_TABLE, table)?);
+
+    Ok(fib_rule(libc::AF_INET, 0, FIB_RULE_INVERT, attrs))
+}
+
+fn suppress_prefix_rule(table: u32, prefix_len: u32) -> Result<FibRuleHdr, CliClientError> {
+    let mut attrs = RtBuffer::new();
+    attrs.push(attr(FRA_PRIORITY, SUPPRESS_PREFIX_RULE_PRIORITY)?);
+    attrs.push(attr(FRA_TABLE, table)?);
+    attrs.push(attr(FRA_SUPPRESS_PREFIXLEN, prefix_len)?);
+
+    Ok(fib_rule(libc::AF_INET, 0, 0, attrs))
+}
+
+fn exclude_rule(destination: IpAddr, prefix_len: u8) -> Result<FibRuleHdr, CliClientError> {
+    let mut attrs = RtBuffer::new();
+    attrs.push(attr(FRA_PRIORITY, EXCLUDE_RULE_PRIORITY)?);
+    if prefix_len > 0 {
+        attrs.push(attr(FRA_DST, ip_octets(&destination))?);
+    }
+    attrs.push(attr(FRA_TABLE, RT_TABLE_MAIN)?);
+
+    let family = match destination {
+        IpAddr::V4(_) => libc::AF_INET,
+        IpAddr::V6(_) => libc::AF_INET6,
+    };
+
+    Ok(fib_rule(family, prefix_len, 0, attrs))
+}
+
+fn source_rule(source: IpAddr, table: u32) -> Result<FibRuleHdr, CliClientError> {
+    let mut attrs = RtBuffer::new();
+    attrs.push(attr(FRA_PRIORITY, SOURCE_RULE_PRIORITY)?);
+    attrs.push(attr(FRA_SRC, ip_octets(&source))?);
+    attrs.push(attr(FRA_TABLE, table)?);
+
+    let family = match source {
+        IpAddr::V4(_) => libc::AF_INET,
+        IpAddr::V6(_) => libc::AF_INET6,
+    };
+
+    Ok(FibRuleHdr {
+        src_len: full_prefix_len(&source),
+        ..fib_rule(family, 0, 0, attrs)
+    })
+}
+
+fn fib_rule(family: i32, dst_len: u8, flags: u32, rtattrs: RtBuffer<u16, Buffer>) -> FibRuleHdr {
+    FibRuleHdr {
+        family: family as u8,
+        dst_len,
+        src_len: 0,
+        tos: 0,
+        // The table is set with FRA_TABLE (ids > 255)
+        table: libc::RT_TABLE_UNSPEC,
+        res1: 0,
+        res2: 0,
+        action: FR_ACT_TO_TBL,
+        flags,
+        rtattrs,
+    }
+}
+
+fn attr<T, P>(rta_type: T, payload: P) -> Result<Rtattr<T, Buffer>, CliClientError>
+where
+    T: RtaType,
+    P: Size + ToBytes,
+{
+    RtattrBuilder::default()
+        .rta_type(rta_type)
+        .rta_payload(payload)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build attribute. {:?}", e)))
+}
+
+// Send a request and wait for the kernel's acknowledgement
+fn request<T, P>(router: &NlRouter, nl_type: T, flags: NlmF, payload: P) -> Result<(), RequestError>
+where
+    T: NlType + Debug,
+    P: Size + ToBytes + Debug,
+{
+    let responses = router
+        .send::<_, _, u16, Buffer>(nl_type, flags | NlmF::ACK, NlPayload::Payload(payload))
+        .map_err(|e| RequestError {
+            errno: errno(&e),
+            message: format!("{:?}", e),
+        })?;
+
+    for response in responses {
+        response.map_err(|e| RequestError {
+            errno: errno(&e),
+            message: format!("{:?}", e),
+        })?;
+    }
+
+    Ok(())
+}
+
+// Create request that succeeds if the entry already exists. Returns false if it did.
+fn request_exists_ok<T, P>(router: &NlRouter, nl_type: T, payload: P) -> Result<bool, RequestError>
+where
+    T: NlType + Debug,
+    P: Size + ToBytes + Debug,
+{
+    match request(router, nl_type, NlmF::CREATE | NlmF::EXCL, payload) {
+        Ok(_) => Ok(true),
+        Err(e) if e.errno == Some(libc::EEXIST) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Delete request that succeeds if the entry does not exist (e.g., deleted with its interface)
+fn request_missing_ok<T, P>(router: &NlRouter, nl_type: T, payload: P) -> Result<(), RequestError>
+where
+    T: NlType + Debug,
+    P: Size + ToBytes + Debug,
+{
+    match request(router, nl_type, NlmF::empty(), payload) {
+        Err(e) if matches!(e.errno, Some(libc::ESRCH) | Some(libc::ENOENT)) => Ok(()),
+        result => result,
+    }
+}
+
+fn errno<T, P>(error: &RouterError<T, P>) -> Option<i32> {
+    match error {
+        RouterError::Nlmsgerr(error) => Some(-*error.error()),
+        _ => None,
+    }
+}
+
+fn address_family(address: &IpAddr) -> RtAddrFamily {
+    match address {
+        IpAddr::V4(_) => RtAddrFamily::Inet,
+        IpAddr::V6(_) => RtAddrFamily::Inet6,
+    }
+}
+
+fn full_prefix_len(address: &IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn ip_octets(address: &IpAddr) -> Vec<u8> {
+    match address {
+        IpAddr::V4(address) => address.octets().to_vec(),
+        IpAddr::V6(address) => address.octets().to_vec(),
+    }
+}
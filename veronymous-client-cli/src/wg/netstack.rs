@@ -0,0 +1,939 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::{IoError, ParseError, ProxyError};
+use crate::wg::backend::{
+    parse_address, RoutingOptions, TunnelBackend, TunnelHealth, TunnelStatus, WG_MTU,
+};
+use crate::wg::network::resolve_endpoint;
+use crate::wg::userspace::{key_bytes, peer_health, send};
+use boringtun::noise::{Tunn, TunnResult};
+use boringtun::x25519::{PublicKey, StaticSecret};
+use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
+use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
+use smoltcp::socket::{dns, tcp};
+use smoltcp::time::Instant;
+use smoltcp::wire::{DnsQueryType, HardwareAddress, IpAddress, IpCidr};
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::os::unix::io::AsRawFd;
+use std::sync::{mpsc as std_mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, oneshot};
+use veronymous_client::client::state::VpnConnection;
+
+// No host interface. Reported by the status.
+const INTERFACE_NAME: &str = "netstack";
+
+const MAX_PACKET_SIZE: usize = 65535;
+const TCP_BUFFER_SIZE: usize = 65535;
+
+// Chunks in flight between the stack and a proxy client
+const CHANNEL_CAPACITY: usize = 16;
+
+// Maximum wait for a datagram before servicing the sockets
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// boringtun expects the timers to be updated every 250ms
+const TIMER_INTERVAL: Duration = Duration::from_millis(250);
+
+const CONNECT_TIMEOUT: smoltcp::time::Duration = smoltcp::time::Duration::from_secs(30);
+
+const FIRST_LOCAL_PORT: u16 = 49152;
+
+pub const MAX_DNS_SERVERS: usize = 4;
+
+/*
+* Userspace wireguard (boringtun) with an in-process TCP/IP stack (smoltcp).
+* Does not create an interface or change the host routing, so it does not require root.
+* The connections are opened through a Dialer (e.g., by the local proxy).
+*/
+pub struct NetstackBackend {
+    commands: mpsc::UnboundedSender<StackCommand>,
+
+    // Used instead of the connection's resolvers if set
+    dns_servers: Vec<IpAddr>,
+
+    up: Mutex<bool>,
+}
+
+// Opens connections through the tunnel
+#[derive(Clone)]
+pub struct Dialer {
+    commands: mpsc::UnboundedSender<StackCommand>,
+}
+
+/*
+* A TCP connection through the tunnel.
+* Dropping to_remote closes the connection. from_remote is closed by the remote host.
+*/
+pub struct TcpChannel {
+    pub to_remote: mpsc::Sender<Vec<u8>>,
+
+    pub from_remote: mpsc::Receiver<Vec<u8>>,
+}
+
+enum StackCommand {
+    Configure {
+        config: StackConfig,
+        reply: std_mpsc::Sender<Result<(), CliClientError>>,
+    },
+
+    Shutdown {
+        reply: std_mpsc::Sender<()>,
+    },
+
+    Health {
+        reply: std_mpsc::Sender<Option<TunnelHealth>>,
+    },
+
+    Connect {
+        target: SocketAddr,
+        reply: oneshot::Sender<Result<TcpChannel, CliClientError>>,
+    },
+
+    Resolve {
+        name: String,
+        reply: oneshot::Sender<Result<Vec<IpAddr>, CliClientError>>,
+    },
+}
+
+struct StackConfig {
+    private_key: StaticSecret,
+
+    peer_public_key: PublicKey,
+
+    endpoint: SocketAddr,
+
+    // (address, prefix length)
+    addresses: Vec<(IpAddr, u8)>,
+
+    dns_servers: Vec<IpAddress>,
+
+    mtu: u32,
+
+    persistent_keepalive: Option<u16>,
+}
+
+impl NetstackBackend {
+    pub fn new(dns_servers: Vec<IpAddr>) -> Result<Self, CliClientError> {
+        let (commands, receiver) = mpsc::unbounded_channel();
+
+        thread::Builder::new()
+            .name("wg-netstack".to_string())
+            .spawn(move || Stack::new(receiver).run())
+            .map_err(|e| IoError(format!("Could not start the network stack. {:?}", e)))?;
+
+        Ok(Self {
+            commands,
+            dns_servers,
+            up: Mutex::new(false),
+        })
+    }
+
+    pub fn dialer(&self) -> Dialer {
+        Dialer {
+            commands: self.commands.clone(),
+        }
+    }
+
+    fn configure(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let addresses = connection
+            .client_addresses
+            .iter()
+            .map(|address| parse_address(address))
+            .collect::<Result<Vec<(IpAddr, u8)>, CliClientError>>()?;
+
+        let dns_servers = match self.dns_servers.is_empty() {
+            true => connection
+                .dns_servers
+                .iter()
+                .map(|server| {
+                    server
+                        .parse::<IpAddr>()
+                        .map_err(|e| ParseError(format!("Invalid DNS server {}. {:?}", server, e)))
+                })
+                .collect::<Result<Vec<IpAddr>, CliClientError>>()?,
+            false => self.dns_servers.clone(),
+        };
+
+        let config = StackConfig {
+            private_key: StaticSecret::from(key_bytes(&connection.client_private_key)?),
+            peer_public_key: PublicKey::from(key_bytes(&connection.wg_public_key)?),
+            endpoint: resolve_endpoint(&connection.wg_endpoint)?,
+            addresses,
+            dns_servers: dns_servers
+                .into_iter()
+                .take(MAX_DNS_SERVERS)
+                .map(IpAddress::from)
+                .collect(),
+            mtu: options.mtu(connection),
+            persistent_keepalive: options.persistent_keepalive(connection),
+        };
+
+        let (reply, result) = std_mpsc::channel();
+        send_command(&self.commands, StackCommand::Configure { config, reply })?;
+
+        result.recv().map_err(|_| stack_stopped())??;
+
+        *self.up.lock().unwrap() = true;
+
+        Ok(())
+    }
+}
+
+impl TunnelBackend for NetstackBackend {
+    fn up(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        self.configure(connection, options)
+    }
+
+    /*
+     * Replace the keys in place.
+     * The open connections are only closed if the addresses changed.
+     */
+    fn reconfigure(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        self.configure(connection, options)
+    }
+
+    fn down(&self) -> Result<(), CliClientError> {
+        let (reply, result) = std_mpsc::channel();
+        send_command(&self.commands, StackCommand::Shutdown { reply })?;
+
+        result.recv().map_err(|_| stack_stopped())?;
+
+        *self.up.lock().unwrap() = false;
+
+        Ok(())
+    }
+
+    // Nothing is left on the host
+    fn recover(&self) -> Result<(), CliClientError> {
+        Ok(())
+    }
+
+    fn status(&self) -> Result<TunnelStatus, CliClientError> {
+        match *self.up.lock().unwrap() {
+            false => Ok(TunnelStatus::Down),
+            true => Ok(TunnelStatus::Up {
+                interface: INTERFACE_NAME.to_string(),
+            }),
+        }
+    }
+
+    fn health(&self) -> Result<Option<TunnelHealth>, CliClientError> {
+        let (reply, result) = std_mpsc::channel();
+        send_command(&self.commands, StackCommand::Health { reply })?;
+
+        result.recv().map_err(|_| stack_stopped())
+    }
+}
+
+impl Dialer {
+    pub async fn connect(&self, target: SocketAddr) -> Result<TcpChannel, CliClientError> {
+        let (reply, result) = oneshot::channel();
+        send_command(&self.commands, StackCommand::Connect { target, reply })?;
+
+        result.await.map_err(|_| stack_stopped())?
+    }
+
+    // Resolves the name through the tunnel
+    pub async fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, CliClientError> {
+        let (reply, result) = oneshot::channel();
+        send_command(
+            &self.commands,
+            StackCommand::Resolve {
+                name: name.to_string(),
+                reply,
+            },
+        )?;
+
+        result.await.map_err(|_| stack_stopped())?
+    }
+}
+
+fn send_command(
+    commands: &mpsc::UnboundedSender<StackCommand>,
+    command: StackCommand,
+) -> Result<(), CliClientError> {
+    commands.send(command).map_err(|_| stack_stopped())
+}
+
+fn stack_stopped() -> CliClientError {
+    ProxyError("The network stack has stopped.".to_string())
+}
+
+fn tunnel_down() -> CliClientError {
+    ProxyError("The tunnel is not up.".to_string())
+}
+
+/*
+* Runs on its own thread. Owns the wireguard session and the smoltcp interface.
+*/
+struct Stack {
+    commands: mpsc::UnboundedReceiver<StackCommand>,
+
+    device: PacketQueue,
+
+    network: Option<Network>,
+
+    peer: Option<Peer>,
+
+    connections: Vec<Connection>,
+
+    queries: Vec<Query>,
+
+    // Local index of the next session. 24 bits.
+    next_index: u32,
+
+    next_port: u16,
+
+    last_timers: std::time::Instant,
+
+    datagram: Vec<u8>,
+
+    buffer: Vec<u8>,
+}
+
+struct Network {
+    iface: Interface,
+
+    sockets: SocketSet<'static>,
+
+    dns: SocketHandle,
+
+    addresses: Vec<(IpAddr, u8)>,
+
+    dns_servers: Vec<IpAddress>,
+}
+
+struct Peer {
+    tunnel: Box<Tunn>,
+
+    socket: UdpSocket,
+
+    endpoint: SocketAddr,
+}
+
+struct Connection {
+    handle: SocketHandle,
+
+    // Set until the connection is established
+    reply: Option<oneshot::Sender<Result<TcpChannel, CliClientError>>>,
+
+    to_remote: mpsc::Receiver<Vec<u8>>,
+
+    // Dropped when the remote host closes the connection
+    from_remote: Option<mpsc::Sender<Vec<u8>>>,
+
+    // Received from the client but not sent yet
+    pending: Vec<u8>,
+
+    // The client closed the connection
+    client_closed: bool,
+
+    // Kept until the connection is established
+    channel: Option<TcpChannel>,
+}
+
+struct Query {
+    handle: dns::QueryHandle,
+
+    reply: oneshot::Sender<Result<Vec<IpAddr>, CliClientError>>,
+}
+
+impl Stack {
+    fn new(commands: mpsc::UnboundedReceiver<StackCommand>) -> Self {
+        Self {
+            commands,
+            device: PacketQueue::new(WG_MTU),
+            network: None,
+            peer: None,
+            connections: vec![],
+            queries: vec![],
+            next_index: 0,
+            next_port: FIRST_LOCAL_PORT,
+            last_timers: std::time::Instant::now(),
+            datagram: vec![0u8; MAX_PACKET_SIZE],
+            buffer: vec![0u8; MAX_PACKET_SIZE],
+        }
+    }
+
+    fn run(mut self) {
+        loop {
+            loop {
+                match self.commands.try_recv() {
+                    Ok(command) => self.handle_command(command),
+                    Err(TryRecvError::Empty) => break,
+                    // The backend and the dialers are gone
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+
+            self.receive_datagrams();
+            self.update_timers();
+
+            if let Some(network) = &mut self.network {
+                network
+                    .iface
+                    .poll(Instant::now(), &mut self.device, &mut network.sockets);
+
+                service_connections(network, &mut self.connections);
+                service_queries(network, &mut self.queries);
+
+                network
+                    .iface
+                    .poll(Instant::now(), &mut self.device, &mut network.sockets);
+            }
+
+            self.send_packets();
+        }
+    }
+
+    fn handle_command(&mut self, command: StackCommand) {
+        match command {
+            StackCommand::Configure { config, reply } => {
+                let _ = reply.send(self.configure(config));
+            }
+            StackCommand::Shutdown { reply } => {
+                self.shutdown();
+                let _ = reply.send(());
+            }
+            StackCommand::Health { reply } => {
+                let _ = reply.send(self.peer.as_ref().map(|peer| peer_health(&peer.tunnel)));
+            }
+            StackCommand::Connect { target, reply } => match self.connect(target) {
+                Ok(connection) => self.connections.push(Connection::new(connection, reply)),
+                Err(e) => {
+                    let _ = reply.send(Err(e));
+                }
+            },
+            StackCommand::Resolve { name, reply } => match self.resolve(&name) {
+                Ok(handle) => self.queries.push(Query { handle, reply }),
+                Err(e) => {
+                    let _ = reply.send(Err(e));
+                }
+            },
+        }
+    }
+
+    fn configure(&mut self, config: StackConfig) -> Result<(), CliClientError> {
+        // Keep the socket if the endpoint's address family did not change
+        let socket = match self.peer.take() {
+            Some(peer) if peer.endpoint.is_ipv4() == config.endpoint.is_ipv4() => peer.socket,
+            _ => bind_socket(&config.endpoint)?,
+        };
+
+        self.next_index = (self.next_index + 1) & 0x00ff_ffff;
+
+        let mut tunnel = Box::new(Tunn::new(
+            config.private_key,
+            config.peer_public_key,
+            None,
+            config.persistent_keepalive,
+            self.next_index,
+            None,
+        ));
+
+        // Start the handshake without waiting for traffic
+        if let TunnResult::WriteToNetwork(datagram) =
+            tunnel.format_handshake_initiation(&mut self.buffer, false)
+        {
+            send(&socket, &config.endpoint, datagram);
+        }
+
+        self.peer = Some(Peer {
+            tunnel,
+            socket,
+            endpoint: config.endpoint,
+        });
+
+        match &mut self.network {
+            Some(network)
+                if network.addresses == config.addresses && self.device.mtu == config.mtu =>
+            {
+                network.set_dns_servers(config.dns_servers);
+            }
+            _ => {
+                // The open connections use the old addresses
+                self.close_all();
+                self.device = PacketQueue::new(config.mtu);
+                self.network = Some(Network::new(
+                    &mut self.device,
+                    config.addresses,
+                    config.dns_servers,
+                )?);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        self.close_all();
+        self.network = None;
+        self.peer = None;
+        self.device = PacketQueue::new(WG_MTU);
+    }
+
+    fn close_all(&mut self) {
+        for connection in self.connections.drain(..) {
+            if let Some(reply) = connection.reply {
+                let _ = reply.send(Err(tunnel_down()));
+            }
+        }
+
+        for query in self.queries.drain(..) {
+            let _ = query.reply.send(Err(tunnel_down()));
+        }
+    }
+
+    fn connect(&mut self, target: SocketAddr) -> Result<SocketHandle, CliClientError> {
+        let network = self.network.as_mut().ok_or_else(tunnel_down)?;
+
+        let mut socket = tcp::Socket::new(
+            tcp::SocketBuffer::new(vec![0u8; TCP_BUFFER_SIZE]),
+            tcp::SocketBuffer::new(vec![0u8; TCP_BUFFER_SIZE]),
+        );
+        socket.set_timeout(Some(CONNECT_TIMEOUT));
+
+        let local_port = self.next_port;
+        self.next_port = match self.next_port {
+            u16::MAX => FIRST_LOCAL_PORT,
+            port => port + 1,
+        };
+
+        socket
+            .connect(
+                network.iface.context(),
+                (IpAddress::from(target.ip()), target.port()),
+                local_port,
+            )
+            .map_err(|e| ProxyError(format!("Could not connect to {}. {:?}", target, e)))?;
+
+        Ok(network.sockets.add(socket))
+    }
+
+    fn resolve(&mut self, name: &str) -> Result<dns::QueryHandle, CliClientError> {
+        let network = self.network.as_mut().ok_or_else(tunnel_down)?;
+
+        if network.dns_servers.is_empty() {
+            return Err(ProxyError("No DNS servers are configured.".to_string()));
+        }
+
+        // IPv6 only if the tunnel has no IPv4 address
+        let query_type = match network
+            .addresses
+            .iter()
+            .any(|(address, _)| address.is_ipv4())
+        {
+            true => DnsQueryType::A,
+            false => DnsQueryType::Aaaa,
+        };
+
+        let socket = network.sockets.get_mut::<dns::Socket>(network.dns);
+
+        socket
+            .start_query(network.iface.context(), name, query_type)
+            .map_err(|e| ProxyError(format!("Could not resolve {}. {:?}", name, e)))
+    }
+
+    // Wait up to the poll interval for datagrams from the peer
+    fn receive_datagrams(&mut self) {
+        let peer = match &mut self.peer {
+            None => {
+                thread::sleep(POLL_INTERVAL);
+                return;
+            }
+            Some(peer) => peer,
+        };
+
+        if !wait_readable(&peer.socket, POLL_INTERVAL) {
+            return;
+        }
+
+        loop {
+            let (size, source) = match peer.socket.recv_from(&mut self.datagram) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    debug!("Could not receive from the peer. {:?}", e);
+                    return;
+                }
+            };
+
+            match peer.tunnel.decapsulate(
+                Some(source.ip()),
+                &self.datagram[..size],
+                &mut self.buffer,
+            ) {
+                TunnResult::WriteToNetwork(response) => {
+                    send(&peer.socket, &peer.endpoint, response);
+
+                    // Send the queued packets
+                    while let TunnResult::WriteToNetwork(queued) =
+                        peer.tunnel.decapsulate(None, &[], &mut self.buffer)
+                    {
+                        send(&peer.socket, &peer.endpoint, queued);
+                    }
+                }
+                TunnResult::WriteToTunnelV4(packet, _) => self.device.rx.push_back(packet.to_vec()),
+                TunnResult::WriteToTunnelV6(packet, _) => self.device.rx.push_back(packet.to_vec()),
+                TunnResult::Err(e) => debug!("Could not decapsulate datagram. {:?}", e),
+                TunnResult::Done => {}
+            }
+        }
+    }
+
+    // Encapsulate the packets from smoltcp
+    fn send_packets(&mut self) {
+        let peer = match &mut self.peer {
+            None => {
+                self.device.tx.clear();
+                return;
+            }
+            Some(peer) => peer,
+        };
+
+        while let Some(packet) = self.device.tx.pop_front() {
+            match peer.tunnel.encapsulate(&packet, &mut self.buffer) {
+                TunnResult::WriteToNetwork(datagram) => {
+                    send(&peer.socket, &peer.endpoint, datagram)
+                }
+                TunnResult::Err(e) => debug!("Could not encapsulate packet. {:?}", e),
+                _ => {}
+            }
+        }
+    }
+
+    // Handshakes and keepalives
+    fn update_timers(&mut self) {
+        if self.last_timers.elapsed() < TIMER_INTERVAL {
+            return;
+        }
+        self.last_timers = std::time::Instant::now();
+
+        if let Some(peer) = &mut self.peer {
+            match peer.tunnel.update_timers(&mut self.buffer) {
+                TunnResult::WriteToNetwork(datagram) => {
+                    send(&peer.socket, &peer.endpoint, datagram)
+                }
+                TunnResult::Err(e) => debug!("Wireguard timer error. {:?}", e),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Network {
+    fn new(
+        device: &mut PacketQueue,
+        addresses: Vec<(IpAddr, u8)>,
+        dns_servers: Vec<IpAddress>,
+    ) -> Result<Self, CliClientError> {
+        let mut config = Config::new(HardwareAddress::Ip);
+        config.random_seed = rand::random();
+
+        let mut iface = Interface::new(config, device, Instant::now());
+
+        iface.update_ip_addrs(|ip_addrs| {
+            for (address, prefix_len) in &addresses {
+                if ip_addrs
+                    .push(IpCidr::new(IpAddress::from(*address), *prefix_len))
+                    .is_err()
+                {
+                    warn!("Ignoring address {}/{}.", address, prefix_len);
+                }
+            }
+        });
+
+        // Everything goes to the peer. The gateway is not used on an IP medium.
+        for (address, _) in &addresses {
+            let result = match address {
+                IpAddr::V4(address) => iface.routes_mut().add_default_ipv4_route((*address).into()),
+                IpAddr::V6(address) => iface.routes_mut().add_default_ipv6_route((*address).into()),
+            };
+
+            result.map_err(|e| ProxyError(format!("Could not add route. {:?}", e)))?;
+        }
+
+        let mut sockets = SocketSet::new(vec![]);
+        let dns = sockets.add(dns::Socket::new(&dns_servers, vec![]));
+
+        Ok(Self {
+            iface,
+            sockets,
+            dns,
+            addresses,
+            dns_servers,
+        })
+    }
+
+    fn set_dns_servers(&mut self, dns_servers: Vec<IpAddress>) {
+        if self.dns_servers != dns_servers {
+            self.sockets
+                .get_mut::<dns::Socket>(self.dns)
+                .update_servers(&dns_servers);
+            self.dns_servers = dns_servers;
+        }
+    }
+}
+
+impl Connection {
+    fn new(
+        handle: SocketHandle,
+        reply: oneshot::Sender<Result<TcpChannel, CliClientError>>,
+    ) -> Self {
+        let (to_remote, to_remote_receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        let (from_remote_sender, from_remote) = mpsc::channel(CHANNEL_CAPACITY);
+
+        Self {
+            handle,
+            reply: Some(reply),
+            to_remote: to_remote_receiver,
+            from_remote: Some(from_remote_sender),
+            pending: vec![],
+            client_closed: false,
+            channel: Some(TcpChannel {
+                to_remote,
+                from_remote,
+            }),
+        }
+    }
+
+    // Returns false once the connection is finished
+    fn service(&mut self, socket: &mut tcp::Socket) -> bool {
+        if self.reply.is_some() {
+            return self.service_connecting(socket);
+        }
+
+        // Remote host to client
+        while socket.can_recv() {
+            let sender = match &self.from_remote {
+                None => break,
+                Some(sender) => sender,
+            };
+
+            match sender.try_reserve() {
+                Ok(permit) => match socket.recv(|data| (data.len(), data.to_vec())) {
+                    Ok(data) => permit.send(data),
+                    Err(_) => break,
+                },
+                // Wait for the client to catch up
+                Err(mpsc::error::TrySendError::Full(_)) => break,
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    socket.abort();
+                    return false;
+                }
+            }
+        }
+
+        // The remote host closed its side
+        if !socket.may_recv() && !socket.can_recv() {
+            self.from_remote = None;
+        }
+
+        // Client to remote host
+        while !self.client_closed && socket.can_send() {
+            if self.pending.is_empty() {
+                match self.to_remote.try_recv() {
+                    Ok(data) => self.pending = data,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.client_closed = true;
+                        socket.close();
+                        break;
+                    }
+                }
+            }
+
+            match socket.send_slice(&self.pending) {
+                Ok(0) | Err(_) => break,
+                Ok(size) => {
+                    self.pending.drain(..size);
+                }
+            }
+        }
+
+        socket.is_open() || self.from_remote.is_some()
+    }
+
+    fn service_connecting(&mut self, socket: &mut tcp::Socket) -> bool {
+        match socket.state() {
+            tcp::State::Established => {
+                if let (Some(reply), Some(channel)) = (self.reply.take(), self.channel.take()) {
+                    // The client is gone
+                    if reply.send(Ok(channel)).is_err() {
+                        socket.abort();
+                        return false;
+                    }
+                }
+
+                true
+            }
+            tcp::State::Closed => {
+                if let Some(reply) = self.reply.take() {
+                    let _ = reply.send(Err(ProxyError("Connection refused.".to_string())));
+                }
+
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+fn service_connections(network: &mut Network, connections: &mut Vec<Connection>) {
+    connections.retain_mut(|connection| {
+        let socket = network.sockets.get_mut::<tcp::Socket>(connection.handle);
+
+        if connection.service(socket) {
+            return true;
+        }
+
+        network.sockets.remove(connection.handle);
+
+        false
+    });
+}
+
+fn service_queries(network: &mut Network, queries: &mut Vec<Query>) {
+    let socket = network.sockets.get_mut::<dns::Socket>(network.dns);
+
+    let mut index = 0;
+    while index < queries.len() {
+        let result = match socket.get_query_result(queries[index].handle) {
+            Err(dns::GetQueryResultError::Pending) => {
+                index += 1;
+                continue;
+            }
+            Ok(addresses) => Ok(addresses
+                .iter()
+                .map(|address| IpAddr::from(*address))
+                .collect()),
+            Err(e) => Err(ProxyError(format!("Could not resolve name. {:?}", e))),
+        };
+
+        let query = queries.remove(index);
+        let _ = query.reply.send(result);
+    }
+}
+
+fn bind_socket(endpoint: &SocketAddr) -> Result<UdpSocket, CliClientError> {
+    let address = match endpoint {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+
+    let socket = UdpSocket::bind(address)
+        .map_err(|e| IoError(format!("Could not bind udp socket. {:?}", e)))?;
+
+    socket
+        .set_nonblocking(true)
+        .map_err(|e| IoError(format!("Could not set socket non-blocking. {:?}", e)))?;
+
+    Ok(socket)
+}
+
+fn wait_readable(socket: &UdpSocket, timeout: Duration) -> bool {
+    let mut poll_fd = libc::pollfd {
+        fd: socket.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) > 0 }
+}
+
+/*
+* smoltcp device. Packets from the tunnel are queued in rx, packets to the tunnel in tx.
+*/
+struct PacketQueue {
+    rx: VecDeque<Vec<u8>>,
+
+    tx: VecDeque<Vec<u8>>,
+
+    mtu: u32,
+}
+
+impl PacketQueue {
+    fn new(mtu: u32) -> Self {
+        Self {
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+            mtu,
+        }
+    }
+}
+
+struct QueueRxToken {
+    packet: Vec<u8>,
+}
+
+struct QueueTxToken<'a> {
+    tx: &'a mut VecDeque<Vec<u8>>,
+}
+
+impl Device for PacketQueue {
+    type RxToken<'a>
+        = QueueRxToken
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = QueueTxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let packet = self.rx.pop_front()?;
+
+        Some((QueueRxToken { packet }, QueueTxToken { tx: &mut self.tx }))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        Some(QueueTxToken { tx: &mut self.tx })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = Medium::Ip;
+        capabilities.max_transmission_unit = self.mtu as usize;
+
+        capabilities
+    }
+}
+
+impl RxToken for QueueRxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.packet)
+    }
+}
+
+impl<'a> TxToken for QueueTxToken<'a> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut packet = vec![0u8; len];
+        let result = f(&mut packet);
+
+        self.tx.push_back(packet);
+
+        result
+    }
+}
@@ -0,0 +1,337 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::{CommandError, NetlinkError};
+use crate::wg::backend::{TunnelOperation, RT_TABLE_MAIN};
+use crate::wg::firewall::KillSwitchRules;
+use crate::wg::routing_state::InstalledEntry;
+use crate::wg::{dns, firewall, namespace, netlink, routing_state};
+use neli::router::synchronous::NlRouter;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+
+/*
+* Host network configuration (addresses, routes and rules) shared by the backends.
+* The wireguard specific operations are executed by the backends.
+*/
+pub fn apply_network_operation(
+    router: &NlRouter,
+    interface: &str,
+    operation: &TunnelOperation,
+) -> Result<(), CliClientError> {
+    match operation {
+        TunnelOperation::InstallKillSwitch {
+            interfaces,
+            endpoint,
+            hosts,
+            allow_lan,
+            excluded,
+        } => {
+            firewall::install_kill_switch(&KillSwitchRules {
+                interfaces,
+                endpoint: resolve_endpoint(endpoint)?,
+                hosts,
+                allow_lan: *allow_lan,
+                excluded,
+            })?;
+        }
+        TunnelOperation::RemoveKillSwitch => {
+            firewall::remove_kill_switch()?;
+        }
+        TunnelOperation::ConfigureDns { servers } => {
+            dns::configure_dns(interface, servers)?;
+        }
+        TunnelOperation::RestoreDns => {
+            dns::restore_dns(interface)?;
+        }
+        TunnelOperation::AddOutOfBandRoutes { hosts } => {
+            set_out_of_band_routes(router, hosts)?;
+        }
+        TunnelOperation::RemoveOutOfBandRoutes => {
+            remove_recorded(router, |entry| {
+                matches!(entry, InstalledEntry::OutOfBandRoute { .. })
+            })?;
+        }
+        TunnelOperation::DeleteInterface => {
+            if let Some(index) = netlink::link_index(router, interface)? {
+                netlink::delete_link(router, index)?;
+            }
+        }
+        TunnelOperation::AddAddress {
+            address,
+            prefix_len,
+        } => {
+            netlink::add_address(
+                router,
+                link_index(router, interface)?,
+                *address,
+                *prefix_len,
+            )?;
+        }
+        TunnelOperation::FlushAddresses => {
+            netlink::flush_addresses(router, link_index(router, interface)?)?;
+        }
+        TunnelOperation::SetLinkUp { mtu } => {
+            netlink::set_link_up(router, link_index(router, interface)?, *mtu)?;
+        }
+        TunnelOperation::AddDefaultRoute { table } => {
+            add_route(
+                router,
+                interface,
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                0,
+                *table,
+            )?;
+        }
+        TunnelOperation::AddRoute {
+            destination,
+            prefix_len,
+            table,
+        } => {
+            add_route(router, interface, *destination, *prefix_len, *table)?;
+        }
+        TunnelOperation::AddExcludeRule {
+            destination,
+            prefix_len,
+        } => {
+            if netlink::add_exclude_rule(router, *destination, *prefix_len)? {
+                routing_state::record(InstalledEntry::ExcludeRule {
+                    destination: *destination,
+                    prefix_len: *prefix_len,
+                })?;
+            }
+        }
+        TunnelOperation::AddSourceRule { source, table } => {
+            if netlink::add_source_rule(router, *source, *table)? {
+                routing_state::record(InstalledEntry::SourceRule {
+                    source: *source,
+                    table: *table,
+                })?;
+            }
+        }
+        TunnelOperation::RemoveSourceRouting { table } => {
+            remove_recorded(router, |entry| match entry {
+                InstalledEntry::SourceRule {
+                    table: rule_table, ..
+                } => rule_table == table,
+                InstalledEntry::Route {
+                    table: route_table, ..
+                } => route_table == table,
+                _ => false,
+            })?;
+        }
+        TunnelOperation::AddFwmarkRule { fwmark, table } => {
+            if netlink::add_fwmark_rule(router, *fwmark, *table)? {
+                routing_state::record(InstalledEntry::FwmarkRule {
+                    fwmark: *fwmark,
+                    table: *table,
+                })?;
+            }
+        }
+        TunnelOperation::AddSuppressPrefixRule { table, prefix_len } => {
+            if netlink::add_suppress_prefix_rule(router, *table, *prefix_len)? {
+                routing_state::record(InstalledEntry::SuppressPrefixRule {
+                    table: *table,
+                    prefix_len: *prefix_len,
+                })?;
+            }
+        }
+        TunnelOperation::ReplaceDefaultRoute { table } => {
+            replace_route(
+                router,
+                interface,
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                0,
+                *table,
+            )?;
+        }
+        TunnelOperation::ReplaceRoute {
+            destination,
+            prefix_len,
+            table,
+        } => {
+            replace_route(router, interface, *destination, *prefix_len, *table)?;
+        }
+        TunnelOperation::RemoveRoutingConfiguration => {
+            remove_recorded(router, |_| true)?;
+        }
+        TunnelOperation::CreateNamespace { namespace } => {
+            namespace::create_namespace(namespace)?;
+        }
+        TunnelOperation::DeleteNamespace { namespace } => {
+            namespace::delete_namespace(namespace)?;
+        }
+        TunnelOperation::MoveToNamespace { namespace } => {
+            namespace::move_link(router, link_index(router, interface)?, namespace)?;
+        }
+        TunnelOperation::AddInterfaceRoute {
+            destination,
+            prefix_len,
+        } => {
+            netlink::add_route(
+                router,
+                link_index(router, interface)?,
+                *destination,
+                *prefix_len,
+                RT_TABLE_MAIN,
+            )?;
+        }
+        TunnelOperation::ConfigureNamespaceDns { namespace, servers } => {
+            namespace::configure_dns(namespace, servers)?;
+        }
+        TunnelOperation::SelectInterface { .. }
+        | TunnelOperation::SelectNamespace { .. }
+        | TunnelOperation::CreateInterface
+        | TunnelOperation::ConfigureDevice { .. }
+        | TunnelOperation::UpdateEndpoint { .. }
+        | TunnelOperation::SetFwmark { .. }
+        | TunnelOperation::WaitForHandshake => {
+            return Err(NetlinkError(format!(
+                "Not a network operation. {:?}",
+                operation
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn link_index(router: &NlRouter, interface: &str) -> Result<i32, CliClientError> {
+    match netlink::link_index(router, interface)? {
+        Some(index) => Ok(index),
+        None => Err(NetlinkError(format!(
+            "Interface {} does not exist.",
+            interface
+        ))),
+    }
+}
+
+fn add_route(
+    router: &NlRouter,
+    interface: &str,
+    destination: IpAddr,
+    prefix_len: u8,
+    table: u32,
+) -> Result<(), CliClientError> {
+    let index = link_index(router, interface)?;
+
+    if netlink::add_route(router, index, destination, prefix_len, table)? {
+        routing_state::record(InstalledEntry::Route {
+            destination,
+            prefix_len,
+            table,
+        })?;
+    }
+
+    Ok(())
+}
+
+fn replace_route(
+    router: &NlRouter,
+    interface: &str,
+    destination: IpAddr,
+    prefix_len: u8,
+    table: u32,
+) -> Result<(), CliClientError> {
+    let index = link_index(router, interface)?;
+    netlink::replace_route(router, index, destination, prefix_len, table)?;
+
+    routing_state::record(InstalledEntry::Route {
+        destination,
+        prefix_len,
+        table,
+    })
+}
+
+pub fn resolve_endpoint(endpoint: &str) -> Result<SocketAddr, CliClientError> {
+    endpoint
+        .to_socket_addrs()
+        .map_err(|e| CommandError(format!("Could not resolve {}. {:?}", endpoint, e)))?
+        .next()
+        .ok_or_else(|| CommandError(format!("Could not resolve {}.", endpoint)))
+}
+
+/*
+* Set the routes that will not use the vpn tunnel.
+* Token issuer endpoint - To prevent correlation with the auth token
+*/
+fn set_out_of_band_routes(router: &NlRouter, hosts: &[String]) -> Result<(), CliClientError> {
+    for host in hosts {
+        let addrs = host
+            .to_socket_addrs()
+            .map_err(|err| CommandError(err.to_string()))?;
+
+        for addr in addrs {
+            // Best effort. There might be no route to the address (e.g., IPv6).
+            match netlink::add_current_route(router, addr.ip()) {
+                Ok(true) => routing_state::record(InstalledEntry::OutOfBandRoute {
+                    destination: addr.ip(),
+                })?,
+                Ok(false) => {}
+                Err(e) => debug!("{:?}", e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/*
+* Remove the routes and rules recorded by the previous operations, most recent first.
+* The entries that are not selected or could not be removed are kept.
+*/
+fn remove_recorded<F>(router: &NlRouter, selected: F) -> Result<(), CliClientError>
+where
+    F: Fn(&InstalledEntry) -> bool,
+{
+    let mut remaining = vec![];
+    let mut error = None;
+
+    for entry in routing_state::take()? {
+        if !selected(&entry) {
+            remaining.push(entry);
+            continue;
+        }
+
+        let result = match &entry {
+            InstalledEntry::Route {
+                destination,
+                prefix_len,
+                table,
+            } => netlink::delete_route(router, *destination, *prefix_len, *table),
+            InstalledEntry::FwmarkRule { fwmark, table } => {
+                netlink::delete_fwmark_rule(router, *fwmark, *table)
+            }
+            InstalledEntry::SuppressPrefixRule { table, prefix_len } => {
+                netlink::delete_suppress_prefix_rule(router, *table, *prefix_len)
+            }
+            InstalledEntry::ExcludeRule {
+                destination,
+                prefix_len,
+            } => netlink::delete_exclude_rule(router, *destination, *prefix_len),
+            InstalledEntry::SourceRule { source, table } => {
+                netlink::delete_source_rule(router, *source, *table)
+            }
+            InstalledEntry::OutOfBandRoute { destination } => netlink::delete_route(
+                router,
+                *destination,
+                match destination {
+                    IpAddr::V4(_) => 32,
+                    IpAddr::V6(_) => 128,
+                },
+                RT_TABLE_MAIN,
+            ),
+        };
+
+        if let Err(e) = result {
+            warn!("{:?}", e);
+
+            remaining.push(entry);
+            error = Some(e);
+        }
+    }
+
+    routing_state::restore(remaining)?;
+
+    match error {
+        None => Ok(()),
+        Some(e) => Err(e),
+    }
+}
@@ -0,0 +1,161 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::{IoError, NetlinkError};
+use crate::wg::netlink;
+use crate::wg::netlink::RoutePath;
+use neli::consts::socket::NlFamily;
+use neli::router::synchronous::NlRouter;
+use neli::utils::Groups;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/*
+* Looked up to find the default routes. Documentation addresses, never routed specifically.
+*/
+const IPV4_PROBE_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+const IPV6_PROBE_ADDRESS: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// Longer than the scheduling delays and small clock adjustments
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetworkEvent {
+    // A link, address or route changed. Includes the tunnel's own changes.
+    Changed,
+
+    // The host was suspended
+    Resumed { suspended: Duration },
+}
+
+/*
+* The host's default routes, taken by the tunnel's own packets (marked with the fwmark).
+* A different path means the host moved to another network.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetworkPath {
+    pub ipv4: Option<RoutePath>,
+
+    pub ipv6: Option<RoutePath>,
+}
+
+impl NetworkPath {
+    pub fn current(fwmark: u32) -> Result<Self, CliClientError> {
+        let router = netlink::connect()?;
+
+        Ok(Self {
+            ipv4: netlink::route_path(&router, IPV4_PROBE_ADDRESS, fwmark)?,
+            ipv6: netlink::route_path(&router, IPV6_PROBE_ADDRESS, fwmark)?,
+        })
+    }
+
+    // No route out of the host
+    pub fn is_down(&self) -> bool {
+        self.ipv4.is_none() && self.ipv6.is_none()
+    }
+}
+
+/*
+* Report the host network changes (rtnetlink link, address and route notifications)
+* and the resumes (the wall clock advanced more than the monotonic clock, which stops while suspended).
+* The events come in bursts, the receiver should let them settle.
+*/
+pub fn watch_network() -> Result<UnboundedReceiver<NetworkEvent>, CliClientError> {
+    let groups = Groups::new_groups(&[
+        libc::RTNLGRP_LINK,
+        libc::RTNLGRP_IPV4_IFADDR,
+        libc::RTNLGRP_IPV4_ROUTE,
+        libc::RTNLGRP_IPV6_IFADDR,
+        libc::RTNLGRP_IPV6_ROUTE,
+    ]);
+
+    let (router, notifications) = NlRouter::connect(NlFamily::Route, None, groups)
+        .map_err(|e| NetlinkError(format!("Could not subscribe to network changes. {:?}", e)))?;
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    spawn_watcher("netlink", {
+        let sender = sender.clone();
+
+        move || {
+            // Closes the subscription if dropped
+            let _router = router;
+
+            for notification in notifications {
+                if let Err(e) = notification {
+                    warn!("Stopped watching the network changes. {:?}", e);
+                    return;
+                }
+
+                if sender.send(NetworkEvent::Changed).is_err() {
+                    return;
+                }
+            }
+        }
+    })?;
+
+    spawn_watcher("clock", move || watch_clock(sender))?;
+
+    Ok(receiver)
+}
+
+fn watch_clock(sender: UnboundedSender<NetworkEvent>) {
+    while !sender.is_closed() {
+        let (time, instant) = (SystemTime::now(), Instant::now());
+
+        thread::sleep(CLOCK_CHECK_INTERVAL);
+
+        if let Some(suspended) = suspended_time(time.elapsed().ok(), instant.elapsed()) {
+            if sender.send(NetworkEvent::Resumed { suspended }).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// None if the clocks advanced alike. The wall clock might also have been set.
+fn suspended_time(wall_elapsed: Option<Duration>, monotonic_elapsed: Duration) -> Option<Duration> {
+    wall_elapsed?
+        .checked_sub(monotonic_elapsed)
+        .filter(|suspended| *suspended > SUSPEND_THRESHOLD)
+}
+
+fn spawn_watcher<F>(name: &str, watcher: F) -> Result<(), CliClientError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    thread::Builder::new()
+        .name(format!("watch-{}", name))
+        .spawn(watcher)
+        .map(|_| ())
+        .map_err(|e| IoError(format!("Could not start the {} watcher. {:?}", name, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wg::network_events::suspended_time;
+    use std::time::Duration;
+
+    #[test]
+    fn test_suspended_time() {
+        let interval = Duration::from_secs(5);
+
+        assert_eq!(
+            Some(Duration::from_secs(3600)),
+            suspended_time(Some(interval + Duration::from_secs(3600)), interval)
+        );
+
+        // Scheduling delays
+        assert_eq!(
+            None,
+            suspended_time(Some(interval + Duration::from_secs(2)), interval)
+        );
+
+        // The wall clock was set back
+        assert_eq!(None, suspended_time(None, interval));
+        assert_eq!(None, suspended_time(Some(Duration::from_secs(1)), interval));
+    }
+}
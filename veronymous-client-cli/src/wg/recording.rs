@@ -0,0 +1,928 @@
+use crate::error::CliClientError;
+use crate::wg::backend::{
+    plan_down, plan_reconfigure, plan_recover, plan_refresh, plan_retire, plan_rotate, plan_up,
+    RoutingOptions, TunnelBackend, TunnelInterfaces, TunnelOperation, TunnelStatus,
+};
+use std::sync::Mutex;
+use veronymous_client::client::state::VpnConnection;
+
+/*
+* Dry-run backend. Records the operations instead of changing the host network.
+*/
+pub struct RecordingBackend {
+    interfaces: Mutex<TunnelInterfaces>,
+
+    operations: Mutex<Vec<TunnelOperation>>,
+
+    up: Mutex<bool>,
+}
+
+impl RecordingBackend {
+    pub fn new(interface: String) -> Self {
+        Self {
+            interfaces: Mutex::new(TunnelInterfaces::new(interface)),
+            operations: Mutex::new(vec![]),
+            up: Mutex::new(false),
+        }
+    }
+
+    // Recorded operations, oldest first
+    #[cfg(test)]
+    pub fn operations(&self) -> Vec<TunnelOperation> {
+        self.operations.lock().unwrap().clone()
+    }
+
+    fn record(&self, operations: Vec<TunnelOperation>, up: bool) {
+        for operation in &operations {
+            info!("[dry-run] {:?}", operation);
+        }
+
+        self.operations.lock().unwrap().extend(operations);
+        *self.up.lock().unwrap() = up;
+    }
+}
+
+impl TunnelBackend for RecordingBackend {
+    fn up(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap().clone();
+        self.record(plan_up(connection, options, &interfaces)?, true);
+
+        Ok(())
+    }
+
+    fn reconfigure(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap().clone();
+        self.record(plan_reconfigure(connection, options, &interfaces)?, true);
+
+        Ok(())
+    }
+
+    fn rotate(
+        &self,
+        previous: &VpnConnection,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        self.record(
+            plan_rotate(previous, connection, options, &interfaces)?,
+            true,
+        );
+
+        interfaces.swap();
+
+        Ok(())
+    }
+
+    fn retire_previous(&self, options: &RoutingOptions) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap().clone();
+        self.record(plan_retire(options, &interfaces), true);
+
+        Ok(())
+    }
+
+    fn refresh(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap().clone();
+        self.record(plan_refresh(connection, options, &interfaces)?, true);
+
+        Ok(())
+    }
+
+    fn down(&self) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap().clone();
+        self.record(plan_down(&interfaces), false);
+
+        Ok(())
+    }
+
+    fn recover(&self) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap().clone();
+        self.record(plan_recover(&interfaces), false);
+
+        Ok(())
+    }
+
+    fn status(&self) -> Result<TunnelStatus, CliClientError> {
+        match *self.up.lock().unwrap() {
+            false => Ok(TunnelStatus::Down),
+            true => Ok(TunnelStatus::Up {
+                interface: self.interfaces.lock().unwrap().active.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wg::backend::{
+        endpoint_host, plan_down, plan_reconfigure, plan_refresh, plan_rotate, plan_up,
+        standby_interface, HealthCheck, KillSwitch, Rotation, RoutingOptions, SplitTunnel,
+        TunnelBackend, TunnelInterfaces, TunnelOperation, TunnelStatus, RT_TABLE_MAIN, WG_MTU,
+    };
+    use crate::wg::recording::RecordingBackend;
+    use std::net::IpAddr;
+    use std::time::Duration;
+    use veronymous_client::client::state::VpnConnection;
+    use veronymous_client::vpn::TunnelHints;
+
+    #[test]
+    fn test_up() {
+        let backend = RecordingBackend::new("veron0".to_string());
+
+        backend
+            .up(&connection(&["10.8.0.2", "fd00::2/64"]), &options(false))
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                TunnelOperation::AddOutOfBandRoutes {
+                    hosts: vec!["token.veronymous.io:443".to_string()]
+                },
+                TunnelOperation::DeleteInterface,
+                TunnelOperation::CreateInterface,
+                TunnelOperation::AddAddress {
+                    address: "10.8.0.2".parse().unwrap(),
+                    prefix_len: 32
+                },
+                TunnelOperation::AddAddress {
+                    address: "fd00::2".parse().unwrap(),
+                    prefix_len: 64
+                },
+                configure_device(),
+                TunnelOperation::SetLinkUp { mtu: WG_MTU },
+                TunnelOperation::SetFwmark { fwmark: 51820 },
+                TunnelOperation::AddDefaultRoute { table: 51820 },
+                TunnelOperation::AddFwmarkRule {
+                    fwmark: 51820,
+                    table: 51820
+                },
+                TunnelOperation::AddSuppressPrefixRule {
+                    table: RT_TABLE_MAIN,
+                    prefix_len: 0
+                },
+                TunnelOperation::ConfigureDns {
+                    servers: vec!["10.64.0.1".parse().unwrap()]
+                },
+            ],
+            backend.operations()
+        );
+        assert_eq!(
+            TunnelStatus::Up {
+                interface: "veron0".to_string()
+            },
+            backend.status().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_tunnel_only() {
+        let backend = RecordingBackend::new("veron0".to_string());
+
+        backend
+            .up(&connection(&["10.8.0.2"]), &options(true))
+            .unwrap();
+
+        // No routing or DNS through the tunnel
+        assert_eq!(
+            Some(&TunnelOperation::SetLinkUp { mtu: WG_MTU }),
+            backend.operations().last()
+        );
+    }
+
+    #[test]
+    fn test_reconfigure_and_down() {
+        let backend = RecordingBackend::new("veron0".to_string());
+
+        backend
+            .reconfigure(&connection(&["10.8.0.3"]), &options(true))
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                configure_device(),
+                TunnelOperation::FlushAddresses,
+                TunnelOperation::AddAddress {
+                    address: "10.8.0.3".parse().unwrap(),
+                    prefix_len: 32
+                },
+            ],
+            backend.operations()
+        );
+
+        backend.down().unwrap();
+
+        // The kill switch is removed last
+        assert_eq!(
+            [
+                TunnelOperation::RestoreDns,
+                TunnelOperation::DeleteInterface,
+                TunnelOperation::SelectInterface {
+                    interface: "veron0r".to_string()
+                },
+                TunnelOperation::DeleteInterface,
+                TunnelOperation::RemoveRoutingConfiguration,
+                TunnelOperation::RemoveKillSwitch
+            ],
+            backend.operations()[3..]
+        );
+        assert_eq!(TunnelStatus::Down, backend.status().unwrap());
+    }
+
+    #[test]
+    fn test_kill_switch() {
+        let backend = RecordingBackend::new("veron0".to_string());
+        let options = RoutingOptions {
+            kill_switch: KillSwitch::Enabled { allow_lan: true },
+            kill_switch_hosts: vec!["192.0.2.1".parse().unwrap()],
+            ..options(false)
+        };
+        let install_kill_switch = TunnelOperation::InstallKillSwitch {
+            interfaces: vec!["veron0".to_string(), "veron0r".to_string()],
+            endpoint: "wg1.ny.veronymous.io:51820".to_string(),
+            hosts: vec!["192.0.2.1".parse().unwrap()],
+            allow_lan: true,
+            excluded: vec![],
+        };
+
+        backend.up(&connection(&["10.8.0.2"]), &options).unwrap();
+
+        // Installed before the existing interface is deleted
+        assert_eq!(
+            [
+                install_kill_switch.clone(),
+                TunnelOperation::AddOutOfBandRoutes {
+                    hosts: vec!["token.veronymous.io:443".to_string()]
+                },
+                TunnelOperation::DeleteInterface,
+            ],
+            backend.operations()[..3]
+        );
+
+        // Replaced for the next epoch, never removed
+        let up_count = backend.operations().len();
+        backend
+            .reconfigure(&connection(&["10.8.0.3"]), &options)
+            .unwrap();
+
+        let operations = backend.operations();
+        assert_eq!(install_kill_switch, operations[up_count]);
+        assert!(!operations.contains(&TunnelOperation::RemoveKillSwitch));
+    }
+
+    #[test]
+    fn test_resolve_kill_switch_hosts() {
+        let options = RoutingOptions {
+            out_of_band_hosts: vec!["192.0.2.1:443".to_string()],
+            kill_switch: KillSwitch::Enabled { allow_lan: false },
+            ..options(false)
+        };
+
+        let resolved = options
+            .resolve_kill_switch_hosts(&["192.0.2.2:7777".to_string(), "192.0.2.1:80".to_string()])
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                "192.0.2.1".parse::<IpAddr>().unwrap(),
+                "192.0.2.2".parse().unwrap()
+            ],
+            resolved.kill_switch_hosts
+        );
+
+        // Not resolved without the kill switch
+        let resolved = options(false)
+            .resolve_kill_switch_hosts(&["192.0.2.2:7777".to_string()])
+            .unwrap();
+        assert!(resolved.kill_switch_hosts.is_empty());
+    }
+
+    #[test]
+    fn test_endpoint_host() {
+        assert_eq!(
+            "files.veronymous.io:443",
+            endpoint_host("https://files.veronymous.io/servers.json")
+        );
+        assert_eq!(
+            "localhost:9090",
+            endpoint_host("http://localhost:9090/servers.json")
+        );
+        assert_eq!(
+            "files.veronymous.io:80",
+            endpoint_host("http://files.veronymous.io")
+        );
+        assert_eq!(
+            "localhost.veronymous.io:7777",
+            endpoint_host("localhost.veronymous.io:7777")
+        );
+        assert_eq!("[2001:db8::1]:443", endpoint_host("https://[2001:db8::1]/"));
+    }
+
+    #[test]
+    fn test_refresh() {
+        let backend = RecordingBackend::new("veron0".to_string());
+        let connection = connection(&["10.8.0.2"]);
+        let options = RoutingOptions {
+            kill_switch: KillSwitch::Enabled { allow_lan: false },
+            ..options(false)
+        };
+
+        backend.up(&connection, &options).unwrap();
+
+        let up_count = backend.operations().len();
+        backend.refresh(&connection, &options).unwrap();
+
+        // The interface and the addresses are kept
+        assert_eq!(
+            vec![
+                TunnelOperation::InstallKillSwitch {
+                    interfaces: vec!["veron0".to_string(), "veron0r".to_string()],
+                    endpoint: "wg1.ny.veronymous.io:51820".to_string(),
+                    hosts: vec![],
+                    allow_lan: false,
+                    excluded: vec![],
+                },
+                TunnelOperation::RemoveOutOfBandRoutes,
+                TunnelOperation::AddOutOfBandRoutes {
+                    hosts: vec!["token.veronymous.io:443".to_string()]
+                },
+                TunnelOperation::UpdateEndpoint {
+                    peer_public_key: "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=".to_string(),
+                    endpoint: "wg1.ny.veronymous.io:51820".to_string(),
+                },
+                TunnelOperation::ConfigureDns {
+                    servers: vec!["10.64.0.1".parse().unwrap()]
+                },
+            ],
+            backend.operations()[up_count..]
+        );
+        assert_eq!(
+            TunnelStatus::Up {
+                interface: "veron0".to_string()
+            },
+            backend.status().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rotate() {
+        let backend = RecordingBackend::new("veron0".to_string());
+
+        backend
+            .up(&connection(&["10.8.0.2"]), &options(false))
+            .unwrap();
+
+        let up_count = backend.operations().len();
+        backend
+            .rotate(
+                &connection(&["10.8.0.2"]),
+                &connection(&["10.8.0.3"]),
+                &options(false),
+            )
+            .unwrap();
+
+        // The routes are moved once the new tunnel is up
+        assert_eq!(
+            [
+                TunnelOperation::AddOutOfBandRoutes {
+                    hosts: vec!["token.veronymous.io:443".to_string()]
+                },
+                TunnelOperation::SelectInterface {
+                    interface: "veron0r".to_string()
+                },
+                TunnelOperation::DeleteInterface,
+                TunnelOperation::CreateInterface,
+                TunnelOperation::AddAddress {
+                    address: "10.8.0.3".parse().unwrap(),
+                    prefix_len: 32
+                },
+                configure_device(),
+                TunnelOperation::SetFwmark { fwmark: 51820 },
+                TunnelOperation::SetLinkUp { mtu: WG_MTU },
+                TunnelOperation::WaitForHandshake,
+                // The open connections from the previous address keep their tunnel
+                TunnelOperation::SelectInterface {
+                    interface: "veron0".to_string()
+                },
+                TunnelOperation::ReplaceDefaultRoute { table: 51821 },
+                TunnelOperation::AddSourceRule {
+                    source: "10.8.0.2".parse().unwrap(),
+                    table: 51821
+                },
+                TunnelOperation::SelectInterface {
+                    interface: "veron0r".to_string()
+                },
+                TunnelOperation::ReplaceDefaultRoute { table: 51820 },
+                TunnelOperation::AddFwmarkRule {
+                    fwmark: 51820,
+                    table: 51820
+                },
+                TunnelOperation::AddSuppressPrefixRule {
+                    table: RT_TABLE_MAIN,
+                    prefix_len: 0
+                },
+                TunnelOperation::ConfigureDns {
+                    servers: vec!["10.64.0.1".parse().unwrap()]
+                },
+            ],
+            backend.operations()[up_count..]
+        );
+        assert_eq!(
+            TunnelStatus::Up {
+                interface: "veron0r".to_string()
+            },
+            backend.status().unwrap()
+        );
+
+        // The previous interface and its routing are deleted after the grace period
+        let rotate_count = backend.operations().len();
+        backend.retire_previous(&options(false)).unwrap();
+
+        assert_eq!(
+            [
+                TunnelOperation::RemoveSourceRouting { table: 51821 },
+                TunnelOperation::SelectInterface {
+                    interface: "veron0".to_string()
+                },
+                TunnelOperation::DeleteInterface,
+            ],
+            backend.operations()[rotate_count..]
+        );
+
+        // And used for the next rotation
+        let retire_count = backend.operations().len();
+        backend
+            .rotate(
+                &connection(&["10.8.0.3"]),
+                &connection(&["10.8.0.4"]),
+                &options(false),
+            )
+            .unwrap();
+
+        assert_eq!(
+            TunnelOperation::SelectInterface {
+                interface: "veron0".to_string()
+            },
+            backend.operations()[retire_count + 1]
+        );
+        assert!(
+            backend.operations()[retire_count..].contains(&TunnelOperation::AddSourceRule {
+                source: "10.8.0.3".parse().unwrap(),
+                table: 51821
+            })
+        );
+    }
+
+    #[test]
+    fn test_standby_interface() {
+        assert_eq!("veron0r", standby_interface("veron0"));
+        assert_eq!("wg-veronymous-r", standby_interface("wg-veronymous-0"));
+        assert_eq!("wg-veronymous-s", standby_interface("wg-veronymous-r"));
+    }
+
+    #[test]
+    fn test_recover() {
+        let backend = RecordingBackend::new("veron0".to_string());
+
+        backend.recover().unwrap();
+
+        // The kill switch of the previous run is kept until the tunnel is up
+        assert_eq!(
+            vec![
+                TunnelOperation::RestoreDns,
+                TunnelOperation::DeleteInterface,
+                TunnelOperation::SelectInterface {
+                    interface: "veron0r".to_string()
+                },
+                TunnelOperation::DeleteInterface,
+                TunnelOperation::RemoveRoutingConfiguration,
+            ],
+            backend.operations()
+        );
+        assert_eq!(TunnelStatus::Down, backend.status().unwrap());
+    }
+
+    #[test]
+    fn test_tunnel_settings() {
+        let backend = RecordingBackend::new("wg-veron".to_string());
+        let mut connection = connection(&["10.8.0.2"]);
+        connection.tunnel_hints = TunnelHints {
+            mtu: Some(1280),
+            persistent_keepalive: Some(25),
+        };
+        let options = RoutingOptions {
+            fwmark: 1234,
+            table: 100,
+            allowed_ips: vec!["10.0.0.0/8".to_string()],
+            ..options(false)
+        };
+
+        // The server's hints are used if not set by the user
+        backend.up(&connection, &options).unwrap();
+
+        let operations = backend.operations();
+        assert_eq!(
+            TunnelOperation::ConfigureDevice {
+                private_key: "private_key".to_string(),
+                peer_public_key: "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=".to_string(),
+                endpoint: "wg1.ny.veronymous.io:51820".to_string(),
+                allowed_ips: vec![("10.0.0.0".parse::<IpAddr>().unwrap(), 8)],
+                persistent_keepalive: Some(25),
+            },
+            operations[4]
+        );
+        assert_eq!(TunnelOperation::SetLinkUp { mtu: 1280 }, operations[5]);
+        assert_eq!(
+            [
+                TunnelOperation::SetFwmark { fwmark: 1234 },
+                TunnelOperation::AddDefaultRoute { table: 100 },
+                TunnelOperation::AddFwmarkRule {
+                    fwmark: 1234,
+                    table: 100
+                },
+            ],
+            operations[6..9]
+        );
+        assert_eq!(
+            TunnelStatus::Up {
+                interface: "wg-veron".to_string()
+            },
+            backend.status().unwrap()
+        );
+
+        // The user's settings take precedence
+        let options = RoutingOptions {
+            mtu: Some(1380),
+            persistent_keepalive: Some(0),
+            ..options
+        };
+        backend.up(&connection, &options).unwrap();
+
+        let operations = &backend.operations()[operations.len()..];
+        assert!(matches!(
+            operations[4],
+            TunnelOperation::ConfigureDevice {
+                persistent_keepalive: None,
+                ..
+            }
+        ));
+        assert_eq!(TunnelOperation::SetLinkUp { mtu: 1380 }, operations[5]);
+    }
+
+    #[test]
+    fn test_split_tunnel_include() {
+        let backend = RecordingBackend::new("veron0".to_string());
+        let options = RoutingOptions {
+            split_tunnel: SplitTunnel::Include {
+                networks: vec![("10.0.0.0".parse().unwrap(), 8)],
+            },
+            ..options(false)
+        };
+
+        backend.up(&connection(&["10.8.0.2"]), &options).unwrap();
+
+        // Only the included networks are sent to the server and routed. No DNS.
+        let operations = backend.operations();
+        assert!(operations.contains(&TunnelOperation::ConfigureDevice {
+            private_key: "private_key".to_string(),
+            peer_public_key: "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=".to_string(),
+            endpoint: "wg1.ny.veronymous.io:51820".to_string(),
+            allowed_ips: vec![("10.0.0.0".parse::<IpAddr>().unwrap(), 8)],
+            persistent_keepalive: None,
+        }));
+        assert_eq!(
+            [
+                TunnelOperation::SetFwmark { fwmark: 51820 },
+                TunnelOperation::AddRoute {
+                    destination: "10.0.0.0".parse().unwrap(),
+                    prefix_len: 8,
+                    table: 51820
+                },
+                TunnelOperation::AddFwmarkRule {
+                    fwmark: 51820,
+                    table: 51820
+                },
+                TunnelOperation::AddSuppressPrefixRule {
+                    table: RT_TABLE_MAIN,
+                    prefix_len: 0
+                },
+            ],
+            operations[operations.len() - 4..]
+        );
+
+        // Moved to the standby interface
+        let up_count = operations.len();
+        backend
+            .rotate(
+                &connection(&["10.8.0.2"]),
+                &connection(&["10.8.0.3"]),
+                &RoutingOptions {
+                    rotation: Rotation::Seamless {
+                        grace_period: Duration::from_secs(30),
+                    },
+                    ..options
+                },
+            )
+            .unwrap();
+
+        let operations = backend.operations();
+        assert!(
+            operations[up_count..].contains(&TunnelOperation::ReplaceRoute {
+                destination: "10.0.0.0".parse().unwrap(),
+                prefix_len: 8,
+                table: 51820
+            })
+        );
+        assert!(!operations[up_count..]
+            .contains(&TunnelOperation::ReplaceDefaultRoute { table: 51820 }));
+    }
+
+    #[test]
+    fn test_split_tunnel_exclude() {
+        let backend = RecordingBackend::new("veron0".to_string());
+        let excluded = vec![
+            ("192.168.0.0".parse().unwrap(), 16),
+            ("2001:db8::".parse().unwrap(), 32),
+        ];
+        let options = RoutingOptions {
+            kill_switch: KillSwitch::Enabled { allow_lan: false },
+            split_tunnel: SplitTunnel::Exclude {
+                networks: excluded.clone(),
+            },
+            ..options(false)
+        };
+
+        backend.up(&connection(&["10.8.0.2"]), &options).unwrap();
+
+        let operations = backend.operations();
+        assert_eq!(
+            TunnelOperation::InstallKillSwitch {
+                interfaces: vec!["veron0".to_string(), "veron0r".to_string()],
+                endpoint: "wg1.ny.veronymous.io:51820".to_string(),
+                hosts: vec![],
+                allow_lan: false,
+                excluded,
+            },
+            operations[0]
+        );
+        assert!(operations.contains(&configure_device()));
+
+        // Bypass the tunnel before the traffic is sent to it
+        assert_eq!(
+            [
+                TunnelOperation::AddDefaultRoute { table: 51820 },
+                TunnelOperation::AddExcludeRule {
+                    destination: "192.168.0.0".parse().unwrap(),
+                    prefix_len: 16
+                },
+                TunnelOperation::AddExcludeRule {
+                    destination: "2001:db8::".parse().unwrap(),
+                    prefix_len: 32
+                },
+                TunnelOperation::AddFwmarkRule {
+                    fwmark: 51820,
+                    table: 51820
+                },
+            ],
+            operations[8..12]
+        );
+    }
+
+    #[test]
+    fn test_split_tunnel_resolve() {
+        assert_eq!(
+            SplitTunnel::Disabled,
+            SplitTunnel::resolve(&[], &[]).unwrap()
+        );
+        assert_eq!(
+            SplitTunnel::Include {
+                networks: vec![
+                    ("10.0.0.0".parse().unwrap(), 8),
+                    ("127.0.0.1".parse().unwrap(), 32),
+                    ("198.51.100.7".parse().unwrap(), 32),
+                ]
+            },
+            SplitTunnel::resolve(
+                &[
+                    "198.51.100.7".to_string(),
+                    "10.0.0.0/8".to_string(),
+                    "localhost".to_string(),
+                    "10.0.0.0/8".to_string(),
+                ],
+                &[]
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            SplitTunnel::Exclude {
+                networks: vec![("2001:db8::".parse().unwrap(), 32)]
+            },
+            SplitTunnel::resolve(&[], &["2001:db8::/32".to_string()]).unwrap()
+        );
+
+        // The tunnel routes are IPv4 only
+        assert!(SplitTunnel::resolve(&["2001:db8::/32".to_string()], &[]).is_err());
+        assert!(SplitTunnel::resolve(&["10.0.0.0/33".to_string()], &[]).is_err());
+        assert!(
+            SplitTunnel::resolve(&["10.0.0.0/8".to_string()], &["localhost".to_string()]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_resolve_split_tunnel() {
+        let options = RoutingOptions {
+            split_exclude: vec!["198.51.100.7".to_string()],
+            ..options(false)
+        };
+
+        assert_eq!(
+            SplitTunnel::Exclude {
+                networks: vec![("198.51.100.7".parse().unwrap(), 32)]
+            },
+            options.resolve_split_tunnel().unwrap().split_tunnel
+        );
+
+        // Every destination goes through the tunnel
+        let options = RoutingOptions {
+            tunnel_only: true,
+            ..options
+        };
+
+        assert_eq!(
+            SplitTunnel::Disabled,
+            options.resolve_split_tunnel().unwrap().split_tunnel
+        );
+    }
+
+    #[test]
+    fn test_namespace() {
+        let interfaces =
+            TunnelInterfaces::in_namespace("veronx0".to_string(), "veronx0".to_string());
+        let connection = connection(&["10.8.0.2"]);
+        let in_namespace = TunnelOperation::SelectNamespace {
+            namespace: Some("veronx0".to_string()),
+        };
+        let namespace_routes = [
+            TunnelOperation::AddInterfaceRoute {
+                destination: "0.0.0.0".parse().unwrap(),
+                prefix_len: 0,
+            },
+            TunnelOperation::AddInterfaceRoute {
+                destination: "::".parse().unwrap(),
+                prefix_len: 0,
+            },
+            TunnelOperation::ConfigureNamespaceDns {
+                namespace: "veronx0".to_string(),
+                servers: vec!["10.64.0.1".parse().unwrap()],
+            },
+        ];
+
+        // Created in the host's namespace, configured in its own. The host's routing is kept.
+        let mut expected = vec![
+            TunnelOperation::CreateNamespace {
+                namespace: "veronx0".to_string(),
+            },
+            TunnelOperation::DeleteInterface,
+            TunnelOperation::CreateInterface,
+            TunnelOperation::MoveToNamespace {
+                namespace: "veronx0".to_string(),
+            },
+            in_namespace.clone(),
+            TunnelOperation::AddAddress {
+                address: "10.8.0.2".parse().unwrap(),
+                prefix_len: 32,
+            },
+            configure_device(),
+            TunnelOperation::SetLinkUp { mtu: WG_MTU },
+        ];
+        expected.extend(namespace_routes.clone());
+
+        assert_eq!(
+            expected,
+            plan_up(&connection, &options(false), &interfaces).unwrap()
+        );
+
+        let mut expected = vec![
+            in_namespace.clone(),
+            configure_device(),
+            TunnelOperation::FlushAddresses,
+            TunnelOperation::AddAddress {
+                address: "10.8.0.2".parse().unwrap(),
+                prefix_len: 32,
+            },
+        ];
+        expected.extend(namespace_routes);
+
+        assert_eq!(
+            expected,
+            plan_reconfigure(&connection, &options(false), &interfaces).unwrap()
+        );
+        assert_eq!(
+            vec![
+                in_namespace,
+                TunnelOperation::UpdateEndpoint {
+                    peer_public_key: "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=".to_string(),
+                    endpoint: "wg1.ny.veronymous.io:51820".to_string(),
+                },
+            ],
+            plan_refresh(&connection, &options(false), &interfaces).unwrap()
+        );
+        assert!(plan_rotate(&connection, &connection, &options(false), &interfaces).is_err());
+
+        // The routing state and the kill switch may belong to another connection
+        assert_eq!(
+            vec![
+                TunnelOperation::DeleteNamespace {
+                    namespace: "veronx0".to_string(),
+                },
+                TunnelOperation::DeleteInterface,
+            ],
+            plan_down(&interfaces)
+        );
+    }
+
+    #[test]
+    fn test_invalid_address() {
+        let backend = RecordingBackend::new("veron0".to_string());
+
+        assert!(backend
+            .up(&connection(&["10.8.0.2/33"]), &options(false))
+            .is_err());
+        assert!(backend
+            .up(&connection(&["not an address"]), &options(false))
+            .is_err());
+        assert!(backend
+            .up(
+                &connection(&["10.8.0.2"]),
+                &RoutingOptions {
+                    allowed_ips: vec!["0.0.0.0/64".to_string()],
+                    ..options(false)
+                }
+            )
+            .is_err());
+
+        // Nothing is applied
+        assert!(backend.operations().is_empty());
+    }
+
+    fn options(tunnel_only: bool) -> RoutingOptions {
+        RoutingOptions {
+            tunnel_only,
+            out_of_band_hosts: vec!["token.veronymous.io:443".to_string()],
+            kill_switch: KillSwitch::Disabled,
+            kill_switch_hosts: vec![],
+            mtu: None,
+            fwmark: 51820,
+            table: 51820,
+            persistent_keepalive: None,
+            allowed_ips: vec!["0.0.0.0/0".to_string(), "::/0".to_string()],
+            rotation: Rotation::InPlace,
+            health_check: HealthCheck {
+                interval: Duration::from_secs(15),
+                ping_address: None,
+            },
+            split_include: vec![],
+            split_exclude: vec![],
+            split_tunnel: SplitTunnel::Disabled,
+        }
+    }
+
+    fn connection(addresses: &[&str]) -> VpnConnection {
+        VpnConnection::new(
+            addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+            "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=".to_string(),
+            "wg1.ny.veronymous.io:51820".to_string(),
+            "private_key".to_string(),
+            "public_key".to_string(),
+            "new_york".to_string(),
+            vec!["10.64.0.1".to_string()],
+        )
+    }
+
+    fn configure_device() -> TunnelOperation {
+        TunnelOperation::ConfigureDevice {
+            private_key: "private_key".to_string(),
+            peer_public_key: "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=".to_string(),
+            endpoint: "wg1.ny.veronymous.io:51820".to_string(),
+            allowed_ips: vec![
+                ("0.0.0.0".parse::<IpAddr>().unwrap(), 0),
+                ("::".parse::<IpAddr>().unwrap(), 0),
+            ],
+            persistent_keepalive: None,
+        }
+    }
+}
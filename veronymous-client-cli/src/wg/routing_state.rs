@@ -0,0 +1,161 @@
+use crate::constants::app::ROUTING_STATE_FILE_PATH;
+use crate::error::CliClientError;
+use crate::error::CliClientError::{EncodingError, IoError, ParseError, ReadFileError};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/*
+* A route or rule installed by the client.
+* Recorded when created so that exactly those are removed on disconnect,
+* or on the next start if the client did not exit cleanly.
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum InstalledEntry {
+    Route {
+        destination: IpAddr,
+        prefix_len: u8,
+        table: u32,
+    },
+
+    FwmarkRule {
+        fwmark: u32,
+        table: u32,
+    },
+
+    SuppressPrefixRule {
+        table: u32,
+        prefix_len: u32,
+    },
+
+    // The network bypasses the tunnel
+    ExcludeRule {
+        destination: IpAddr,
+        prefix_len: u8,
+    },
+
+    // The traffic from the address (the previous tunnel's) uses the table
+    SourceRule {
+        source: IpAddr,
+        table: u32,
+    },
+
+    // Host route in the main table, pinned to the network it was added on
+    OutOfBandRoute {
+        destination: IpAddr,
+    },
+}
+
+pub fn record(entry: InstalledEntry) -> Result<(), CliClientError> {
+    record_in(Path::new(ROUTING_STATE_FILE_PATH), entry)
+}
+
+// Installed entries, most recent first. The state is cleared.
+pub fn take() -> Result<Vec<InstalledEntry>, CliClientError> {
+    take_from(Path::new(ROUTING_STATE_FILE_PATH))
+}
+
+// Entries (as returned by take) that could not be removed are kept for the next attempt
+pub fn restore(mut entries: Vec<InstalledEntry>) -> Result<(), CliClientError> {
+    entries.reverse();
+
+    save(Path::new(ROUTING_STATE_FILE_PATH), &entries)
+}
+
+fn record_in(path: &Path, entry: InstalledEntry) -> Result<(), CliClientError> {
+    let mut entries = load(path)?;
+
+    if !entries.contains(&entry) {
+        entries.push(entry);
+        save(path, &entries)?;
+    }
+
+    Ok(())
+}
+
+fn take_from(path: &Path) -> Result<Vec<InstalledEntry>, CliClientError> {
+    let mut entries = load(path)?;
+    entries.reverse();
+
+    if path.exists() {
+        fs::remove_file(path)
+            .map_err(|e| IoError(format!("Could not remove routing state. {:?}", e)))?;
+    }
+
+    Ok(entries)
+}
+
+fn load(path: &Path) -> Result<Vec<InstalledEntry>, CliClientError> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    let contents = fs::read(path)
+        .map_err(|e| ReadFileError(format!("Could not read routing state. {:?}", e)))?;
+
+    serde_json::from_slice(&contents)
+        .map_err(|e| ParseError(format!("Could not parse routing state. {:?}", e)))
+}
+
+fn save(path: &Path, entries: &[InstalledEntry]) -> Result<(), CliClientError> {
+    if entries.is_empty() {
+        return match path.exists() {
+            true => fs::remove_file(path).map_err(|e| IoError(e.to_string())),
+            false => Ok(()),
+        };
+    }
+
+    let parent = path.parent().unwrap();
+
+    // Create parent directory if it does not exist
+    if !parent.exists() {
+        fs::create_dir_all(parent).map_err(|e| IoError(e.to_string()))?;
+    }
+
+    let contents = serde_json::to_vec(entries).map_err(|e| EncodingError(e.to_string()))?;
+
+    fs::write(path, contents).map_err(|e| IoError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wg::routing_state::{load, record_in, save, take_from, InstalledEntry};
+    use std::fs;
+
+    #[test]
+    fn test_record_and_take() {
+        let dir = std::env::temp_dir().join(format!("veronymous-routing-{}", std::process::id()));
+        let path = dir.join("routing.json");
+
+        let route = InstalledEntry::Route {
+            destination: "1.2.3.4".parse().unwrap(),
+            prefix_len: 32,
+            table: 254,
+        };
+        let rule = InstalledEntry::FwmarkRule {
+            fwmark: 51820,
+            table: 51820,
+        };
+
+        record_in(&path, route.clone()).unwrap();
+        record_in(&path, rule.clone()).unwrap();
+
+        // Not recorded twice (e.g., on reconnect)
+        record_in(&path, route.clone()).unwrap();
+
+        // Removed in reverse order
+        assert_eq!(vec![rule.clone(), route.clone()], take_from(&path).unwrap());
+        assert!(!path.exists());
+        assert!(take_from(&path).unwrap().is_empty());
+
+        // Kept for the next attempt
+        save(&path, std::slice::from_ref(&route)).unwrap();
+        assert_eq!(vec![route], load(&path).unwrap());
+
+        save(&path, &[]).unwrap();
+        assert!(!path.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::IoError;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+const TUN_PATH: &str = "/dev/net/tun";
+
+// linux/if_tun.h
+const TUNSETIFF: libc::c_ulong = 0x400454ca;
+const IFF_TUN: libc::c_short = 0x0001;
+const IFF_NO_PI: libc::c_short = 0x1000;
+
+// struct ifreq (linux/if.h)
+#[repr(C)]
+struct IfReq {
+    name: [u8; libc::IFNAMSIZ],
+
+    flags: libc::c_short,
+
+    padding: [u8; 22],
+}
+
+/*
+* Layer 3 TUN interface. The interface is deleted when the device is dropped.
+*/
+pub struct TunDevice {
+    file: File,
+}
+
+impl TunDevice {
+    pub fn open(name: &str) -> Result<Self, CliClientError> {
+        if name.is_empty() || name.len() >= libc::IFNAMSIZ {
+            return Err(IoError(format!("Invalid interface name {}.", name)));
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(TUN_PATH)
+            .map_err(|e| IoError(format!("Could not open {}. {:?}", TUN_PATH, e)))?;
+
+        let mut request = IfReq {
+            name: [0; libc::IFNAMSIZ],
+            flags: IFF_TUN | IFF_NO_PI,
+            padding: [0; 22],
+        };
+        request.name[..name.len()].copy_from_slice(name.as_bytes());
+
+        let result = unsafe { libc::ioctl(file.as_raw_fd(), TUNSETIFF as _, &mut request) };
+        if result < 0 {
+            return Err(IoError(format!(
+                "Could not create tun interface {}. {:?}",
+                name,
+                io::Error::last_os_error()
+            )));
+        }
+
+        Ok(Self { file })
+    }
+
+    // Read a single packet. None if no packet arrived before the timeout.
+    pub fn read(&self, buffer: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        let result = unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+
+            return match error.kind() {
+                io::ErrorKind::Interrupted => Ok(None),
+                _ => Err(error),
+            };
+        }
+        if result == 0 {
+            return Ok(None);
+        }
+
+        (&self.file).read(buffer).map(Some)
+    }
+
+    pub fn write(&self, packet: &[u8]) -> io::Result<()> {
+        (&self.file).write_all(packet)
+    }
+}
@@ -0,0 +1,676 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::{IoError, ParseError};
+use crate::wg::backend::{
+    plan_down, plan_reconfigure, plan_recover, plan_refresh, plan_retire, plan_rotate, plan_up,
+    wait_for_handshake, RoutingOptions, TunnelBackend, TunnelHealth, TunnelInterfaces,
+    TunnelOperation, TunnelStatus,
+};
+use crate::wg::network::{apply_network_operation, resolve_endpoint};
+use crate::wg::tun::TunDevice;
+use crate::wg::wireguard::decode_key;
+use crate::wg::{namespace, netlink};
+use boringtun::noise::{Tunn, TunnResult};
+use boringtun::x25519::{PublicKey, StaticSecret};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+use veronymous_client::client::state::VpnConnection;
+
+const MAX_PACKET_SIZE: usize = 65535;
+
+// How often the workers check if the device is stopped
+const POLL_TIMEOUT: Duration = Duration::from_millis(250);
+
+// boringtun expects the timers to be updated every 250ms
+const TIMER_INTERVAL: Duration = Duration::from_millis(250);
+
+/*
+* Userspace wireguard (boringtun) over a TUN interface.
+* For hosts without the wireguard kernel module. Still requires CAP_NET_ADMIN.
+* The tunnel only lives as long as the process.
+*/
+pub struct UserspaceBackend {
+    interfaces: Mutex<TunnelInterfaces>,
+
+    // By interface name. Two while rotating.
+    devices: Mutex<HashMap<String, UserspaceDevice>>,
+}
+
+impl UserspaceBackend {
+    pub fn new(interface: String) -> Self {
+        Self {
+            interfaces: Mutex::new(TunnelInterfaces::new(interface)),
+            devices: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // The TUN interface is moved to the network namespace
+    pub fn in_namespace(interface: String, namespace: String) -> Self {
+        Self {
+            interfaces: Mutex::new(TunnelInterfaces::in_namespace(interface, namespace)),
+            devices: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /*
+     * The operations apply to the active interface until another one is selected,
+     * in the host's namespace until another one is selected.
+     */
+    fn execute(
+        &self,
+        interfaces: &TunnelInterfaces,
+        operations: &[TunnelOperation],
+    ) -> Result<(), CliClientError> {
+        let mut router = netlink::connect()?;
+        let mut devices = self.devices.lock().unwrap();
+        let mut interface = interfaces.active.clone();
+
+        for operation in operations {
+            match operation {
+                TunnelOperation::SelectInterface {
+                    interface: selected,
+                } => {
+                    interface = selected.clone();
+                }
+                // The device's socket stays in the host's namespace
+                TunnelOperation::SelectNamespace { namespace } => {
+                    router = namespace::router(namespace.as_deref())?;
+                }
+                TunnelOperation::CreateInterface => {
+                    devices.insert(interface.clone(), UserspaceDevice::start(&interface)?);
+                }
+                TunnelOperation::DeleteInterface => {
+                    // Dropping the device deletes the interface
+                    devices.remove(&interface);
+
+                    // Leftover interface (e.g., from the kernel backend)
+                    apply_network_operation(&router, &interface, operation)?;
+                }
+                TunnelOperation::ConfigureDevice {
+                    private_key,
+                    peer_public_key,
+                    endpoint,
+                    allowed_ips,
+                    persistent_keepalive,
+                } => {
+                    let device = running_device(&devices, &interface)?;
+
+                    device.configure(
+                        private_key,
+                        peer_public_key,
+                        resolve_endpoint(endpoint)?,
+                        allowed_ips,
+                        *persistent_keepalive,
+                    )?;
+                }
+                TunnelOperation::UpdateEndpoint { endpoint, .. } => {
+                    running_device(&devices, &interface)?
+                        .set_endpoint(resolve_endpoint(endpoint)?)?;
+                }
+                TunnelOperation::SetFwmark { fwmark } => {
+                    running_device(&devices, &interface)?.set_fwmark(*fwmark)?;
+                }
+                TunnelOperation::WaitForHandshake => {
+                    let device = running_device(&devices, &interface)?;
+
+                    wait_for_handshake(|| Ok(device.handshake_done()))?;
+                }
+                operation => apply_network_operation(&router, &interface, operation)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TunnelBackend for UserspaceBackend {
+    fn up(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(&interfaces, &plan_up(connection, options, &interfaces)?)
+    }
+
+    // The TUN interface stays up. Only the keys, addresses and routes are replaced.
+    fn reconfigure(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(
+            &interfaces,
+            &plan_reconfigure(connection, options, &interfaces)?,
+        )
+    }
+
+    fn rotate(
+        &self,
+        previous: &VpnConnection,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        let operations = plan_rotate(previous, connection, options, &interfaces)?;
+
+        if let Err(e) = self.execute(&interfaces, &operations) {
+            // The active tunnel keeps the traffic
+            if let Err(e) = self.execute(&interfaces, &plan_retire(options, &interfaces)) {
+                warn!("{:?}", e);
+            }
+
+            return Err(e);
+        }
+
+        interfaces.swap();
+
+        Ok(())
+    }
+
+    fn retire_previous(&self, options: &RoutingOptions) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(&interfaces, &plan_retire(options, &interfaces))
+    }
+
+    fn refresh(
+        &self,
+        connection: &VpnConnection,
+        options: &RoutingOptions,
+    ) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(
+            &interfaces,
+            &plan_refresh(connection, options, &interfaces)?,
+        )
+    }
+
+    fn down(&self) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(&interfaces, &plan_down(&interfaces))
+    }
+
+    fn recover(&self) -> Result<(), CliClientError> {
+        let interfaces = self.interfaces.lock().unwrap();
+
+        self.execute(&interfaces, &plan_recover(&interfaces))
+    }
+
+    fn status(&self) -> Result<TunnelStatus, CliClientError> {
+        let interface = self.interfaces.lock().unwrap().active.clone();
+
+        match self.devices.lock().unwrap().contains_key(&interface) {
+            false => Ok(TunnelStatus::Down),
+            true => Ok(TunnelStatus::Up { interface }),
+        }
+    }
+
+    fn health(&self) -> Result<Option<TunnelHealth>, CliClientError> {
+        let interface = self.interfaces.lock().unwrap().active.clone();
+
+        Ok(self
+            .devices
+            .lock()
+            .unwrap()
+            .get(&interface)
+            .and_then(|device| device.health()))
+    }
+}
+
+fn running_device<'a>(
+    devices: &'a HashMap<String, UserspaceDevice>,
+    interface: &str,
+) -> Result<&'a UserspaceDevice, CliClientError> {
+    devices.get(interface).ok_or_else(|| {
+        IoError(format!(
+            "The userspace device {} is not running.",
+            interface
+        ))
+    })
+}
+
+struct UserspaceDevice {
+    state: Arc<Mutex<DeviceState>>,
+
+    running: Arc<AtomicBool>,
+
+    workers: Vec<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct DeviceState {
+    peer: Option<Peer>,
+
+    fwmark: Option<u32>,
+
+    // Local index of the next session. 24 bits.
+    next_index: u32,
+}
+
+struct Peer {
+    tunnel: Box<Tunn>,
+
+    socket: Arc<UdpSocket>,
+
+    endpoint: SocketAddr,
+
+    // (address, prefix length)
+    allowed_ips: Vec<(IpAddr, u8)>,
+}
+
+impl UserspaceDevice {
+    fn start(interface: &str) -> Result<Self, CliClientError> {
+        let tun = Arc::new(TunDevice::open(interface)?);
+        let state = Arc::new(Mutex::new(DeviceState::default()));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let workers = vec![
+            spawn_worker("tun", {
+                let (tun, state, running) = (tun.clone(), state.clone(), running.clone());
+                move || tun_worker(tun, state, running)
+            })?,
+            spawn_worker("udp", {
+                let (state, running) = (state.clone(), running.clone());
+                move || udp_worker(tun, state, running)
+            })?,
+            spawn_worker("timers", {
+                let (state, running) = (state.clone(), running.clone());
+                move || timer_worker(state, running)
+            })?,
+        ];
+
+        Ok(Self {
+            state,
+            running,
+            workers,
+        })
+    }
+
+    /*
+     * Replace the peer's session with the new keys.
+     * The socket is kept if the endpoint's address family did not change.
+     */
+    fn configure(
+        &self,
+        private_key: &str,
+        peer_public_key: &str,
+        endpoint: SocketAddr,
+        allowed_ips: &[(IpAddr, u8)],
+        persistent_keepalive: Option<u16>,
+    ) -> Result<(), CliClientError> {
+        let private_key = StaticSecret::from(key_bytes(private_key)?);
+        let peer_public_key = PublicKey::from(key_bytes(peer_public_key)?);
+
+        let mut state = self.state.lock().unwrap();
+
+        let socket = match &state.peer {
+            Some(peer) if peer.endpoint.is_ipv4() == endpoint.is_ipv4() => peer.socket.clone(),
+            _ => Arc::new(bind_socket(&endpoint, state.fwmark)?),
+        };
+
+        state.next_index = (state.next_index + 1) & 0x00ff_ffff;
+
+        let mut tunnel = Box::new(Tunn::new(
+            private_key,
+            peer_public_key,
+            None,
+            persistent_keepalive,
+            state.next_index,
+            None,
+        ));
+
+        // Start the handshake without waiting for traffic
+        let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+        if let TunnResult::WriteToNetwork(datagram) =
+            tunnel.format_handshake_initiation(&mut buffer, false)
+        {
+            send(&socket, &endpoint, datagram);
+        }
+
+        state.peer = Some(Peer {
+            tunnel,
+            socket,
+            endpoint,
+            allowed_ips: allowed_ips.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    fn health(&self) -> Option<TunnelHealth> {
+        let state = self.state.lock().unwrap();
+        let peer = state.peer.as_ref()?;
+
+        Some(peer_health(&peer.tunnel))
+    }
+
+    fn handshake_done(&self) -> bool {
+        match &self.state.lock().unwrap().peer {
+            Some(peer) => peer.tunnel.time_since_last_handshake().is_some(),
+            None => false,
+        }
+    }
+
+    // The socket is replaced if the endpoint's address family changed
+    fn set_endpoint(&self, endpoint: SocketAddr) -> Result<(), CliClientError> {
+        let mut state = self.state.lock().unwrap();
+        let fwmark = state.fwmark;
+
+        let peer = match state.peer.as_mut() {
+            None => return Ok(()),
+            Some(peer) => peer,
+        };
+
+        if peer.endpoint.is_ipv4() != endpoint.is_ipv4() {
+            peer.socket = Arc::new(bind_socket(&endpoint, fwmark)?);
+        }
+        peer.endpoint = endpoint;
+
+        Ok(())
+    }
+
+    fn set_fwmark(&self, fwmark: u32) -> Result<(), CliClientError> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(peer) = &state.peer {
+            set_socket_fwmark(&peer.socket, fwmark)?;
+        }
+
+        state.fwmark = Some(fwmark);
+
+        Ok(())
+    }
+}
+
+impl Drop for UserspaceDevice {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("Userspace wireguard worker panicked.");
+            }
+        }
+    }
+}
+
+fn spawn_worker<F>(name: &str, worker: F) -> Result<JoinHandle<()>, CliClientError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    thread::Builder::new()
+        .name(format!("wg-{}", name))
+        .spawn(worker)
+        .map_err(|e| IoError(format!("Could not start the {} worker. {:?}", name, e)))
+}
+
+// Packets from the TUN interface to the peer
+fn tun_worker(tun: Arc<TunDevice>, state: Arc<Mutex<DeviceState>>, running: Arc<AtomicBool>) {
+    let mut packet = vec![0u8; MAX_PACKET_SIZE];
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+
+    while running.load(Ordering::Relaxed) {
+        let size = match tun.read(&mut packet, POLL_TIMEOUT) {
+            Ok(Some(size)) => size,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("Could not read from the tun interface. {:?}", e);
+                break;
+            }
+        };
+
+        let mut state = state.lock().unwrap();
+        let peer = match state.peer.as_mut() {
+            None => continue,
+            Some(peer) => peer,
+        };
+
+        match peer.tunnel.encapsulate(&packet[..size], &mut buffer) {
+            TunnResult::WriteToNetwork(datagram) => send(&peer.socket, &peer.endpoint, datagram),
+            TunnResult::Err(e) => debug!("Could not encapsulate packet. {:?}", e),
+            _ => {}
+        }
+    }
+}
+
+// Datagrams from the peer to the TUN interface
+fn udp_worker(tun: Arc<TunDevice>, state: Arc<Mutex<DeviceState>>, running: Arc<AtomicBool>) {
+    let mut datagram = vec![0u8; MAX_PACKET_SIZE];
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+
+    while running.load(Ordering::Relaxed) {
+        let socket = state
+            .lock()
+            .unwrap()
+            .peer
+            .as_ref()
+            .map(|peer| peer.socket.clone());
+
+        let socket = match socket {
+            None => {
+                thread::sleep(POLL_TIMEOUT);
+                continue;
+            }
+            Some(socket) => socket,
+        };
+
+        let (size, source) = match socket.recv_from(&mut datagram) {
+            Ok(received) => received,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => {
+                error!("Could not receive from the peer. {:?}", e);
+                break;
+            }
+        };
+
+        let mut state = state.lock().unwrap();
+        let peer = match state.peer.as_mut() {
+            None => continue,
+            Some(peer) => peer,
+        };
+
+        // Replaced socket
+        if !Arc::ptr_eq(&peer.socket, &socket) {
+            continue;
+        }
+
+        match peer
+            .tunnel
+            .decapsulate(Some(source.ip()), &datagram[..size], &mut buffer)
+        {
+            TunnResult::WriteToNetwork(response) => {
+                send(&peer.socket, &peer.endpoint, response);
+
+                // Send the queued packets
+                while let TunnResult::WriteToNetwork(queued) =
+                    peer.tunnel.decapsulate(None, &[], &mut buffer)
+                {
+                    send(&peer.socket, &peer.endpoint, queued);
+                }
+            }
+            TunnResult::WriteToTunnelV4(packet, source) => {
+                write_packet(&tun, &peer.allowed_ips, packet, source.into())
+            }
+            TunnResult::WriteToTunnelV6(packet, source) => {
+                write_packet(&tun, &peer.allowed_ips, packet, source.into())
+            }
+            TunnResult::Err(e) => debug!("Could not decapsulate datagram. {:?}", e),
+            TunnResult::Done => {}
+        }
+    }
+}
+
+// Handshakes and keepalives
+fn timer_worker(state: Arc<Mutex<DeviceState>>, running: Arc<AtomicBool>) {
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+
+    while running.load(Ordering::Relaxed) {
+        thread::sleep(TIMER_INTERVAL);
+
+        let mut state = state.lock().unwrap();
+        let peer = match state.peer.as_mut() {
+            None => continue,
+            Some(peer) => peer,
+        };
+
+        match peer.tunnel.update_timers(&mut buffer) {
+            TunnResult::WriteToNetwork(datagram) => send(&peer.socket, &peer.endpoint, datagram),
+            TunnResult::Err(e) => debug!("Wireguard timer error. {:?}", e),
+            _ => {}
+        }
+    }
+}
+
+fn write_packet(tun: &TunDevice, allowed_ips: &[(IpAddr, u8)], packet: &[u8], source: IpAddr) {
+    if !is_allowed(allowed_ips, &source) {
+        debug!("Dropping packet from {}. Not an allowed ip.", source);
+        return;
+    }
+
+    if let Err(e) = tun.write(packet) {
+        debug!("Could not write to the tun interface. {:?}", e);
+    }
+}
+
+pub(super) fn send(socket: &UdpSocket, endpoint: &SocketAddr, datagram: &[u8]) {
+    if let Err(e) = socket.send_to(datagram, endpoint) {
+        debug!("Could not send to {}. {:?}", endpoint, e);
+    }
+}
+
+fn bind_socket(endpoint: &SocketAddr, fwmark: Option<u32>) -> Result<UdpSocket, CliClientError> {
+    let address = match endpoint {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+
+    let socket = UdpSocket::bind(address)
+        .map_err(|e| IoError(format!("Could not bind udp socket. {:?}", e)))?;
+
+    // Lets the workers check if the device is stopped
+    socket
+        .set_read_timeout(Some(POLL_TIMEOUT))
+        .map_err(|e| IoError(format!("Could not set socket timeout. {:?}", e)))?;
+
+    if let Some(fwmark) = fwmark {
+        set_socket_fwmark(&socket, fwmark)?;
+    }
+
+    Ok(socket)
+}
+
+// Marks the encrypted traffic so it skips the tunnel routing table
+fn set_socket_fwmark(socket: &UdpSocket, fwmark: u32) -> Result<(), CliClientError> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_MARK,
+            &fwmark as *const u32 as *const libc::c_void,
+            std::mem::size_of::<u32>() as libc::socklen_t,
+        )
+    };
+
+    if result < 0 {
+        return Err(IoError(format!(
+            "Could not set the socket fwmark. {:?}",
+            io::Error::last_os_error()
+        )));
+    }
+
+    Ok(())
+}
+
+pub(super) fn peer_health(tunnel: &Tunn) -> TunnelHealth {
+    let (time_since_handshake, tx_bytes, rx_bytes, _, _) = tunnel.stats();
+
+    TunnelHealth {
+        last_handshake: time_since_handshake.and_then(|time| SystemTime::now().checked_sub(time)),
+        rx_bytes: rx_bytes as u64,
+        tx_bytes: tx_bytes as u64,
+    }
+}
+
+pub(super) fn key_bytes(key: &str) -> Result<[u8; 32], CliClientError> {
+    decode_key(key)?
+        .try_into()
+        .map_err(|_| ParseError("Invalid key length.".to_string()))
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+    )
+}
+
+fn is_allowed(allowed_ips: &[(IpAddr, u8)], address: &IpAddr) -> bool {
+    allowed_ips
+        .iter()
+        .any(|(network, prefix_len)| match (network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), *prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), *prefix_len)
+            }
+            _ => false,
+        })
+}
+
+fn prefix_matches(network: &[u8], address: &[u8], prefix_len: u8) -> bool {
+    let prefix_len = prefix_len as usize;
+    let full_bytes = prefix_len / 8;
+    let remaining_bits = prefix_len % 8;
+
+    if network[..full_bytes] != address[..full_bytes] {
+        return false;
+    }
+
+    if remaining_bits == 0 {
+        return true;
+    }
+
+    let mask = 0xffu8 << (8 - remaining_bits);
+
+    (network[full_bytes] & mask) == (address[full_bytes] & mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wg::userspace::is_allowed;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_is_allowed() {
+        let all: Vec<(IpAddr, u8)> =
+            vec![("0.0.0.0".parse().unwrap(), 0), ("::".parse().unwrap(), 0)];
+        assert!(is_allowed(&all, &"10.8.0.1".parse().unwrap()));
+        assert!(is_allowed(&all, &"2001:db8::1".parse().unwrap()));
+
+        let subnets: Vec<(IpAddr, u8)> = vec![
+            ("10.8.0.0".parse().unwrap(), 20),
+            ("fd00::".parse().unwrap(), 64),
+        ];
+        assert!(is_allowed(&subnets, &"10.8.15.255".parse().unwrap()));
+        assert!(!is_allowed(&subnets, &"10.8.16.1".parse().unwrap()));
+        assert!(is_allowed(&subnets, &"fd00::2".parse().unwrap()));
+        assert!(!is_allowed(&subnets, &"fd00:0:0:1::2".parse().unwrap()));
+
+        // No address family mix
+        let v4_only: Vec<(IpAddr, u8)> = vec![("0.0.0.0".parse().unwrap(), 0)];
+        assert!(!is_allowed(&v4_only, &"::1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,382 @@
+use crate::error::CliClientError;
+use crate::error::CliClientError::{NetlinkError, ParseError};
+use crate::wg::backend::TunnelHealth;
+use neli::consts::nl::NlmF;
+use neli::consts::socket::NlFamily;
+use neli::genl::{AttrTypeBuilder, Genlmsghdr, GenlmsghdrBuilder, Nlattr, NlattrBuilder};
+use neli::nl::NlPayload;
+use neli::router::synchronous::NlRouter;
+use neli::types::{Buffer, GenlBuffer};
+use neli::utils::Groups;
+use neli::{Size, ToBytes};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// linux/wireguard.h
+const WG_GENL_NAME: &str = "wireguard";
+const WG_GENL_VERSION: u8 = 1;
+const WG_CMD_GET_DEVICE: u8 = 0;
+const WG_CMD_SET_DEVICE: u8 = 1;
+
+const WGDEVICE_A_IFNAME: u16 = 2;
+const WGDEVICE_A_PRIVATE_KEY: u16 = 3;
+const WGDEVICE_A_FWMARK: u16 = 7;
+const WGDEVICE_A_PEERS: u16 = 8;
+
+const WGPEER_A_PUBLIC_KEY: u16 = 1;
+const WGPEER_A_FLAGS: u16 = 3;
+const WGPEER_A_ENDPOINT: u16 = 4;
+const WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL: u16 = 5;
+const WGPEER_A_LAST_HANDSHAKE_TIME: u16 = 6;
+const WGPEER_A_RX_BYTES: u16 = 7;
+const WGPEER_A_TX_BYTES: u16 = 8;
+const WGPEER_A_ALLOWEDIPS: u16 = 9;
+const WGPEER_F_REPLACE_ALLOWEDIPS: u32 = 1 << 1;
+const WGPEER_F_UPDATE_ONLY: u32 = 1 << 2;
+
+const WGALLOWEDIP_A_FAMILY: u16 = 1;
+const WGALLOWEDIP_A_IPADDR: u16 = 2;
+const WGALLOWEDIP_A_CIDR_MASK: u16 = 3;
+
+// Entries of the nested attribute arrays (peers, allowed ips)
+const NESTED_ENTRY: u16 = 0;
+
+const KEY_LENGTH: usize = 32;
+
+pub struct WgPeer<'a> {
+    // Base64 encoded public key
+    pub public_key: &'a str,
+
+    pub endpoint: SocketAddr,
+
+    // (address, prefix length)
+    pub allowed_ips: &'a [(IpAddr, u8)],
+
+    // Seconds. Disabled if None.
+    pub persistent_keepalive: Option<u16>,
+}
+
+/*
+* Set the device's private key and add (or update) the peer.
+* Equivalent to "wg set <device> private-key <key> peer <peer> allowed-ips <ips> endpoint <endpoint>".
+*/
+pub fn configure_device(
+    device: &str,
+    private_key: &str,
+    peer: &WgPeer,
+) -> Result<(), CliClientError> {
+    let mut allowed_ip_entries = Vec::with_capacity(peer.allowed_ips.len());
+
+    for (address, prefix_len) in peer.allowed_ips {
+        let (family, octets) = match address {
+            IpAddr::V4(address) => (libc::AF_INET as u16, address.octets().to_vec()),
+            IpAddr::V6(address) => (libc::AF_INET6 as u16, address.octets().to_vec()),
+        };
+
+        let allowed_ip = nest(
+            attr(NESTED_ENTRY, Vec::<u8>::new())?,
+            &[
+                attr(WGALLOWEDIP_A_FAMILY, family)?,
+                attr(WGALLOWEDIP_A_IPADDR, octets)?,
+                attr(WGALLOWEDIP_A_CIDR_MASK, *prefix_len)?,
+            ],
+        )?;
+
+        allowed_ip_entries.push(allowed_ip);
+    }
+
+    let allowed_ips = nest(
+        attr(WGPEER_A_ALLOWEDIPS, Vec::<u8>::new())?,
+        &allowed_ip_entries,
+    )?;
+
+    let peer_entry = nest(
+        attr(NESTED_ENTRY, Vec::<u8>::new())?,
+        &[
+            attr(WGPEER_A_PUBLIC_KEY, decode_key(peer.public_key)?)?,
+            attr(WGPEER_A_FLAGS, WGPEER_F_REPLACE_ALLOWEDIPS)?,
+            attr(WGPEER_A_ENDPOINT, sockaddr(&peer.endpoint))?,
+            attr(
+                WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL,
+                peer.persistent_keepalive.unwrap_or(0),
+            )?,
+            allowed_ips,
+        ],
+    )?;
+
+    let peers = nest(attr(WGDEVICE_A_PEERS, Vec::<u8>::new())?, &[peer_entry])?;
+
+    let mut attrs = GenlBuffer::new();
+    attrs.push(attr(WGDEVICE_A_IFNAME, format!("{}\0", device))?);
+    attrs.push(attr(WGDEVICE_A_PRIVATE_KEY, decode_key(private_key)?)?);
+    attrs.push(peers);
+
+    set_device(attrs)
+}
+
+// Equivalent to "wg set <device> peer <peer> endpoint <endpoint>". The peer must exist.
+pub fn set_peer_endpoint(
+    device: &str,
+    peer_public_key: &str,
+    endpoint: &SocketAddr,
+) -> Result<(), CliClientError> {
+    let peer_entry = nest(
+        attr(NESTED_ENTRY, Vec::<u8>::new())?,
+        &[
+            attr(WGPEER_A_PUBLIC_KEY, decode_key(peer_public_key)?)?,
+            attr(WGPEER_A_FLAGS, WGPEER_F_UPDATE_ONLY)?,
+            attr(WGPEER_A_ENDPOINT, sockaddr(endpoint))?,
+        ],
+    )?;
+
+    let mut attrs = GenlBuffer::new();
+    attrs.push(attr(WGDEVICE_A_IFNAME, format!("{}\0", device))?);
+    attrs.push(nest(
+        attr(WGDEVICE_A_PEERS, Vec::<u8>::new())?,
+        &[peer_entry],
+    )?);
+
+    set_device(attrs)
+}
+
+// Equivalent to "wg set <device> fwmark <fwmark>"
+pub fn set_fwmark(device: &str, fwmark: u32) -> Result<(), CliClientError> {
+    let mut attrs = GenlBuffer::new();
+    attrs.push(attr(WGDEVICE_A_IFNAME, format!("{}\0", device))?);
+    attrs.push(attr(WGDEVICE_A_FWMARK, fwmark)?);
+
+    set_device(attrs)
+}
+
+/*
+* Most recent handshake and transfer counters of the device's peer. None if there is no peer.
+* Equivalent to "wg show <device> latest-handshakes" and "wg show <device> transfer".
+*/
+pub fn peer_stats(device: &str) -> Result<Option<TunnelHealth>, CliClientError> {
+    let (router, family_id) = connect()?;
+
+    let mut attrs = GenlBuffer::new();
+    attrs.push(attr(WGDEVICE_A_IFNAME, format!("{}\0", device))?);
+
+    let message = GenlmsghdrBuilder::<u8, u16>::default()
+        .cmd(WG_CMD_GET_DEVICE)
+        .version(WG_GENL_VERSION)
+        .attrs(attrs)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build wireguard request. {:?}", e)))?;
+
+    let responses = router
+        .send::<_, _, u16, Genlmsghdr<u8, u16>>(family_id, NlmF::DUMP, NlPayload::Payload(message))
+        .map_err(|e| NetlinkError(format!("Could not get the wireguard device. {:?}", e)))?;
+
+    let mut stats = None;
+
+    for response in responses {
+        let response = response
+            .map_err(|e| NetlinkError(format!("Could not get the wireguard device. {:?}", e)))?;
+
+        let device = match response.nl_payload() {
+            NlPayload::Payload(device) => device,
+            _ => continue,
+        };
+
+        let device_attrs = device.attrs().get_attr_handle();
+        let peers = match device_attrs.get_nested_attributes::<u16>(WGDEVICE_A_PEERS) {
+            Ok(peers) => peers,
+            // No peer
+            Err(_) => continue,
+        };
+
+        for peer in peers.iter() {
+            let peer_attrs = peer
+                .get_attr_handle::<u16>()
+                .map_err(|e| NetlinkError(format!("Invalid wireguard peer. {:?}", e)))?;
+
+            // The device has a single peer
+            stats = Some(TunnelHealth {
+                last_handshake: peer_attrs
+                    .get_attribute(WGPEER_A_LAST_HANDSHAKE_TIME)
+                    .and_then(|time| handshake_time(time.nla_payload().as_ref())),
+                rx_bytes: peer_attrs
+                    .get_attr_payload_as::<u64>(WGPEER_A_RX_BYTES)
+                    .unwrap_or(0),
+                tx_bytes: peer_attrs
+                    .get_attr_payload_as::<u64>(WGPEER_A_TX_BYTES)
+                    .unwrap_or(0),
+            });
+        }
+    }
+
+    Ok(stats)
+}
+
+// struct __kernel_timespec. Zero if there was no handshake.
+fn handshake_time(timespec: &[u8]) -> Option<SystemTime> {
+    if timespec.len() < 16 {
+        return None;
+    }
+
+    let seconds = i64::from_ne_bytes(timespec[..8].try_into().unwrap());
+    let nanoseconds = i64::from_ne_bytes(timespec[8..16].try_into().unwrap());
+
+    if seconds <= 0 && nanoseconds <= 0 {
+        return None;
+    }
+
+    Some(UNIX_EPOCH + Duration::new(seconds as u64, nanoseconds as u32))
+}
+
+fn connect() -> Result<(NlRouter, u16), CliClientError> {
+    let (router, _) = NlRouter::connect(NlFamily::Generic, None, Groups::empty())
+        .map_err(|e| NetlinkError(format!("Could not open generic netlink socket. {:?}", e)))?;
+
+    // Fails if the wireguard kernel module is not available
+    let family_id = router
+        .resolve_genl_family(WG_GENL_NAME)
+        .map_err(|e| NetlinkError(format!("Could not resolve wireguard family. {:?}", e)))?;
+
+    Ok((router, family_id))
+}
+
+fn set_device(attrs: GenlBuffer<u16, Buffer>) -> Result<(), CliClientError> {
+    let (router, family_id) = connect()?;
+
+    let message = GenlmsghdrBuilder::<u8, u16>::default()
+        .cmd(WG_CMD_SET_DEVICE)
+        .version(WG_GENL_VERSION)
+        .attrs(attrs)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build wireguard request. {:?}", e)))?;
+
+    let responses = router
+        .send::<_, _, u16, Genlmsghdr<u8, u16>>(family_id, NlmF::ACK, NlPayload::Payload(message))
+        .map_err(|e| NetlinkError(format!("Could not configure wireguard. {:?}", e)))?;
+
+    for response in responses {
+        response.map_err(|e| NetlinkError(format!("Could not configure wireguard. {:?}", e)))?;
+    }
+
+    Ok(())
+}
+
+fn attr<P>(nla_type: u16, payload: P) -> Result<Nlattr<u16, Buffer>, CliClientError>
+where
+    P: Size + ToBytes,
+{
+    let attr_type = AttrTypeBuilder::default()
+        .nla_type(nla_type)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build attribute. {:?}", e)))?;
+
+    NlattrBuilder::default()
+        .nla_type(attr_type)
+        .nla_payload(payload)
+        .build()
+        .map_err(|e| NetlinkError(format!("Could not build attribute. {:?}", e)))
+}
+
+fn nest(
+    mut parent: Nlattr<u16, Buffer>,
+    children: &[Nlattr<u16, Buffer>],
+) -> Result<Nlattr<u16, Buffer>, CliClientError> {
+    for child in children {
+        parent = parent
+            .nest(child)
+            .map_err(|e| NetlinkError(format!("Could not build nested attribute. {:?}", e)))?;
+    }
+
+    Ok(parent)
+}
+
+pub fn decode_key(key: &str) -> Result<Vec<u8>, CliClientError> {
+    let key = base64::decode(key).map_err(|e| ParseError(format!("Invalid key. {:?}", e)))?;
+
+    if key.len() != KEY_LENGTH {
+        return Err(ParseError(format!(
+            "Invalid key length {}. Expected {}.",
+            key.len(),
+            KEY_LENGTH
+        )));
+    }
+
+    Ok(key)
+}
+
+// struct sockaddr_in / sockaddr_in6
+fn sockaddr(address: &SocketAddr) -> Vec<u8> {
+    let mut sockaddr = Vec::new();
+
+    match address {
+        SocketAddr::V4(address) => {
+            sockaddr.extend_from_slice(&(libc::AF_INET as u16).to_ne_bytes());
+            sockaddr.extend_from_slice(&address.port().to_be_bytes());
+            sockaddr.extend_from_slice(&address.ip().octets());
+            sockaddr.extend_from_slice(&[0u8; 8]);
+        }
+        SocketAddr::V6(address) => {
+            sockaddr.extend_from_slice(&(libc::AF_INET6 as u16).to_ne_bytes());
+            sockaddr.extend_from_slice(&address.port().to_be_bytes());
+            sockaddr.extend_from_slice(&address.flowinfo().to_be_bytes());
+            sockaddr.extend_from_slice(&address.ip().octets());
+            sockaddr.extend_from_slice(&address.scope_id().to_ne_bytes());
+        }
+    }
+
+    sockaddr
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wg::wireguard::{decode_key, handshake_time, sockaddr};
+    use std::net::SocketAddr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_sockaddr() {
+        let address: SocketAddr = "10.1.2.3:51820".parse().unwrap();
+        let encoded = sockaddr(&address);
+
+        assert_eq!(16, encoded.len());
+        assert_eq!(
+            libc::AF_INET as u16,
+            u16::from_ne_bytes([encoded[0], encoded[1]])
+        );
+        assert_eq!([0xca, 0x6c], encoded[2..4]);
+        assert_eq!([10, 1, 2, 3], encoded[4..8]);
+
+        let address: SocketAddr = "[2001:db8::1]:51820".parse().unwrap();
+        let encoded = sockaddr(&address);
+
+        assert_eq!(28, encoded.len());
+        assert_eq!(
+            libc::AF_INET6 as u16,
+            u16::from_ne_bytes([encoded[0], encoded[1]])
+        );
+        assert_eq!([0x20, 0x01, 0x0d, 0xb8], encoded[8..12]);
+    }
+
+    #[test]
+    fn test_handshake_time() {
+        let mut timespec = 1_700_000_000i64.to_ne_bytes().to_vec();
+        timespec.extend_from_slice(&500i64.to_ne_bytes());
+
+        assert_eq!(
+            Some(UNIX_EPOCH + Duration::new(1_700_000_000, 500)),
+            handshake_time(&timespec)
+        );
+        assert_eq!(None, handshake_time(&[0u8; 16]));
+        assert_eq!(None, handshake_time(&[]));
+    }
+
+    #[test]
+    fn test_decode_key() {
+        assert_eq!(
+            32,
+            decode_key("/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=")
+                .unwrap()
+                .len()
+        );
+        assert!(decode_key("c2hvcnQ=").is_err());
+        assert!(decode_key("not base64!").is_err());
+    }
+}
@@ -1,13 +1,26 @@
 package io.veronymous.client.jni;
 
+import io.veronymous.client.exceptions.IllegalStateException;
+
 public class GetServersResult {
 
     private final String[] servers;
+    // JSON array of the servers with their metadata (country, city, features, ...)
+    private final String serversInfo;
+    private final boolean hasError;
+    private final String error;
     private final ServersStateResult serversStateResult;
 
 
-    public GetServersResult(String[] servers, ServersStateResult serversStateResult) {
+    public GetServersResult(String[] servers,
+                            String serversInfo,
+                            boolean hasError,
+                            String error,
+                            ServersStateResult serversStateResult) {
         this.servers = servers;
+        this.serversInfo = serversInfo;
+        this.hasError = hasError;
+        this.error = error;
         this.serversStateResult = serversStateResult;
     }
 
@@ -16,6 +29,23 @@ public class GetServersResult {
         return servers;
     }
 
+    public String getServersInfo() throws IllegalStateException {
+        if (this.hasError)
+            throw new IllegalStateException("Has an error.");
+
+        return serversInfo;
+    }
+
+    public boolean hasError() {
+        return hasError;
+    }
+
+    public String getError() throws IllegalStateException {
+        if (!this.hasError)
+            throw new IllegalStateException("Does not have error.");
+        return error;
+    }
+
     public ServersStateResult getServersStateResult() {
         return serversStateResult;
     }
@@ -10,7 +10,8 @@ use veronymous_client::error::VeronymousClientError;
 use veronymous_client::error::VeronymousClientError::VeronymousError;
 use veronymous_client::oidc::client::OidcClient;
 use veronymous_client::oidc::credentials::UserCredentials;
-
+use veronymous_client::proxy::parse_proxy;
+use veronymous_client::servers::query::ServerFilter;
 use veronymous_client::servers::VpnServers;
 use veronymous_client::veronymous_token::client::VeronymousTokenClient;
 
@@ -57,14 +58,48 @@ pub extern "system" fn Java_io_veronymous_client_jni_VeronymousClientJni_getServ
         },
     );
 
-    let java_server_list = to_j_array(&mut env, servers_state.list_domains().into());
+    // Servers sorted by name with their metadata (country, city, features, ...)
+    let servers = servers_state.list_servers(&ServerFilter::default());
+    let server_list = servers.iter().map(|server| server.domain.clone()).collect();
+
+    let java_server_list = to_j_array(&mut env, server_list);
+
+    // The server names are still returned if the metadata could not be serialized
+    let (java_servers_info, java_has_error, java_error) = match to_string(&servers) {
+        Ok(servers_info) => {
+            let java_servers_info: JObject = env
+                .new_string(servers_info)
+                .expect("Could not create Java string")
+                .into();
+
+            (
+                java_servers_info,
+                JValue::Bool(false as jboolean),
+                JObject::null(),
+            )
+        }
+        Err(error) => {
+            let java_error: JObject = env
+                .new_string(format!("Could not serialize servers. {:?}", error))
+                .expect("Could not create Java string")
+                .into();
+
+            (JObject::null(), JValue::Bool(true as jboolean), java_error)
+        }
+    };
 
     let java_servers_result = env
         .new_object(
             GET_SERVERS_RESULT_CLASS,
-            format!("([Ljava/lang/String;L{};)V", SERVERS_STATE_RESULT),
+            format!(
+                "([Ljava/lang/String;Ljava/lang/String;ZLjava/lang/String;L{};)V",
+                SERVERS_STATE_RESULT
+            ),
             &[
                 JValue::Object(&java_server_list),
+                JValue::Object(&java_servers_info),
+                java_has_error,
+                JValue::Object(&java_error),
                 JValue::Object(&java_servers_state),
             ],
         )
@@ -107,16 +142,22 @@ pub extern "system" fn Java_io_veronymous_client_jni_VeronymousClientJni_connect
 
     // Create Veronymous client
     let connect_result = runtime.block_on(async {
+        // Optional SOCKS5 proxy for the token issuer and IdP traffic
+        let proxy = parse_proxy(&VERONYMOUS_CLIENT_CONFIG.proxy)?;
+
         // Create open id connect client
         let oidc_client = OidcClient::new(
             VERONYMOUS_CLIENT_CONFIG.oidc_endpoint.clone(),
             VERONYMOUS_CLIENT_CONFIG.oidc_client_id.clone(),
+            proxy.clone(),
         );
 
         // Create token client
         let token_client = VeronymousTokenClient::create(
             &VERONYMOUS_CLIENT_CONFIG.token_endpoint,
             &VERONYMOUS_CLIENT_CONFIG.token_endpoint_ca,
+            &VERONYMOUS_CLIENT_CONFIG.token_endpoint_pins,
+            &proxy,
         )
         .await
         .map_err(|e| VeronymousError(format!("{:?}", e)))?;
@@ -246,16 +287,22 @@ pub extern "system" fn Java_io_veronymous_client_jni_VeronymousClientJni_authent
     let runtime = runtime::Runtime::new().expect("Could not create tokio runtime.");
 
     let authentication_result = runtime.block_on(async {
+        // Optional SOCKS5 proxy for the token issuer and IdP traffic
+        let proxy = parse_proxy(&VERONYMOUS_CLIENT_CONFIG.proxy)?;
+
         // Create open id connect client
         let oidc_client = OidcClient::new(
             VERONYMOUS_CLIENT_CONFIG.oidc_endpoint.clone(),
             VERONYMOUS_CLIENT_CONFIG.oidc_client_id.clone(),
+            proxy.clone(),
         );
 
         // Create token client
         let token_client = VeronymousTokenClient::create(
             &VERONYMOUS_CLIENT_CONFIG.token_endpoint,
             &VERONYMOUS_CLIENT_CONFIG.token_endpoint_ca,
+            &VERONYMOUS_CLIENT_CONFIG.token_endpoint_pins,
+            &proxy,
         )
         .await
         .map_err(|e| VeronymousError(format!("{:?}", e)))?;
@@ -286,16 +333,22 @@ pub extern "system" fn Java_io_veronymous_client_jni_VeronymousClientJni_refresh
     let runtime = runtime::Runtime::new().expect("Could not create tokio runtime.");
 
     let authentication_result = runtime.block_on(async {
+        // Optional SOCKS5 proxy for the token issuer and IdP traffic
+        let proxy = parse_proxy(&VERONYMOUS_CLIENT_CONFIG.proxy)?;
+
         // Create open id connect client
         let oidc_client = OidcClient::new(
             VERONYMOUS_CLIENT_CONFIG.oidc_endpoint.clone(),
             VERONYMOUS_CLIENT_CONFIG.oidc_client_id.clone(),
+            proxy.clone(),
         );
 
         // Create token client
         let token_client = VeronymousTokenClient::create(
             &VERONYMOUS_CLIENT_CONFIG.token_endpoint,
             &VERONYMOUS_CLIENT_CONFIG.token_endpoint_ca,
+            &VERONYMOUS_CLIENT_CONFIG.token_endpoint_pins,
+            &proxy,
         )
         .await
         .expect("Could not create token client.");
@@ -1,7 +1,65 @@
+use std::env;
+use std::fs;
+use std::path::Path;
 use tonic_build;
 
+// Signed servers list bundled as the last resort. The signature is read from <file>.sig.
+const SERVERS_SNAPSHOT_ENV: &str = "VERONYMOUS_SERVERS_SNAPSHOT";
+
+// Base64 Ed25519 key of the servers list signer, compiled into the production builds
+const SERVERS_PUBLIC_KEY_ENV: &str = "VERONYMOUS_SERVERS_PUBLIC_KEY";
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::compile_protos("./proto/veronymous_user_token_service.proto")?;
 
+    bundle_servers_snapshot()?;
+
+    println!("cargo:rerun-if-env-changed={}", SERVERS_PUBLIC_KEY_ENV);
+    if is_production() && env::var(SERVERS_PUBLIC_KEY_ENV).is_err() {
+        return Err(format!(
+            "{} must be set for the production builds.",
+            SERVERS_PUBLIC_KEY_ENV
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/*
+* Copy the snapshot to OUT_DIR for include_bytes.
+* Empty files if none is set. The production builds require one.
+*/
+fn bundle_servers_snapshot() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-env-changed={}", SERVERS_SNAPSHOT_ENV);
+
+    let out_dir = env::var("OUT_DIR")?;
+    let out_dir = Path::new(&out_dir);
+
+    let (servers, signature) = match env::var(SERVERS_SNAPSHOT_ENV) {
+        Ok(path) => {
+            let signature_path = format!("{}.sig", path);
+            println!("cargo:rerun-if-changed={}", path);
+            println!("cargo:rerun-if-changed={}", signature_path);
+
+            (fs::read(&path)?, fs::read(&signature_path)?)
+        }
+        Err(_) if is_production() => {
+            return Err(format!(
+                "{} must be set to a signed servers list for the production builds.",
+                SERVERS_SNAPSHOT_ENV
+            )
+            .into())
+        }
+        Err(_) => (vec![], vec![]),
+    };
+
+    fs::write(out_dir.join("bundled_servers.json"), servers)?;
+    fs::write(out_dir.join("bundled_servers.json.sig"), signature)?;
+
     Ok(())
 }
+
+fn is_production() -> bool {
+    env::var("CARGO_FEATURE_PRODUCTION").is_ok()
+}
@@ -10,11 +10,14 @@ use crate::error::VeronymousClientError::{
 use crate::oidc::client::OidcClient;
 use crate::oidc::credentials::{OidcCredentials, OidcCredentialsStatus, UserCredentials};
 use crate::servers::VpnServers;
+use crate::tls::parse_pinned_tls_config;
+use crate::transport::pinned_relay;
 use crate::veronymous_token::client::VeronymousTokenClient;
 use crate::vpn::VpnProfile;
 use crate::wg::generate_keypair;
 use rand::thread_rng;
 use std::time::{SystemTime, UNIX_EPOCH};
+use tonic::transport::Uri;
 use veronymous_router_client::VeronymousRouterClient;
 use veronymous_token::token::{get_current_epoch, VeronymousToken};
 
@@ -160,13 +163,17 @@ impl VeronymousClient {
         vpn_profile: &VpnProfile,
         auth_token: VeronymousToken,
     ) -> Result<VpnConnection, VeronymousClientError> {
-        let root_cert = match &vpn_profile.root_cert {
-            None => None,
-            Some(cert) => Some(cert.as_bytes()),
+        // The token is only sent through the pinned TLS session if the agent is pinned
+        let (agent_endpoint, root_cert) = match Self::pinned_agent_endpoint(vpn_profile).await? {
+            Some(relay_endpoint) => (relay_endpoint, None),
+            None => (
+                vpn_profile.agent_endpoint.clone(),
+                vpn_profile.root_cert.as_ref().map(|cert| cert.as_bytes()),
+            ),
         };
 
         // Create the client
-        let mut router_client = VeronymousRouterClient::new(&vpn_profile.agent_endpoint, root_cert)
+        let mut router_client = VeronymousRouterClient::new(&agent_endpoint, root_cert)
             .await
             .map_err(|e| ConnectError(format!("Could not create router agent client. {:?}", e)))?;
 
@@ -181,7 +188,7 @@ impl VeronymousClient {
             .await
             .map_err(|e| ConnectError(format!("Could not create connection. {:?}", e)))?;
 
-        let vpn_connection = VpnConnection::new(
+        let mut vpn_connection = VpnConnection::new(
             vec![
                 connection.ipv4_address.to_string(),
                 connection.ipv6_address.to_string(),
@@ -191,11 +198,35 @@ impl VeronymousClient {
             private_key,
             public_key,
             vpn_profile.domain.clone(),
+            vpn_profile.dns_servers.clone(),
         );
+        vpn_connection.tunnel_hints = vpn_profile.tunnel_hints.clone();
 
         Ok(vpn_connection)
     }
 
+    /*
+     * The router client opens its own connection. With pins, it connects to a local relay
+     * forwarding to the agent through a pinned TLS session. None if the agent is not pinned.
+     */
+    async fn pinned_agent_endpoint(
+        vpn_profile: &VpnProfile,
+    ) -> Result<Option<String>, VeronymousClientError> {
+        let pinned_tls =
+            match parse_pinned_tls_config(&vpn_profile.root_cert, &vpn_profile.agent_pins)? {
+                None => return Ok(None),
+                Some(pinned_tls) => pinned_tls,
+            };
+
+        let agent_uri: Uri = match vpn_profile.agent_endpoint.contains("://") {
+            true => vpn_profile.agent_endpoint.parse(),
+            false => format!("https://{}", vpn_profile.agent_endpoint).parse(),
+        }
+        .map_err(|e| ParseError(format!("Could not parse agent endpoint. {:?}", e)))?;
+
+        Ok(Some(pinned_relay(&agent_uri, pinned_tls).await?))
+    }
+
     /*
      * Ensure that the client state contains the required token info
      */
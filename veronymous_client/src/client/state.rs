@@ -4,6 +4,7 @@ use std::collections::HashMap;
 use veronymous_token::root::RootVeronymousToken;
 
 use crate::oidc::credentials::OidcCredentials;
+use crate::vpn::TunnelHints;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ClientState {
@@ -61,6 +62,14 @@ pub struct VpnConnection {
     pub client_public_key: String,
 
     pub domain: String,
+
+    // Resolvers reached through the tunnel. Missing for connections saved by older clients.
+    #[serde(default)]
+    pub dns_servers: Vec<String>,
+
+    // Set from the server profile
+    #[serde(default)]
+    pub tunnel_hints: TunnelHints,
 }
 
 impl VpnConnection {
@@ -71,6 +80,7 @@ impl VpnConnection {
         client_private_key: String,
         client_public_key: String,
         domain: String,
+        dns_servers: Vec<String>,
     ) -> Self {
         Self {
             client_addresses,
@@ -79,6 +89,8 @@ impl VpnConnection {
             client_private_key,
             client_public_key,
             domain,
+            dns_servers,
+            tunnel_hints: TunnelHints::default(),
         }
     }
 }
@@ -125,6 +137,18 @@ impl VpnConnections {
         }
     }
 
+    // e.g., the connection stopped working and must be replaced
+    pub fn remove_connection(&mut self, epoch: &u64, domain: &String) -> Option<VpnConnection> {
+        let connections = self.connections.get_mut(epoch)?;
+        let connection = connections.remove(domain);
+
+        if connections.is_empty() {
+            self.connections.remove(epoch);
+        }
+
+        connection
+    }
+
     pub fn clear_old(&mut self, current_epoch: u64) {
         self.connections.retain(|&epoch, _| epoch >= current_epoch);
     }
@@ -208,7 +232,7 @@ impl EpochMap<IssuerInfo> for IssuerInfos {
 
 #[cfg(test)]
 mod tests {
-    use crate::client::state::EpochMap;
+    use crate::client::state::{EpochMap, VpnConnection, VpnConnections};
     use std::collections::HashMap;
 
     struct TestEpochMap {
@@ -236,4 +260,57 @@ mod tests {
         assert!(epoch_map.map.contains_key(&30));
         assert!(!epoch_map.map.contains_key(&10));
     }
+
+    #[test]
+    fn test_remove_connection() {
+        let mut connections = VpnConnections::empty();
+        let new_york = "new_york".to_string();
+        let toronto = "toronto".to_string();
+
+        connections.add_connection(connection(&new_york), 10, new_york.clone());
+        connections.add_connection(connection(&toronto), 10, toronto.clone());
+
+        assert!(connections.remove_connection(&20, &new_york).is_none());
+        assert_eq!(
+            new_york,
+            connections
+                .remove_connection(&10, &new_york)
+                .unwrap()
+                .domain
+        );
+        assert!(!connections.has_connection(&10, &new_york));
+        assert!(connections.has_connection(&10, &toronto));
+
+        // The epoch is removed with its last connection
+        connections.remove_connection(&10, &toronto);
+        assert!(connections.connections.is_empty());
+    }
+
+    #[test]
+    fn test_vpn_connection_without_dns_servers() {
+        let connection_json = r#"{
+            "client_addresses": ["10.8.0.2", "fd00::2"],
+            "wg_public_key": "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=",
+            "wg_endpoint": "wg1.ny.veronymous.io:51820",
+            "client_private_key": "private_key",
+            "client_public_key": "public_key",
+            "domain": "new_york"
+        }"#;
+
+        let connection: VpnConnection = serde_json::from_str(connection_json).unwrap();
+
+        assert!(connection.dns_servers.is_empty());
+    }
+
+    fn connection(domain: &str) -> VpnConnection {
+        VpnConnection::new(
+            vec!["10.8.0.2".to_string()],
+            "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=".to_string(),
+            "wg1.ny.veronymous.io:51820".to_string(),
+            "private_key".to_string(),
+            "public_key".to_string(),
+            domain.to_string(),
+            vec![],
+        )
+    }
 }
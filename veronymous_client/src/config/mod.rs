@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use std::net::IpAddr;
 
 lazy_static! {
     pub static ref VERONYMOUS_CLIENT_CONFIG: VeronymousClientConfig =
@@ -21,8 +22,20 @@ pub struct VeronymousClientConfig {
 
     pub token_endpoint_ca: Option<String>,
 
+    // Token issuer public key pins (sha256/<base64>). Every additional pin is a backup.
+    pub token_endpoint_pins: Vec<String>,
+
     pub servers_endpoint: String,
 
+    // Servers list mirrors. Tried in order if the servers endpoint is unavailable.
+    pub servers_mirrors: Vec<String>,
+
+    // Base64 Ed25519 public key of the servers list signer. Unsigned lists are refused if set.
+    pub servers_public_key: Option<String>,
+
+    // Accept unsigned servers lists if there is no public key (e.g., local development)
+    pub allow_unsigned_servers: bool,
+
     // Hosts that must not go through the vpn tunnel
     // This is required to prevent correlation from some applications (e.g., token-issuer auth token)
     pub out_of_band_hosts: Vec<String>,
@@ -32,6 +45,70 @@ pub struct VeronymousClientConfig {
 
     // Subscription OIDC user role
     pub sub_oidc_role: String,
+
+    // SOCKS5 proxy for the token issuer and IdP traffic (e.g., socks5h://127.0.0.1:9050 for Tor)
+    pub proxy: Option<String>,
+
+    pub tunnel: TunnelConfig,
+}
+
+// Wireguard interface settings. Overridden by the CLI flags.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TunnelConfig {
+    pub interface: String,
+
+    // Overrides the server's suggestion
+    pub mtu: Option<u32>,
+
+    // Marks the tunnel's own packets so they skip the tunnel routing table
+    pub fwmark: u32,
+
+    // Routing table of the tunnel's default route
+    pub table: u32,
+
+    // Seconds. Overrides the server's suggestion.
+    pub persistent_keepalive: Option<u16>,
+
+    // Traffic sent to the server (<address>/<prefix length>)
+    pub allowed_ips: Vec<String>,
+
+    // Bring the next epoch's connection up before tearing down the current one
+    pub seamless_rotation: bool,
+
+    // Seconds the previous connection is kept after a seamless rotation
+    pub rotation_grace_period: u64,
+
+    // Seconds between the tunnel health checks. 0 disables the health checks.
+    pub health_check_interval: u64,
+
+    // Pinged through the tunnel on every health check (e.g., the server's internal address)
+    pub health_check_address: Option<IpAddr>,
+
+    // Only these destinations go through the tunnel (<address>/<prefix length> or domain)
+    pub split_include: Vec<String>,
+
+    // These destinations bypass the tunnel. Cannot be combined with split_include.
+    pub split_exclude: Vec<String>,
+}
+
+impl Default for TunnelConfig {
+    fn default() -> Self {
+        Self {
+            interface: "veron0".to_string(),
+            mtu: None,
+            fwmark: 51820,
+            table: 51820,
+            persistent_keepalive: None,
+            allowed_ips: vec!["0.0.0.0/0".to_string(), "::/0".to_string()],
+            seamless_rotation: true,
+            rotation_grace_period: 30,
+            health_check_interval: 15,
+            health_check_address: None,
+            split_include: vec![],
+            split_exclude: vec![],
+        }
+    }
 }
 
 #[cfg(feature = "dev-local")]
@@ -54,10 +131,16 @@ impl Default for VeronymousClientConfig {
             oidc_client_id: "auth-client".to_string(),
             token_endpoint: "https://localhost.veronymous.io:9123".to_string(),
             token_endpoint_ca: Some("-----BEGIN CERTIFICATE-----\nMIIDyzCCArOgAwIBAgIUANb3hm6n1wwhGkjB0XN2fctauGUwDQYJKoZIhvcNAQEL\nBQAwdTELMAkGA1UEBhMCQ0ExEDAOBgNVBAgMB09udGFyaW8xDzANBgNVBAcMBk90\ndGF3YTEhMB8GA1UECgwYSW50ZXJuZXQgV2lkZ2l0cyBQdHkgTHRkMSAwHgYDVQQD\nDBdsb2NhbGhvc3QudmVyb255bW91cy5pbzAeFw0yMjEyMjExMzI3NDFaFw0yNzEy\nMjAxMzI3NDFaMHUxCzAJBgNVBAYTAkNBMRAwDgYDVQQIDAdPbnRhcmlvMQ8wDQYD\nVQQHDAZPdHRhd2ExITAfBgNVBAoMGEludGVybmV0IFdpZGdpdHMgUHR5IEx0ZDEg\nMB4GA1UEAwwXbG9jYWxob3N0LnZlcm9ueW1vdXMuaW8wggEiMA0GCSqGSIb3DQEB\nAQUAA4IBDwAwggEKAoIBAQCxx0+i60ptd2flxcBw+OpQM2oBm/riL0wGqOWc6j2F\nhEDJkfjcK4Fcc+8hcyGNNy11f2l59yuCY7wJhyZPXhyXi0lrkN328hPo19rYzYze\n83AQYKcq9XucAGbv9kRRSVyyeKu45DqSinClgfZzgB6qRNMB8yZl7cqhVwjLpa47\nVUH4zhDHYfKfH8cBMXGlW2gPexJWqGeusXhuXCd8dHoCzzGr6+NCxkzffpsLI3FN\nLPNXPaq8cYynyi/tO4A3QX6gTOCmKnwlNtZTpHUBy4BKV2HZ4XRVojfH+lOuylL3\nqgzYkQWsqaizZEIzlg5iEh4py50HsTq/JOXpXgfD7eadAgMBAAGjUzBRMB0GA1Ud\nDgQWBBT1Rui71l7VsTyoZvYmkSOTxZFz8TAfBgNVHSMEGDAWgBT1Rui71l7VsTyo\nZvYmkSOTxZFz8TAPBgNVHRMBAf8EBTADAQH/MA0GCSqGSIb3DQEBCwUAA4IBAQAl\n+zDuALuo50w4PClws1ZGRGVYZQqgDKU32oR1zo+rSGbrqcO5yH2aanCeOX5oIJqC\nC1VPyjAbZ6x8kUTfzp+OtT2J3RJTA/jTaP2opR9QHZZ+uYQkalZky/djjsNNw2+X\nvlw2UZ+OfZI/hVEArEo7tc+qUvzcdhbthJOtSFhcQaY04Jd659Cj4svsZm8Jui+v\ngjZpJE1Ezp2hVVMAU7zO1Joe/CqcUnbpQXCPdZ0Wk2XxDwSXKtgY3VyAFJrS/DP7\ngdqvcZekbRaQmNXsK0CUjw5n2pDdgiu4XfN+FL0RN6nuC1ZRw3zNM6Y0qynib697\neFfhCIv9u/9vLexDNm0o\n-----END CERTIFICATE-----".to_string()),
+            token_endpoint_pins: vec![],
             servers_endpoint: "http://localhost:9090/servers.json".to_string(),
+            servers_mirrors: vec![],
+            servers_public_key: None,
+            allow_unsigned_servers: true,
             out_of_band_hosts: vec![],
             sub_oidc_client_id: "user-token-service".to_string(),
             sub_oidc_role: "vpn-user".to_string(),
+            proxy: None,
+            tunnel: TunnelConfig::default(),
         }
     }
 }
@@ -112,13 +195,20 @@ emyPxgcYxn/eR44/KJ4EBs+lVDR3veyJm+kXQ99b21/+jh5Xos1AnX5iItreGCc=
 -----END CERTIFICATE-----"
                     .to_string(),
             ),
+            token_endpoint_pins: vec![],
             servers_endpoint: "https://files.veronymous.io/servers.json".to_string(),
+            servers_mirrors: vec![],
+            // Set at build time. Also verifies the bundled list.
+            servers_public_key: option_env!("VERONYMOUS_SERVERS_PUBLIC_KEY").map(str::to_string),
+            allow_unsigned_servers: false,
             out_of_band_hosts: vec![
                 "token-issuer.veronymous.io:443".to_string(),
                 "idp.veronymous.io:443".to_string(),
             ],
             sub_oidc_client_id: "user-token-issuer".to_string(),
             sub_oidc_role: "vpn-user".to_string(),
+            proxy: None,
+            tunnel: TunnelConfig::default(),
         }
     }
 }
@@ -138,10 +228,16 @@ impl Default for VeronymousClientConfig {
             token_endpoint: "https://token-service.192.168.2.41.veronymous.io".to_string(),
             // token_endpoint_ca: "-----BEGIN CERTIFICATE-----\nMIIDyzCCArOgAwIBAgIUANb3hm6n1wwhGkjB0XN2fctauGUwDQYJKoZIhvcNAQEL\nBQAwdTELMAkGA1UEBhMCQ0ExEDAOBgNVBAgMB09udGFyaW8xDzANBgNVBAcMBk90\ndGF3YTEhMB8GA1UECgwYSW50ZXJuZXQgV2lkZ2l0cyBQdHkgTHRkMSAwHgYDVQQD\nDBdsb2NhbGhvc3QudmVyb255bW91cy5pbzAeFw0yMjEyMjExMzI3NDFaFw0yNzEy\nMjAxMzI3NDFaMHUxCzAJBgNVBAYTAkNBMRAwDgYDVQQIDAdPbnRhcmlvMQ8wDQYD\nVQQHDAZPdHRhd2ExITAfBgNVBAoMGEludGVybmV0IFdpZGdpdHMgUHR5IEx0ZDEg\nMB4GA1UEAwwXbG9jYWxob3N0LnZlcm9ueW1vdXMuaW8wggEiMA0GCSqGSIb3DQEB\nAQUAA4IBDwAwggEKAoIBAQCxx0+i60ptd2flxcBw+OpQM2oBm/riL0wGqOWc6j2F\nhEDJkfjcK4Fcc+8hcyGNNy11f2l59yuCY7wJhyZPXhyXi0lrkN328hPo19rYzYze\n83AQYKcq9XucAGbv9kRRSVyyeKu45DqSinClgfZzgB6qRNMB8yZl7cqhVwjLpa47\nVUH4zhDHYfKfH8cBMXGlW2gPexJWqGeusXhuXCd8dHoCzzGr6+NCxkzffpsLI3FN\nLPNXPaq8cYynyi/tO4A3QX6gTOCmKnwlNtZTpHUBy4BKV2HZ4XRVojfH+lOuylL3\nqgzYkQWsqaizZEIzlg5iEh4py50HsTq/JOXpXgfD7eadAgMBAAGjUzBRMB0GA1Ud\nDgQWBBT1Rui71l7VsTyoZvYmkSOTxZFz8TAfBgNVHSMEGDAWgBT1Rui71l7VsTyo\nZvYmkSOTxZFz8TAPBgNVHRMBAf8EBTADAQH/MA0GCSqGSIb3DQEBCwUAA4IBAQAl\n+zDuALuo50w4PClws1ZGRGVYZQqgDKU32oR1zo+rSGbrqcO5yH2aanCeOX5oIJqC\nC1VPyjAbZ6x8kUTfzp+OtT2J3RJTA/jTaP2opR9QHZZ+uYQkalZky/djjsNNw2+X\nvlw2UZ+OfZI/hVEArEo7tc+qUvzcdhbthJOtSFhcQaY04Jd659Cj4svsZm8Jui+v\ngjZpJE1Ezp2hVVMAU7zO1Joe/CqcUnbpQXCPdZ0Wk2XxDwSXKtgY3VyAFJrS/DP7\ngdqvcZekbRaQmNXsK0CUjw5n2pDdgiu4XfN+FL0RN6nuC1ZRw3zNM6Y0qynib697\neFfhCIv9u/9vLexDNm0o\n-----END CERTIFICATE-----".to_string(),
             token_endpoint_ca: Some("-----BEGIN CERTIFICATE-----\nMIIEPTCCAyWgAwIBAgIUMtcvG69O61fUIz0bbv97vK9oW6kwDQYJKoZIhvcNAQEL\nBQAwga0xCzAJBgNVBAYTAkNBMRAwDgYDVQQIDAdPbnRhcmlvMQ8wDQYDVQQHDAZP\ndHRhd2ExJDAiBgNVBAoMG1Zlcm9ueW1vdXMgVGVjaG5vbG9naWVzIEluYzEUMBIG\nA1UECwwLRGV2ZWxvcG1lbnQxGjAYBgNVBAMMEWRldi52ZXJvbnltb3VzLmlvMSMw\nIQYJKoZIhvcNAQkBFhRuYm91bWFAdmVyb255bW91cy5pbzAeFw0yMjEyMDgxMTMz\nNDFaFw0yNzEyMDcxMTMzNDFaMIGtMQswCQYDVQQGEwJDQTEQMA4GA1UECAwHT250\nYXJpbzEPMA0GA1UEBwwGT3R0YXdhMSQwIgYDVQQKDBtWZXJvbnltb3VzIFRlY2hu\nb2xvZ2llcyBJbmMxFDASBgNVBAsMC0RldmVsb3BtZW50MRowGAYDVQQDDBFkZXYu\ndmVyb255bW91cy5pbzEjMCEGCSqGSIb3DQEJARYUbmJvdW1hQHZlcm9ueW1vdXMu\naW8wggEiMA0GCSqGSIb3DQEBAQUAA4IBDwAwggEKAoIBAQC5DMCIXm8A6xuMaQof\nJr0f2u27HcKhCwr1ch83HtY1+YB6x+7l+ALdsjE7+Ifb3h0p25t4kwpUYj7kyI+k\nqKzaWwZI2SpiyC8ComNO8KI6fRs8rxgxHI9PeF2J7TwiJ7KxdTqhJ/avGkBmDnOt\n6nXo7m/szakMY3EzywFtAeKaV74QFcVKWLdvC6DnwvXxIV7VAG5odfDPMoZbK/Um\nG5I4IXGbSJ9dOjeEnZZbDa6lOsv3vkvRbgWaa5aWGPkKbPy6Jq4qQuKuIqJQB2eX\n5xzS0fV9U8GTOHNymFdMS/f3KMSGp0e7ATod3E8QJEHA761FvkC2rttPlKma7Km9\n+B1rAgMBAAGjUzBRMB0GA1UdDgQWBBT5a9ZBITxCBAa6JGhqgHx6WiJvITAfBgNV\nHSMEGDAWgBT5a9ZBITxCBAa6JGhqgHx6WiJvITAPBgNVHRMBAf8EBTADAQH/MA0G\nCSqGSIb3DQEBCwUAA4IBAQAFn3Wrc/Mj+OJEq8Nr5VOzDveNjzj2an4qZjtwP5lt\n6XOPBNFAFwjd9Cncby6maFNwfTwluPOmP0fcbXh5/hKJtd5FY1kzHcx64rlN0vNJ\n1BleDCNDq5pQfVs+mCm4+SlruqTzeKSnUZvcB0valEWSL/5ApjSdq9112USQHLXn\nIKx/xHR1TWI/NcQ99ONdjMC1YH4EfciwpQDl1UHhSLu+xzxbpwTGxIiZwyvqAhHt\nl7WEy76k+nrcUg/AdUHqg1zoxWam2V7ONuGVnYW78NhloKmtUFLb9/JkxN63xLt3\nq5PoTRvpiVzN3kKHEq3BeafutFTqfBiu6rl9gHKVD3ER\n-----END CERTIFICATE-----".to_string()),
+            token_endpoint_pins: vec![],
             servers_endpoint: "http://servers.192.168.2.41.veronymous.io/servers.json".to_string(),
+            servers_mirrors: vec![],
+            servers_public_key: None,
+            allow_unsigned_servers: true,
             out_of_band_hosts: vec![],
             sub_oidc_client_id: "user-token-service".to_string(),
             sub_oidc_role: "vpn-user".to_string(),
+            proxy: None,
+            tunnel: TunnelConfig::default(),
         }
     }
 }
@@ -53,4 +53,21 @@ pub enum VeronymousClientError {
     #[error("Veronymous error. {0}")]
     VeronymousError(String),
 
+    #[error("Proxy error. {0}")]
+    ProxyError(String),
+
+    #[error("Certificate pin mismatch. {0}")]
+    PinMismatch(String),
+
+    #[error("Signature error. {0}")]
+    SignatureError(String),
+
+    #[error("Downgrade error. {0}")]
+    DowngradeError(String),
+
+    #[error("Digest mismatch. {0}")]
+    DigestMismatch(String),
+
+    #[error("Io error. {0}")]
+    IoError(String),
 }
@@ -8,7 +8,10 @@ pub mod client;
 pub mod config;
 pub mod error;
 pub mod oidc;
+pub mod proxy;
 pub mod servers;
+pub mod tls;
+pub mod transport;
 pub mod veronymous_token;
 pub mod vpn;
 mod wg;
@@ -1,6 +1,11 @@
 use crate::error::VeronymousClientError;
-use crate::error::VeronymousClientError::{DeserializationError, OidcError};
+use crate::error::VeronymousClientError::{
+    AuthRequired, DeserializationError, HttpError, OidcError, VeronymousError,
+};
 use crate::oidc::credentials::{OidcCredentials, UserCredentials};
+use crate::proxy::Socks5Proxy;
+use reqwest::Response;
+use serde::Deserialize;
 use std::collections::HashMap;
 
 const GRANT_TYPE: &str = "grant_type";
@@ -13,19 +18,33 @@ const REFRESH_TOKEN_GRANT: &str = "refresh_token";
 
 // TODO: Put in http constants module
 const STATUS_OK: u16 = 200;
+const STATUS_BAD_REQUEST: u16 = 400;
+const STATUS_UNAUTHORIZED: u16 = 401;
+
+// The credentials or the refresh token were rejected (RFC 6749 section 5.2)
+const INVALID_GRANT: &str = "invalid_grant";
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
 
 pub struct OidcClient {
     token_endpoint: String,
 
     // Public OIDC client
     client_id: String,
+
+    // Optional SOCKS5 proxy for the IdP requests
+    proxy: Option<Socks5Proxy>,
 }
 
 impl OidcClient {
-    pub fn new(token_endpoint: String, client_id: String) -> Self {
+    pub fn new(token_endpoint: String, client_id: String, proxy: Option<Socks5Proxy>) -> Self {
         Self {
             token_endpoint,
             client_id,
+            proxy,
         }
     }
 
@@ -34,7 +53,7 @@ impl OidcClient {
         credentials: &UserCredentials,
     ) -> Result<OidcCredentials, VeronymousClientError> {
         // Http client
-        let client = reqwest::Client::new();
+        let client = self.http_client()?;
 
         // Request form
         let mut body = HashMap::new();
@@ -49,15 +68,14 @@ impl OidcClient {
             .form(&body)
             .send()
             .await
-            .map_err(|e| OidcError(format!("Could not fetch user tokens. {:?}", e)))?;
+            .map_err(|e| HttpError(format!("Could not fetch user tokens. {:?}", e)))?;
 
         // Response code must be 200
         if response.status().as_u16() != STATUS_OK {
-            return Err(OidcError(format!(
-                "Got bad response code. Expected {}, but got {}.",
-                STATUS_OK,
-                response.status()
-            )));
+            return Err(match Self::rejected_grant(response).await {
+                Ok(()) => OidcError("Invalid username or password.".to_string()),
+                Err(e) => e,
+            });
         }
 
         // Parse the body
@@ -74,7 +92,7 @@ impl OidcClient {
     ) -> Result<(), VeronymousClientError> {
         debug!("Refreshing OIDC credentials...");
         // Http client
-        let client = reqwest::Client::new();
+        let client = self.http_client()?;
 
         // Request form
         let mut body = HashMap::new();
@@ -88,15 +106,15 @@ impl OidcClient {
             .form(&body)
             .send()
             .await
-            .map_err(|e| OidcError(format!("Could not refresh user tokens. {:?}", e)))?;
+            .map_err(|e| HttpError(format!("Could not refresh user tokens. {:?}", e)))?;
 
         // Response code must be 200
         if response.status().as_u16() != STATUS_OK {
-            return Err(OidcError(format!(
-                "Got bad response code. Expected {}, but got {}.",
-                STATUS_OK,
-                response.status()
-            )));
+            // The refresh token expired or was revoked. The user must log in again.
+            return Err(match Self::rejected_grant(response).await {
+                Ok(()) => AuthRequired(),
+                Err(e) => e,
+            });
         }
 
         // Parse the body
@@ -110,20 +128,74 @@ impl OidcClient {
 
         Ok(())
     }
+
+    fn http_client(&self) -> Result<reqwest::Client, VeronymousClientError> {
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.reqwest_proxy()?);
+        }
+
+        builder
+            .build()
+            .map_err(|e| VeronymousError(format!("Could not create http client. {:?}", e)))
+    }
+
+    /*
+     * Ok if the IdP rejected the grant. Otherwise, the error for the bad response
+     * (e.g., the IdP is unavailable).
+     */
+    async fn rejected_grant(response: Response) -> Result<(), VeronymousClientError> {
+        let status = response.status().as_u16();
+        let body = response.text().await.unwrap_or_default();
+
+        match is_invalid_grant(status, &body) {
+            true => Ok(()),
+            false => Err(HttpError(format!(
+                "Got bad response code. Expected {}, but got {}.",
+                STATUS_OK, status
+            ))),
+        }
+    }
+}
+
+fn is_invalid_grant(status: u16, body: &str) -> bool {
+    if status != STATUS_BAD_REQUEST && status != STATUS_UNAUTHORIZED {
+        return false;
+    }
+
+    match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(response) => response.error == INVALID_GRANT,
+        Err(_) => false,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::oidc::client::OidcClient;
+    use crate::oidc::client::{is_invalid_grant, OidcClient};
     use crate::oidc::credentials::UserCredentials;
 
+    #[test]
+    fn test_is_invalid_grant() {
+        let invalid_grant =
+            r#"{"error":"invalid_grant","error_description":"Invalid user credentials"}"#;
+
+        assert!(is_invalid_grant(401, invalid_grant));
+        assert!(is_invalid_grant(400, invalid_grant));
+
+        // e.g., the IdP is unavailable
+        assert!(!is_invalid_grant(503, invalid_grant));
+        assert!(!is_invalid_grant(400, r#"{"error":"invalid_request"}"#));
+        assert!(!is_invalid_grant(401, "<html>Unauthorized</html>"));
+    }
+
     #[tokio::test]
     async fn test_oidc_client() {
         let token_endpoint: &str =
             "http://172.20.0.3:8080/realms/veronymous-vpn/protocol/openid-connect/token";
         let oidc_client_id: &str = "auth-client";
 
-        let client = OidcClient::new(token_endpoint.to_string(), oidc_client_id.to_string());
+        let client = OidcClient::new(token_endpoint.to_string(), oidc_client_id.to_string(), None);
 
         let credentials = UserCredentials::new("user1".to_string(), "password".to_string());
 
@@ -46,6 +46,13 @@ impl OidcCredentials {
         };
     }
 
+    // Unix time after which the user must authenticate again
+    pub fn refresh_token_expiry(&self) -> Result<u64, VeronymousClientError> {
+        let refresh_token: RefreshTokenPayload = decode_jwt_payload(&self.refresh_token)?;
+
+        Ok(refresh_token.exp)
+    }
+
     pub fn has_subscription(&self) -> Result<bool, VeronymousClientError> {
         // Decode the access token
         let access_token: AccessTokenPayload = decode_jwt_payload(&self.access_token)?;
@@ -0,0 +1,192 @@
+use crate::error::VeronymousClientError;
+use crate::error::VeronymousClientError::{DigestMismatch, IllegalArgumentError};
+use reqwest::header::HeaderMap;
+use sha2::{Digest, Sha256};
+
+// RFC 9530
+pub const CONTENT_DIGEST_HEADER: &str = "Content-Digest";
+// RFC 3230 (legacy)
+pub const DIGEST_HEADER: &str = "Digest";
+
+const SHA_256: &str = "sha-256";
+
+/*
+* Verify the sha-256 digest of the response body.
+* Content-Digest takes precedence over the legacy Digest header.
+*/
+pub fn verify_body_digest(headers: &HeaderMap, body: &[u8]) -> Result<(), VeronymousClientError> {
+    let expected = match (
+        header_value(headers, CONTENT_DIGEST_HEADER)?,
+        header_value(headers, DIGEST_HEADER)?,
+    ) {
+        (Some(content_digest), _) => find_sha256(&content_digest, true),
+        (None, Some(digest)) => find_sha256(&digest, false),
+        (None, None) => {
+            return Err(IllegalArgumentError(
+                "Response is missing the 'Content-Digest' and 'Digest' headers.".to_string(),
+            ))
+        }
+    };
+
+    let expected = match expected {
+        Some(expected) => expected,
+        None => {
+            return Err(IllegalArgumentError(
+                "Response does not contain a sha-256 digest.".to_string(),
+            ))
+        }
+    };
+
+    let actual = Sha256::digest(body);
+
+    if !digest_matches(&expected, actual.as_slice()) {
+        return Err(DigestMismatch(format!(
+            "Expected sha-256 digest {}, but got {}.",
+            expected,
+            base64::encode(actual)
+        )));
+    }
+
+    Ok(())
+}
+
+pub fn header_value(
+    headers: &HeaderMap,
+    name: &str,
+) -> Result<Option<String>, VeronymousClientError> {
+    match headers.get(name) {
+        None => Ok(None),
+        Some(value) => {
+            let value = value.to_str().map_err(|e| {
+                IllegalArgumentError(format!("Could not decode '{}' header. {:?}", name, e))
+            })?;
+
+            Ok(Some(value.to_string()))
+        }
+    }
+}
+
+// Algorithms of the IANA digest registries. Base64 values can contain '=' too.
+const DIGEST_ALGORITHMS: &[&str] = &[
+    "sha-256",
+    "sha-512",
+    "sha",
+    "md5",
+    "unixsum",
+    "unixcksum",
+    "adler32",
+    "crc32c",
+    "id-sha-256",
+    "id-sha-512",
+];
+
+/*
+* Find the sha-256 value in a digest header.
+* Content-Digest: sha-256=:<base64>:, sha-512=:<base64>:
+* Digest: SHA-256=<base64>, MD5=<base64>
+* A bare value is treated as a legacy sha-256 digest.
+*/
+fn find_sha256(header: &str, structured: bool) -> Option<String> {
+    for entry in header.split(',') {
+        let (algorithm, value) = match split_algorithm(entry.trim()) {
+            Some(parts) => parts,
+            None => continue,
+        };
+
+        if algorithm.eq_ignore_ascii_case(SHA_256) {
+            let value = value.trim();
+
+            return match structured {
+                true => Some(value.trim_matches(':').to_string()),
+                false => Some(value.to_string()),
+            };
+        }
+    }
+
+    if !structured && !header.contains(',') && split_algorithm(header.trim()).is_none() {
+        return Some(header.trim().to_string());
+    }
+
+    None
+}
+
+// Splits "<algorithm>=<value>" if the entry starts with a known algorithm
+fn split_algorithm(entry: &str) -> Option<(&str, &str)> {
+    let (algorithm, value) = entry.split_once('=')?;
+    let algorithm = algorithm.trim();
+
+    DIGEST_ALGORITHMS
+        .iter()
+        .any(|known| algorithm.eq_ignore_ascii_case(known))
+        .then_some((algorithm, value))
+}
+
+// The digest is base64 encoded. Hex encoding is accepted for legacy servers.
+fn digest_matches(expected: &str, actual: &[u8]) -> bool {
+    if let Ok(expected) = base64::decode(expected) {
+        if expected == actual {
+            return true;
+        }
+    }
+
+    let actual_hex: String = actual.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    expected.eq_ignore_ascii_case(&actual_hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::VeronymousClientError::DigestMismatch;
+    use crate::servers::digest::verify_body_digest;
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    const BODY: &[u8] = b"{\"hello\": \"world\"}";
+    // sha-256 of BODY
+    const BODY_SHA256: &str = "X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=";
+    const BODY_SHA256_HEX: &str =
+        "5f8f04f6a3a892aaabbddb6cf273894493773960d4a325b105fee46eef4304f1";
+
+    #[test]
+    fn test_content_digest() {
+        let headers = headers(
+            "Content-Digest",
+            &format!("sha-512=:AAAA:, sha-256=:{}:", BODY_SHA256),
+        );
+
+        verify_body_digest(&headers, BODY).unwrap();
+
+        let result = verify_body_digest(&headers, b"{\"hello\": \"evil\"}");
+        assert!(matches!(result, Err(DigestMismatch(_))));
+    }
+
+    #[test]
+    fn test_legacy_digest() {
+        verify_body_digest(
+            &headers("Digest", &format!("SHA-256={}", BODY_SHA256)),
+            BODY,
+        )
+        .unwrap();
+        verify_body_digest(&headers("Digest", BODY_SHA256_HEX), BODY).unwrap();
+        // Bare base64 value, '=' padding included
+        verify_body_digest(&headers("Digest", BODY_SHA256), BODY).unwrap();
+
+        let result = verify_body_digest(&headers("Digest", "SHA-256=AAAA"), BODY);
+        assert!(matches!(result, Err(DigestMismatch(_))));
+
+        let result = verify_body_digest(&headers("Digest", "AAAA=="), BODY);
+        assert!(matches!(result, Err(DigestMismatch(_))));
+    }
+
+    #[test]
+    fn test_missing_digest() {
+        assert!(verify_body_digest(&HeaderMap::new(), BODY).is_err());
+        assert!(verify_body_digest(&headers("Digest", "MD5=AAAA"), BODY).is_err());
+    }
+
+    fn headers(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+
+        headers
+    }
+}
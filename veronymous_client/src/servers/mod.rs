@@ -1,19 +1,24 @@
+pub mod digest;
+pub mod query;
+pub mod signature;
+pub mod source;
+
 use crate::config::VERONYMOUS_CLIENT_CONFIG;
 use crate::error::VeronymousClientError;
 use crate::error::VeronymousClientError::{
-    DeserializationError, HttpError, IllegalArgumentError, NotFoundError, ParseError,
+    DowngradeError, NotFoundError, ParseError, SignatureError,
 };
+use crate::servers::signature::{verify_signed_servers, SignedServers};
+use crate::servers::source::{default_sources, ServersDocument, ServersSource};
 use crate::vpn::VpnProfile;
 use rand::Rng;
-use reqwest::Response;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 
-type DomainId = String;
-type ServerId = String;
-type ServersMap = HashMap<DomainId, HashMap<ServerId, VpnProfile>>;
-
-const DIGEST_HEADER: &str = "Digest";
+pub type DomainId = String;
+pub type ServerId = String;
+pub type ServersMap = HashMap<DomainId, HashMap<ServerId, VpnProfile>>;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct VpnServers {
@@ -22,6 +27,25 @@ pub struct VpnServers {
 
     // The digest of the servers file
     pub digest: Option<String>,
+
+    // Serial of the signed servers list. Lists that are not newer are refused.
+    #[serde(default)]
+    pub serial: u64,
+
+    // sha-256 of the current list. A signed list with the same serial must be identical.
+    #[serde(default)]
+    pub body_digest: Option<String>,
+
+    // Cache validators of the servers file
+    #[serde(default)]
+    pub etag: Option<String>,
+
+    #[serde(default)]
+    pub last_modified: Option<String>,
+
+    // The endpoint that sent the validators. They are only sent back to it.
+    #[serde(default)]
+    pub validators_endpoint: Option<String>,
 }
 
 impl VpnServers {
@@ -29,6 +53,11 @@ impl VpnServers {
         Self {
             servers: ServersMap::new(),
             digest: None,
+            serial: 0,
+            body_digest: None,
+            etag: None,
+            last_modified: None,
+            validators_endpoint: None,
         }
     }
 
@@ -42,29 +71,49 @@ impl VpnServers {
         domains
     }
 
+    // Update from the servers endpoint and mirrors
     pub async fn update(&mut self) -> Result<bool, VeronymousClientError> {
-        let servers_endpoint = &VERONYMOUS_CLIENT_CONFIG.servers_endpoint;
-
-        if self.is_update_required(servers_endpoint).await? {
-            let response = reqwest::get(servers_endpoint)
-                .await
-                .map_err(|e| HttpError(format!("Could not fetch servers. {:?}", e)))?;
+        let public_key = VERONYMOUS_CLIENT_CONFIG.servers_public_key.as_deref();
 
-            let digest = Self::get_digest(&response)?;
+        self.update_from(&default_sources(), public_key).await
+    }
 
-            let servers = response
-                .json::<ServersMap>()
-                .await
-                .map_err(|e| ParseError(format!("Could not parse servers response. {:?}", e)))?;
+    /*
+     * Update from the first available source.
+     * The lists must be signed with the public key (base64 Ed25519).
+     * Returns false if the servers did not change.
+     * Fails with the last error if no source provided a usable list.
+     */
+    pub async fn update_from(
+        &mut self,
+        sources: &[Box<dyn ServersSource>],
+        public_key: Option<&str>,
+    ) -> Result<bool, VeronymousClientError> {
+        let mut last_error = NotFoundError("No servers list is available.".to_string());
 
-            self.servers = servers;
-            self.digest = Some(digest);
+        for source in sources {
+            let document = match source.fetch(self).await {
+                // Still up to date
+                Ok(None) if !self.servers.is_empty() => return Ok(false),
+                Ok(None) => continue,
+                Ok(Some(document)) => document,
+                Err(e) => {
+                    warn!("Could not fetch servers from {}. {:?}", source.name(), e);
+                    last_error = e;
+                    continue;
+                }
+            };
 
-            Ok(true)
-        } else {
-            // Update is not required, do nothing
-            Ok(false)
+            match self.apply_document(document, public_key) {
+                Ok(_) => return Ok(true),
+                Err(e) => {
+                    warn!("Refused servers from {}. {:?}", source.name(), e);
+                    last_error = e;
+                }
+            }
         }
+
+        Err(last_error)
     }
 
     pub fn find_server(&self, domain: &DomainId) -> Result<&VpnProfile, VeronymousClientError> {
@@ -95,58 +144,110 @@ impl VpnServers {
         return Err(NotFoundError(format!("Could not find server")));
     }
 
-    async fn is_update_required(
+    /*
+     * Refuse signed lists that are not newer than the current one.
+     * The current list can be served again (e.g., by another mirror).
+     */
+    fn check_serial(
         &self,
-        servers_endpoint: &String,
-    ) -> Result<bool, VeronymousClientError> {
-        // Get the current digest
-        let digest = match &self.digest {
-            None => return Ok(true),
-            Some(digest) => digest,
-        };
+        signed_servers: &SignedServers,
+        body: &[u8],
+    ) -> Result<(), VeronymousClientError> {
+        if signed_servers.serial > self.serial {
+            return Ok(());
+        }
 
-        let file_metadata = Self::get_servers_metadata(servers_endpoint).await?;
+        if signed_servers.serial == self.serial {
+            // No list yet (e.g., the first list has serial 0)
+            let unchanged = match &self.body_digest {
+                Some(digest) => *digest == body_digest(body),
+                None => self.servers.is_empty(),
+            };
 
-        Ok(digest.to_string() != file_metadata.digest)
+            if unchanged {
+                return Ok(());
+            }
+        }
+
+        Err(DowngradeError(format!(
+            "Servers list serial {} is not newer than the current serial {}.",
+            signed_servers.serial, self.serial
+        )))
     }
 
-    async fn get_servers_metadata(
-        servers_endpoint: &String,
-    ) -> Result<FileMetadata, VeronymousClientError> {
-        let metadata_endpoint = servers_endpoint.to_string() + "/metadata";
+    /*
+     * Authenticate and parse a servers document. Nothing is replaced.
+     * The unsigned lists keep the current serial. Their agent pins are dropped.
+     */
+    pub(crate) fn verify_document(
+        &self,
+        document: &ServersDocument,
+        public_key: Option<&str>,
+    ) -> Result<SignedServers, VeronymousClientError> {
+        match (public_key, &document.signature) {
+            (Some(public_key), Some(signature)) => {
+                let signed_servers = verify_signed_servers(public_key, &document.body, signature)?;
 
-        let metadata = reqwest::get(metadata_endpoint)
-            .await
-            .map_err(|e| HttpError(format!("Could not get file metadata. {:?}", e)))?
-            .json::<FileMetadata>()
-            .await
-            .map_err(|e| HttpError(format!("Could not parse file metadata. {:?}", e)))?;
+                self.check_serial(&signed_servers, &document.body)?;
 
-        Ok(metadata)
-    }
+                Ok(signed_servers)
+            }
+            // Unsigned lists are refused
+            (Some(_), None) => Err(SignatureError("Servers list is not signed.".to_string())),
+            // e.g., a production build without a key
+            (None, _) if !VERONYMOUS_CLIENT_CONFIG.allow_unsigned_servers => Err(SignatureError(
+                "No servers public key is configured. Set servers_public_key.".to_string(),
+            )),
+            (None, _) => {
+                let mut servers =
+                    serde_json::from_slice::<ServersMap>(&document.body).map_err(|e| {
+                        ParseError(format!("Could not parse servers response. {:?}", e))
+                    })?;
 
-    // Get the digest header value
-    fn get_digest(response: &Response) -> Result<String, VeronymousClientError> {
-        let digest = match response.headers().get(DIGEST_HEADER) {
-            None => {
-                return Err(IllegalArgumentError(format!(
-                    "Response is missing the 'Digest' header."
-                )));
+                // Whoever can swap the list could swap the pins
+                for profile in servers
+                    .values_mut()
+                    .flat_map(|profiles| profiles.values_mut())
+                {
+                    profile.agent_pins.clear();
+                }
+
+                Ok(SignedServers {
+                    serial: self.serial,
+                    servers,
+                })
             }
-            Some(digest) => digest,
-        };
+        }
+    }
 
-        let digest = digest
-            .to_str()
-            .map_err(|e| DeserializationError(format!("Could not decode digest. {:?}", e)))?;
+    // Verify and apply a servers document. Nothing is replaced if it is refused.
+    fn apply_document(
+        &mut self,
+        document: ServersDocument,
+        public_key: Option<&str>,
+    ) -> Result<(), VeronymousClientError> {
+        let signed_servers = self.verify_document(&document, public_key)?;
+
+        self.servers = signed_servers.servers;
+        self.serial = signed_servers.serial;
+        self.body_digest = Some(body_digest(&document.body));
+        self.digest = document.digest;
+        self.etag = document.etag;
+        self.last_modified = document.last_modified;
+        self.validators_endpoint = document.validators_endpoint;
+
+        Ok(())
+    }
 
-        Ok(digest.to_string())
+    // Cache validators sent by the endpoint. Another mirror's are not comparable.
+    pub(crate) fn has_validators(&self, endpoint: &str) -> bool {
+        self.validators_endpoint.as_deref() == Some(endpoint)
+            && (self.etag.is_some() || self.last_modified.is_some())
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
-struct FileMetadata {
-    digest: String,
+fn body_digest(body: &[u8]) -> String {
+    base64::encode(Sha256::digest(body))
 }
 
 // #[cfg(test)]
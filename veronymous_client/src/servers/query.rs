@@ -0,0 +1,278 @@
+use crate::servers::{DomainId, ServerId, VpnServers};
+use crate::vpn::{ServerFeature, VpnProfile};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+// Region key for the servers without a region
+pub const UNKNOWN_REGION: &str = "Other";
+
+/*
+* Summary of a server domain for the server pickers.
+* A connection picks a random server of the domain, so the features are the ones
+* supported by every server.
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub domain: DomainId,
+
+    // Falls back to the domain
+    pub display_name: String,
+
+    pub country: Option<String>,
+
+    pub city: Option<String>,
+
+    pub region: Option<String>,
+
+    // Total capacity of the domain's servers
+    pub capacity: Option<u32>,
+
+    pub features: Vec<ServerFeature>,
+}
+
+impl ServerInfo {
+    pub fn new(domain: &DomainId, profiles: &HashMap<ServerId, VpnProfile>) -> Self {
+        // Sort for a stable result
+        let mut profiles: Vec<(&ServerId, &VpnProfile)> = profiles.iter().collect();
+        profiles.sort_by(|a, b| a.0.cmp(b.0));
+
+        let first = |field: fn(&VpnProfile) -> &Option<String>| {
+            profiles
+                .iter()
+                .find_map(|(_, profile)| field(profile).clone())
+        };
+
+        let capacities: Vec<u32> = profiles
+            .iter()
+            .filter_map(|(_, profile)| profile.metadata.capacity)
+            .collect();
+
+        let mut features: Vec<ServerFeature> = match profiles.first() {
+            None => vec![],
+            Some((_, profile)) => profile.metadata.features.clone(),
+        };
+        features.retain(|feature| {
+            *feature != ServerFeature::Unknown
+                && profiles
+                    .iter()
+                    .all(|(_, profile)| profile.metadata.features.contains(feature))
+        });
+        features.sort();
+        features.dedup();
+
+        Self {
+            domain: domain.clone(),
+            display_name: first(|profile| &profile.metadata.display_name)
+                .unwrap_or_else(|| domain.clone()),
+            country: first(|profile| &profile.metadata.country),
+            city: first(|profile| &profile.metadata.city),
+            region: first(|profile| &profile.metadata.region),
+            capacity: match capacities.is_empty() {
+                true => None,
+                false => Some(capacities.iter().sum()),
+            },
+            features,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ServerFilter {
+    // ISO 3166-1 alpha-2 country code. Case insensitive.
+    pub country: Option<String>,
+
+    pub feature: Option<ServerFeature>,
+}
+
+impl ServerFilter {
+    pub fn new(country: Option<String>, feature: Option<ServerFeature>) -> Self {
+        Self { country, feature }
+    }
+
+    pub fn matches(&self, server: &ServerInfo) -> bool {
+        if let Some(country) = &self.country {
+            match &server.country {
+                Some(server_country) if server_country.eq_ignore_ascii_case(country) => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(feature) = &self.feature {
+            if !server.features.contains(feature) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl VpnServers {
+    // Servers matching the filter, sorted by name
+    pub fn list_servers(&self, filter: &ServerFilter) -> Vec<ServerInfo> {
+        let mut servers: Vec<ServerInfo> = self
+            .servers
+            .iter()
+            .map(|(domain, profiles)| ServerInfo::new(domain, profiles))
+            .filter(|server| filter.matches(server))
+            .collect();
+
+        servers.sort_by(|a, b| {
+            a.display_name
+                .to_lowercase()
+                .cmp(&b.display_name.to_lowercase())
+                .then_with(|| a.domain.cmp(&b.domain))
+        });
+
+        servers
+    }
+}
+
+// Group the servers by region. Keeps the order of the servers within a region.
+pub fn group_by_region(servers: Vec<ServerInfo>) -> BTreeMap<String, Vec<ServerInfo>> {
+    let mut regions: BTreeMap<String, Vec<ServerInfo>> = BTreeMap::new();
+
+    for server in servers {
+        let region = server
+            .region
+            .clone()
+            .unwrap_or_else(|| UNKNOWN_REGION.to_string());
+
+        regions.entry(region).or_default().push(server);
+    }
+
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::servers::query::{group_by_region, ServerFilter, UNKNOWN_REGION};
+    use crate::servers::VpnServers;
+    use crate::vpn::{ServerFeature, ServerMetadata, VpnProfile};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_list_servers() {
+        let servers = vpn_servers();
+
+        let all = servers.list_servers(&ServerFilter::default());
+        let names: Vec<&str> = all.iter().map(|s| s.display_name.as_str()).collect();
+        assert_eq!(vec!["legacy_domain", "New York", "Toronto"], names);
+
+        // Aggregated over the domain's servers
+        let new_york = &all[1];
+        assert_eq!(Some(300), new_york.capacity);
+        assert_eq!(vec![ServerFeature::Ipv6], new_york.features);
+    }
+
+    #[test]
+    fn test_filter_servers() {
+        let servers = vpn_servers();
+
+        let canada = servers.list_servers(&ServerFilter::new(Some("ca".to_string()), None));
+        assert_eq!(1, canada.len());
+        assert_eq!("toronto", canada[0].domain);
+
+        let multihop =
+            servers.list_servers(&ServerFilter::new(None, Some(ServerFeature::Multihop)));
+        assert_eq!(1, multihop.len());
+        assert_eq!("toronto", multihop[0].domain);
+
+        let ipv6_us = servers.list_servers(&ServerFilter::new(
+            Some("US".to_string()),
+            Some(ServerFeature::Ipv6),
+        ));
+        assert_eq!(1, ipv6_us.len());
+        assert_eq!("new_york", ipv6_us[0].domain);
+    }
+
+    #[test]
+    fn test_group_by_region() {
+        let servers = vpn_servers();
+
+        let regions = group_by_region(servers.list_servers(&ServerFilter::default()));
+
+        assert_eq!(2, regions.len());
+        assert_eq!(2, regions["North America"].len());
+        assert_eq!("legacy_domain", regions[UNKNOWN_REGION][0].domain);
+    }
+
+    fn vpn_servers() -> VpnServers {
+        let mut servers = VpnServers::new();
+
+        servers.servers.insert(
+            "new_york".to_string(),
+            HashMap::from([
+                (
+                    "server_1".to_string(),
+                    vpn_profile(
+                        "new_york",
+                        Some("US"),
+                        Some(100),
+                        vec![ServerFeature::Ipv6, ServerFeature::Multihop],
+                    ),
+                ),
+                (
+                    "server_2".to_string(),
+                    vpn_profile("new_york", Some("US"), Some(200), vec![ServerFeature::Ipv6]),
+                ),
+            ]),
+        );
+        servers.servers.insert(
+            "toronto".to_string(),
+            HashMap::from([(
+                "server_1".to_string(),
+                vpn_profile(
+                    "toronto",
+                    Some("CA"),
+                    None,
+                    vec![ServerFeature::Multihop, ServerFeature::Obfuscation],
+                ),
+            )]),
+        );
+        servers.servers.insert(
+            "legacy_domain".to_string(),
+            HashMap::from([(
+                "server_1".to_string(),
+                vpn_profile("legacy_domain", None, None, vec![]),
+            )]),
+        );
+
+        servers
+    }
+
+    fn vpn_profile(
+        domain: &str,
+        country: Option<&str>,
+        capacity: Option<u32>,
+        features: Vec<ServerFeature>,
+    ) -> VpnProfile {
+        let metadata = match country {
+            None => ServerMetadata::default(),
+            Some(country) => ServerMetadata {
+                display_name: Some(
+                    match country {
+                        "CA" => "Toronto",
+                        _ => "New York",
+                    }
+                    .to_string(),
+                ),
+                country: Some(country.to_string()),
+                city: None,
+                region: Some("North America".to_string()),
+                capacity,
+                features,
+            },
+        };
+
+        VpnProfile::new(
+            domain.to_string(),
+            "localhost.veronymous.io:7777".to_string(),
+            None,
+            "wg1.ny.veronymous.io:51820".to_string(),
+            "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=".to_string(),
+            vec![],
+            metadata,
+        )
+    }
+}
@@ -0,0 +1,193 @@
+use crate::error::VeronymousClientError;
+use crate::error::VeronymousClientError::{DecodingError, ParseError, SignatureError};
+use crate::servers::ServersMap;
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+/*
+* Signed servers list document.
+* The serial must increase with every published list (rollback protection).
+*/
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SignedServers {
+    pub serial: u64,
+
+    pub servers: ServersMap,
+}
+
+/*
+* Verify the detached Ed25519 signature of the servers list.
+* The public key and signature are base64 encoded.
+*/
+pub fn verify_signed_servers(
+    public_key: &str,
+    body: &[u8],
+    signature: &str,
+) -> Result<SignedServers, VeronymousClientError> {
+    let public_key: [u8; 32] = base64::decode(public_key.trim())
+        .map_err(|e| DecodingError(format!("Could not decode servers public key. {:?}", e)))?
+        .try_into()
+        .map_err(|_| DecodingError("Servers public key has the wrong length.".to_string()))?;
+
+    let public_key = VerifyingKey::from_bytes(&public_key)
+        .map_err(|e| DecodingError(format!("Invalid servers public key. {:?}", e)))?;
+
+    let signature = base64::decode(signature.trim())
+        .map_err(|e| DecodingError(format!("Could not decode servers signature. {:?}", e)))?;
+
+    let signature = Signature::from_slice(&signature)
+        .map_err(|e| DecodingError(format!("Invalid servers signature. {:?}", e)))?;
+
+    public_key
+        .verify_strict(body, &signature)
+        .map_err(|e| SignatureError(format!("Servers list signature is invalid. {:?}", e)))?;
+
+    // Only parse the content once it is authenticated
+    let signed_servers = serde_json::from_slice(body)
+        .map_err(|e| ParseError(format!("Could not parse signed servers list. {:?}", e)))?;
+
+    Ok(signed_servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::VeronymousClientError::{DowngradeError, SignatureError};
+    use crate::servers::signature::{verify_signed_servers, SignedServers};
+    use crate::servers::source::ServersDocument;
+    use crate::servers::{body_digest, ServersMap, VpnServers};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const SERVERS_BODY: &str = r#"{
+        "serial": 7,
+        "servers": {
+            "dev_domain": {
+                "server_1": {
+                    "domain": "dev_domain",
+                    "agent_endpoint": "localhost.veronymous.io:7777",
+                    "root_cert": null,
+                    "wg_endpoint": "wg1.ny.veronymous.io:51820",
+                    "wg_key": "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg="
+                }
+            }
+        }
+    }"#;
+
+    #[test]
+    fn test_verify_signed_servers() {
+        let signing_key = SigningKey::from_bytes(&[3u8; 32]);
+        let public_key = base64::encode(signing_key.verifying_key().to_bytes());
+        let signature = base64::encode(signing_key.sign(SERVERS_BODY.as_bytes()).to_bytes());
+
+        let signed_servers =
+            verify_signed_servers(&public_key, SERVERS_BODY.as_bytes(), &signature).unwrap();
+
+        assert_eq!(7, signed_servers.serial);
+        assert!(signed_servers.servers.contains_key("dev_domain"));
+    }
+
+    #[test]
+    fn test_reject_tampered_servers() {
+        let signing_key = SigningKey::from_bytes(&[3u8; 32]);
+        let public_key = base64::encode(signing_key.verifying_key().to_bytes());
+        let signature = base64::encode(signing_key.sign(SERVERS_BODY.as_bytes()).to_bytes());
+
+        let tampered = SERVERS_BODY.replace("wg1.ny", "wg1.evil");
+
+        let result = verify_signed_servers(&public_key, tampered.as_bytes(), &signature);
+        assert!(matches!(result, Err(SignatureError(_))));
+    }
+
+    #[test]
+    fn test_reject_wrong_key() {
+        let signing_key = SigningKey::from_bytes(&[3u8; 32]);
+        let other_key = SigningKey::from_bytes(&[4u8; 32]);
+        let public_key = base64::encode(other_key.verifying_key().to_bytes());
+        let signature = base64::encode(signing_key.sign(SERVERS_BODY.as_bytes()).to_bytes());
+
+        let result = verify_signed_servers(&public_key, SERVERS_BODY.as_bytes(), &signature);
+        assert!(matches!(result, Err(SignatureError(_))));
+    }
+
+    #[test]
+    fn test_verify_document_with_public_key() {
+        let signing_key = SigningKey::from_bytes(&[3u8; 32]);
+        let public_key = base64::encode(signing_key.verifying_key().to_bytes());
+        let signature = base64::encode(signing_key.sign(SERVERS_BODY.as_bytes()).to_bytes());
+        let vpn_servers = VpnServers::new();
+
+        let unsigned = ServersDocument::new(SERVERS_BODY.as_bytes().to_vec(), None);
+        assert!(matches!(
+            vpn_servers.verify_document(&unsigned, Some(&public_key)),
+            Err(SignatureError(_))
+        ));
+
+        let signed = ServersDocument::new(SERVERS_BODY.as_bytes().to_vec(), Some(signature));
+        let signed_servers = vpn_servers
+            .verify_document(&signed, Some(&public_key))
+            .unwrap();
+        assert_eq!(7, signed_servers.serial);
+    }
+
+    #[test]
+    fn test_pins_require_signature() {
+        let pinned = SERVERS_BODY.replace(
+            r#""root_cert": null,"#,
+            r#""root_cert": null, "agent_pins": ["sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="],"#,
+        );
+        let pins = |signed_servers: &SignedServers| {
+            signed_servers.servers["dev_domain"]["server_1"]
+                .agent_pins
+                .len()
+        };
+        let vpn_servers = VpnServers::new();
+
+        let signing_key = SigningKey::from_bytes(&[3u8; 32]);
+        let public_key = base64::encode(signing_key.verifying_key().to_bytes());
+        let signature = base64::encode(signing_key.sign(pinned.as_bytes()).to_bytes());
+        let signed = ServersDocument::new(pinned.as_bytes().to_vec(), Some(signature));
+        let signed_servers = vpn_servers
+            .verify_document(&signed, Some(&public_key))
+            .unwrap();
+        assert_eq!(1, pins(&signed_servers));
+
+        // Unsigned lists are only accepted by the dev builds
+        let servers = serde_json::to_string(&signed_servers.servers).unwrap();
+        let unsigned = ServersDocument::new(servers.into_bytes(), None);
+        if let Ok(unsigned_servers) = vpn_servers.verify_document(&unsigned, None) {
+            assert_eq!(0, pins(&unsigned_servers));
+        }
+    }
+
+    #[test]
+    fn test_reject_downgrade() {
+        let mut vpn_servers = VpnServers::new();
+        vpn_servers.serial = 7;
+        vpn_servers.body_digest = Some(body_digest(SERVERS_BODY.as_bytes()));
+
+        let signed_servers = |serial| SignedServers {
+            serial,
+            servers: ServersMap::new(),
+        };
+
+        assert!(matches!(
+            vpn_servers.check_serial(&signed_servers(6), SERVERS_BODY.as_bytes()),
+            Err(DowngradeError(_))
+        ));
+
+        // Same serial, different content
+        let tampered = SERVERS_BODY.replace("wg1.ny", "wg1.evil");
+        assert!(matches!(
+            vpn_servers.check_serial(&signed_servers(7), tampered.as_bytes()),
+            Err(DowngradeError(_))
+        ));
+
+        // The current list again
+        vpn_servers
+            .check_serial(&signed_servers(7), SERVERS_BODY.as_bytes())
+            .unwrap();
+
+        vpn_servers
+            .check_serial(&signed_servers(8), tampered.as_bytes())
+            .unwrap();
+    }
+}
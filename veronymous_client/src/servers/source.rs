@@ -0,0 +1,619 @@
+use crate::config::VERONYMOUS_CLIENT_CONFIG;
+use crate::error::VeronymousClientError;
+use crate::error::VeronymousClientError::{HttpError, IoError, NotFoundError};
+use crate::servers::digest::{
+    header_value, verify_body_digest, CONTENT_DIGEST_HEADER, DIGEST_HEADER,
+};
+use crate::servers::VpnServers;
+use async_trait::async_trait;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Response, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+// Detached signature of a local servers file (<file>.sig)
+const SIGNATURE_FILE_SUFFIX: &str = ".sig";
+
+// Signed snapshot set at build time (VERONYMOUS_SERVERS_SNAPSHOT). Empty if none.
+const BUNDLED_SERVERS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/bundled_servers.json"));
+const BUNDLED_SERVERS_SIGNATURE: &str =
+    include_str!(concat!(env!("OUT_DIR"), "/bundled_servers.json.sig"));
+
+/*
+* Servers list as delivered by a source.
+* The content is not parsed until it is verified.
+*/
+#[derive(Clone, Debug)]
+pub struct ServersDocument {
+    pub body: Vec<u8>,
+
+    // Detached base64 Ed25519 signature of the body
+    pub signature: Option<String>,
+
+    pub digest: Option<String>,
+
+    pub etag: Option<String>,
+
+    pub last_modified: Option<String>,
+
+    // The endpoint that sent the validators
+    pub validators_endpoint: Option<String>,
+}
+
+impl ServersDocument {
+    pub fn new(body: Vec<u8>, signature: Option<String>) -> Self {
+        Self {
+            body,
+            signature,
+            digest: None,
+            etag: None,
+            last_modified: None,
+            validators_endpoint: None,
+        }
+    }
+}
+
+#[async_trait]
+pub trait ServersSource: Send + Sync {
+    fn name(&self) -> String;
+
+    // Fetch the servers list. None if it did not change since the current one.
+    async fn fetch(
+        &self,
+        current: &VpnServers,
+    ) -> Result<Option<ServersDocument>, VeronymousClientError>;
+}
+
+/*
+* Servers endpoint followed by its mirrors.
+* The next mirror is tried if one is unavailable or serves a corrupted list.
+*/
+pub struct HttpServersSource {
+    endpoints: Vec<String>,
+
+    // The detached signature (<endpoint>/signature) is fetched if set
+    public_key: Option<String>,
+}
+
+impl HttpServersSource {
+    pub fn new(endpoints: Vec<String>, public_key: Option<String>) -> Self {
+        Self {
+            endpoints,
+            public_key,
+        }
+    }
+
+    async fn fetch_endpoint(
+        &self,
+        endpoint: &String,
+        current: &VpnServers,
+    ) -> Result<Option<ServersDocument>, VeronymousClientError> {
+        // Servers without cache validators: fall back to the metadata endpoint
+        if !current.has_validators(endpoint) {
+            match Self::is_update_required(endpoint, current).await {
+                Ok(false) => return Ok(None),
+                Ok(true) => {}
+                Err(e) => debug!("Could not check the servers metadata. {:?}", e),
+            }
+        }
+
+        let response = Self::fetch_servers(endpoint, current).await?;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+
+        let response = response
+            .error_for_status()
+            .map_err(|e| HttpError(format!("Could not fetch servers. {:?}", e)))?;
+
+        let headers = response.headers().clone();
+
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| HttpError(format!("Could not read servers response. {:?}", e)))?;
+
+        // The body must be intact before anything is replaced
+        verify_body_digest(&headers, &body)?;
+
+        let signature = match self.public_key {
+            Some(_) => Self::get_signature(endpoint).await?,
+            None => None,
+        };
+
+        let mut document = ServersDocument::new(body.to_vec(), signature);
+
+        // The metadata endpoint is compared against the legacy digest header
+        document.digest = match header_value(&headers, DIGEST_HEADER)? {
+            Some(digest) => Some(digest),
+            None => header_value(&headers, CONTENT_DIGEST_HEADER)?,
+        };
+        document.etag = header_value(&headers, ETAG.as_str())?;
+        document.last_modified = header_value(&headers, LAST_MODIFIED.as_str())?;
+        document.validators_endpoint = Some(endpoint.clone());
+
+        Ok(Some(document))
+    }
+
+    // Conditional GET. The server answers 304 if the servers did not change.
+    async fn fetch_servers(
+        endpoint: &String,
+        current: &VpnServers,
+    ) -> Result<Response, VeronymousClientError> {
+        let mut request = reqwest::Client::new().get(endpoint);
+
+        // e.g., the validators of another mirror
+        if !current.has_validators(endpoint) {
+            return request
+                .send()
+                .await
+                .map_err(|e| HttpError(format!("Could not fetch servers. {:?}", e)));
+        }
+
+        if let Some(etag) = &current.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        if let Some(last_modified) = &current.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        request
+            .send()
+            .await
+            .map_err(|e| HttpError(format!("Could not fetch servers. {:?}", e)))
+    }
+
+    // Get the detached signature of the servers file. None if the list is not signed.
+    async fn get_signature(endpoint: &String) -> Result<Option<String>, VeronymousClientError> {
+        let signature_endpoint = endpoint.to_string() + "/signature";
+
+        let response = reqwest::get(signature_endpoint)
+            .await
+            .map_err(|e| HttpError(format!("Could not fetch servers signature. {:?}", e)))?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let signature = response
+            .error_for_status()
+            .map_err(|e| HttpError(format!("Could not fetch servers signature. {:?}", e)))?
+            .text()
+            .await
+            .map_err(|e| HttpError(format!("Could not read servers signature. {:?}", e)))?;
+
+        Ok(Some(signature))
+    }
+
+    async fn is_update_required(
+        endpoint: &String,
+        current: &VpnServers,
+    ) -> Result<bool, VeronymousClientError> {
+        // Get the current digest
+        let digest = match &current.digest {
+            None => return Ok(true),
+            Some(digest) => digest,
+        };
+
+        let file_metadata = Self::get_servers_metadata(endpoint).await?;
+
+        Ok(digest.to_string() != file_metadata.digest)
+    }
+
+    async fn get_servers_metadata(
+        endpoint: &String,
+    ) -> Result<FileMetadata, VeronymousClientError> {
+        let metadata_endpoint = endpoint.to_string() + "/metadata";
+
+        let metadata = reqwest::get(metadata_endpoint)
+            .await
+            .map_err(|e| HttpError(format!("Could not get file metadata. {:?}", e)))?
+            .json::<FileMetadata>()
+            .await
+            .map_err(|e| HttpError(format!("Could not parse file metadata. {:?}", e)))?;
+
+        Ok(metadata)
+    }
+}
+
+#[async_trait]
+impl ServersSource for HttpServersSource {
+    fn name(&self) -> String {
+        format!("http ({})", self.endpoints.join(", "))
+    }
+
+    async fn fetch(
+        &self,
+        current: &VpnServers,
+    ) -> Result<Option<ServersDocument>, VeronymousClientError> {
+        let mut last_error = HttpError("No servers endpoint is configured.".to_string());
+
+        for endpoint in &self.endpoints {
+            let document = match self.fetch_endpoint(endpoint, current).await {
+                Ok(None) => return Ok(None),
+                Ok(Some(document)) => document,
+                Err(e) => {
+                    warn!("Could not fetch servers from {}. {:?}", endpoint, e);
+                    last_error = e;
+                    continue;
+                }
+            };
+
+            // e.g., a mirror serving an unsigned or older list
+            match current.verify_document(&document, self.public_key.as_deref()) {
+                Ok(_) => return Ok(Some(document)),
+                Err(e) => {
+                    warn!("Refused servers from {}. {:?}", endpoint, e);
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+/*
+* Servers list file for self-hosted or air-gapped setups.
+* An optional detached signature is read from <file>.sig.
+*/
+pub struct FileServersSource {
+    path: String,
+}
+
+impl FileServersSource {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl ServersSource for FileServersSource {
+    fn name(&self) -> String {
+        format!("file ({})", self.path)
+    }
+
+    async fn fetch(
+        &self,
+        current: &VpnServers,
+    ) -> Result<Option<ServersDocument>, VeronymousClientError> {
+        let body = fs::read(&self.path).map_err(|e| {
+            IoError(format!(
+                "Could not read servers file {}. {:?}",
+                self.path, e
+            ))
+        })?;
+
+        let digest = format!("sha-256=:{}:", base64::encode(Sha256::digest(&body)));
+
+        if current.digest.as_ref() == Some(&digest) {
+            return Ok(None);
+        }
+
+        let signature_path = signature_path(&self.path);
+
+        let signature = match signature_path.exists() {
+            true => Some(fs::read_to_string(&signature_path).map_err(|e| {
+                IoError(format!(
+                    "Could not read servers signature file {:?}. {:?}",
+                    signature_path, e
+                ))
+            })?),
+            false => None,
+        };
+
+        let mut document = ServersDocument::new(body, signature);
+        document.digest = Some(digest);
+
+        Ok(Some(document))
+    }
+}
+
+// e.g., servers.json.sig
+fn signature_path(path: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}", path, SIGNATURE_FILE_SUFFIX))
+}
+
+/*
+* Last resort for the first run (e.g., the servers endpoint and mirrors are blocked).
+* Verified like the downloaded lists. A cached list is kept.
+*/
+pub struct BundledServersSource;
+
+#[async_trait]
+impl ServersSource for BundledServersSource {
+    fn name(&self) -> String {
+        "bundled".to_string()
+    }
+
+    async fn fetch(
+        &self,
+        current: &VpnServers,
+    ) -> Result<Option<ServersDocument>, VeronymousClientError> {
+        if BUNDLED_SERVERS.is_empty() {
+            return Err(NotFoundError("No servers list is bundled.".to_string()));
+        }
+
+        if !current.servers.is_empty() {
+            return Ok(None);
+        }
+
+        let signature = match BUNDLED_SERVERS_SIGNATURE.trim() {
+            "" => None,
+            signature => Some(signature.to_string()),
+        };
+
+        Ok(Some(ServersDocument::new(
+            BUNDLED_SERVERS.to_vec(),
+            signature,
+        )))
+    }
+}
+
+// Servers endpoint and the client's mirrors, followed by the bundled list
+pub fn default_sources() -> Vec<Box<dyn ServersSource>> {
+    servers_sources(
+        &VERONYMOUS_CLIENT_CONFIG.servers_mirrors,
+        VERONYMOUS_CLIENT_CONFIG.servers_public_key.clone(),
+    )
+}
+
+// Servers endpoint and the mirrors, followed by the bundled list. Signed with the public key.
+pub fn servers_sources(
+    mirrors: &[String],
+    public_key: Option<String>,
+) -> Vec<Box<dyn ServersSource>> {
+    let mut endpoints = vec![VERONYMOUS_CLIENT_CONFIG.servers_endpoint.clone()];
+    endpoints.extend(mirrors.iter().cloned());
+
+    vec![
+        Box::new(HttpServersSource::new(endpoints, public_key)),
+        Box::new(BundledServersSource),
+    ]
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct FileMetadata {
+    digest: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::VeronymousClientError;
+    use crate::error::VeronymousClientError::{HttpError, NotFoundError};
+    use crate::servers::source::{
+        signature_path, BundledServersSource, FileServersSource, HttpServersSource,
+        ServersDocument, ServersSource, BUNDLED_SERVERS,
+    };
+    use crate::servers::VpnServers;
+    use async_trait::async_trait;
+    use sha2::{Digest, Sha256};
+    use std::fs;
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const SERVERS_BODY: &str = r#"{
+        "dev_domain": {
+            "server_1": {
+                "domain": "dev_domain",
+                "agent_endpoint": "localhost.veronymous.io:7777",
+                "root_cert": null,
+                "wg_endpoint": "wg1.ny.veronymous.io:51820",
+                "wg_key": "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg="
+            }
+        }
+    }"#;
+
+    struct UnavailableSource;
+
+    #[async_trait]
+    impl ServersSource for UnavailableSource {
+        fn name(&self) -> String {
+            "unavailable".to_string()
+        }
+
+        async fn fetch(
+            &self,
+            _current: &VpnServers,
+        ) -> Result<Option<ServersDocument>, VeronymousClientError> {
+            Err(HttpError("Unavailable.".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mirror_failover() {
+        // Nothing is listening on the first endpoint
+        let unavailable = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let unavailable_endpoint =
+            format!("http://{}/servers.json", unavailable.local_addr().unwrap());
+        drop(unavailable);
+
+        let mirror_endpoint = serve_once(SERVERS_BODY).await;
+
+        let source = HttpServersSource::new(vec![unavailable_endpoint, mirror_endpoint], None);
+
+        let mut servers = VpnServers::new();
+        assert!(servers
+            .update_from(&[Box::new(source)], None)
+            .await
+            .unwrap());
+
+        assert!(servers.servers.contains_key("dev_domain"));
+        assert_eq!(Some("\"7\"".to_string()), servers.etag);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_failover_on_refused_list() {
+        // Intact, but not a servers list
+        let corrupted_endpoint = serve_once(r#"{"dev_domain": []}"#).await;
+        let mirror_endpoint = serve_once(SERVERS_BODY).await;
+
+        let source = HttpServersSource::new(vec![corrupted_endpoint, mirror_endpoint], None);
+
+        let mut servers = VpnServers::new();
+        assert!(servers
+            .update_from(&[Box::new(source)], None)
+            .await
+            .unwrap());
+        assert!(servers.servers.contains_key("dev_domain"));
+    }
+
+    #[tokio::test]
+    async fn test_validators_per_endpoint() {
+        let endpoint = serve_once(SERVERS_BODY).await;
+
+        let mut servers = VpnServers::new();
+        assert!(servers
+            .update_from(
+                &[Box::new(HttpServersSource::new(
+                    vec![endpoint.clone()],
+                    None
+                ))],
+                None
+            )
+            .await
+            .unwrap());
+        assert_eq!(Some(endpoint), servers.validators_endpoint);
+
+        // Not modified for the endpoint that sent the validators
+        let endpoint = serve_once(SERVERS_BODY).await;
+        servers.validators_endpoint = Some(endpoint.clone());
+        // The test servers answer a single request. No metadata check.
+        servers.digest = None;
+        assert!(!servers
+            .update_from(
+                &[Box::new(HttpServersSource::new(vec![endpoint], None))],
+                None
+            )
+            .await
+            .unwrap());
+
+        // Another mirror is not sent the validators
+        let mirror_endpoint = serve_once(SERVERS_BODY).await;
+        assert!(servers
+            .update_from(
+                &[Box::new(HttpServersSource::new(
+                    vec![mirror_endpoint.clone()],
+                    None
+                ))],
+                None
+            )
+            .await
+            .unwrap());
+        assert_eq!(Some(mirror_endpoint), servers.validators_endpoint);
+    }
+
+    // Answers a single request with the body. Returns the endpoint.
+    async fn serve_once(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/servers.json", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+
+            // Read the request headers
+            let mut received = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !received.windows(4).any(|window| window == b"\r\n\r\n") {
+                let read = socket.read(&mut buffer).await.unwrap();
+                assert_ne!(0, read);
+                received.extend_from_slice(&buffer[..read]);
+            }
+
+            // The client's validators match
+            let headers = String::from_utf8_lossy(&received).to_lowercase();
+            if headers.contains("if-none-match: \"7\"") {
+                socket
+                    .write_all(b"HTTP/1.1 304 Not Modified\r\nconnection: close\r\n\r\n")
+                    .await
+                    .unwrap();
+                socket.shutdown().await.unwrap();
+                return;
+            }
+
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\ncontent-digest: sha-256=:{}:\r\netag: \"7\"\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                base64::encode(Sha256::digest(body.as_bytes())),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            socket.shutdown().await.unwrap();
+        });
+
+        endpoint
+    }
+
+    #[tokio::test]
+    async fn test_file_source() {
+        let path =
+            std::env::temp_dir().join(format!("veronymous-servers-{}.json", std::process::id()));
+        fs::write(&path, SERVERS_BODY).unwrap();
+
+        let sources: Vec<Box<dyn ServersSource>> = vec![Box::new(FileServersSource::new(
+            path.to_str().unwrap().to_string(),
+        ))];
+
+        let mut servers = VpnServers::new();
+        assert!(servers.update_from(&sources, None).await.unwrap());
+        assert!(servers.servers.contains_key("dev_domain"));
+
+        // Unchanged file
+        assert!(!servers.update_from(&sources, None).await.unwrap());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bundled_source() {
+        let result = BundledServersSource.fetch(&VpnServers::new()).await;
+
+        // Set at build time
+        match BUNDLED_SERVERS.is_empty() {
+            true => assert!(matches!(result, Err(NotFoundError(_)))),
+            false => assert!(matches!(result, Ok(Some(_)))),
+        }
+    }
+
+    #[test]
+    fn test_signature_path() {
+        assert_eq!(
+            PathBuf::from("/etc/veronymous-vpn/servers.json.sig"),
+            signature_path("/etc/veronymous-vpn/servers.json")
+        );
+        assert_eq!(PathBuf::from("servers.sig"), signature_path("servers"));
+    }
+
+    #[tokio::test]
+    async fn test_source_fallback() {
+        let path = std::env::temp_dir().join(format!(
+            "veronymous-fallback-servers-{}.json",
+            std::process::id()
+        ));
+        fs::write(&path, SERVERS_BODY).unwrap();
+
+        let sources: Vec<Box<dyn ServersSource>> = vec![
+            Box::new(UnavailableSource),
+            Box::new(FileServersSource::new(path.to_str().unwrap().to_string())),
+        ];
+
+        let mut servers = VpnServers::new();
+        assert!(servers.update_from(&sources, None).await.unwrap());
+        assert!(servers.servers.contains_key("dev_domain"));
+
+        fs::remove_file(path).unwrap();
+
+        // Every source failed
+        let mut servers = VpnServers::new();
+        let sources: Vec<Box<dyn ServersSource>> = vec![Box::new(UnavailableSource)];
+        assert!(matches!(
+            servers.update_from(&sources, None).await,
+            Err(HttpError(_))
+        ));
+    }
+}
@@ -0,0 +1,330 @@
+use crate::error::VeronymousClientError;
+use crate::error::VeronymousClientError::{ConnectError, ParseError, PinMismatch};
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::io;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+pub type TlsConfig = ClientConfig;
+
+// Pins are formatted as "sha256/<base64 hash of the DER SubjectPublicKeyInfo>"
+const PIN_PREFIX: &str = "sha256/";
+const PIN_MISMATCH: &str = "Server certificate does not match the pinned public keys.";
+
+const H2_ALPN: &[u8] = b"h2";
+
+/*
+* SHA-256 hashes of the pinned server public keys.
+* Every pin after the first one is a backup (e.g., for key rotation).
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpkiPins {
+    hashes: Vec<[u8; 32]>,
+}
+
+impl SpkiPins {
+    pub fn parse(pins: &[String]) -> Result<Self, VeronymousClientError> {
+        let mut hashes = Vec::with_capacity(pins.len());
+
+        for pin in pins {
+            let encoded = pin.strip_prefix(PIN_PREFIX).unwrap_or(pin);
+
+            let hash = base64::decode(encoded)
+                .map_err(|e| ParseError(format!("Could not decode pin '{}'. {:?}", pin, e)))?
+                .try_into()
+                .map_err(|_| ParseError(format!("Pin '{}' is not a SHA-256 hash.", pin)))?;
+
+            hashes.push(hash);
+        }
+
+        Ok(Self { hashes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn matches(&self, certificate: &[u8]) -> Result<bool, VeronymousClientError> {
+        let hash = spki_hash(certificate)?;
+
+        Ok(self.hashes.contains(&hash))
+    }
+}
+
+// SHA-256 hash of the certificate's DER encoded SubjectPublicKeyInfo
+pub fn spki_hash(certificate: &[u8]) -> Result<[u8; 32], VeronymousClientError> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate)
+        .map_err(|e| ParseError(format!("Could not parse certificate. {:?}", e)))?;
+
+    Ok(Sha256::digest(certificate.tbs_certificate.subject_pki.raw).into())
+}
+
+/*
+* Regular CA verification followed by the public key pin check.
+* Only the end-entity certificate is pinned. The intermediates are sent by the server
+* and could be appended by anyone.
+*/
+struct PinnedCertVerifier {
+    webpki: WebPkiVerifier,
+
+    pins: SpkiPins,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.webpki.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        let matches = self
+            .pins
+            .matches(&end_entity.0)
+            .map_err(|e| rustls::Error::InvalidCertificateData(e.to_string()))?;
+
+        if !matches {
+            return Err(rustls::Error::General(PIN_MISMATCH.to_string()));
+        }
+
+        Ok(verified)
+    }
+}
+
+/*
+* TLS configuration with public key pinning.
+* Trusts the given CA or the webpki roots if none is given.
+*/
+pub fn pinned_tls_config(
+    ca: &Option<String>,
+    pins: &SpkiPins,
+) -> Result<TlsConfig, VeronymousClientError> {
+    let mut roots = RootCertStore::empty();
+
+    match ca {
+        Some(ca) => {
+            let certificates = rustls_pemfile::certs(&mut ca.as_bytes())
+                .map_err(|e| ParseError(format!("Could not read CA certificate. {:?}", e)))?;
+
+            let (added, _) = roots.add_parsable_certificates(&certificates);
+
+            if added == 0 {
+                return Err(ParseError(
+                    "CA does not contain a valid certificate.".to_string(),
+                ));
+            }
+        }
+        None => {
+            roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            }));
+        }
+    }
+
+    let verifier = PinnedCertVerifier {
+        webpki: WebPkiVerifier::new(roots, None),
+        pins: pins.clone(),
+    };
+
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+
+    // gRPC
+    config.alpn_protocols = vec![H2_ALPN.to_vec()];
+
+    Ok(config)
+}
+
+// Optional pinned TLS configuration. None if no pins are set.
+pub fn parse_pinned_tls_config(
+    ca: &Option<String>,
+    pins: &[String],
+) -> Result<Option<Arc<TlsConfig>>, VeronymousClientError> {
+    let pins = SpkiPins::parse(pins)?;
+
+    if pins.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Arc::new(pinned_tls_config(ca, &pins)?)))
+}
+
+pub async fn connect_tls<S>(
+    config: Arc<TlsConfig>,
+    host: &str,
+    stream: S,
+) -> Result<TlsStream<S>, VeronymousClientError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let server_name = ServerName::try_from(host)
+        .map_err(|e| ParseError(format!("Invalid server name '{}'. {:?}", host, e)))?;
+
+    TlsConnector::from(config)
+        .connect(server_name, stream)
+        .await
+        .map_err(|e| to_tls_error(host, e))
+}
+
+/*
+* Find a pin mismatch in an error's source chain.
+* The tonic and hyper errors wrap the connector errors.
+*/
+pub fn find_pin_mismatch(error: &(dyn Error + 'static)) -> Option<VeronymousClientError> {
+    let mut current = Some(error);
+
+    while let Some(error) = current {
+        if let Some(PinMismatch(message)) = error.downcast_ref::<VeronymousClientError>() {
+            return Some(PinMismatch(message.clone()));
+        }
+
+        current = error.source();
+    }
+
+    None
+}
+
+fn to_tls_error(host: &str, error: io::Error) -> VeronymousClientError {
+    // The verifier reports pin mismatches as a general TLS error
+    let is_pin_mismatch = match error
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<rustls::Error>())
+    {
+        Some(rustls::Error::General(message)) => message == PIN_MISMATCH,
+        _ => false,
+    };
+
+    if is_pin_mismatch {
+        PinMismatch(format!("{} {}", host, PIN_MISMATCH))
+    } else {
+        ConnectError(format!("TLS handshake with {} failed. {:?}", host, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::VeronymousClientError::PinMismatch;
+    use crate::tls::{connect_tls, parse_pinned_tls_config, spki_hash, SpkiPins, PIN_PREFIX};
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::TlsAcceptor;
+
+    #[test]
+    fn test_parse_pins() {
+        let hash = base64::encode([7u8; 32]);
+
+        let pins = SpkiPins::parse(&[format!("{}{}", PIN_PREFIX, hash), hash.clone()]).unwrap();
+        assert_eq!(vec![[7u8; 32], [7u8; 32]], pins.hashes);
+
+        assert!(SpkiPins::parse(&[]).unwrap().is_empty());
+        assert!(SpkiPins::parse(&["sha256/not-base64!".to_string()]).is_err());
+        assert!(SpkiPins::parse(&[base64::encode([7u8; 16])]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pinned_connection() {
+        let (ca, leaf) = certificates();
+        let leaf_pin = pin(&leaf.serialize_der_with_signer(&ca).unwrap());
+        let backup_pin = format!("{}{}", PIN_PREFIX, base64::encode([1u8; 32]));
+
+        // Primary pin
+        let reply = connect_pinned(&ca, &leaf, vec![leaf_pin.clone()]).await;
+        assert_eq!(b"ok".to_vec(), reply.unwrap());
+
+        // Backup pin
+        let reply = connect_pinned(&ca, &leaf, vec![backup_pin.clone(), leaf_pin]).await;
+        assert_eq!(b"ok".to_vec(), reply.unwrap());
+
+        // The CA is valid but the key is not pinned
+        let result = connect_pinned(&ca, &leaf, vec![backup_pin]).await;
+        assert!(matches!(result, Err(PinMismatch(_))));
+    }
+
+    async fn connect_pinned(
+        ca: &Certificate,
+        leaf: &Certificate,
+        pins: Vec<String>,
+    ) -> Result<Vec<u8>, crate::error::VeronymousClientError> {
+        let server_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(
+                    leaf.serialize_der_with_signer(ca).unwrap(),
+                )],
+                rustls::PrivateKey(leaf.serialize_private_key_der()),
+            )
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+
+            if let Ok(mut stream) = TlsAcceptor::from(Arc::new(server_config))
+                .accept(socket)
+                .await
+            {
+                stream.write_all(b"ok").await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+
+        let tls_config = parse_pinned_tls_config(&Some(ca.serialize_pem().unwrap()), &pins)
+            .unwrap()
+            .unwrap();
+
+        let socket = TcpStream::connect(address).await.unwrap();
+        let mut stream = connect_tls(tls_config, "localhost", socket).await?;
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await.unwrap();
+
+        Ok(reply)
+    }
+
+    fn certificates() -> (Certificate, Certificate) {
+        let mut ca_params = CertificateParams::new(vec![]);
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(ca_params).unwrap();
+
+        let leaf = Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()]))
+            .unwrap();
+
+        (ca, leaf)
+    }
+
+    fn pin(certificate: &[u8]) -> String {
+        format!(
+            "{}{}",
+            PIN_PREFIX,
+            base64::encode(spki_hash(certificate).unwrap())
+        )
+    }
+}
@@ -0,0 +1,222 @@
+use crate::error::VeronymousClientError;
+use crate::error::VeronymousClientError::ConnectError;
+use crate::proxy::Socks5Proxy;
+use crate::tls::{connect_tls, TlsConfig};
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{copy_bidirectional, AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tonic::transport::{Channel, Endpoint, Uri};
+use tower::service_fn;
+
+const HTTPS_PORT: u16 = 443;
+const HTTP_PORT: u16 = 80;
+
+// The client must connect to the relay right after it is started
+const RELAY_ACCEPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+pub type BoxedStream = Box<dyn AsyncStream>;
+
+/*
+* Connect a tonic channel.
+* Uses a custom connector when the traffic goes through a proxy or the certificates are pinned.
+*/
+pub async fn connect_channel(
+    endpoint: Endpoint,
+    proxy: &Option<Socks5Proxy>,
+    pinned_tls: Option<Arc<TlsConfig>>,
+) -> Result<Channel, tonic::transport::Error> {
+    if proxy.is_none() && pinned_tls.is_none() {
+        return endpoint.connect().await;
+    }
+
+    let proxy = proxy.clone();
+
+    endpoint
+        .connect_with_connector(service_fn(move |uri: Uri| {
+            let proxy = proxy.clone();
+            let pinned_tls = pinned_tls.clone();
+
+            async move { connect_stream(&uri, &proxy, pinned_tls).await }
+        }))
+        .await
+}
+
+/*
+* Open a stream to the uri's host. Directly or through the proxy.
+* The TLS handshake is done here if the certificates are pinned. Otherwise by the endpoint.
+*/
+pub async fn connect_stream(
+    uri: &Uri,
+    proxy: &Option<Socks5Proxy>,
+    pinned_tls: Option<Arc<TlsConfig>>,
+) -> Result<BoxedStream, VeronymousClientError> {
+    let stream: BoxedStream = match proxy {
+        None => {
+            let (host, port) = uri_host_port(uri)?;
+
+            Box::new(
+                TcpStream::connect((host, port))
+                    .await
+                    .map_err(|e| ConnectError(format!("Could not connect to {}. {:?}", uri, e)))?,
+            )
+        }
+        Some(proxy) => Box::new(proxy.connect(uri).await?),
+    };
+
+    match pinned_tls {
+        None => Ok(stream),
+        Some(tls_config) => {
+            let (host, _) = uri_host_port(uri)?;
+
+            Ok(Box::new(connect_tls(tls_config, host, stream).await?))
+        }
+    }
+}
+
+/*
+* Loopback endpoint for the clients that open their own connection (e.g., the router client).
+* The pinned TLS session is established first. The client's single connection is then relayed
+* through it, so everything it sends goes to the verified server.
+* Only a connection from this process is relayed. Other local processes can reach the listener.
+* Returns the endpoint to give to the client (e.g., http://127.0.0.1:41234).
+*/
+pub async fn pinned_relay(
+    uri: &Uri,
+    pinned_tls: Arc<TlsConfig>,
+) -> Result<String, VeronymousClientError> {
+    let mut server = connect_stream(uri, &None, Some(pinned_tls)).await?;
+
+    let listener = TcpListener::bind(("127.0.0.1", 0))
+        .await
+        .map_err(|e| ConnectError(format!("Could not start the relay. {:?}", e)))?;
+    let address = listener
+        .local_addr()
+        .map_err(|e| ConnectError(format!("Could not start the relay. {:?}", e)))?;
+
+    tokio::spawn(async move {
+        let mut client = match tokio::time::timeout(
+            RELAY_ACCEPT_TIMEOUT,
+            accept_own_connection(&listener),
+        )
+        .await
+        {
+            Ok(Ok(client)) => client,
+            Ok(Err(e)) => {
+                debug!("Could not accept the relayed connection. {:?}", e);
+                return;
+            }
+            Err(_) => {
+                debug!("The relayed connection was not opened.");
+                return;
+            }
+        };
+
+        // Only one connection is relayed
+        drop(listener);
+
+        if let Err(e) = copy_bidirectional(&mut client, &mut server).await {
+            debug!("Relayed connection closed. {:?}", e);
+        }
+    });
+
+    Ok(format!("http://{}", address))
+}
+
+async fn accept_own_connection(listener: &TcpListener) -> std::io::Result<TcpStream> {
+    loop {
+        let (client, peer) = listener.accept().await?;
+
+        if is_own_connection(&peer) {
+            return Ok(client);
+        }
+
+        warn!(
+            "Refused a relayed connection from another process ({}).",
+            peer
+        );
+    }
+}
+
+// The peer's address is the local address of one of this process' sockets
+fn is_own_connection(peer: &SocketAddr) -> bool {
+    let entries = match fs::read_dir("/proc/self/fd") {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("Could not list the file descriptors. {:?}", e);
+            return false;
+        }
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<RawFd>().ok())
+        .any(|fd| local_ipv4_address(fd).as_ref() == Some(peer))
+}
+
+// None if the descriptor is not an IPv4 socket
+fn local_ipv4_address(fd: RawFd) -> Option<SocketAddr> {
+    let mut address: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    let mut length = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockname(
+            fd,
+            &mut address as *mut libc::sockaddr_in as *mut libc::sockaddr,
+            &mut length,
+        )
+    };
+
+    if result != 0 || address.sin_family != libc::AF_INET as libc::sa_family_t {
+        return None;
+    }
+
+    Some(SocketAddr::from((
+        Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr)),
+        u16::from_be(address.sin_port),
+    )))
+}
+
+pub fn uri_host_port(uri: &Uri) -> Result<(&str, u16), VeronymousClientError> {
+    let host = match uri.host() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return Err(ConnectError(format!("Uri is missing the host. {}", uri))),
+    };
+
+    let port = match uri.port_u16() {
+        Some(port) => port,
+        None => match uri.scheme_str() {
+            Some("https") => HTTPS_PORT,
+            _ => HTTP_PORT,
+        },
+    };
+
+    Ok((host, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::{accept_own_connection, is_own_connection};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn test_accept_own_connection() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = TcpStream::connect(address).await.unwrap();
+        let relayed = accept_own_connection(&listener).await.unwrap();
+
+        assert_eq!(client.local_addr().unwrap(), relayed.peer_addr().unwrap());
+
+        // Not a socket of this process
+        assert!(!is_own_connection(&"127.0.0.1:1".parse().unwrap()));
+    }
+}
@@ -11,6 +11,9 @@ use veronymous_token::serde::Serializable as TokenSerializable;
 use crate::config::VERONYMOUS_CLIENT_CONFIG;
 use crate::error::VeronymousClientError;
 use crate::error::VeronymousClientError::TokenClientError;
+use crate::proxy::Socks5Proxy;
+use crate::tls::{find_pin_mismatch, parse_pinned_tls_config};
+use crate::transport::connect_channel;
 use crate::veronymous_token::grpc::veronymous_user_token_service::{TokenInfo, TokenInfoRequest, TokenRequest};
 use crate::veronymous_token::grpc::veronymous_user_token_service::veronymous_user_token_service_client::VeronymousUserTokenServiceClient;
 
@@ -29,11 +32,16 @@ impl VeronymousTokenClient {
     pub async fn create(
         endpoint: &String,
         ca: &Option<String>,
+        pins: &[String],
+        proxy: &Option<Socks5Proxy>,
     ) -> Result<Self, VeronymousClientError> {
         let mut endpoint = Endpoint::from_str(endpoint.as_str()).unwrap();
 
+        // The TLS handshake is done by the connector when the issuer's public key is pinned
+        let pinned_tls = parse_pinned_tls_config(ca, pins)?;
+
         // TLS config
-        if let Some(ca) = ca {
+        if let (Some(ca), None) = (ca, &pinned_tls) {
             let ca = tonic::transport::Certificate::from_pem(Vec::from(ca.as_bytes()));
 
             let tls_config = tonic::transport::ClientTlsConfig::new().ca_certificate(ca);
@@ -41,9 +49,14 @@ impl VeronymousTokenClient {
             endpoint = endpoint.tls_config(tls_config).unwrap();
         }
 
-        let grpc_client = VeronymousUserTokenServiceClient::connect(endpoint)
+        let channel = connect_channel(endpoint, proxy, pinned_tls)
             .await
-            .map_err(|e| TokenClientError(format!("Could not connect to token issuer. {:?}", e)))?;
+            .map_err(|e| match find_pin_mismatch(&e) {
+                Some(pin_mismatch) => pin_mismatch,
+                None => TokenClientError(format!("Could not connect to token issuer. {:?}", e)),
+            })?;
+
+        let grpc_client = VeronymousUserTokenServiceClient::new(channel);
 
         Ok(Self { grpc_client })
     }
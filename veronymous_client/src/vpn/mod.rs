@@ -1,4 +1,7 @@
+use crate::error::VeronymousClientError;
+use crate::error::VeronymousClientError::ParseError;
 use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct VpnProfile {
@@ -14,6 +17,92 @@ pub struct VpnProfile {
 
     // Wireguard server public key
     pub wg_key: String,
+
+    // Router agent public key pins (sha256/<base64>). Every additional pin is a backup.
+    #[serde(default)]
+    pub agent_pins: Vec<String>,
+
+    // Resolvers reached through the tunnel (e.g., 10.64.0.1)
+    #[serde(default)]
+    pub dns_servers: Vec<String>,
+
+    // Optional display information. Missing for older servers lists.
+    #[serde(flatten, default)]
+    pub metadata: ServerMetadata,
+
+    #[serde(flatten, default)]
+    pub tunnel_hints: TunnelHints,
+}
+
+// Suggested tunnel settings for the server. Used unless configured by the user.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TunnelHints {
+    #[serde(default)]
+    pub mtu: Option<u32>,
+
+    // Seconds
+    #[serde(default)]
+    pub persistent_keepalive: Option<u16>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerMetadata {
+    #[serde(default)]
+    pub display_name: Option<String>,
+
+    // ISO 3166-1 alpha-2 country code (e.g., CA)
+    #[serde(default)]
+    pub country: Option<String>,
+
+    #[serde(default)]
+    pub city: Option<String>,
+
+    // Geographic region (e.g., North America)
+    #[serde(default)]
+    pub region: Option<String>,
+
+    // Maximum number of concurrent connections
+    #[serde(default)]
+    pub capacity: Option<u32>,
+
+    #[serde(default)]
+    pub features: Vec<ServerFeature>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerFeature {
+    Ipv6,
+    Multihop,
+    Obfuscation,
+
+    // Features added by newer servers lists
+    #[serde(other)]
+    Unknown,
+}
+
+impl ServerFeature {
+    pub fn parse(feature: &str) -> Result<Self, VeronymousClientError> {
+        match feature.to_lowercase().as_str() {
+            "ipv6" => Ok(Self::Ipv6),
+            "multihop" => Ok(Self::Multihop),
+            "obfuscation" => Ok(Self::Obfuscation),
+            _ => Err(ParseError(format!("Unknown server feature '{}'.", feature))),
+        }
+    }
+}
+
+impl Display for ServerFeature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Ipv6 => "ipv6",
+            Self::Multihop => "multihop",
+            Self::Obfuscation => "obfuscation",
+            Self::Unknown => "unknown",
+        };
+
+        write!(f, "{}", name)
+    }
 }
 
 impl VpnProfile {
@@ -23,6 +112,8 @@ impl VpnProfile {
         root_cert: Option<String>,
         wg_endpoint: String,
         wg_key: String,
+        agent_pins: Vec<String>,
+        metadata: ServerMetadata,
     ) -> Self {
         Self {
             domain,
@@ -30,13 +121,18 @@ impl VpnProfile {
             root_cert,
             wg_endpoint,
             wg_key,
+            agent_pins,
+            // Set by the servers list
+            dns_servers: vec![],
+            metadata,
+            tunnel_hints: TunnelHints::default(),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::vpn::VpnProfile;
+    use crate::vpn::{ServerFeature, ServerMetadata, TunnelHints, VpnProfile};
 
     #[test]
     fn test_vpn_profile_json() {
@@ -50,6 +146,63 @@ mod tests {
         assert_eq!(vpn_profile, vpn_profile_parsed)
     }
 
+    #[test]
+    fn test_vpn_profile_without_pins() {
+        let vpn_profile_json = r#"{
+            "domain": "dev_domain",
+            "agent_endpoint": "localhost.veronymous.io:7777",
+            "root_cert": null,
+            "wg_endpoint": "wg1.ny.veronymous.io:51820",
+            "wg_key": "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg="
+        }"#;
+
+        let vpn_profile: VpnProfile = serde_json::from_str(vpn_profile_json).unwrap();
+
+        assert!(vpn_profile.agent_pins.is_empty());
+        assert!(vpn_profile.dns_servers.is_empty());
+        assert_eq!(TunnelHints::default(), vpn_profile.tunnel_hints);
+        assert_eq!(ServerMetadata::default(), vpn_profile.metadata);
+    }
+
+    #[test]
+    fn test_vpn_profile_metadata() {
+        let vpn_profile_json = r#"{
+            "domain": "dev_domain",
+            "agent_endpoint": "localhost.veronymous.io:7777",
+            "root_cert": null,
+            "wg_endpoint": "wg1.ny.veronymous.io:51820",
+            "wg_key": "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=",
+            "display_name": "New York",
+            "country": "US",
+            "city": "New York",
+            "region": "North America",
+            "capacity": 250,
+            "features": ["ipv6", "quantum_resistant", "multihop"],
+            "mtu": 1280,
+            "persistent_keepalive": 25
+        }"#;
+
+        let vpn_profile: VpnProfile = serde_json::from_str(vpn_profile_json).unwrap();
+
+        assert_eq!(Some("US".to_string()), vpn_profile.metadata.country);
+        assert_eq!(Some(250), vpn_profile.metadata.capacity);
+        assert_eq!(
+            vec![
+                ServerFeature::Ipv6,
+                ServerFeature::Unknown,
+                ServerFeature::Multihop
+            ],
+            vpn_profile.metadata.features
+        );
+        assert_eq!(
+            TunnelHints {
+                mtu: Some(1280),
+                persistent_keepalive: Some(25)
+            },
+            vpn_profile.tunnel_hints
+        );
+    }
+
     fn vpn_profile() -> VpnProfile {
         let root_cert = "-----BEGIN CERTIFICATE-----
 MIID0TCCArmgAwIBAgIUCVuNppf++HHklyxMgrGWTPNTKMgwDQYJKoZIhvcNAQEL
@@ -81,6 +234,20 @@ wIieRFPJFKt7IAQE8g3/2VF12EeS
             root_cert: Some(root_cert.to_string()),
             wg_endpoint: "wg1.ny.veronymous.io:51820".to_string(),
             wg_key: "/ZjSUjxcDiHHxBifHX0yVekKklDmczNv8k7M3AgmXXg=".to_string(),
+            agent_pins: vec!["sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_string()],
+            dns_servers: vec!["10.64.0.1".to_string(), "fd00:64::1".to_string()],
+            metadata: ServerMetadata {
+                display_name: Some("New York".to_string()),
+                country: Some("US".to_string()),
+                city: Some("New York".to_string()),
+                region: Some("North America".to_string()),
+                capacity: Some(250),
+                features: vec![ServerFeature::Ipv6],
+            },
+            tunnel_hints: TunnelHints {
+                mtu: Some(1380),
+                persistent_keepalive: None,
+            },
         }
     }
 }